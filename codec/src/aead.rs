@@ -0,0 +1,320 @@
+//! Encrypt and authenticate the encoded messages with pre-shared keys.
+//!
+//! Each message sealed by [AeadCodec] is laid out as:
+//!
+//! ```text
+//! | algo: u8 | key_id: u32 (BE) | nonce: 12 bytes | ciphertext | tag: 16 bytes |
+//! ```
+//!
+//! The algo and key_id are authenticated as associated data. The nonce is random, so a key
+//! should be rotated long before 2^32 messages are sealed with it.
+//!
+//! The codec does not know the frame carrying the msg, the seq and action are not bound to the
+//! ciphertext. Whoever on the path can replay a sealed msg, or swap the msgs between the requests
+//! sealed with the same key. Use TLS when the transport is not trusted against replay.
+//!
+//! Only the msg is encrypted, the blob and the error strings of `RpcErrCodec` do not go through
+//! the codec, and are sent as is.
+
+use crate::owned::Owned;
+use crate::{Codec, CodecError, FORMAT_AEAD};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use chacha20poly1305::aead::{Aead, AeadCore, AeadInPlace, KeyInit, OsRng, Payload};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+const ALGO_CHACHA20_POLY1305: u8 = 1;
+const ALGO_AES256_GCM: u8 = 2;
+
+const HEAD_LEN: usize = 5;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+enum Cipher {
+    ChaCha20Poly1305(ChaCha20Poly1305),
+    /// The expanded key schedule of AES is much larger than ChaCha20's
+    Aes256Gcm(Box<Aes256Gcm>),
+}
+
+/// A 256-bit pre-shared key along with its cipher, cheap to clone.
+#[derive(Clone)]
+pub struct AeadKey(Arc<Cipher>);
+
+impl AeadKey {
+    pub fn chacha20_poly1305(key: &[u8; 32]) -> Self {
+        Self(Arc::new(Cipher::ChaCha20Poly1305(ChaCha20Poly1305::new(key.into()))))
+    }
+
+    pub fn aes256_gcm(key: &[u8; 32]) -> Self {
+        Self(Arc::new(Cipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into())))))
+    }
+
+    #[inline]
+    fn algo(&self) -> u8 {
+        match self.0.as_ref() {
+            Cipher::ChaCha20Poly1305(_) => ALGO_CHACHA20_POLY1305,
+            Cipher::Aes256Gcm(_) => ALGO_AES256_GCM,
+        }
+    }
+}
+
+impl fmt::Debug for AeadKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.as_ref() {
+            Cipher::ChaCha20Poly1305(_) => f.write_str("AeadKey(ChaCha20Poly1305)"),
+            Cipher::Aes256Gcm(_) => f.write_str("AeadKey(Aes256Gcm)"),
+        }
+    }
+}
+
+/// Provides the keys of [AeadCodec] by key id.
+///
+/// Because the codec is constructed by `Default` on each connection, the provider usually
+/// refers to a global [AeadKeyRing]:
+///
+/// ```ignore
+/// static KEYS: LazyLock<AeadKeyRing> = LazyLock::new(AeadKeyRing::new);
+///
+/// #[derive(Default)]
+/// struct MyKeys;
+///
+/// impl AeadKeyProvider for MyKeys {
+///     fn current_key(&self) -> Option<(u32, AeadKey)> {
+///         KEYS.current_key()
+///     }
+///
+///     fn get_key(&self, key_id: u32) -> Option<AeadKey> {
+///         KEYS.get_key(key_id)
+///     }
+/// }
+///
+/// type MyCodec = AeadCodec<MsgpCodec, MyKeys>;
+/// ```
+pub trait AeadKeyProvider: Default + Send + Sync + 'static {
+    /// The key id and key to encrypt with, None to fail the encoding
+    fn current_key(&self) -> Option<(u32, AeadKey)>;
+
+    /// The key to decrypt the messages sealed with `key_id`
+    fn get_key(&self, key_id: u32) -> Option<AeadKey>;
+}
+
+/// A set of keys by id, with one of them to encrypt.
+///
+/// To rotate the key without failing the requests in flight:
+/// 1. [insert()](Self::insert) the new key on all the peers,
+/// 2. [set_current()](Self::set_current) to the new key id,
+/// 3. [remove()](Self::remove) the old key after the responses sealed by it are received.
+#[derive(Default)]
+pub struct AeadKeyRing(RwLock<KeyRingInner>);
+
+#[derive(Default)]
+struct KeyRingInner {
+    current: Option<u32>,
+    keys: HashMap<u32, AeadKey>,
+}
+
+impl AeadKeyRing {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace a key, the first key inserted becomes the current one.
+    pub fn insert(&self, key_id: u32, key: AeadKey) {
+        let mut inner = self.0.write().unwrap();
+        inner.keys.insert(key_id, key);
+        if inner.current.is_none() {
+            inner.current = Some(key_id);
+        }
+    }
+
+    /// Encrypt with `key_id` from now on, returns false if the key does not exist.
+    pub fn set_current(&self, key_id: u32) -> bool {
+        let mut inner = self.0.write().unwrap();
+        if inner.keys.contains_key(&key_id) {
+            inner.current = Some(key_id);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Remove a key, the messages sealed by it can no longer be decrypted.
+    ///
+    /// If it is the current key, the encoding fails until another one is set.
+    pub fn remove(&self, key_id: u32) -> Option<AeadKey> {
+        let mut inner = self.0.write().unwrap();
+        if inner.current == Some(key_id) {
+            inner.current = None;
+        }
+        inner.keys.remove(&key_id)
+    }
+
+    pub fn current_key(&self) -> Option<(u32, AeadKey)> {
+        let inner = self.0.read().unwrap();
+        let key_id = inner.current?;
+        inner.keys.get(&key_id).map(|key| (key_id, key.clone()))
+    }
+
+    #[inline]
+    pub fn get_key(&self, key_id: u32) -> Option<AeadKey> {
+        self.0.read().unwrap().keys.get(&key_id).cloned()
+    }
+}
+
+/// Wraps codec `C`, encrypt and authenticate the encoded msg with ChaCha20-Poly1305 or
+/// AES-256-GCM, by the keys from `K`.
+///
+/// It protects the payload on the transports without TLS, both peers should share the same
+/// keys. The format id is [FORMAT_AEAD], so it can be served along with a plain codec by
+/// [MultiCodec](crate::MultiCodec), but only one `AeadCodec` is distinguishable.
+#[derive(Default)]
+pub struct AeadCodec<C: Codec, K: AeadKeyProvider> {
+    inner: C,
+    keys: K,
+}
+
+impl<C: Codec, K: AeadKeyProvider> AeadCodec<C, K> {
+    #[inline]
+    pub fn new(inner: C, keys: K) -> Self {
+        Self { inner, keys }
+    }
+}
+
+impl<C: Codec, K: AeadKeyProvider> Codec for AeadCodec<C, K> {
+    const FORMAT: u8 = FORMAT_AEAD;
+
+    #[inline]
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+        let mut buf = Vec::new();
+        self.encode_into(task, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// sererialized the msg into buf (with std::io::Writer), and return the size written
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
+        let Some((key_id, key)) = self.keys.current_key() else {
+            return Err(CodecError::encode::<T>("no aead key"));
+        };
+        let pre_len = buf.len();
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        buf.push(key.algo());
+        buf.extend_from_slice(&key_id.to_be_bytes());
+        buf.extend_from_slice(&nonce);
+        let msg_start = buf.len();
+        if let Err(e) = self.inner.encode_into(task, buf) {
+            buf.truncate(pre_len);
+            return Err(e);
+        }
+        let (head, msg) = buf[pre_len..].split_at_mut(msg_start - pre_len);
+        let aad = &head[..HEAD_LEN];
+        let r = match key.0.as_ref() {
+            Cipher::ChaCha20Poly1305(c) => c.encrypt_in_place_detached(&nonce, aad, msg),
+            Cipher::Aes256Gcm(c) => c.encrypt_in_place_detached(&nonce, aad, msg),
+        };
+        match r {
+            Ok(tag) => {
+                buf.extend_from_slice(&tag);
+                Ok(buf.len() - pre_len)
+            }
+            Err(_) => {
+                buf.truncate(pre_len);
+                Err(CodecError::encode::<T>("aead encrypt failed"))
+            }
+        }
+    }
+
+    /// The msg is decrypted into a buffer of its own, so `T` should own its data, the types
+    /// borrowing from `buf` (like `&'a str`) fail to decode.
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+        if buf.len() < HEAD_LEN + NONCE_LEN + TAG_LEN {
+            return Err(CodecError::decode::<T>("aead msg too short"));
+        }
+        let (aad, sealed) = buf.split_at(HEAD_LEN);
+        let key_id = u32::from_be_bytes([aad[1], aad[2], aad[3], aad[4]]);
+        let Some(key) = self.keys.get_key(key_id) else {
+            return Err(CodecError::decode::<T>(format!("unknown aead key {}", key_id)));
+        };
+        if key.algo() != aad[0] {
+            return Err(CodecError::decode::<T>(format!("aead algo {} mismatch", aad[0])));
+        }
+        let (nonce, msg) = sealed.split_at(NONCE_LEN);
+        let payload = Payload { msg, aad };
+        let r = match key.0.as_ref() {
+            Cipher::ChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+            Cipher::Aes256Gcm(c) => c.decrypt(nonce.into(), payload),
+        };
+        match r {
+            Ok(plain) => match self.inner.decode::<Owned<T>>(&plain) {
+                Ok(owned) => Ok(owned.0),
+                Err(e) => Err(e.with_type::<T>()),
+            },
+            Err(_) => Err(CodecError::decode::<T>("aead decrypt failed")),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod tests {
+    use super::*;
+    use crate::MsgpCodec;
+    use std::sync::LazyLock;
+
+    static KEYS: LazyLock<AeadKeyRing> = LazyLock::new(AeadKeyRing::new);
+
+    #[derive(Default)]
+    struct TestKeys;
+
+    impl AeadKeyProvider for TestKeys {
+        fn current_key(&self) -> Option<(u32, AeadKey)> {
+            KEYS.current_key()
+        }
+
+        fn get_key(&self, key_id: u32) -> Option<AeadKey> {
+            KEYS.get_key(key_id)
+        }
+    }
+
+    #[test]
+    fn test_aead() {
+        let codec = AeadCodec::<MsgpCodec, TestKeys>::default();
+        assert!(codec.encode(&1u32).unwrap_err().is_encode());
+        KEYS.insert(1, AeadKey::chacha20_poly1305(&[1; 32]));
+
+        let mut buf = b"head".to_vec();
+        let size = codec.encode_into(&(10, "a"), &mut buf).expect("encode");
+        assert_eq!(size, buf.len() - 4);
+        assert_eq!(&buf[4..9], &[ALGO_CHACHA20_POLY1305, 0, 0, 0, 1]);
+        let decoded: (i32, String) = codec.decode(&buf[4..]).expect("decode");
+        assert_eq!(decoded, (10, "a".to_string()));
+        // Tampered
+        let last = buf.len() - 1;
+        buf[last] ^= 1;
+        assert!(codec.decode::<(i32, String)>(&buf[4..]).is_err());
+        assert!(codec.decode::<(i32, String)>(&buf[4..10]).is_err());
+        // Decrypted into a buffer of its own, the owned types are decoded as usual
+        let buf = codec.encode(&(Some(10), vec!["a"], Err::<(), _>(2u8))).expect("encode");
+        let decoded: (Option<i32>, Vec<String>, Result<(), u8>) = codec.decode(&buf).unwrap();
+        assert_eq!(decoded, (Some(10), vec!["a".to_string()], Err(2)));
+        // The borrowed types are not
+        let e = codec.decode::<(Option<i32>, Vec<&str>, Result<(), u8>)>(&buf).unwrap_err();
+        assert!(e.is_decode());
+        assert!(e.type_name().contains("&str"), "{}", e);
+
+        // Rotate to AES-256-GCM, the old msg can be decoded until key 1 is removed
+        let old = codec.encode(&"old").expect("encode");
+        KEYS.insert(2, AeadKey::aes256_gcm(&[2; 32]));
+        assert!(KEYS.set_current(2));
+        assert!(!KEYS.set_current(3));
+        let new = codec.encode(&"new").expect("encode");
+        assert_eq!(&new[..5], &[ALGO_AES256_GCM, 0, 0, 0, 2]);
+        assert_eq!(codec.decode::<String>(&new).expect("decode"), "new");
+        assert_eq!(codec.decode::<String>(&old).expect("decode"), "old");
+        KEYS.remove(1);
+        let e = codec.decode::<String>(&old).unwrap_err();
+        assert!(e.to_string().ends_with("unknown aead key 1"));
+    }
+}
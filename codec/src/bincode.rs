@@ -0,0 +1,66 @@
+use crate::{Codec, CodecError, FORMAT_BINCODE};
+use ::bincode::config::{Configuration, standard};
+use serde::{Deserialize, Serialize};
+
+/// Codec of [bincode](https://docs.rs/bincode) with the standard configuration (varint encoded
+/// integers), for the traffic between Rust peers.
+///
+/// NOTE: bincode is not self-describing, the types relying on `deserialize_any` (like
+/// `#[serde(untagged)]` or `#[serde(flatten)]`) or skipping fields on serialize are not supported.
+#[derive(Default)]
+pub struct BincodeCodec();
+
+const CONFIG: Configuration = standard();
+
+impl Codec for BincodeCodec {
+    const FORMAT: u8 = FORMAT_BINCODE;
+
+    #[inline(always)]
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+        ::bincode::serde::encode_to_vec(task, CONFIG).map_err(CodecError::encode::<T>)
+    }
+
+    /// sererialized the msg into buf (with std::io::Writer), and return the size written
+    #[inline(always)]
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
+        ::bincode::serde::encode_into_std_write(task, buf, CONFIG).map_err(CodecError::encode::<T>)
+    }
+
+    #[inline(always)]
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+        match ::bincode::serde::borrow_decode_from_slice::<T, _>(buf, CONFIG) {
+            Err(e) => Err(CodecError::decode::<T>(e)),
+            Ok((s, _)) => Ok(s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Req<'a> {
+        id: u64,
+        name: &'a str,
+        args: Vec<i32>,
+        opt: Option<String>,
+    }
+
+    #[test]
+    fn test_bincode() {
+        let codec = BincodeCodec::default();
+        let encoded = codec.encode(&()).expect("encode");
+        let _decoded: () = codec.decode(&encoded).expect("decode");
+
+        let req = Req { id: 1, name: "hello", args: vec![-1, 2], opt: None };
+        let mut buf = vec![0u8; 4];
+        let size = codec.encode_into(&req, &mut buf).expect("encode");
+        assert_eq!(size, buf.len() - 4);
+        assert_eq!(&buf[4..], codec.encode(&req).expect("encode"));
+        let decoded: Req = codec.decode(&buf[4..]).expect("decode");
+        assert_eq!(decoded, req);
+        assert!(codec.decode::<Req>(&buf[4..6]).is_err());
+    }
+}
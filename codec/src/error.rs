@@ -0,0 +1,85 @@
+use std::error::Error;
+use std::fmt;
+
+/// The error of [Codec](crate::Codec), carries the name of the message type and the underlying
+/// serde error.
+pub struct CodecError {
+    type_name: &'static str,
+    encode: bool,
+    source: Box<dyn Error + Send + Sync + 'static>,
+}
+
+impl CodecError {
+    /// Failed to encode a message of type `T`
+    #[inline]
+    pub fn encode<T: ?Sized>(e: impl Into<Box<dyn Error + Send + Sync + 'static>>) -> Self {
+        Self { type_name: std::any::type_name::<T>(), encode: true, source: e.into() }
+    }
+
+    /// Failed to decode a message of type `T`
+    #[inline]
+    pub fn decode<T: ?Sized>(e: impl Into<Box<dyn Error + Send + Sync + 'static>>) -> Self {
+        Self { type_name: std::any::type_name::<T>(), encode: false, source: e.into() }
+    }
+
+    /// Replace the type name with `T`, for the error of a wrapper type
+    #[cfg(feature = "aead")]
+    #[inline]
+    pub(crate) fn with_type<T: ?Sized>(self) -> Self {
+        Self { type_name: std::any::type_name::<T>(), ..self }
+    }
+
+    /// The name of the message type, by [std::any::type_name]
+    #[inline(always)]
+    pub fn type_name(&self) -> &'static str {
+        self.type_name
+    }
+
+    #[inline(always)]
+    pub fn is_encode(&self) -> bool {
+        self.encode
+    }
+
+    #[inline(always)]
+    pub fn is_decode(&self) -> bool {
+        !self.encode
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = if self.encode { "encode" } else { "decode" };
+        write!(f, "{} {}: {}", op, self.type_name, self.source)
+    }
+}
+
+impl fmt::Debug for CodecError {
+    #[inline]
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Error for CodecError {
+    #[inline]
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_error() {
+        let e = CodecError::decode::<u32>("invalid type");
+        assert!(e.is_decode());
+        assert_eq!(e.type_name(), "u32");
+        assert_eq!(e.to_string(), "decode u32: invalid type");
+        assert_eq!(e.source().unwrap().to_string(), "invalid type");
+        let e = CodecError::encode::<Vec<u8>>(String::from("too large"));
+        assert!(e.is_encode());
+        assert_eq!(format!("{:?}", e), "encode alloc::vec::Vec<u8>: too large");
+    }
+}
@@ -0,0 +1,60 @@
+use crate::{Codec, CodecError, FORMAT_JSON};
+use serde::{Deserialize, Serialize};
+
+/// Codec of JSON by [serde_json](https://docs.rs/serde_json), for inspecting the traffic and
+/// calling the services from other languages.
+///
+/// The msg is plain utf8 JSON text, for example the args `(10, 20)` are sent as `[10,20]`.
+/// The error strings of `RpcErrCodec` (`String` and the internal `rpc_*` errors) are not
+/// encoded by the codec, a JSON peer reads them as is.
+///
+/// To serve JSON along with another codec, use [MultiCodec](crate::MultiCodec) on the
+/// server-side.
+#[derive(Default)]
+pub struct JsonCodec();
+
+impl Codec for JsonCodec {
+    const FORMAT: u8 = FORMAT_JSON;
+
+    #[inline(always)]
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+        serde_json::to_vec(task).map_err(CodecError::encode::<T>)
+    }
+
+    /// sererialized the msg into buf (with std::io::Writer), and return the size written
+    #[inline(always)]
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
+        let pre_len = buf.len();
+        if let Err(e) = serde_json::to_writer(&mut *buf, task) {
+            Err(CodecError::encode::<T>(e))
+        } else {
+            Ok(buf.len() - pre_len)
+        }
+    }
+
+    #[inline(always)]
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+        serde_json::from_slice::<T>(buf).map_err(CodecError::decode::<T>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json() {
+        let codec = JsonCodec::default();
+        let encoded = codec.encode(&()).expect("encode");
+        assert_eq!(encoded, b"null");
+        let _decoded: () = codec.decode(&encoded).expect("decode");
+
+        let mut buf = b"head".to_vec();
+        let size = codec.encode_into(&(10, "a"), &mut buf).expect("encode");
+        assert_eq!(&buf[4..], b"[10,\"a\"]");
+        assert_eq!(size, buf.len() - 4);
+        let decoded: (i32, &str) = codec.decode(&buf[4..]).expect("decode");
+        assert_eq!(decoded, (10, "a"));
+        assert!(codec.decode::<(i32, &str)>(b"[10,").is_err());
+    }
+}
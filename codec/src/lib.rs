@@ -14,18 +14,78 @@
 
 use serde::{Deserialize, Serialize};
 
+/// Format id 0 means the default codec of the server, which is also what old clients send
+pub const FORMAT_DEFAULT: u8 = 0;
+pub const FORMAT_MSGPACK: u8 = 1;
+pub const FORMAT_BINCODE: u8 = 2;
+pub const FORMAT_JSON: u8 = 3;
+pub const FORMAT_PROTOBUF: u8 = 4;
+pub const FORMAT_AEAD: u8 = 5;
+
 /// The codec is immutable, if need changing (like setting up cipher), should have inner
-/// mutablilty
+/// mutablilty, refer to `AeadCodec` (feature `aead`).
 pub trait Codec: Default + Send + Sync + Sized + 'static {
-    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, ()>;
+    /// The format id stamped on the request header by the client-side.
+    ///
+    /// Refer to the `FORMAT_*` constants, user-defined codec should use id >= 128.
+    const FORMAT: u8 = FORMAT_DEFAULT;
+
+    /// Construct the codec for the format id in the request header on the server-side,
+    /// returns None if not supported.
+    ///
+    /// By default, accepts [FORMAT_DEFAULT] and `Self::FORMAT`.
+    #[inline]
+    fn from_format(format: u8) -> Option<Self> {
+        if format == FORMAT_DEFAULT || format == Self::FORMAT {
+            Some(Self::default())
+        } else {
+            None
+        }
+    }
+
+    /// All the format ids accepted by [Codec::from_format()], in the order of preference
+    #[inline]
+    fn formats() -> Vec<u8> {
+        vec![Self::FORMAT]
+    }
+
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError>;
 
     /// sererialized the msg into buf (with std::io::Writer), and return the size written
-    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, ()>;
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError>;
 
-    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, ()>;
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError>;
 }
 
+mod error;
+pub use error::CodecError;
+
+mod multi;
+pub use multi::MultiCodec;
+
 #[cfg(feature = "msgpack")]
 mod msgpack;
 #[cfg(feature = "msgpack")]
 pub use msgpack::*;
+
+#[cfg(feature = "bincode")]
+mod bincode;
+#[cfg(feature = "bincode")]
+pub use self::bincode::*;
+
+#[cfg(feature = "json")]
+mod json;
+#[cfg(feature = "json")]
+pub use json::*;
+
+#[cfg(feature = "prost")]
+mod protobuf;
+#[cfg(feature = "prost")]
+pub use protobuf::*;
+
+#[cfg(feature = "aead")]
+mod aead;
+#[cfg(feature = "aead")]
+mod owned;
+#[cfg(feature = "aead")]
+pub use aead::*;
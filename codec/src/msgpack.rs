@@ -1,41 +1,30 @@
-use crate::Codec;
+use crate::{Codec, CodecError, FORMAT_MSGPACK};
 use serde::{Deserialize, Serialize};
 
 #[derive(Default)]
 pub struct MsgpCodec();
 
 impl Codec for MsgpCodec {
+    const FORMAT: u8 = FORMAT_MSGPACK;
+
     #[inline(always)]
-    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, ()> {
-        match rmp_serde::encode::to_vec_named(task) {
-            Ok(buf) => return Ok(buf),
-            Err(e) => {
-                log::error!("encode error: {:?}", e);
-                return Err(());
-            }
-        }
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+        rmp_serde::encode::to_vec_named(task).map_err(CodecError::encode::<T>)
     }
 
     /// sererialized the msg into buf (with std::io::Writer), and return the size written
-    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, ()> {
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
         let pre_len = buf.len();
         if let Err(e) = rmp_serde::encode::write_named(buf, task) {
-            log::error!("encode error: {:?}", e);
-            return Err(());
+            Err(CodecError::encode::<T>(e))
         } else {
             Ok(buf.len() - pre_len)
         }
     }
 
     #[inline(always)]
-    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, ()> {
-        match rmp_serde::decode::from_slice::<T>(buf) {
-            Err(e) => {
-                log::warn!("decode error: {:?}", e);
-                return Err(());
-            }
-            Ok(s) => return Ok(s),
-        }
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+        rmp_serde::decode::from_slice::<T>(buf).map_err(CodecError::decode::<T>)
     }
 }
 
@@ -50,5 +39,9 @@ mod tests {
         let encoded = codec.encode(&()).expect("encode");
         println!("encoded () size :{}", encoded.len());
         let _decoded: () = codec.decode(&encoded).expect("decode");
+        let e = codec.decode::<u32>(&encoded).unwrap_err();
+        assert!(e.is_decode());
+        assert_eq!(e.type_name(), "u32");
+        assert!(e.to_string().starts_with("decode u32: "));
     }
 }
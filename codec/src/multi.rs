@@ -0,0 +1,113 @@
+use crate::{Codec, CodecError};
+use serde::{Deserialize, Serialize};
+
+/// A codec supporting two formats, selected by the format id in request header on the
+/// server-side.
+///
+/// It can be nested to support more formats, for example during a migration:
+///
+/// ```ignore
+/// type ServerCodec = MultiCodec<MsgpCodec, MultiCodec<JsonCodec, BincodeCodec>>;
+/// ```
+///
+/// The default (and the format stamped on the client-side) is `A`.
+pub enum MultiCodec<A: Codec, B: Codec> {
+    A(A),
+    B(B),
+}
+
+impl<A: Codec, B: Codec> Default for MultiCodec<A, B> {
+    #[inline]
+    fn default() -> Self {
+        Self::A(A::default())
+    }
+}
+
+impl<A: Codec, B: Codec> Codec for MultiCodec<A, B> {
+    const FORMAT: u8 = A::FORMAT;
+
+    #[inline]
+    fn from_format(format: u8) -> Option<Self> {
+        if let Some(a) = A::from_format(format) {
+            return Some(Self::A(a));
+        }
+        B::from_format(format).map(Self::B)
+    }
+
+    #[inline]
+    fn formats() -> Vec<u8> {
+        let mut formats = A::formats();
+        for f in B::formats() {
+            if !formats.contains(&f) {
+                formats.push(f);
+            }
+        }
+        formats
+    }
+
+    #[inline(always)]
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            Self::A(a) => a.encode(task),
+            Self::B(b) => b.encode(task),
+        }
+    }
+
+    #[inline(always)]
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
+        match self {
+            Self::A(a) => a.encode_into(task, buf),
+            Self::B(b) => b.encode_into(task, buf),
+        }
+    }
+
+    #[inline(always)]
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+        match self {
+            Self::A(a) => a.decode(buf),
+            Self::B(b) => b.decode(buf),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[derive(Default)]
+    struct OtherCodec(MsgpCodec);
+
+    impl Codec for OtherCodec {
+        const FORMAT: u8 = 200;
+
+        fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+            self.0.encode(task)
+        }
+
+        fn encode_into<T: Serialize>(
+            &self, task: &T, buf: &mut Vec<u8>,
+        ) -> Result<usize, CodecError> {
+            self.0.encode_into(task, buf)
+        }
+
+        fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+            self.0.decode(buf)
+        }
+    }
+
+    #[test]
+    fn test_multi_codec() {
+        type C = MultiCodec<MsgpCodec, OtherCodec>;
+        assert_eq!(C::FORMAT, FORMAT_MSGPACK);
+        assert_eq!(C::formats(), vec![FORMAT_MSGPACK, 200]);
+        assert!(matches!(C::from_format(FORMAT_DEFAULT), Some(MultiCodec::A(_))));
+        assert!(matches!(C::from_format(FORMAT_MSGPACK), Some(MultiCodec::A(_))));
+        let codec = C::from_format(200).expect("other");
+        assert!(matches!(codec, MultiCodec::B(_)));
+        assert!(C::from_format(FORMAT_JSON).is_none());
+        let encoded = codec.encode(&1u32).expect("encode");
+        let decoded: u32 = codec.decode(&encoded).expect("decode");
+        assert_eq!(decoded, 1);
+    }
+}
@@ -0,0 +1,247 @@
+//! Deserialize `T: Deserialize<'a>` from a buffer not living for `'a`.
+//!
+//! The data borrowed from the buffer is handed to the visitors of `T` as owned (`visit_str()`
+//! instead of `visit_borrowed_str()`), so the types owning their data are decoded as usual, while
+//! the types borrowing (like `&'a str`) fail with the "invalid type" error of serde.
+//!
+//! Used by the codec decoding from a scratch buffer of its own, refer to `AeadCodec`.
+
+use serde::Deserialize;
+use serde::de::{
+    self, DeserializeSeed, Deserializer, EnumAccess, MapAccess, SeqAccess, VariantAccess, Visitor,
+};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Decoded by the inner codec with any lifetime, wraps the `T` of lifetime `'a`
+pub(crate) struct Owned<'a, T>(pub(crate) T, PhantomData<&'a ()>);
+
+impl<'de, 'a, T: Deserialize<'a>> Deserialize<'de> for Owned<'a, T> {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(OwnedDe::new(deserializer)).map(|v| Owned(v, PhantomData))
+    }
+}
+
+struct OwnedDe<'a, 'de, D>(D, PhantomData<(&'a (), &'de ())>);
+
+impl<'a, 'de, D> OwnedDe<'a, 'de, D> {
+    #[inline(always)]
+    fn new(inner: D) -> Self {
+        Self(inner, PhantomData)
+    }
+}
+
+macro_rules! forward_deserialize {
+    ($($method: ident($($arg: ident: $ty: ty),*)),* $(,)?) => {
+        $(
+            #[inline]
+            fn $method<V: Visitor<'a>>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, Self::Error> {
+                self.0.$method($($arg,)* OwnedVisitor::new(visitor))
+            }
+        )*
+    };
+}
+
+impl<'a, 'de, D: Deserializer<'de>> Deserializer<'a> for OwnedDe<'a, 'de, D> {
+    type Error = D::Error;
+
+    forward_deserialize! {
+        deserialize_any(), deserialize_bool(), deserialize_i8(), deserialize_i16(),
+        deserialize_i32(), deserialize_i64(), deserialize_i128(), deserialize_u8(),
+        deserialize_u16(), deserialize_u32(), deserialize_u64(), deserialize_u128(),
+        deserialize_f32(), deserialize_f64(), deserialize_char(), deserialize_str(),
+        deserialize_string(), deserialize_bytes(), deserialize_byte_buf(), deserialize_option(),
+        deserialize_unit(), deserialize_unit_struct(name: &'static str),
+        deserialize_newtype_struct(name: &'static str), deserialize_seq(),
+        deserialize_tuple(len: usize), deserialize_tuple_struct(name: &'static str, len: usize),
+        deserialize_map(), deserialize_struct(name: &'static str, fields: &'static [&'static str]),
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]),
+        deserialize_identifier(), deserialize_ignored_any(),
+    }
+
+    #[inline]
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+struct OwnedVisitor<'a, 'de, V>(V, PhantomData<(&'a (), &'de ())>);
+
+impl<'a, 'de, V> OwnedVisitor<'a, 'de, V> {
+    #[inline(always)]
+    fn new(inner: V) -> Self {
+        Self(inner, PhantomData)
+    }
+}
+
+macro_rules! forward_visit {
+    ($($method: ident($ty: ty)),* $(,)?) => {
+        $(
+            #[inline]
+            fn $method<E: de::Error>(self, v: $ty) -> Result<Self::Value, E> {
+                self.0.$method(v)
+            }
+        )*
+    };
+}
+
+impl<'a, 'de, V: Visitor<'a>> Visitor<'de> for OwnedVisitor<'a, 'de, V> {
+    type Value = V::Value;
+
+    #[inline]
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.expecting(f)
+    }
+
+    forward_visit! {
+        visit_bool(bool), visit_i8(i8), visit_i16(i16), visit_i32(i32), visit_i64(i64),
+        visit_i128(i128), visit_u8(u8), visit_u16(u16), visit_u32(u32), visit_u64(u64),
+        visit_u128(u128), visit_f32(f32), visit_f64(f64), visit_char(char), visit_str(&str),
+        visit_string(String), visit_bytes(&[u8]), visit_byte_buf(Vec<u8>),
+    }
+
+    #[inline]
+    fn visit_borrowed_str<E: de::Error>(self, v: &'de str) -> Result<Self::Value, E> {
+        self.0.visit_str(v)
+    }
+
+    #[inline]
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        self.0.visit_bytes(v)
+    }
+
+    #[inline]
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.0.visit_none()
+    }
+
+    #[inline]
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        self.0.visit_unit()
+    }
+
+    #[inline]
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        self.0.visit_some(OwnedDe::new(deserializer))
+    }
+
+    #[inline]
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self, deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        self.0.visit_newtype_struct(OwnedDe::new(deserializer))
+    }
+
+    #[inline]
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_seq(OwnedAccess::new(seq))
+    }
+
+    #[inline]
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_map(OwnedAccess::new(map))
+    }
+
+    #[inline]
+    fn visit_enum<A: EnumAccess<'de>>(self, data: A) -> Result<Self::Value, A::Error> {
+        self.0.visit_enum(OwnedAccess::new(data))
+    }
+}
+
+struct OwnedSeed<'a, 'de, S>(S, PhantomData<(&'a (), &'de ())>);
+
+impl<'a, 'de, S: DeserializeSeed<'a>> DeserializeSeed<'de> for OwnedSeed<'a, 'de, S> {
+    type Value = S::Value;
+
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<S::Value, D::Error> {
+        self.0.deserialize(OwnedDe::new(deserializer))
+    }
+}
+
+/// Wraps the SeqAccess, MapAccess, EnumAccess and VariantAccess of the inner deserializer
+struct OwnedAccess<'a, 'de, A>(A, PhantomData<(&'a (), &'de ())>);
+
+impl<'a, 'de, A> OwnedAccess<'a, 'de, A> {
+    #[inline(always)]
+    fn new(inner: A) -> Self {
+        Self(inner, PhantomData)
+    }
+}
+
+impl<'a, 'de, A: SeqAccess<'de>> SeqAccess<'a> for OwnedAccess<'a, 'de, A> {
+    type Error = A::Error;
+
+    #[inline]
+    fn next_element_seed<T: DeserializeSeed<'a>>(
+        &mut self, seed: T,
+    ) -> Result<Option<T::Value>, A::Error> {
+        self.0.next_element_seed(OwnedSeed(seed, PhantomData))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, 'de, A: MapAccess<'de>> MapAccess<'a> for OwnedAccess<'a, 'de, A> {
+    type Error = A::Error;
+
+    #[inline]
+    fn next_key_seed<K: DeserializeSeed<'a>>(
+        &mut self, seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        self.0.next_key_seed(OwnedSeed(seed, PhantomData))
+    }
+
+    #[inline]
+    fn next_value_seed<V: DeserializeSeed<'a>>(&mut self, seed: V) -> Result<V::Value, A::Error> {
+        self.0.next_value_seed(OwnedSeed(seed, PhantomData))
+    }
+
+    #[inline]
+    fn size_hint(&self) -> Option<usize> {
+        self.0.size_hint()
+    }
+}
+
+impl<'a, 'de, A: EnumAccess<'de>> EnumAccess<'a> for OwnedAccess<'a, 'de, A> {
+    type Error = A::Error;
+    type Variant = OwnedAccess<'a, 'de, A::Variant>;
+
+    #[inline]
+    fn variant_seed<V: DeserializeSeed<'a>>(
+        self, seed: V,
+    ) -> Result<(V::Value, Self::Variant), A::Error> {
+        let (value, variant) = self.0.variant_seed(OwnedSeed(seed, PhantomData))?;
+        Ok((value, OwnedAccess::new(variant)))
+    }
+}
+
+impl<'a, 'de, A: VariantAccess<'de>> VariantAccess<'a> for OwnedAccess<'a, 'de, A> {
+    type Error = A::Error;
+
+    #[inline]
+    fn unit_variant(self) -> Result<(), A::Error> {
+        self.0.unit_variant()
+    }
+
+    #[inline]
+    fn newtype_variant_seed<T: DeserializeSeed<'a>>(self, seed: T) -> Result<T::Value, A::Error> {
+        self.0.newtype_variant_seed(OwnedSeed(seed, PhantomData))
+    }
+
+    #[inline]
+    fn tuple_variant<V: Visitor<'a>>(self, len: usize, visitor: V) -> Result<V::Value, A::Error> {
+        self.0.tuple_variant(len, OwnedVisitor::new(visitor))
+    }
+
+    #[inline]
+    fn struct_variant<V: Visitor<'a>>(
+        self, fields: &'static [&'static str], visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        self.0.struct_variant(fields, OwnedVisitor::new(visitor))
+    }
+}
@@ -0,0 +1,394 @@
+//! Protobuf codec by [prost](https://docs.rs/prost)
+//!
+//! The [Codec] trait is generic over serde, while prost messages are not serde types. So a prost
+//! message is carried by the [Proto] wrapper, which is serialized as the protobuf bytes of
+//! the message:
+//!
+//! - With [ProstCodec], the msg on the wire is exactly the protobuf encoding of the message,
+//!   so the peers in other languages can decode it with the same schema.
+//! - With other codecs, the message is carried as a bytes field, which is handy to migrate
+//!   between codecs.
+//!
+//! ``` ignore
+//! use razor_rpc_codec::Proto;
+//!
+//! #[endpoint_async(GreeterClient)]
+//! pub trait Greeter {
+//!     async fn hello(&self, req: Proto<HelloReq>) -> Result<Proto<HelloResp>, RpcError<String>>;
+//! }
+//! ```
+//!
+//! ProstCodec only accepts [Proto] and `()` (as the empty msg), other types fail to encode
+//! or decode. The args of a method should be wrapped into one message.
+
+use crate::{Codec, CodecError, FORMAT_PROTOBUF};
+use serde::de::{self, Deserializer, Visitor};
+use serde::ser::{self, Impossible, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+/// The newtype name of [Proto] in serde
+const PROTO_NAME: &str = "$razor_rpc::Proto";
+
+/// A prost message which can be used as the request or response of [Codec], refer to the
+/// module doc.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Proto<M: prost::Message + Default>(pub M);
+
+impl<M: prost::Message + Default> Proto<M> {
+    #[inline(always)]
+    pub fn into_inner(self) -> M {
+        self.0
+    }
+}
+
+impl<M: prost::Message + Default> From<M> for Proto<M> {
+    #[inline(always)]
+    fn from(msg: M) -> Self {
+        Self(msg)
+    }
+}
+
+impl<M: prost::Message + Default> Deref for Proto<M> {
+    type Target = M;
+
+    #[inline(always)]
+    fn deref(&self) -> &M {
+        &self.0
+    }
+}
+
+impl<M: prost::Message + Default> DerefMut for Proto<M> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut M {
+        &mut self.0
+    }
+}
+
+struct RawBytes<'a>(&'a [u8]);
+
+impl Serialize for RawBytes<'_> {
+    #[inline(always)]
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_bytes(self.0)
+    }
+}
+
+impl<M: prost::Message + Default> Serialize for Proto<M> {
+    #[inline]
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_newtype_struct(PROTO_NAME, &RawBytes(&self.0.encode_to_vec()))
+    }
+}
+
+struct ProtoVisitor<M>(PhantomData<M>);
+
+impl<M: prost::Message + Default> ProtoVisitor<M> {
+    #[inline]
+    fn decode<E: de::Error>(buf: &[u8]) -> Result<Proto<M>, E> {
+        M::decode(buf).map(Proto).map_err(E::custom)
+    }
+}
+
+impl<'de, M: prost::Message + Default> Visitor<'de> for ProtoVisitor<M> {
+    type Value = Proto<M>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("protobuf bytes")
+    }
+
+    #[inline]
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+        d.deserialize_bytes(self)
+    }
+
+    #[inline]
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Self::decode(v)
+    }
+
+    /// For the codecs without bytes type, like JSON
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut buf = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(b) = seq.next_element::<u8>()? {
+            buf.push(b);
+        }
+        Self::decode(&buf)
+    }
+}
+
+impl<'de, M: prost::Message + Default> Deserialize<'de> for Proto<M> {
+    #[inline]
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        d.deserialize_newtype_struct(PROTO_NAME, ProtoVisitor(PhantomData))
+    }
+}
+
+/// Codec of protobuf, only for [Proto] messages, refer to the module doc.
+#[derive(Default)]
+pub struct ProstCodec();
+
+impl Codec for ProstCodec {
+    const FORMAT: u8 = FORMAT_PROTOBUF;
+
+    #[inline(always)]
+    fn encode<T: Serialize>(&self, task: &T) -> Result<Vec<u8>, CodecError> {
+        let mut buf = Vec::new();
+        self.encode_into(task, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// sererialized the msg into buf (with std::io::Writer), and return the size written
+    fn encode_into<T: Serialize>(&self, task: &T, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
+        let pre_len = buf.len();
+        if let Err(e) = task.serialize(ProtoSerializer { buf: &mut *buf }) {
+            buf.truncate(pre_len);
+            return Err(CodecError::encode::<T>(e));
+        }
+        Ok(buf.len() - pre_len)
+    }
+
+    #[inline(always)]
+    fn decode<'a, T: Deserialize<'a>>(&self, buf: &'a [u8]) -> Result<T, CodecError> {
+        T::deserialize(ProtoDeserializer { buf }).map_err(CodecError::decode::<T>)
+    }
+}
+
+#[derive(Debug)]
+struct ProtoError(String);
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+impl ser::Error for ProtoError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+impl de::Error for ProtoError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Self(msg.to_string())
+    }
+}
+
+const NOT_PROTO: &str = "ProstCodec only accepts Proto<M> or ()";
+
+/// Write the bytes of [Proto] as is
+struct ProtoSerializer<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+macro_rules! not_proto {
+    ($($f: ident($($t: ty),*)),* $(,)?) => {
+        $(
+            fn $f(self, $(_: $t),*) -> Result<(), ProtoError> {
+                Err(ser::Error::custom(NOT_PROTO))
+            }
+        )*
+    };
+}
+
+impl<'a> Serializer for ProtoSerializer<'a> {
+    type Ok = ();
+    type Error = ProtoError;
+    type SerializeSeq = Impossible<(), ProtoError>;
+    type SerializeTuple = Impossible<(), ProtoError>;
+    type SerializeTupleStruct = Impossible<(), ProtoError>;
+    type SerializeTupleVariant = Impossible<(), ProtoError>;
+    type SerializeMap = Impossible<(), ProtoError>;
+    type SerializeStruct = Impossible<(), ProtoError>;
+    type SerializeStructVariant = Impossible<(), ProtoError>;
+
+    #[inline(always)]
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), ProtoError> {
+        self.buf.extend_from_slice(v);
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn serialize_unit(self) -> Result<(), ProtoError> {
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self, name: &'static str, value: &T,
+    ) -> Result<(), ProtoError> {
+        if name == PROTO_NAME { value.serialize(self) } else { Err(ser::Error::custom(NOT_PROTO)) }
+    }
+
+    not_proto!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_none(),
+        serialize_unit_struct(&'static str),
+        serialize_unit_variant(&'static str, u32, &'static str),
+    );
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _: &T) -> Result<(), ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self, _: &'static str, _: u32, _: &'static str, _: &T,
+    ) -> Result<(), ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_tuple_struct(
+        self, _: &'static str, _: usize,
+    ) -> Result<Self::SerializeTupleStruct, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_tuple_variant(
+        self, _: &'static str, _: u32, _: &'static str, _: usize,
+    ) -> Result<Self::SerializeTupleVariant, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_struct(
+        self, _: &'static str, _: usize,
+    ) -> Result<Self::SerializeStruct, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+
+    fn serialize_struct_variant(
+        self, _: &'static str, _: u32, _: &'static str, _: usize,
+    ) -> Result<Self::SerializeStructVariant, ProtoError> {
+        Err(ser::Error::custom(NOT_PROTO))
+    }
+}
+
+/// Hand the whole msg to [Proto] as bytes
+struct ProtoDeserializer<'de> {
+    buf: &'de [u8],
+}
+
+impl<'de> Deserializer<'de> for ProtoDeserializer<'de> {
+    type Error = ProtoError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _: V) -> Result<V::Value, ProtoError> {
+        Err(de::Error::custom(NOT_PROTO))
+    }
+
+    #[inline(always)]
+    fn deserialize_bytes<V: Visitor<'de>>(self, v: V) -> Result<V::Value, ProtoError> {
+        v.visit_borrowed_bytes(self.buf)
+    }
+
+    #[inline(always)]
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, v: V) -> Result<V::Value, ProtoError> {
+        v.visit_borrowed_bytes(self.buf)
+    }
+
+    #[inline(always)]
+    fn deserialize_unit<V: Visitor<'de>>(self, v: V) -> Result<V::Value, ProtoError> {
+        v.visit_unit()
+    }
+
+    #[inline(always)]
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self, name: &'static str, v: V,
+    ) -> Result<V::Value, ProtoError> {
+        if name == PROTO_NAME {
+            v.visit_newtype_struct(self)
+        } else {
+            Err(de::Error::custom(NOT_PROTO))
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        option unit_struct seq tuple tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Hello {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(uint64, tag = "2")]
+        id: u64,
+    }
+
+    #[test]
+    fn test_prost() {
+        let codec = ProstCodec::default();
+        let encoded = codec.encode(&()).expect("encode");
+        assert!(encoded.is_empty());
+        let _decoded: () = codec.decode(&encoded).expect("decode");
+
+        let msg = Proto(Hello { name: "razor".to_string(), id: 7 });
+        let mut buf = b"head".to_vec();
+        let size = codec.encode_into(&msg, &mut buf).expect("encode");
+        assert_eq!(size, buf.len() - 4);
+        // The wire is the protobuf encoding
+        assert_eq!(&buf[4..], prost::Message::encode_to_vec(&msg.0));
+        let decoded: Proto<Hello> = codec.decode(&buf[4..]).expect("decode");
+        assert_eq!(decoded, msg);
+        assert_eq!(decoded.name, "razor");
+
+        // Only Proto and () are supported
+        assert!(codec.encode(&1u32).is_err());
+        assert!(codec.encode(&(1, 2)).is_err());
+        assert!(codec.decode::<String>(&buf[4..]).is_err());
+        assert!(codec.decode::<Proto<Hello>>(&[0xff]).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_prost_with_json() {
+        let codec = JsonCodec::default();
+        let msg = Proto(Hello { name: "razor".to_string(), id: 7 });
+        let encoded = codec.encode(&msg).expect("encode");
+        let decoded: Proto<Hello> = codec.decode(&encoded).expect("decode");
+        assert_eq!(decoded, msg);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_prost_with_msgpack() {
+        let codec = MsgpCodec::default();
+        let msg = Proto(Hello { name: "razor".to_string(), id: 7 });
+        let encoded = codec.encode(&msg).expect("encode");
+        let decoded: Proto<Hello> = codec.decode(&encoded).expect("decode");
+        assert_eq!(decoded, msg);
+    }
+}
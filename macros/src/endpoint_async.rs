@@ -74,6 +74,30 @@ fn get_result_type_from_future(ty: &syn::Type) -> Option<&syn::Type> {
     None
 }
 
+/// Get `T` from `impl Stream<Item = T>`
+fn get_item_type_from_stream(ty: &syn::Type) -> Option<&syn::Type> {
+    if let syn::Type::ImplTrait(type_impl) = ty {
+        for bound in &type_impl.bounds {
+            if let syn::TypeParamBound::Trait(trait_bound) = bound {
+                if let Some(segment) = trait_bound.path.segments.last() {
+                    if segment.ident == "Stream" {
+                        if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                            for arg in &args.args {
+                                if let syn::GenericArgument::Binding(binding) = arg {
+                                    if binding.ident == "Item" {
+                                        return Some(&binding.ty);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
 fn check_return_type(return_type: &syn::ReturnType, returns_impl_future: bool) -> bool {
     if returns_impl_future {
         // For impl Future, we need to check the Output type
@@ -92,6 +116,21 @@ fn check_return_type(return_type: &syn::ReturnType, returns_impl_future: bool) -
     }
 }
 
+/// Check if the type is `Result<(), _>`, for the one-way methods
+fn is_unit_result(ty: &syn::Type) -> bool {
+    if let syn::Type::Path(type_path) = ty {
+        if let Some(segment) = type_path.path.segments.last() {
+            if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                if let Some(syn::GenericArgument::Type(syn::Type::Tuple(tuple))) = args.args.first()
+                {
+                    return tuple.elems.is_empty();
+                }
+            }
+        }
+    }
+    false
+}
+
 fn check_result_type(ty: &syn::Type) -> bool {
     // Check if type is Result<_, RpcError<_>>
     if let syn::Type::Path(type_path) = ty {
@@ -195,9 +234,46 @@ fn generate_trait_impl(
             // Construct service method name "TraitName.method_name"
             let service_method = format!("{}.{}", trait_name, method_name);
 
+            // Fire-and-forget, the response is not waited
+            let is_oneway = method.attrs.iter().any(|attr| attr.path.is_ident("oneway"));
+
             // Handle return type
             let return_type = &method_sig.output;
 
+            // Server-streaming returns `impl Stream`, client-streaming takes `impl Stream`
+            let stream_item = if let ReturnType::Type(_, ty) = return_type {
+                get_item_type_from_stream(ty)
+            } else {
+                None
+            };
+            let is_input = arg_type.and_then(get_item_type_from_stream).is_some();
+            if is_oneway && (stream_item.is_some() || is_input) {
+                panic!("Method `{}` cannot be oneway and streaming at the same time.", method_name);
+            }
+            if let Some(item_type) = stream_item {
+                if is_input {
+                    panic!(
+                        "Method `{}` cannot take and return a stream at the same time.",
+                        method_name
+                    );
+                }
+                if !check_result_type(item_type) {
+                    panic!(
+                        "Method `{}` has invalid stream item type. Streaming methods must yield `Result<_, RpcError<_>>`.",
+                        method_name
+                    );
+                }
+                let (Some(arg_name), Some(arg_type)) = (arg_name, arg_type) else {
+                    panic!("Method `{}` should have a named parameter.", method_name);
+                };
+                impl_methods.push(quote! {
+                    fn #method_name(&self, #arg_name: #arg_type) #return_type {
+                        self.endpoint.call_stream(#service_method, #arg_name)
+                    }
+                });
+                continue;
+            }
+
             // Check if this is an impl Future return type
             let returns_impl_future = if let ReturnType::Type(_, ty) = return_type {
                 get_result_type_from_future(ty).is_some()
@@ -216,23 +292,46 @@ fn generate_trait_impl(
                 );
             }
 
+            if is_oneway {
+                let output_type = match return_type {
+                    ReturnType::Type(_, ty) if returns_impl_future => {
+                        get_result_type_from_future(ty)
+                    }
+                    ReturnType::Type(_, ty) => Some(ty.as_ref()),
+                    ReturnType::Default => None,
+                };
+                if !output_type.map_or(false, is_unit_result) {
+                    panic!(
+                        "Method `{}` is oneway, it must return `Result<(), RpcError<_>>`.",
+                        method_name
+                    );
+                }
+            }
+
             // Generate method implementation
             let method_impl = if let Some(arg_type) = arg_type {
                 // Method with arguments
                 let arg_name = arg_name.unwrap();
+                let call = if is_input {
+                    quote! { self.endpoint.call_input(#service_method, #arg_name).await }
+                } else if is_oneway {
+                    quote! { self.endpoint.call_oneway(#service_method, &#arg_name).await }
+                } else {
+                    quote! { self.endpoint.call(#service_method, &#arg_name).await }
+                };
                 if is_async_method {
                     if returns_impl_future {
                         quote! {
                             fn #method_name(&self, #arg_name: #arg_type) #return_type {
                                 async move {
-                                    self.endpoint.call(#service_method, &#arg_name).await
+                                    #call
                                 }
                             }
                         }
                     } else {
                         quote! {
                             async fn #method_name(&self, #arg_name: #arg_type) #return_type {
-                                self.endpoint.call(#service_method, &#arg_name).await
+                                #call
                             }
                         }
                     }
@@ -240,7 +339,7 @@ fn generate_trait_impl(
                     // For non-async methods, we still need to return a future
                     quote! {
                         async fn #method_name(&self, #arg_name: #arg_type) #return_type {
-                            self.endpoint.call(#service_method, &#arg_name).await
+                            #call
                         }
                     }
                 }
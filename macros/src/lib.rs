@@ -16,6 +16,15 @@ mod service_mux_struct;
 /// - `async fn`
 /// - `impl Future`
 /// - trait methods wrapped by `async_trait`
+/// - server-streaming: `fn` returning `impl Stream<Item = Result<T, RpcError<E>>> + Send`
+/// - client-streaming: taking `impl Stream<Item = T> + Send` as the argument
+///
+/// The streaming methods require a dispatch running the requests outside of the connection
+/// coroutine, like `DispatchSpawn`. Otherwise they are answered with `RpcIntErr::Internal`.
+///
+/// The methods are listed in `ServiceStatic::methods()` as "Service.method", and advertised to
+/// the clients with stable numeric ids by handshake. The requests from old clients by string
+/// are still served.
 ///
 /// # Usage
 ///
@@ -173,6 +182,13 @@ pub fn service_mux_struct(_attr: TokenStream, item: TokenStream) -> TokenStream
 ///     - If trait is not async_trait, then the method should use the signature `impl Future + Send`
 ///     - No fn is allowed.
 ///     - All method should have one and only argument, and return type should be `Result<Resp, RpcError<E>>`, where `E: RpcErrCodec`
+///     - Server-streaming: `fn` returning `impl Stream<Item = Result<Resp, RpcError<E>>> + Send`
+///     - Client-streaming: the argument is `impl Stream<Item = Req> + Send`, the items are sent after the request
+///     - A method cannot take and return a stream at the same time
+///     - One-way: a method marked with `#[oneway]` returns `Result<(), RpcError<E>>` as soon as the request is written, the server does not answer it
+///
+/// The methods are called by "Trait.method", which is sent as the numeric id from
+/// `razor_stream::proto::method_id()` when the server advertises it by handshake.
 ///
 /// # Usage
 ///
@@ -210,3 +226,27 @@ pub fn service_mux_struct(_attr: TokenStream, item: TokenStream) -> TokenStream
 pub fn endpoint_async(attr: TokenStream, item: TokenStream) -> TokenStream {
     endpoint_async::endpoint_async(attr, item)
 }
+
+/// A marker attribute for methods in an `#[endpoint_async]` trait that need no response
+/// (fire-and-forget), like telemetry.
+///
+/// The method must return `Result<(), RpcError<E>>`, which is Ok once the request is written.
+/// Streaming methods cannot be oneway. The server side needs no change, the response is dropped.
+///
+/// ``` ignore
+/// use razor_rpc::client::{endpoint_async, oneway};
+/// use razor_rpc::error::RpcError;
+/// use std::future::Future;
+///
+/// #[endpoint_async(MetricClient)]
+/// pub trait MetricService {
+///     #[oneway]
+///     fn report(&self, value: u64) -> impl Future<Output = Result<(), RpcError<()>>> + Send;
+/// }
+/// ```
+///
+/// Refer to document of attr macro `#[endpoint_async]`
+#[proc_macro_attribute]
+pub fn oneway(_attr: TokenStream, item: TokenStream) -> TokenStream {
+    item
+}
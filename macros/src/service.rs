@@ -25,6 +25,39 @@ fn get_result_type_from_future(ty: &syn::Type) -> Option<&syn::Type> {
     None
 }
 
+/// Get `T` from `impl Stream<Item = T>`
+fn get_item_type_from_stream(ty: &syn::Type) -> Option<&syn::Type> {
+    if let syn::Type::ImplTrait(type_impl) = ty {
+        for bound in &type_impl.bounds {
+            if let syn::TypeParamBound::Trait(trait_bound) = bound {
+                if let Some(segment) = trait_bound.path.segments.last() {
+                    if segment.ident == "Stream" {
+                        if let syn::PathArguments::AngleBracketed(args) = &segment.arguments {
+                            for arg in &args.args {
+                                if let syn::GenericArgument::Binding(binding) = arg {
+                                    if binding.ident == "Item" {
+                                        return Some(&binding.ty);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+    None
+}
+
+/// How the method is called by the handler
+enum MethodKind {
+    Unary,
+    /// Returns `impl Stream`
+    ServerStream,
+    /// Takes `impl Stream<Item = T>`, with the item type
+    ClientStream(Type),
+}
+
 pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let input = parse_macro_input!(item as Item);
 
@@ -89,17 +122,32 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                                 false
                             };
 
+                            let returns_impl_stream =
+                                if let ReturnType::Type(_, ty) = &method.sig.output {
+                                    get_item_type_from_stream(ty).is_some()
+                                } else {
+                                    false
+                                };
+
                             let should_await =
                                 is_async_method || returns_pin_box_future || returns_impl_future;
 
-                            if !should_await {
+                            let kind = if returns_impl_stream && !is_async_method {
+                                MethodKind::ServerStream
+                            } else if let Some(item_ty) = get_item_type_from_stream(&arg_ty) {
+                                MethodKind::ClientStream(item_ty.clone())
+                            } else {
+                                MethodKind::Unary
+                            };
+
+                            if !should_await && !matches!(kind, MethodKind::ServerStream) {
                                 panic!(
-                                    "Service methods must be `async fn`, return `impl Future`, or be wrapped by `async_trait`. Method `{}` is not.",
+                                    "Service methods must be `async fn`, return `impl Future` or `impl Stream`, or be wrapped by `async_trait`. Method `{}` is not.",
                                     method_name
                                 );
                             }
 
-                            Some((method_name, arg_ty))
+                            Some((method_name, arg_ty, kind))
                         } else {
                             None
                         }
@@ -109,8 +157,59 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 })
                 .collect();
 
-            let handler_methods = methods_data.iter().map(|(method_name, arg_ty)| {
+            let handler_methods = methods_data.iter().map(|(method_name, arg_ty, kind)| {
                 let handler_name = format_ident!("__handle_{}", method_name);
+                match kind {
+                    MethodKind::ServerStream => {
+                        return quote! {
+                            async fn #handler_name<C: razor_rpc::Codec>(&self, req: razor_rpc::server::task::APIServerReq<C>) {
+                                let arg = match req.req.as_ref() {
+                                    None => {
+                                        unreachable!();
+                                    }
+                                    Some(buf) => match req.codec.decode::<#arg_ty>(&buf) {
+                                        Ok(arg) => arg,
+                                        Err(e) => {
+                                            req.set_decode_error(e);
+                                            return;
+                                        }
+                                    },
+                                };
+
+                                req.set_stream(self.#method_name(arg)).await;
+                            }
+                        };
+                    }
+                    MethodKind::ClientStream(item_ty) => {
+                        return quote! {
+                            async fn #handler_name<C: razor_rpc::Codec>(&self, mut req: razor_rpc::server::task::APIServerReq<C>) {
+                                let (input, broken) = match req.take_input::<#item_ty>() {
+                                    Ok(r) => r,
+                                    Err(e) => {
+                                        req.set_rpc_error(e);
+                                        return;
+                                    }
+                                };
+
+                                let res = self.#method_name(input).await;
+
+                                if broken.load(std::sync::atomic::Ordering::Acquire) {
+                                    req.set_rpc_error(razor_rpc::error::RpcIntErr::Decode);
+                                    return;
+                                }
+                                match res {
+                                    Ok(resp) => {
+                                        req.set_result(resp);
+                                    }
+                                    Err(e) => {
+                                        req.set_error(e);
+                                    }
+                                }
+                            }
+                        };
+                    }
+                    MethodKind::Unary => {}
+                }
                 quote! {
                     async fn #handler_name<C: razor_rpc::Codec>(&self, req: razor_rpc::server::task::APIServerReq<C>) {
                         let arg = match req.req.as_ref() {
@@ -119,8 +218,8 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                             }
                             Some(buf) => match req.codec.decode::<#arg_ty>(&buf) {
                                 Ok(arg) => arg,
-                                Err(_) => {
-                                    req.set_rpc_error(razor_rpc::error::RpcIntErr::Decode);
+                                Err(e) => {
+                                    req.set_decode_error(e);
                                     return;
                                 }
                             },
@@ -140,7 +239,7 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 }
             });
 
-            let dispatch_arms = methods_data.iter().map(|(method_name, _)| {
+            let dispatch_arms = methods_data.iter().map(|(method_name, _, _)| {
                 let method_name_str = method_name.to_string();
                 let handler_name = format_ident!("__handle_{}", method_name);
                 quote! {
@@ -148,6 +247,11 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                 }
             });
 
+            // Advertised to the clients with the numeric ids, refer to ServiceStatic::methods()
+            let method_names = methods_data
+                .iter()
+                .map(|(method_name, _, _)| format!("{}.{}", service_name_pascal, method_name));
+
             let (impl_generics, _ty_generics, where_clause) = item_impl.generics.split_for_impl();
 
             let mut service_trait_generics = item_impl.generics.clone();
@@ -164,7 +268,12 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                     const SERVICE_NAME: &'static str = #service_name_pascal;
                     fn serve(&self, req: razor_rpc::server::task::APIServerReq<C>) -> impl std::future::Future<Output = ()> + Send {
                         async move {
-                            match req.method.as_str() {
+                            // The request might have waited in the queue of the dispatcher
+                            if req.is_expired() {
+                                req.set_rpc_error(razor_rpc::error::RpcIntErr::Deadline);
+                                return;
+                            }
+                            match &*req.method {
                                 #(#dispatch_arms)*
                                 _ => {
                                     req.set_rpc_error(razor_rpc::error::RpcIntErr::Method);
@@ -172,6 +281,9 @@ pub fn service(_attr: TokenStream, item: TokenStream) -> TokenStream {
                             }
                         }
                     }
+                    fn methods(&self, names: &mut Vec<&'static str>) {
+                        names.extend_from_slice(&[#(#method_names),*]);
+                    }
                 }
             };
 
@@ -17,17 +17,28 @@ pub fn service_mux_struct(_attr: TokenStream, item: TokenStream) -> TokenStream
         }
     });
 
+    let field_methods = fields.iter().map(|field| {
+        let field_name = field.ident.as_ref().unwrap();
+        let field_type = &field.ty;
+        quote! {
+            <#field_type as razor_rpc::server::ServiceStatic<C>>::methods(&self.#field_name, names);
+        }
+    });
+
     let expanded = quote! {
         impl<C: razor_rpc::Codec> razor_rpc::server::ServiceStatic <C> for #struct_name {
             const SERVICE_NAME: &'static str = "";
             fn serve(&self, req: razor_rpc::server::task::APIServerReq<C>) -> impl std::future::Future<Output = ()> + Send {
                 async move {
-                    match req.service.as_str() {
+                    match &*req.service {
                         #(#field_handlers)*
                         _ => req.set_rpc_error(razor_rpc::error::RpcIntErr::Service),
                     }
                 }
             }
+            fn methods(&self, names: &mut Vec<&'static str>) {
+                #(#field_methods)*
+            }
         }
     };
 
@@ -1,15 +1,22 @@
 pub mod task;
-pub use razor_rpc_macros::endpoint_async;
+pub use razor_rpc_macros::{endpoint_async, oneway};
 pub use razor_stream::client::ClientCaller;
 pub use task::*;
 
 use crate::Codec;
 use crate::error::{EncodedErr, RpcErrCodec, RpcError, RpcIntErr};
+use futures::future::{Either, select};
+use futures::pin_mut;
+use futures::stream::{Stream, StreamExt};
+use razor_stream::client::task::{ClientTaskCancel, ClientTaskCommon, ClientTaskSink};
 pub use razor_stream::client::{
     ClientCallerBlocking, ClientConfig, ClientFacts, ClientPool, ClientTransport, FailoverPool,
 };
+use razor_stream::proto::RPC_STREAM_WINDOW;
+pub use razor_stream::proto::RpcMeta;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Instant;
 
 pub type APIClientDefault<IO, C> = razor_stream::client::ClientDefault<APIClientReq, IO, C>;
 
@@ -46,6 +53,44 @@ where
     pub async fn call<Req, Resp, E>(
         &self, service_method: &'static str, req: &Req,
     ) -> Result<Resp, RpcError<E>>
+    where
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        self.call_with_meta(service_method, req, RpcMeta::new()).await.0
+    }
+
+    /// Call with metadata headers, return the result along with the response metadata
+    pub async fn call_with_meta<Req, Resp, E>(
+        &self, service_method: &'static str, req: &Req, meta: RpcMeta,
+    ) -> (Result<Resp, RpcError<E>>, RpcMeta)
+    where
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        self._call(service_method, req, meta, None).await
+    }
+
+    /// Call with a deadline, the remaining budget is propagated to the server.
+    ///
+    /// Returns RpcIntErr::Deadline when the deadline has passed before the server processing it.
+    pub async fn call_with_deadline<Req, Resp, E>(
+        &self, service_method: &'static str, req: &Req, deadline: Instant,
+    ) -> Result<Resp, RpcError<E>>
+    where
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        self._call(service_method, req, RpcMeta::new(), Some(deadline)).await.0
+    }
+
+    #[inline]
+    async fn _call<Req, Resp, E>(
+        &self, service_method: &'static str, req: &Req, meta: RpcMeta, deadline: Option<Instant>,
+    ) -> (Result<Resp, RpcError<E>>, RpcMeta)
     where
         Req: serde::Serialize + fmt::Debug,
         Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
@@ -53,10 +98,181 @@ where
     {
         let (tx, rx) = crossfire::spsc::bounded_tx_blocking_rx_async::<APIClientReq>(1);
         // TODO should optimize one shot channel
-        <C as ClientCaller>::send_req(&self.caller, make_req(&self.codec, service_method, req, tx))
-            .await;
-        return process_res(&self.codec, rx.recv().await);
+        let mut task = make_req(&self.codec, service_method, req, meta, deadline, tx);
+        // When this future is dropped, the task is abandoned and a cancel frame will be sent.
+        let cancel = ClientTaskCancel::new();
+        task.common.cancel = Some(cancel.clone());
+        let mut guard = CancelOnDrop(Some(cancel));
+        <C as ClientCaller>::send_req(&self.caller, task).await;
+        let res = rx.recv().await;
+        guard.disarm();
+        return process_res(&self.codec, res);
     }
+
+    /// Call a method without waiting for the response, refer to
+    /// [ClientStream::send_oneway()](razor_stream::client::stream::ClientStream::send_oneway).
+    ///
+    /// Returns Ok when the request is written to the connection. When the server does not
+    /// support one-way requests, it waits for the response as [call()](Self::call) does.
+    pub async fn call_oneway<Req, E>(
+        &self, service_method: &'static str, req: &Req,
+    ) -> Result<(), RpcError<E>>
+    where
+        Req: serde::Serialize + fmt::Debug,
+        E: RpcErrCodec,
+    {
+        let (tx, rx) = crossfire::spsc::bounded_tx_blocking_rx_async::<APIClientReq>(1);
+        let mut task = make_req(&self.codec, service_method, req, RpcMeta::new(), None, tx);
+        task.common.oneway = true;
+        <C as ClientCaller>::send_req(&self.caller, task).await;
+        return process_res(&self.codec, rx.recv().await).0;
+    }
+
+    /// Call a server-streaming method, the partial responses are decoded as the items of the
+    /// returned stream, which ends after the first error.
+    ///
+    /// The server sends at most [RPC_STREAM_WINDOW] items ahead of the consumer, more credit is
+    /// granted as the items are taken. Dropping the stream abandons the call.
+    pub fn call_stream<Req, Resp, E>(
+        &self, service_method: &'static str, req: Req,
+    ) -> impl Stream<Item = Result<Resp, RpcError<E>>> + Send + '_
+    where
+        C: Sync,
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static,
+        E: RpcErrCodec,
+    {
+        let (tx, done_rx) = crossfire::spsc::bounded_tx_blocking_rx_async::<APIClientReq>(1);
+        let (frames_tx, frames_rx) = crossfire::mpsc::unbounded_async();
+        let mut task = make_req(&self.codec, service_method, &req, RpcMeta::new(), None, tx);
+        let sink = ClientTaskSink::new(false);
+        task.common.sink = Some(sink.clone());
+        task.frames = Some(frames_tx);
+        let cancel = ClientTaskCancel::new();
+        task.common.cancel = Some(cancel.clone());
+        let state = StreamCall {
+            task: Some(task),
+            frames_rx,
+            done_rx,
+            sink,
+            consumed: 0,
+            finished: false,
+            guard: CancelOnDrop(Some(cancel)),
+        };
+        futures::stream::unfold(state, move |mut state| async move {
+            if state.finished {
+                return None;
+            }
+            if let Some(task) = state.task.take() {
+                <C as ClientCaller>::send_req(&self.caller, task).await;
+            }
+            match state.frames_rx.recv().await {
+                Ok(buf) => {
+                    state.consumed += 1;
+                    if state.consumed >= RPC_STREAM_WINDOW / 2 {
+                        // Err when the task has finished, nothing to do
+                        let _ = state.sink.credit(state.consumed);
+                        state.consumed = 0;
+                    }
+                    let item = match self.codec.decode::<Resp>(&buf) {
+                        Ok(resp) => Ok(resp),
+                        Err(e) => {
+                            log::warn!("{} stream item {}", service_method, e);
+                            state.finished = true;
+                            Err(RpcIntErr::Decode.into())
+                        }
+                    };
+                    Some((item, state))
+                }
+                Err(_) => {
+                    // The frames are closed before the final response
+                    state.finished = true;
+                    let res = state.done_rx.recv().await;
+                    state.guard.disarm();
+                    match process_res::<_, (), E>(&self.codec, res).0 {
+                        Ok(()) => None,
+                        Err(e) => Some((Err(e), state)),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Call a client-streaming method, the items of `input` follow the request.
+    ///
+    /// At most [RPC_STREAM_WINDOW] items are sent ahead of the handler on the server, more credit
+    /// is granted as the handler takes them. Returns as soon as the server answers, even if the
+    /// input is not exhausted.
+    ///
+    /// NOTE: The `task_timeout` of ClientConfig applies to the whole call, including the input.
+    pub async fn call_input<Item, Resp, E, S>(
+        &self, service_method: &'static str, input: S,
+    ) -> Result<Resp, RpcError<E>>
+    where
+        Item: serde::Serialize,
+        S: Stream<Item = Item>,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        let (tx, done_rx) = crossfire::spsc::bounded_tx_blocking_rx_async::<APIClientReq>(1);
+        let sink = ClientTaskSink::new(true);
+        // When this future is dropped, the task is abandoned and a cancel frame will be sent.
+        let cancel = ClientTaskCancel::new();
+        let task = APIClientReq {
+            common: ClientTaskCommon {
+                cancel: Some(cancel.clone()),
+                sink: Some(sink.clone()),
+                ..Default::default()
+            },
+            // The request opens the stream without msg
+            req_msg: None,
+            action: service_method,
+            resp: None,
+            res: None,
+            noti: Some(tx),
+            frames: None,
+        };
+        let mut guard = CancelOnDrop(Some(cancel));
+        <C as ClientCaller>::send_req(&self.caller, task).await;
+        let send_f = async {
+            sink.sent().await;
+            pin_mut!(input);
+            while let Some(item) = input.next().await {
+                let msg = match self.codec.encode(&item) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        log::warn!("{} input item {}", service_method, e);
+                        return Err(RpcIntErr::Encode);
+                    }
+                };
+                sink.send(msg, true).await?;
+            }
+            sink.send(Vec::new(), false).await
+        };
+        let done_f = done_rx.recv();
+        pin_mut!(send_f, done_f);
+        let res = match select(send_f, done_f).await {
+            Either::Left((Err(RpcIntErr::Encode), _)) => return Err(RpcIntErr::Encode.into()),
+            // The result (or the error of the connection) is in the final response
+            Either::Left((_, done_f)) => done_f.await,
+            Either::Right((res, _)) => res,
+        };
+        guard.disarm();
+        process_res(&self.codec, res).0
+    }
+}
+
+/// The state of the stream returned by [AsyncEndpoint::call_stream()]
+struct StreamCall {
+    /// Sent on the first poll
+    task: Option<APIClientReq>,
+    frames_rx: crossfire::AsyncRx<Vec<u8>>,
+    done_rx: crossfire::AsyncRx<APIClientReq>,
+    sink: ClientTaskSink,
+    /// Items taken since the last credit
+    consumed: u32,
+    finished: bool,
+    guard: CancelOnDrop,
 }
 
 impl<C> Clone for AsyncEndpoint<C>
@@ -98,6 +314,44 @@ where
     pub fn call<Req, Resp, E>(
         &self, service_method: &'static str, req: &Req,
     ) -> Result<Resp, RpcError<E>>
+    where
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        self.call_with_meta(service_method, req, RpcMeta::new()).0
+    }
+
+    /// Call with metadata headers, return the result along with the response metadata
+    pub fn call_with_meta<Req, Resp, E>(
+        &self, service_method: &'static str, req: &Req, meta: RpcMeta,
+    ) -> (Result<Resp, RpcError<E>>, RpcMeta)
+    where
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        self._call(service_method, req, meta, None)
+    }
+
+    /// Call with a deadline, the remaining budget is propagated to the server.
+    ///
+    /// Returns RpcIntErr::Deadline when the deadline has passed before the server processing it.
+    pub fn call_with_deadline<Req, Resp, E>(
+        &self, service_method: &'static str, req: &Req, deadline: Instant,
+    ) -> Result<Resp, RpcError<E>>
+    where
+        Req: serde::Serialize + fmt::Debug,
+        Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+        E: RpcErrCodec,
+    {
+        self._call(service_method, req, RpcMeta::new(), Some(deadline)).0
+    }
+
+    #[inline]
+    fn _call<Req, Resp, E>(
+        &self, service_method: &'static str, req: &Req, meta: RpcMeta, deadline: Option<Instant>,
+    ) -> (Result<Resp, RpcError<E>>, RpcMeta)
     where
         Req: serde::Serialize + fmt::Debug,
         Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
@@ -105,7 +359,8 @@ where
     {
         let (tx, rx) = crossfire::spsc::bounded_blocking::<APIClientReq>(1);
         // TODO should optimize one shot channel
-        self.caller.send_req_blocking(make_req(&self.codec, service_method, req, tx));
+        let task = make_req(&self.codec, service_method, req, meta, deadline, tx);
+        self.caller.send_req_blocking(task);
         return process_res(&self.codec, rx.recv());
     }
 }
@@ -130,9 +385,31 @@ where
     }
 }
 
+/// Set the cancel flag when the call future is dropped before the task finishes.
+///
+/// Disarmed when the task has finished, the cancel frame is not sent for nothing.
+struct CancelOnDrop(Option<ClientTaskCancel>);
+
+impl CancelOnDrop {
+    #[inline]
+    fn disarm(&mut self) {
+        self.0 = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(cancel) = self.0.take() {
+            cancel.cancel();
+        }
+    }
+}
+
 #[inline]
 fn make_req<C, Req>(
-    codec: &C, service_method: &'static str, req: &Req, done_tx: crossfire::Tx<APIClientReq>,
+    codec: &C, service_method: &'static str, req: &Req, meta: RpcMeta, deadline: Option<Instant>,
+    done_tx: crossfire::Tx<APIClientReq>,
 ) -> APIClientReq
 where
     C: Codec,
@@ -140,19 +417,20 @@ where
 {
     let req_buf = codec.encode(req).expect("encode");
     APIClientReq {
-        common: Default::default(),
+        common: ClientTaskCommon { meta, deadline, ..Default::default() },
         req_msg: Some(req_buf),
-        action: service_method.to_string(),
+        action: service_method,
         resp: None,
         res: None,
         noti: Some(done_tx),
+        frames: None,
     }
 }
 
 #[inline]
 fn process_res<C, Resp, E>(
     codec: &C, task_res: Result<APIClientReq, crossfire::RecvError>,
-) -> Result<Resp, RpcError<E>>
+) -> (Result<Resp, RpcError<E>>, RpcMeta)
 where
     C: Codec,
     Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
@@ -160,34 +438,79 @@ where
 {
     match task_res {
         Ok(mut task) => {
-            let res = task.res.take().unwrap();
-            match res {
-                Ok(()) => {
-                    if let Some(resp) = task.resp {
-                        match codec.decode(&resp) {
-                            Ok(resp_msg) => return Ok(resp_msg),
-                            Err(()) => return Err(RpcIntErr::Decode.into()),
-                        }
-                    } else {
-                        return Ok(Resp::default());
+            let resp_meta = std::mem::take(&mut task.common.resp_meta);
+            return (decode_res(codec, task), resp_meta);
+        }
+        Err(_) => {
+            return (Err(RpcIntErr::Internal.into()), RpcMeta::new());
+        }
+    }
+}
+
+#[inline]
+fn decode_res<C, Resp, E>(codec: &C, mut task: APIClientReq) -> Result<Resp, RpcError<E>>
+where
+    C: Codec,
+    Resp: for<'a> serde::Deserialize<'a> + Send + fmt::Debug + 'static + Default,
+    E: RpcErrCodec,
+{
+    let res = task.res.take().unwrap();
+    match res {
+        Ok(()) => {
+            if let Some(resp) = task.resp {
+                match codec.decode(&resp) {
+                    Ok(resp_msg) => return Ok(resp_msg),
+                    Err(e) => {
+                        log::warn!("{} resp {}", task.action, e);
+                        return Err(RpcIntErr::Decode.into());
                     }
                 }
-                Err(EncodedErr::Rpc(e)) => {
-                    return Err(RpcError::Rpc(e));
-                }
-                Err(EncodedErr::Num(n)) => match E::decode(codec, Ok(n)) {
-                    Ok(e) => return Err(RpcError::User(e)),
-                    Err(()) => return Err(RpcIntErr::Decode.into()),
-                },
-                Err(EncodedErr::Buf(buf)) => match E::decode(codec, Err(&buf)) {
-                    Ok(e) => return Err(RpcError::User(e)),
-                    Err(()) => return Err(RpcIntErr::Decode.into()),
-                },
-                _ => unreachable!(),
+            } else {
+                return Ok(Resp::default());
             }
         }
-        Err(_) => {
-            return Err(RpcIntErr::Internal.into());
+        Err(EncodedErr::Rpc(e)) => {
+            return Err(RpcError::Rpc(e));
         }
+        Err(EncodedErr::Num(n)) => match E::decode(codec, Ok(n)) {
+            Ok(e) => return Err(RpcError::User(e)),
+            Err(()) => return Err(RpcIntErr::Decode.into()),
+        },
+        Err(EncodedErr::Buf(buf)) => match E::decode(codec, Err(&buf)) {
+            Ok(e) => return Err(RpcError::User(e)),
+            Err(()) => return Err(RpcIntErr::Decode.into()),
+        },
+        _ => unreachable!(),
     }
 }
+
+/// The oneway method compiles when returning `()`:
+///
+/// ```rust
+/// use razor_rpc::client::{endpoint_async, oneway};
+/// use razor_rpc::error::RpcError;
+/// use std::future::Future;
+///
+/// #[endpoint_async(DemoClient)]
+/// pub trait OnewayService {
+///     #[oneway]
+///     fn report(&self, value: u64) -> impl Future<Output = Result<(), RpcError<()>>> + Send;
+/// }
+/// ```
+///
+/// But not when returning a value:
+///
+/// ```compile_fail
+/// use razor_rpc::client::{endpoint_async, oneway};
+/// use razor_rpc::error::RpcError;
+/// use std::future::Future;
+///
+/// #[endpoint_async(DemoClient)]
+/// pub trait OnewayService {
+///     #[oneway]
+///     fn report(&self, value: u64) -> impl Future<Output = Result<u64, RpcError<()>>> + Send;
+/// }
+/// ```
+#[doc(hidden)]
+#[allow(dead_code)]
+fn test_oneway_return_compile_fail() {}
@@ -1,6 +1,6 @@
 use crate::{
     Codec,
-    error::{EncodedErr, RpcIntErr},
+    error::{CodecError, EncodedErr, RpcIntErr},
 };
 use razor_stream::client::task::{
     ClientTask, ClientTaskAction, ClientTaskCommon, ClientTaskDecode, ClientTaskDone,
@@ -13,16 +13,18 @@ use std::io::Write;
 pub struct APIClientReq {
     pub common: ClientTaskCommon,
     pub req_msg: Option<Vec<u8>>,
-    /// action is in "Service.method" format
-    pub action: String,
+    /// action is in "Service.method" format, sent as numeric id when the server advertises it
+    pub action: &'static str,
     pub resp: Option<Vec<u8>>,
     pub res: Option<Result<(), EncodedErr>>,
     pub noti: Option<crossfire::Tx<Self>>,
+    /// Receives the partial responses of server-streaming, dropped before done
+    pub frames: Option<crossfire::MTx<Vec<u8>>>,
 }
 
 impl ClientTaskEncode for APIClientReq {
     #[inline]
-    fn encode_req<C: Codec>(&self, _codec: &C, buf: &mut Vec<u8>) -> Result<usize, ()> {
+    fn encode_req<C: Codec>(&self, _codec: &C, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
         if let Some(msg) = self.req_msg.as_ref() {
             // The msg is pre encoded
             buf.write_all(msg).expect("append msg");
@@ -35,13 +37,25 @@ impl ClientTaskEncode for APIClientReq {
 
 impl ClientTaskDecode for APIClientReq {
     #[inline]
-    fn decode_resp<C: Codec>(&mut self, _codec: &C, buf: &[u8]) -> Result<(), ()> {
+    fn decode_resp<C: Codec>(&mut self, _codec: &C, buf: &[u8]) -> Result<(), CodecError> {
         // Ignore the Codec, as we don't known the resp type yet
         if buf.len() > 0 {
             self.resp.replace(buf.to_vec());
         }
         Ok(())
     }
+
+    #[inline]
+    fn decode_frame<C: Codec>(
+        &mut self, _codec: &C, buf: &[u8], _blob: Option<&[u8]>,
+    ) -> Result<(), CodecError> {
+        let Some(frames) = self.frames.as_ref() else {
+            return Err(CodecError::decode::<Self>("partial response not expected"));
+        };
+        // The receiver might have gone, the task will be cancelled
+        let _ = frames.send(buf.to_vec());
+        Ok(())
+    }
 }
 
 impl ClientTaskDone for APIClientReq {
@@ -63,6 +77,8 @@ impl ClientTaskDone for APIClientReq {
 
     #[inline]
     fn done(mut self) {
+        // Close the frames before the final response
+        self.frames.take();
         let _ = self.noti.take().unwrap().send(self);
     }
 }
@@ -85,7 +101,7 @@ impl std::ops::DerefMut for APIClientReq {
 impl ClientTaskAction for APIClientReq {
     #[inline]
     fn get_action<'a>(&'a self) -> RpcAction<'a> {
-        RpcAction::Str(self.action.as_str())
+        RpcAction::Str(self.action)
     }
 }
 
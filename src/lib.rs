@@ -11,8 +11,9 @@
 //! ## Feature
 //!
 //! - Independent from async runtime (with plugins)
-//! - With service trait very similar to grpc / tarpc (stream in API interface is not supported
-//! currently)
+//! - With service trait very similar to grpc / tarpc, supports server-streaming (return
+//! `impl Stream`) and client-streaming (take `impl Stream`) methods with flow control, refer to
+//! [DispatchSpawn](crate::server::dispatch::DispatchSpawn)
 //! - Support latest `impl Future` definition of rust since 1.75, also support legacy `async_trait`
 //! wrapper
 //! - Each method can have different custom error type (requires the type implements
@@ -3,28 +3,118 @@
 use super::service::ServiceStatic;
 use super::task::{APIServerReq, APIServerResp};
 use captains_log::*;
+use orb::prelude::*;
 use razor_stream::{
     Codec,
-    proto::RpcAction,
-    server::{RpcSvrReq, dispatch::Dispatch, task::RespNoti},
+    error::{CodecError, DispatchErr, RpcIntErr},
+    proto::{RpcAction, method_id, method_ids},
+    server::{
+        RpcSvrReq, cancel::CancelRegistry, dispatch::Dispatch, streaming::StreamRegistry,
+        task::RespNoti,
+    },
 };
+use rustc_hash::FxHashMap;
+use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Interface for all dispatch for API Server
 pub trait APIDispatchTrait: Send + Sync + 'static + Clone {
     type Codec: Codec;
 
+    /// Whether the requests are processed outside of the connection coroutine,
+    /// which is required by the streaming methods. Refer to [APIServerReq::set_stream()] and
+    /// [APIServerReq::take_input()].
+    const CONCURRENT: bool = false;
+
+    /// Process the request, or queue it for later.
+    ///
+    /// The [ServiceStatic::serve()] generated by [`#[service]`](crate::server::service) answers
+    /// RpcIntErr::Deadline when the request has expired in the queue, refer to
+    /// [APIServerReq::is_expired()].
     fn dispatch(
         &self, req: APIServerReq<Self::Codec>,
-    ) -> impl Future<Output = Result<(), ()>> + Send;
+    ) -> impl Future<Output = Result<(), CodecError>> + Send;
+
+    /// The "Service.method" names served, refer to [ServiceStatic::methods()]
+    #[inline]
+    fn methods(&self, _names: &mut Vec<&'static str>) {}
 }
 
 /// A container for everything impl APIDispatchTrait
 ///
 /// Because rust orphan rule forbid blanet impl non-local trait
-#[derive(Clone)]
-pub struct APIDispatch<D: APIDispatchTrait>(D);
+pub struct APIDispatch<D: APIDispatchTrait>(
+    D,
+    Arc<CancelRegistry>,
+    Arc<StreamRegistry>,
+    Arc<MethodIds>,
+);
+
+impl<D: APIDispatchTrait> APIDispatch<D> {
+    #[inline]
+    fn new(inner: D) -> Self {
+        let mut names = Vec::new();
+        inner.methods(&mut names);
+        let methods = Arc::new(MethodIds::new(names));
+        Self(inner, Arc::new(CancelRegistry::new()), Arc::new(StreamRegistry::new()), methods)
+    }
+}
+
+impl<D: APIDispatchTrait> Clone for APIDispatch<D> {
+    /// Dispatch is cloned for every connection, the seq of cancel and stream registry is per
+    /// connection
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(
+            self.0.clone(),
+            Arc::new(CancelRegistry::new()),
+            Arc::new(StreamRegistry::new()),
+            self.3.clone(),
+        )
+    }
+}
+
+/// The methods of the service by numeric id, shared by all the connections
+struct MethodIds {
+    names: Vec<&'static str>,
+    /// id -> (name, service, method)
+    ids: FxHashMap<i32, (&'static str, &'static str, &'static str)>,
+}
+
+impl MethodIds {
+    fn new(names: Vec<&'static str>) -> Self {
+        let mut ids = FxHashMap::default();
+        for (id, name) in method_ids(&names) {
+            if let Some((service, method)) = name.split_once('.') {
+                ids.insert(id, (name, service, method));
+            }
+        }
+        let names = ids.values().map(|(name, _, _)| *name).collect();
+        Self { names, ids }
+    }
+
+    /// Split the action into service and method, without allocation for the known methods
+    #[inline]
+    fn resolve(&self, action: RpcAction) -> Option<(Cow<'static, str>, Cow<'static, str>)> {
+        match action {
+            RpcAction::Num(id) => {
+                let (_, service, method) = self.ids.get(&id)?;
+                Some((Cow::Borrowed(*service), Cow::Borrowed(*method)))
+            }
+            RpcAction::Str(action) => {
+                if let Some((name, service, method)) = self.ids.get(&method_id(action)) {
+                    if *name == action {
+                        return Some((Cow::Borrowed(*service), Cow::Borrowed(*method)));
+                    }
+                }
+                let (service, method) = action.split_once('.')?;
+                Some((Cow::Owned(service.to_string()), Cow::Owned(method.to_string())))
+            }
+        }
+    }
+}
 
 impl<D: APIDispatchTrait> Dispatch for APIDispatch<D> {
     type RespTask = APIServerResp;
@@ -34,24 +124,62 @@ impl<D: APIDispatchTrait> Dispatch for APIDispatch<D> {
     #[inline]
     async fn dispatch_req<'a>(
         &'a self, codec: &Arc<Self::Codec>, req: RpcSvrReq<'a>, noti: RespNoti<Self::RespTask>,
-    ) -> Result<(), ()> {
-        if let RpcAction::Str(action) = req.action {
-            if let Some((service, method)) = action.split_once('.') {
-                return self
-                    .0
-                    .dispatch(APIServerReq::<Self::Codec> {
-                        seq: req.seq,
-                        service: service.to_string(),
-                        method: method.to_string(),
-                        req: Some(req.msg.to_vec()),
-                        codec: codec.clone(),
-                        noti,
-                    })
-                    .await;
-            }
+    ) -> Result<(), DispatchErr> {
+        if req.deadline.is_some_and(|d| d <= Instant::now()) {
+            return Err(RpcIntErr::Deadline.into());
+        }
+        if let Some((service, method)) = self.3.resolve(req.action) {
+            let (streams, input) = if D::CONCURRENT {
+                let input =
+                    if req.more { Some(self.2.open_input(req.seq, noti.clone())) } else { None };
+                (Some(Arc::downgrade(&self.2)), input)
+            } else {
+                (None, None)
+            };
+            self.0
+                .dispatch(APIServerReq::<Self::Codec> {
+                    seq: req.seq,
+                    service,
+                    method,
+                    req: Some(req.msg.to_vec()),
+                    codec: codec.clone(),
+                    noti,
+                    meta: req.meta,
+                    resp_meta: Default::default(),
+                    deadline: req.deadline,
+                    cancel: self.1.register(req.seq),
+                    streams,
+                    input,
+                    decode_err_detail: req.decode_err_detail,
+                    peer: req.peer,
+                })
+                .await?;
+            return Ok(());
         }
         warn!("{:?} invalid action", req);
-        return Err(());
+        let e = CodecError::decode::<RpcAction>(format!("invalid action {:?}", req.action));
+        return Err(e.into());
+    }
+
+    #[inline]
+    fn cancel_req(&self, seq: u64) {
+        self.1.cancel(seq);
+        self.2.close_input(seq);
+    }
+
+    #[inline]
+    fn credit_req(&self, seq: u64, credit: u32) {
+        self.2.grant(seq, credit);
+    }
+
+    #[inline]
+    async fn recv_frame<'a>(&'a self, seq: u64, msg: Option<&'a [u8]>) {
+        self.2.recv_frame(seq, msg).await
+    }
+
+    #[inline]
+    fn methods(&self) -> &[&'static str] {
+        &self.3.names
     }
 }
 
@@ -60,6 +188,7 @@ pub type DispatchInline<C, S> = APIDispatch<Inline<C, S>>;
 /// APIDispatch for inline process in connection coroutine, only for demo
 ///
 /// It will block the next request if your async method blocks.
+/// The streaming methods are not supported, use [DispatchSpawn] instead.
 pub struct Inline<C: Codec, S: ServiceStatic<C> + Clone> {
     service: S,
     _phan: PhantomData<fn(&C)>,
@@ -68,7 +197,7 @@ pub struct Inline<C: Codec, S: ServiceStatic<C> + Clone> {
 impl<C: Codec, S: ServiceStatic<C> + Clone> Inline<C, S> {
     #[inline]
     pub fn new(s: S) -> DispatchInline<C, S> {
-        APIDispatch(Inline { service: s, _phan: Default::default() })
+        APIDispatch::new(Inline { service: s, _phan: Default::default() })
     }
 }
 
@@ -83,8 +212,80 @@ impl<C: Codec, S: ServiceStatic<C> + Clone> APIDispatchTrait for Inline<C, S> {
     type Codec = C;
 
     #[inline]
-    async fn dispatch(&self, req: APIServerReq<C>) -> Result<(), ()> {
+    async fn dispatch(&self, req: APIServerReq<C>) -> Result<(), CodecError> {
         self.service.serve(req).await;
         return Ok(());
     }
+
+    #[inline]
+    fn methods(&self, names: &mut Vec<&'static str>) {
+        self.service.methods(names)
+    }
+}
+
+pub type DispatchSpawn<C, S, RT> = APIDispatch<Spawn<C, S, RT>>;
+
+/// APIDispatch spawning a coroutine for each request in the runtime, supports streaming methods
+pub struct Spawn<C, S, RT>
+where
+    C: Codec,
+    S: ServiceStatic<C> + Clone,
+    RT: AsyncRuntime + Clone + Send + Sync + 'static,
+{
+    service: S,
+    rt: RT,
+    _phan: PhantomData<fn(&C)>,
+}
+
+impl<C, S, RT> Spawn<C, S, RT>
+where
+    C: Codec,
+    S: ServiceStatic<C> + Clone,
+    RT: AsyncRuntime + Clone + Send + Sync + 'static,
+{
+    #[inline]
+    pub fn new(s: S, rt: RT) -> DispatchSpawn<C, S, RT> {
+        APIDispatch::new(Spawn { service: s, rt, _phan: Default::default() })
+    }
+}
+
+impl<C, S, RT> Clone for Spawn<C, S, RT>
+where
+    C: Codec,
+    S: ServiceStatic<C> + Clone,
+    RT: AsyncRuntime + Clone + Send + Sync + 'static,
+{
+    #[inline]
+    fn clone(&self) -> Self {
+        Self { service: self.service.clone(), rt: self.rt.clone(), _phan: Default::default() }
+    }
+}
+
+impl<C, S, RT> APIDispatchTrait for Spawn<C, S, RT>
+where
+    C: Codec,
+    S: ServiceStatic<C> + Clone,
+    RT: AsyncRuntime + Clone + Send + Sync + 'static,
+{
+    type Codec = C;
+
+    const CONCURRENT: bool = true;
+
+    #[inline]
+    async fn dispatch(&self, req: APIServerReq<C>) -> Result<(), CodecError> {
+        let service = self.service.clone();
+        self.rt.spawn_detach(async move {
+            // The request might expire while waiting for the runtime
+            if req.is_expired() {
+                return req.set_rpc_error(RpcIntErr::Deadline);
+            }
+            service.serve(req).await
+        });
+        return Ok(());
+    }
+
+    #[inline]
+    fn methods(&self, names: &mut Vec<&'static str>) {
+        self.service.methods(names)
+    }
 }
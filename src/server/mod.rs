@@ -1,5 +1,5 @@
 pub use razor_rpc_macros::{method, service, service_mux_struct};
-pub use razor_stream::server::{RpcServer, ServerConfig, ServerDefault};
+pub use razor_stream::server::{PeerIdentity, RpcServer, ServerConfig, ServerDefault};
 
 pub mod dispatch;
 mod service;
@@ -16,11 +16,15 @@ pub trait ServiceStatic<C: Codec>: Send + Sync + 'static + Sized {
 
     /// the [`#[service]`](crate::server::service) macro should generate code like this:
     /// ```no_compile
+    /// if req.is_expired() {
+    ///     req.set_rpc_error(RpcIntErr::Deadline);
+    ///     return;
+    /// }
     /// match req.method
     ///     match req.decode::<RequestType>() {
-    ///         Err(())=>{
-    ///             req.set_rpc_error(razor_rpc_core::error::RpcIntErr::Decode);
-    ///             returnl
+    ///         Err(e)=>{
+    ///             req.set_decode_error(e);
+    ///             return;
     ///         }
     ///         Ok(arg)=>{
     ///             match self.#method(arg).await {
@@ -35,6 +39,14 @@ pub trait ServiceStatic<C: Codec>: Send + Sync + 'static + Sized {
     ///     }
     /// ```
     fn serve(&self, req: APIServerReq<C>) -> impl Future<Output = ()> + Send + Sized;
+
+    /// Collect the "Service.method" names served, which are advertised to the clients with
+    /// numeric ids by handshake, refer to [method_id](razor_stream::proto::method_id).
+    ///
+    /// The [`#[service]`](crate::server::service) macro lists all the methods.
+    /// By default there's none, the clients send the names as string.
+    #[inline]
+    fn methods(&self, _names: &mut Vec<&'static str>) {}
 }
 
 impl<S: ServiceStatic<C>, C: Codec> ServiceStatic<C> for Arc<S> {
@@ -44,6 +56,11 @@ impl<S: ServiceStatic<C>, C: Codec> ServiceStatic<C> for Arc<S> {
     fn serve(&self, req: APIServerReq<C>) -> impl Future<Output = ()> + Send + Sized {
         self.as_ref().serve(req)
     }
+
+    #[inline(always)]
+    fn methods(&self, names: &mut Vec<&'static str>) {
+        self.as_ref().methods(names)
+    }
 }
 
 /// Service trait for dyn dispatch, used by [ServiceMuxDyn]
@@ -55,6 +72,8 @@ impl<S: ServiceStatic<C>, C: Codec> ServiceStatic<C> for Arc<S> {
 pub trait ServiceDyn<C: Codec>: Send + Sync + 'static {
     fn get_service_name(&self) -> &'static str;
 
+    fn get_methods(&self, names: &mut Vec<&'static str>);
+
     async fn serve_dyn(&self, req: APIServerReq<C>);
 }
 
@@ -65,6 +84,11 @@ impl<S: ServiceStatic<C>, C: Codec> ServiceDyn<C> for S {
         <Self as ServiceStatic<C>>::SERVICE_NAME
     }
 
+    #[inline(always)]
+    fn get_methods(&self, names: &mut Vec<&'static str>) {
+        <Self as ServiceStatic<C>>::methods(self, names)
+    }
+
     #[inline(always)]
     async fn serve_dyn(&self, req: APIServerReq<C>) {
         self.serve(req).await
@@ -96,11 +120,18 @@ impl<C: Codec> ServiceStatic<C> for ServiceMuxDyn<C> {
     #[inline(always)]
     fn serve(&self, req: APIServerReq<C>) -> impl Future<Output = ()> + Send + Sized {
         async move {
-            if let Some(service) = self.map.get(req.service.as_str()) {
+            if let Some(service) = self.map.get(&*req.service) {
                 service.serve_dyn(req).await
             } else {
                 req.set_rpc_error(RpcIntErr::Service);
             }
         }
     }
+
+    #[inline]
+    fn methods(&self, names: &mut Vec<&'static str>) {
+        for service in self.map.values() {
+            service.get_methods(names);
+        }
+    }
 }
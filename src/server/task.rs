@@ -2,46 +2,122 @@
 //! [Dispatch](crate::server::dispatch)
 
 use crate::{Codec, error::*};
+use futures::future::{Either, select};
+use futures::pin_mut;
+use futures::stream::{Stream, StreamExt};
+use razor_stream::proto::RpcMeta;
+use razor_stream::server::PeerIdentity;
+use razor_stream::server::cancel::CancelToken;
+use razor_stream::server::streaming::{ReqFrames, StreamRegistry};
 use razor_stream::server::task::{RespNoti, ServerTaskEncode, ServerTaskResp};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::borrow::Cow;
 use std::fmt;
 use std::io::Write;
-use std::sync::Arc;
+use std::pin::Pin;
+use std::sync::{
+    Arc, Weak,
+    atomic::{AtomicBool, Ordering},
+};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 pub struct APIServerReq<C: Codec> {
     pub seq: u64,
-    pub service: String,
-    pub method: String,
+    /// Borrowed from the method table of the service when possible, refer to
+    /// [ServiceStatic::methods()](crate::server::ServiceStatic::methods)
+    pub service: Cow<'static, str>,
+    pub method: Cow<'static, str>,
     pub req: Option<Vec<u8>>,
     pub codec: Arc<C>,
     pub noti: RespNoti<APIServerResp>,
+    /// Metadata headers received along with the request
+    pub meta: RpcMeta,
+    /// Metadata headers to send back along with the response
+    pub resp_meta: RpcMeta,
+    /// Deadline propagated from the client, converted from the timeout budget on arrival
+    pub deadline: Option<Instant>,
+    /// Cancelled when the client abandons the request, handlers can watch it to stop early
+    pub cancel: CancelToken,
+    /// Flow control of the connection, None when the dispatch does not support streaming
+    pub streams: Option<Weak<StreamRegistry>>,
+    /// The items following a client-streaming request, refer to [take_input()](Self::take_input)
+    pub input: Option<ReqFrames<APIServerResp>>,
+    /// Reply the detail of decode error, refer to [set_decode_error()](Self::set_decode_error)
+    pub decode_err_detail: bool,
+    /// The client identity verified by the transport, like the certificate of mutual TLS
+    pub peer: Option<Arc<PeerIdentity>>,
 }
 
 impl<C: Codec> APIServerReq<C> {
     #[inline]
-    pub fn decode<'a, R: Deserialize<'a>>(&'a mut self, buf: &'a [u8]) -> Result<R, ()> {
+    pub fn decode<'a, R: Deserialize<'a>>(&'a mut self, buf: &'a [u8]) -> Result<R, CodecError> {
         self.codec.decode::<R>(buf)
     }
 
+    /// The remaining time budget of the request, None if the client does not set a deadline.
+    ///
+    /// You can pass it on to downstream calls with `call_with_deadline()`.
+    #[inline]
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
+    /// Whether the deadline has passed, the request is answered with RpcIntErr::Deadline before
+    /// the handler runs.
+    #[inline]
+    pub fn is_expired(&self) -> bool {
+        if let Some(d) = self.deadline { d <= Instant::now() } else { false }
+    }
+
     #[inline(always)]
     pub fn set_result<R: Serialize>(self, resp: R) {
         match self.codec.encode::<R>(&resp) {
-            Err(()) => {
+            Err(e) => {
+                log::warn!("{}.{} seq={} {}", self.service, self.method, self.seq, e);
                 self.noti.done(APIServerResp {
                     seq: self.seq,
                     msg: None,
                     res: Some(Err(RpcIntErr::Encode.into())),
+                    meta: self.resp_meta,
+                    more: false,
                 });
             }
             Ok(msg) => {
-                self.noti.done(APIServerResp { seq: self.seq, msg: Some(msg), res: Some(Ok(())) });
+                self.noti.done(APIServerResp {
+                    seq: self.seq,
+                    msg: Some(msg),
+                    res: Some(Ok(())),
+                    meta: self.resp_meta,
+                    more: false,
+                });
             }
         }
     }
 
     #[inline(always)]
     pub fn set_rpc_error(self, e: RpcIntErr) {
-        self.noti.done(APIServerResp { seq: self.seq, msg: None, res: Some(Err(e.into())) });
+        self.noti.done(APIServerResp {
+            seq: self.seq,
+            msg: None,
+            res: Some(Err(e.into())),
+            meta: self.resp_meta,
+            more: false,
+        });
+    }
+
+    /// Answer RpcIntErr::Decode when the request fails to decode, with the detail of CodecError
+    /// when [ServerConfig::decode_err_detail](razor_stream::server::ServerConfig) is set.
+    #[inline]
+    pub fn set_decode_error(self, e: CodecError) {
+        log::warn!("{}.{} seq={} {}", self.service, self.method, self.seq, e);
+        self.noti.done(APIServerResp {
+            seq: self.seq,
+            msg: None,
+            res: Some(Err(EncodedErr::decode_err(&e, self.decode_err_detail))),
+            meta: self.resp_meta,
+            more: false,
+        });
     }
 
     #[inline(always)]
@@ -50,7 +126,119 @@ impl<C: Codec> APIServerReq<C> {
             RpcError::Rpc(rpc_int_err) => rpc_int_err.into(),
             RpcError::User(user_err) => user_err.encode(self.codec.as_ref()),
         };
-        self.noti.done(APIServerResp { seq: self.seq, msg: None, res: Some(Err(encoded_err)) });
+        self.noti.done(APIServerResp {
+            seq: self.seq,
+            msg: None,
+            res: Some(Err(encoded_err)),
+            meta: self.resp_meta,
+            more: false,
+        });
+    }
+
+    /// Send the items of a server-streaming method as partial responses, and finish with an empty
+    /// response, or the first error.
+    ///
+    /// Each item waits for the credit from the client, so a slow consumer holds the stream back.
+    /// Stops when the request is cancelled or the connection has closed.
+    /// Answers RpcIntErr::Internal when the dispatch does not support streaming, like
+    /// [DispatchInline](crate::server::dispatch::DispatchInline).
+    pub async fn set_stream<R, E, S>(self, stream: S)
+    where
+        R: Serialize,
+        E: RpcErrCodec,
+        S: Stream<Item = Result<R, RpcError<E>>>,
+    {
+        let Some(registry) = self.streams.as_ref().and_then(|streams| streams.upgrade()) else {
+            return self.set_rpc_error(RpcIntErr::Internal);
+        };
+        let window = registry.open_window(self.seq);
+        drop(registry);
+        let cancel = self.cancel.clone();
+        pin_mut!(stream);
+        loop {
+            let next_f = async {
+                // Err when the connection has closed
+                window.acquire().await.ok()?;
+                Some(stream.next().await)
+            };
+            let cancel_f = cancel.cancelled();
+            pin_mut!(next_f, cancel_f);
+            let item = match select(next_f, cancel_f).await {
+                Either::Left((Some(item), _)) => item,
+                Either::Left((None, _)) => return,
+                Either::Right(_) => return self.set_rpc_error(RpcIntErr::Cancelled),
+            };
+            match item {
+                Some(Ok(resp)) => {
+                    let Ok(msg) = self.codec.encode::<R>(&resp) else {
+                        return self.set_rpc_error(RpcIntErr::Encode);
+                    };
+                    let partial = APIServerResp {
+                        seq: self.seq,
+                        msg: Some(msg),
+                        res: Some(Ok(())),
+                        meta: RpcMeta::new(),
+                        more: true,
+                    };
+                    if self.noti.send(partial).is_err() {
+                        return;
+                    }
+                }
+                Some(Err(e)) => return self.set_error(e),
+                None => break,
+            }
+        }
+        self.noti.done(APIServerResp {
+            seq: self.seq,
+            msg: None,
+            res: Some(Ok(())),
+            meta: self.resp_meta,
+            more: false,
+        });
+    }
+
+    /// Take the items following a client-streaming request, decoded as `T`.
+    ///
+    /// The returned flag is set when an item fails to decode, in which case the stream ends early.
+    /// Returns RpcIntErr::Internal when the dispatch does not support streaming, like
+    /// [DispatchInline](crate::server::dispatch::DispatchInline), or RpcIntErr::Decode when
+    /// the request is not a client-streaming.
+    pub fn take_input<T>(&mut self) -> Result<(ReqStream<T>, Arc<AtomicBool>), RpcIntErr>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        if self.streams.is_none() {
+            return Err(RpcIntErr::Internal);
+        }
+        let Some(frames) = self.input.take() else {
+            return Err(RpcIntErr::Decode);
+        };
+        let broken = Arc::new(AtomicBool::new(false));
+        let state = (frames, self.codec.clone(), broken.clone());
+        let stream = futures::stream::unfold(state, |(mut frames, codec, broken)| async move {
+            let buf = frames.recv().await?;
+            match codec.decode::<T>(&buf) {
+                Ok(item) => Some((item, (frames, codec, broken))),
+                Err(e) => {
+                    log::warn!("input item {}", e);
+                    broken.store(true, Ordering::Release);
+                    None
+                }
+            }
+        });
+        Ok((ReqStream(Box::pin(stream)), broken))
+    }
+}
+
+/// The decoded items of a client-streaming request, refer to [APIServerReq::take_input()]
+pub struct ReqStream<T>(Pin<Box<dyn Stream<Item = T> + Send>>);
+
+impl<T> Stream for ReqStream<T> {
+    type Item = T;
+
+    #[inline]
+    fn poll_next(mut self: Pin<&mut Self>, ctx: &mut Context) -> Poll<Option<T>> {
+        self.0.as_mut().poll_next(ctx)
     }
 }
 
@@ -58,6 +246,9 @@ pub struct APIServerResp {
     pub seq: u64,
     pub msg: Option<Vec<u8>>,
     pub res: Option<Result<(), EncodedErr>>,
+    pub meta: RpcMeta,
+    /// A partial response of server-streaming, refer to [APIServerReq::set_stream()]
+    pub more: bool,
 }
 
 impl fmt::Debug for APIServerResp {
@@ -85,6 +276,16 @@ impl ServerTaskEncode for APIServerResp {
             }
         }
     }
+
+    #[inline]
+    fn get_resp_meta(&self) -> Option<&RpcMeta> {
+        Some(&self.meta)
+    }
+
+    #[inline]
+    fn has_more(&self) -> bool {
+        self.more
+    }
 }
 
 impl ServerTaskResp for APIServerResp {}
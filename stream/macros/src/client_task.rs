@@ -37,6 +37,7 @@ pub fn client_task_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
     let mut resp_field: Option<(Ident, Type)> = None;
     let mut req_blob_field: Option<Ident> = None;
     let mut resp_blob_field: Option<(Ident, Type)> = None;
+    let mut frames_field: Option<Ident> = None;
     let mut field_action: Option<(Ident, Type)> = None; // For #[field(action)]
     let mut static_action: Option<NestedMeta> = None; // For #[client_task(action)]
     let mut res_field: Option<(Ident, Type)> = None;
@@ -78,6 +79,7 @@ pub fn client_task_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
                                         "resp" => resp_field = Some((f_name, f_type)),
                                         "req_blob" => req_blob_field = Some(f_name),
                                         "resp_blob" => resp_blob_field = Some((f_name, f_type)),
+                                        "frames" => frames_field = Some(f_name),
                                         "action" => {
                                             // Handle #[field(action)]
                                             if field_action.is_some() {
@@ -137,6 +139,24 @@ pub fn client_task_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
             fn reserve_resp_blob(&mut self, size: i32) -> Option<&mut [u8]> {
                 razor_stream::buffer::AllocateBuf::reserve(&mut self.#resp_blob_field_name, size)
             }
+
+            #[inline]
+            fn write_resp_blob(&mut self, offset: u64, chunk: &[u8]) -> Result<(), razor_stream::error::RpcIntErr> {
+                razor_stream::buffer::AllocateBuf::write_chunk(&mut self.#resp_blob_field_name, offset, chunk)
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let decode_frame_body = if let Some(frames_field_name) = &frames_field {
+        quote! {
+            #[inline]
+            fn decode_frame<C: razor_stream::Codec>(&mut self, codec: &C, buffer: &[u8], _blob: Option<&[u8]>) -> Result<(), razor_stream::error::CodecError> {
+                let frame = codec.decode(buffer)?;
+                self.#frames_field_name.push(frame);
+                Ok(())
+            }
         }
     } else {
         quote! {}
@@ -436,7 +456,7 @@ pub fn client_task_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
 
         impl #impl_generics_for_impl razor_stream::client::task::ClientTaskEncode for #struct_name #ty_generics_for_impl #where_clause_for_impl {
             #[inline]
-            fn encode_req<C: razor_stream::Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, ()> {
+            fn encode_req<C: razor_stream::Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, razor_stream::error::CodecError> {
                 codec.encode_into(&self.#req_field_name, buf)
             }
 
@@ -445,13 +465,15 @@ pub fn client_task_impl(attr: TokenStream, input: TokenStream) -> TokenStream {
 
         impl #impl_generics_for_impl razor_stream::client::task::ClientTaskDecode for #struct_name #ty_generics_for_impl #where_clause_for_impl {
             #[inline]
-            fn decode_resp<C: razor_stream::Codec>(&mut self, codec: &C, buffer: &[u8]) -> Result<(), ()> {
+            fn decode_resp<C: razor_stream::Codec>(&mut self, codec: &C, buffer: &[u8]) -> Result<(), razor_stream::error::CodecError> {
                 let resp = codec.decode(buffer)?;
                 self.#resp_field_name = Some(resp);
                 Ok(())
             }
 
             #reserve_resp_blob_body
+
+            #decode_frame_body
         }
     };
     TokenStream::from(expanded)
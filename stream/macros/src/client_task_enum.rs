@@ -62,6 +62,8 @@ pub fn client_task_enum_impl(attr: TokenStream, input: TokenStream) -> TokenStre
     let mut get_req_blob_arms = Vec::new();
     let mut decode_resp_arms = Vec::new();
     let mut reserve_resp_blob_arms = Vec::new();
+    let mut write_resp_blob_arms = Vec::new();
+    let mut decode_frame_arms = Vec::new();
     let mut get_action_arms = Vec::new();
     let mut get_result_arms = Vec::new();
     let mut set_custom_error_arms = Vec::new();
@@ -144,6 +146,14 @@ pub fn client_task_enum_impl(attr: TokenStream, input: TokenStream) -> TokenStre
             #enum_name::#variant_name(inner) => razor_stream::client::task::ClientTaskDecode::reserve_resp_blob(inner, size),
         });
 
+        write_resp_blob_arms.push(quote! {
+            #enum_name::#variant_name(inner) => razor_stream::client::task::ClientTaskDecode::write_resp_blob(inner, offset, chunk),
+        });
+
+        decode_frame_arms.push(quote! {
+            #enum_name::#variant_name(inner) => razor_stream::client::task::ClientTaskDecode::decode_frame(inner, codec, buffer, blob),
+        });
+
         get_result_arms.push(quote! {
             #enum_name::#variant_name(inner) => razor_stream::client::task::ClientTaskGetResult::get_result(inner),
         });
@@ -195,7 +205,7 @@ pub fn client_task_enum_impl(attr: TokenStream, input: TokenStream) -> TokenStre
 
         impl #impl_generics razor_stream::client::task::ClientTaskEncode for #enum_name #ty_generics #where_clause {
             #[inline]
-            fn encode_req<C: razor_stream::Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, ()> {
+            fn encode_req<C: razor_stream::Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, razor_stream::error::CodecError> {
                 match self {
                     #(#encode_req_arms)*
                 }
@@ -211,7 +221,7 @@ pub fn client_task_enum_impl(attr: TokenStream, input: TokenStream) -> TokenStre
 
         impl #impl_generics razor_stream::client::task::ClientTaskDecode for #enum_name #ty_generics #where_clause {
             #[inline]
-            fn decode_resp<C: razor_stream::Codec>(&mut self, codec: &C, buffer: &[u8]) -> Result<(), ()> {
+            fn decode_resp<C: razor_stream::Codec>(&mut self, codec: &C, buffer: &[u8]) -> Result<(), razor_stream::error::CodecError> {
                 match self {
                     #(#decode_resp_arms)*
                 }
@@ -223,6 +233,20 @@ pub fn client_task_enum_impl(attr: TokenStream, input: TokenStream) -> TokenStre
                     #(#reserve_resp_blob_arms)*
                 }
             }
+
+            #[inline]
+            fn write_resp_blob(&mut self, offset: u64, chunk: &[u8]) -> Result<(), razor_stream::error::RpcIntErr> {
+                match self {
+                    #(#write_resp_blob_arms)*
+                }
+            }
+
+            #[inline]
+            fn decode_frame<C: razor_stream::Codec>(&mut self, codec: &C, buffer: &[u8], blob: Option<&[u8]>) -> Result<(), razor_stream::error::CodecError> {
+                match self {
+                    #(#decode_frame_arms)*
+                }
+            }
         }
 
         impl #impl_generics razor_stream::client::task::ClientTaskAction for #enum_name #ty_generics #where_clause {
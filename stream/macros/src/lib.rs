@@ -24,7 +24,7 @@ mod server_task_enum;
 /// The macro always generates:
 /// - `Deref` and `DerefMut` to the field marked `#[field(common)]`.
 /// - `ClientTaskEncode` for the `#[field(req)]` and `#[field(req_blob)]` fields.
-/// - `ClientTaskDecode` for the `#[field(resp)]` and `#[field(resp_blob)]` fields.
+/// - `ClientTaskDecode` for the `#[field(resp)]`, `#[field(resp_blob)]` and `#[field(frames)]` fields.
 ///
 /// The macro can also conditionally generate:
 /// - `ClientTaskAction`: Generated if a static action is provided (e.g., `#[client_task(1)]`) or if a field is marked `#[field(action)]`.
@@ -44,6 +44,10 @@ mod server_task_enum;
 /// * `#[field(req_blob)]`: (Optional) Marks a field for an optional request blob. Must implement `AsRef<[u8]>`.
 ///
 /// * `#[field(resp_blob)]`: (Optional) Marks a field for an optional response blob. Must be `Option<T>` where `T` implements `razor_stream::buffer::AllocateBuf`.
+///   A blob received in chunks is written with `AllocateBuf::write_chunk()`.
+///
+/// * `#[field(frames)]`: (Optional) Collects the partial responses of server-streaming, must be a `Vec<T>`
+///   where `T` is decoded from each frame. The blob of the frames is ignored.
 ///
 /// * `#[field(res)]`: (Optional) When used with `#[field(noti)]`, triggers automatic `ClientTaskDone` implementation.
 ///   Must be of type `Option<Result<(), RpcError<E>>>` where `E` implements `razor_stream::error::RpcErrCodec`. Stores the final result of the task.
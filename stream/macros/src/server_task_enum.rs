@@ -64,6 +64,8 @@ pub fn server_task_enum_impl(attrs: TokenStream, input: TokenStream) -> TokenStr
     let mut decode_arms = Vec::new();
     let mut get_action_arms = Vec::new();
     let mut encode_arms = Vec::new();
+    let mut get_resp_meta_arms = Vec::new();
+    let mut has_more_arms = Vec::new();
     let mut set_result_arms = Vec::new();
     let mut where_clauses_for_decode = Vec::new();
 
@@ -206,6 +208,14 @@ pub fn server_task_enum_impl(attrs: TokenStream, input: TokenStream) -> TokenStr
                 #enum_name::#variant_name(task) => task.encode_resp(codec, buf),
             });
 
+            get_resp_meta_arms.push(quote! {
+                #enum_name::#variant_name(task) => task.get_resp_meta(),
+            });
+
+            has_more_arms.push(quote! {
+                #enum_name::#variant_name(task) => task.has_more(),
+            });
+
             set_result_arms.push(quote! {
                 #enum_name::#variant_name(task) => task._set_result(res),
             });
@@ -227,13 +237,12 @@ pub fn server_task_enum_impl(attrs: TokenStream, input: TokenStream) -> TokenStr
                     req: &'a [u8],
                     blob: Option<io_buffer::Buffer>,
                     noti: razor_stream::server::task::RespNoti<#resp_type>,
-                ) -> Result<Self, ()> {
+                ) -> Result<Self, razor_stream::error::CodecError> {
                     match action {
                         #(#decode_arms)*
-                        _ => {
-                            log::error!("Unknown action: {:?}", action);
-                            Err(())
-                        }
+                        _ => Err(razor_stream::error::CodecError::decode::<Self>(
+                            format!("unknown action {:?}", action),
+                        )),
                     }
                 }
             }
@@ -257,6 +266,20 @@ pub fn server_task_enum_impl(attrs: TokenStream, input: TokenStream) -> TokenStr
                         #(#encode_arms)*
                     }
                 }
+
+                #[inline]
+                fn get_resp_meta(&self) -> Option<&razor_stream::proto::RpcMeta> {
+                    match self {
+                        #(#get_resp_meta_arms)*
+                    }
+                }
+
+                #[inline]
+                fn has_more(&self) -> bool {
+                    match self {
+                        #(#has_more_arms)*
+                    }
+                }
             }
 
             impl #impl_generics razor_stream::server::task::ServerTaskDone<#resp_type, #error_type> for #enum_name #ty_generics #where_clause {
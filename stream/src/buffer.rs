@@ -1,9 +1,29 @@
+use crate::error::RpcIntErr;
+
 /// A trait to adapt various type of buffer
+///
+/// A sink consuming the blob incrementally (like a file) can implement it with
+/// [write_chunk()](Self::write_chunk), and return None from [reserve()](Self::reserve).
 pub trait AllocateBuf: 'static + Sized + Send {
     /// Alloc buffer or reserve space to fit blob_len inside the Buffer.
     ///
     /// When size is not enough, return None
     fn reserve<'a>(&'a mut self, _blob_len: i32) -> Option<&'a mut [u8]>;
+
+    /// Write a chunk of the blob received in chunks (refer to `Chunk` in [crate::proto]),
+    /// `offset` is the number of bytes received before it, 0 for the first chunk.
+    ///
+    /// By default, grow with [reserve()](Self::reserve) and copy, which is limited to i32.
+    #[inline]
+    fn write_chunk(&mut self, offset: u64, chunk: &[u8]) -> Result<(), RpcIntErr> {
+        let end = offset + chunk.len() as u64;
+        if end > i32::MAX as u64 {
+            return Err(RpcIntErr::Decode);
+        }
+        let buf = self.reserve(end as i32).ok_or(RpcIntErr::Decode)?;
+        buf[offset as usize..].copy_from_slice(chunk);
+        Ok(())
+    }
 }
 
 /// If Option is None, create a new `Vec<u8>` on call, otherwise grow to fit the requirement
@@ -25,6 +45,11 @@ impl AllocateBuf for Option<Vec<u8>> {
         }
         return self.as_deref_mut();
     }
+
+    #[inline]
+    fn write_chunk(&mut self, offset: u64, chunk: &[u8]) -> Result<(), RpcIntErr> {
+        self.get_or_insert_with(Vec::new).write_chunk(offset, chunk)
+    }
 }
 
 /// Grow to fit the requirement
@@ -40,6 +65,18 @@ impl AllocateBuf for Vec<u8> {
         }
         return Some(self);
     }
+
+    /// Not limited to i32
+    #[inline]
+    fn write_chunk(&mut self, offset: u64, chunk: &[u8]) -> Result<(), RpcIntErr> {
+        let offset = offset as usize;
+        if offset > self.len() {
+            return Err(RpcIntErr::Decode);
+        }
+        self.truncate(offset);
+        self.extend_from_slice(chunk);
+        Ok(())
+    }
 }
 
 /// If Option is None, create a new [io_buffer::Buffer](https://docs.rs/io_buffer) on call.
@@ -67,6 +104,35 @@ impl AllocateBuf for Option<io_buffer::Buffer> {
         }
         return self.as_deref_mut();
     }
+
+    /// Allocate on the first chunk, and grow into a larger buffer keeping the bytes already
+    /// written when the capacity is not enough. Limited to i32.
+    #[inline]
+    fn write_chunk(&mut self, offset: u64, chunk: &[u8]) -> Result<(), RpcIntErr> {
+        let end = offset + chunk.len() as u64;
+        if end > i32::MAX as u64 {
+            return Err(RpcIntErr::Decode);
+        }
+        let (offset, end) = (offset as usize, end as usize);
+        let written = self.as_ref().map(|buf| buf.len()).unwrap_or(0);
+        if offset > written {
+            return Err(RpcIntErr::Decode);
+        }
+        let cap = self.as_ref().map(|buf| buf.capacity()).unwrap_or(0);
+        if cap < end {
+            // Double the capacity to avoid copying on every chunk
+            let cap = end.max(cap * 2).clamp(1, i32::MAX as usize);
+            let mut v = io_buffer::Buffer::alloc(cap as i32).map_err(|_| RpcIntErr::Decode)?;
+            if let Some(buf) = self.as_ref() {
+                v[..offset].copy_from_slice(&buf[..offset]);
+            }
+            self.replace(v);
+        }
+        let buf = self.as_mut().unwrap();
+        buf.set_len(end);
+        buf[offset..end].copy_from_slice(chunk);
+        Ok(())
+    }
 }
 
 /// Check an pre-allocated [io_buffer::Buffer](https://docs.rs/io_buffer).
@@ -85,3 +151,29 @@ impl AllocateBuf for io_buffer::Buffer {
         Some(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_chunk() {
+        let data: Vec<u8> = (0..3000).map(|i| (i % 251) as u8).collect();
+        let mut buf: Option<io_buffer::Buffer> = None;
+        let mut v: Option<Vec<u8>> = None;
+        for (i, chunk) in data.chunks(1000).enumerate() {
+            let offset = (i * 1000) as u64;
+            buf.write_chunk(offset, chunk).expect("write buffer");
+            v.write_chunk(offset, chunk).expect("write vec");
+        }
+        // Grows keeping the bytes written
+        assert_eq!(&buf.as_ref().unwrap()[..], &data[..]);
+        assert_eq!(v.as_ref().unwrap(), &data);
+        // Out of order
+        assert!(buf.write_chunk(4000, &data[..10]).is_err());
+        assert!(v.write_chunk(4000, &data[..10]).is_err());
+        // io_buffer::Buffer is limited to i32
+        let mut buf: Option<io_buffer::Buffer> = None;
+        assert!(buf.write_chunk(i32::MAX as u64, &data[..10]).is_err());
+    }
+}
@@ -1,11 +1,12 @@
 use crate::client::task::*;
 use crate::client::{
     ClientCaller, ClientCallerBlocking, ClientConfig, ClientFacts, ClientPool, ClientTransport,
+    RpcPush,
 };
 use crate::proto::RpcAction;
 use crate::{
     Codec,
-    error::{EncodedErr, RpcIntErr},
+    error::{CodecError, EncodedErr, RpcIntErr},
 };
 use arc_swap::ArcSwapOption;
 use captains_log::filter::LogFilter;
@@ -250,6 +251,16 @@ where
         self.facts.get_config()
     }
 
+    #[inline]
+    fn accept_push(&self) -> bool {
+        self.facts.accept_push()
+    }
+
+    #[inline]
+    fn on_push(&self, push: RpcPush) {
+        self.facts.on_push(push)
+    }
+
     #[inline]
     fn error_handle(&self, task: FailoverTask<F::Task>) {
         if task.should_retry {
@@ -335,7 +346,7 @@ pub struct FailoverTask<T: ClientTask> {
 
 impl<T: ClientTask> ClientTaskEncode for FailoverTask<T> {
     #[inline(always)]
-    fn encode_req<C: Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, ()> {
+    fn encode_req<C: Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, CodecError> {
         self.inner.encode_req(codec, buf)
     }
 
@@ -347,7 +358,7 @@ impl<T: ClientTask> ClientTaskEncode for FailoverTask<T> {
 
 impl<T: ClientTask> ClientTaskDecode for FailoverTask<T> {
     #[inline(always)]
-    fn decode_resp<C: Codec>(&mut self, codec: &C, buf: &[u8]) -> Result<(), ()> {
+    fn decode_resp<C: Codec>(&mut self, codec: &C, buf: &[u8]) -> Result<(), CodecError> {
         self.inner.decode_resp(codec, buf)
     }
 
@@ -355,6 +366,11 @@ impl<T: ClientTask> ClientTaskDecode for FailoverTask<T> {
     fn reserve_resp_blob(&mut self, _size: i32) -> Option<&mut [u8]> {
         self.inner.reserve_resp_blob(_size)
     }
+
+    #[inline(always)]
+    fn write_resp_blob(&mut self, offset: u64, chunk: &[u8]) -> Result<(), RpcIntErr> {
+        self.inner.write_resp_blob(offset, chunk)
+    }
 }
 
 impl<T: ClientTask> ClientTaskDone for FailoverTask<T> {
@@ -1,5 +1,6 @@
 //! The module contains traits defined for the client-side
 
+use crate::proto::{Handshake, Negotiated};
 use crate::{Codec, error::RpcIntErr};
 use captains_log::filter::LogFilter;
 use crossfire::MAsyncRx;
@@ -39,6 +40,22 @@ pub struct ClientConfig {
     pub thresholds: usize,
     /// In bytes. when non-zero, overwrite the default DEFAULT_BUF_SIZE of transport
     pub stream_buf_size: usize,
+    /// Exchange handshake after connect to negotiate protocol version and features.
+    ///
+    /// Old servers are detected and fall back to v1 behavior, you can disable it to save a
+    /// round trip when all the servers are old builds.
+    pub handshake: bool,
+    /// Compress the requests with this algorithm, when the server supports it.
+    ///
+    /// Defaults to [COMPRESS_NONE](crate::compress::COMPRESS_NONE), refer to [crate::compress]
+    /// for the cargo features. Requires `handshake`.
+    pub compression: u8,
+    /// In bytes. Only the msg or blob not smaller than this is compressed
+    pub compress_threshold: usize,
+    /// Ask for the CRC32C trailer on every frame, to detect corruption on the link.
+    ///
+    /// Only enabled when the server supports it, requires `handshake`.
+    pub checksum: bool,
 }
 
 impl Default for ClientConfig {
@@ -51,6 +68,10 @@ impl Default for ClientConfig {
             connect_timeout: Duration::from_secs(10),
             thresholds: 128,
             stream_buf_size: 0,
+            handshake: true,
+            compression: crate::compress::COMPRESS_NONE,
+            compress_threshold: 4096,
+            checksum: false,
         }
     }
 }
@@ -97,6 +118,40 @@ pub trait ClientFacts: orb::AsyncRuntime + Send + Sync + Sized + 'static {
     fn get_client_id(&self) -> u64 {
         0
     }
+
+    /// Whether to accept the messages pushed by the server, negotiated by the handshake.
+    ///
+    /// By default false, the server is not allowed to push on the connections.
+    #[inline(always)]
+    fn accept_push(&self) -> bool {
+        false
+    }
+
+    /// Receive a message pushed by the server, refer to [crate::server::push].
+    ///
+    /// Called from the connection reader coroutine (of any connection in the pool), should not
+    /// block. Only called when [accept_push()](Self::accept_push) returns true.
+    #[inline(always)]
+    fn on_push(&self, _push: RpcPush) {}
+}
+
+/// A message pushed by the server, delivered to [ClientFacts::on_push]
+#[derive(Debug)]
+pub struct RpcPush {
+    /// Numbered by the server on each connection, unrelated to the request seq
+    pub seq: u64,
+    /// The encoded msg, refer to [decode()](Self::decode)
+    pub msg: Vec<u8>,
+    pub blob: Option<Vec<u8>>,
+}
+
+impl RpcPush {
+    #[inline]
+    pub fn decode<'a, C: Codec, T: serde::Deserialize<'a>>(
+        &'a self, codec: &C,
+    ) -> Result<T, crate::error::CodecError> {
+        codec.decode(&self.msg)
+    }
 }
 
 /// A trait to support sending request task in async text, for all router and connection pool
@@ -136,6 +191,11 @@ impl<C: ClientCallerBlocking + Send + Sync> ClientCallerBlocking for Arc<C> {
 /// The implementation can be found on:
 ///
 /// - [razor-rpc-tcp](https://docs.rs/razor-rpc-tcp): For TCP and Unix socket
+/// - [razor-rpc-tls](https://docs.rs/razor-rpc-tls): For TLS over TCP and Unix socket
+/// - [razor-rpc-quic](https://docs.rs/razor-rpc-quic): For QUIC
+/// - [razor-rpc-mem](https://docs.rs/razor-rpc-mem): For the server in the same process
+/// - [razor-rpc-shm](https://docs.rs/razor-rpc-shm): For the processes on the same host, by shared memory
+/// - [razor-rpc-ws](https://docs.rs/razor-rpc-ws): For WebSocket, through the HTTP proxies
 ///
 /// # NOTE:
 ///
@@ -150,6 +210,18 @@ pub trait ClientTransport: fmt::Debug + Send + Sized + 'static {
         addr: &str, conn_id: &str, config: &ClientConfig,
     ) -> impl Future<Output = Result<Self, RpcIntErr>> + Send;
 
+    /// Exchange the handshake right after connect, before any request is sent.
+    ///
+    /// The transport should apply the negotiated settings on the connection.
+    /// Returns [Negotiated::default()] (v1 behavior) when the server is an old build.
+    ///
+    /// By default, the transport does not support handshake.
+    fn handshake<F: ClientFacts>(
+        &self, _logger: &LogFilter, _local: &Handshake,
+    ) -> impl Future<Output = Result<Negotiated, RpcIntErr>> + Send {
+        async { Ok(Negotiated::default()) }
+    }
+
     /// Shutdown the write direction of the connection
     fn close_conn<F: ClientFacts>(&self, logger: &LogFilter) -> impl Future<Output = ()> + Send;
 
@@ -175,12 +247,30 @@ pub struct ClientDefault<T: ClientTask, RT: orb::AsyncRuntime, C: Codec> {
     pub logger: Arc<LogFilter>,
     config: ClientConfig,
     rt: RT,
+    push_tx: Option<crossfire::MTx<RpcPush>>,
     _phan: std::marker::PhantomData<fn(&C, &T)>,
 }
 
 impl<T: ClientTask, RT: orb::AsyncRuntime, C: Codec> ClientDefault<T, RT, C> {
     pub fn new(config: ClientConfig, rt: RT) -> Arc<Self> {
-        Arc::new(Self { logger: Arc::new(LogFilter::new()), config, rt, _phan: Default::default() })
+        Self::_new(config, rt, None)
+    }
+
+    /// Accept server push, the messages are received from the returned channel
+    pub fn new_with_push(config: ClientConfig, rt: RT) -> (Arc<Self>, crossfire::AsyncRx<RpcPush>) {
+        let (tx, rx) = crossfire::mpsc::unbounded_async();
+        (Self::_new(config, rt, Some(tx)), rx)
+    }
+
+    #[inline]
+    fn _new(config: ClientConfig, rt: RT, push_tx: Option<crossfire::MTx<RpcPush>>) -> Arc<Self> {
+        Arc::new(Self {
+            logger: Arc::new(LogFilter::new()),
+            config,
+            rt,
+            push_tx,
+            _phan: Default::default(),
+        })
     }
 
     #[inline]
@@ -209,4 +299,16 @@ impl<T: ClientTask, RT: orb::AsyncRuntime, C: Codec> ClientFacts for ClientDefau
     fn get_config(&self) -> &ClientConfig {
         &self.config
     }
+
+    #[inline]
+    fn accept_push(&self) -> bool {
+        self.push_tx.is_some()
+    }
+
+    #[inline]
+    fn on_push(&self, push: RpcPush) {
+        if let Some(tx) = self.push_tx.as_ref() {
+            let _ = tx.send(push);
+        }
+    }
 }
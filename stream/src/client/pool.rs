@@ -5,8 +5,11 @@ use crate::client::{
 use crate::error::RpcIntErr;
 use captains_log::filter::LogFilter;
 use crossfire::{MAsyncRx, MAsyncTx, MTx, RecvTimeoutError, mpmc};
+use futures::future::{Either, select};
+use futures::pin_mut;
 use orb::prelude::*;
 use std::fmt;
+use std::future::Future;
 use std::marker::PhantomData;
 use std::sync::Arc;
 use std::sync::atomic::{
@@ -181,16 +184,61 @@ impl<F: ClientFacts, P: ClientTransport> ClientPoolInner<F, P> {
         ClientStream::connect(self.facts.clone(), &self.addr, &self.conn_id, None).await
     }
 
+    /// When the server is draining the connection with GOAWAY, replace it with a new connection.
+    ///
+    /// The in-flight tasks on the old connection will finish normally.
+    #[inline(always)]
+    async fn check_goaway(&self, stream: &mut ClientStream<F, P>) -> Result<(), RpcIntErr> {
+        if stream.is_goaway() {
+            logger_debug!(self.logger, "{} reconnect due to goaway", self);
+            // flush what's written before goaway
+            let _ = stream.flush_req().await;
+            *stream = self.connect().await?;
+        }
+        Ok(())
+    }
+
+    #[inline(always)]
+    async fn send_task(
+        &self, stream: &mut ClientStream<F, P>, mut task: F::Task, need_flush: bool,
+    ) -> Result<(), RpcIntErr> {
+        if let Err(e) = self.check_goaway(stream).await {
+            task.set_rpc_error(e.clone());
+            self.facts.error_handle(task);
+            return Err(e);
+        }
+        stream.send_task(task, need_flush).await
+    }
+
+    /// Wait for `f`, meanwhile write the frames of the streaming tasks sent by the stream
+    async fn wait_frames<R>(
+        &self, stream: &mut ClientStream<F, P>, f: impl Future<Output = R>,
+    ) -> Result<R, RpcIntErr> {
+        pin_mut!(f);
+        loop {
+            let frame = {
+                let recv_f = stream.recv_frame();
+                pin_mut!(recv_f);
+                match select(f.as_mut(), recv_f).await {
+                    Either::Left((r, _)) => return Ok(r),
+                    Either::Right((frame, _)) => frame,
+                }
+            };
+            stream.send_frame(frame).await?;
+            stream.flush_frames().await?;
+        }
+    }
+
     #[inline(always)]
     async fn _run_worker(
         &self, _worker_id: usize, stream: &mut ClientStream<F, P>,
     ) -> Result<(), RpcIntErr> {
         loop {
-            match self.rx.recv().await {
+            match self.wait_frames(stream, self.rx.recv()).await? {
                 Ok(task) => {
-                    stream.send_task(task, false).await?;
+                    self.send_task(stream, task, false).await?;
                     while let Ok(task) = self.rx.try_recv() {
-                        stream.send_task(task, false).await?;
+                        self.send_task(stream, task, false).await?;
                     }
                     stream.flush_req().await?;
                 }
@@ -217,18 +265,38 @@ impl<F: ClientFacts, P: ClientTransport> ClientPoolInner<F, P> {
             match self.connect().await {
                 Ok(mut stream) => {
                     logger_trace!(self.logger, "{} worker={} connected", self, worker_id);
+                    self.is_ok.store(true, SeqCst);
                     if worker_id == 0 {
                         // act as monitor
                         'MONITOR: loop {
+                            if stream.is_goaway() {
+                                // Not an error, reconnect without delay
+                                continue 'CONN_LOOP;
+                            }
                             if self.get_workers() > 1 {
-                                F::sleep(ONE_SEC).await;
-                                if stream.ping().await.is_err() {
+                                if self.wait_frames(&mut stream, F::sleep(ONE_SEC)).await.is_err()
+                                    || stream.ping().await.is_err()
+                                {
                                     self.set_err();
                                     // don't cleanup the channel unless only one worker left
                                     continue 'CONN_LOOP;
                                 }
                             } else {
-                                match self.rx.recv_with_timer(F::sleep(ONE_SEC)).await {
+                                let r = match self
+                                    .wait_frames(
+                                        &mut stream,
+                                        self.rx.recv_with_timer(F::sleep(ONE_SEC)),
+                                    )
+                                    .await
+                                {
+                                    Ok(r) => r,
+                                    Err(_) => {
+                                        self.set_err();
+                                        self.cleanup();
+                                        continue 'CONN_LOOP;
+                                    }
+                                };
+                                match r {
                                     Err(RecvTimeoutError::Disconnected) => {
                                         return;
                                     }
@@ -254,7 +322,7 @@ impl<F: ClientFacts, P: ClientTransport> ClientPoolInner<F, P> {
                                                 self.clone().spawn_worker(0);
                                             }
                                         }
-                                        if stream.send_task(task, true).await.is_err() {
+                                        if self.send_task(&mut stream, task, true).await.is_err() {
                                             self.set_err();
                                             if worker_id == 0 {
                                                 self.cleanup();
@@ -8,14 +8,15 @@
 //! is received, it can optionally notify the user through a user-defined channel or another mechanism.
 
 use super::throttler::Throttler;
-use crate::client::task::ClientTaskDone;
+use crate::client::task::{ClientTaskAction, ClientTaskDone, TaskFrame};
 use crate::client::timer::ClientTaskTimer;
+use crate::compress::Compressor;
 use crate::{client::*, proto};
 use captains_log::filter::LogFilter;
 use crossfire::*;
 use futures::pin_mut;
 use orb::prelude::*;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{
     cell::UnsafeCell,
     fmt,
@@ -28,7 +29,6 @@ use std::{
     },
     task::{Context, Poll},
 };
-use sync_utils::time::DelayedTime;
 
 /// ClientStream represents a client-side connection.
 ///
@@ -52,14 +52,31 @@ impl<F: ClientFacts, P: ClientTransport> ClientStream<F, P> {
         async move {
             let client_id = facts.get_client_id();
             let conn = P::connect(addr, conn_id, facts.get_config()).await?;
-            Ok(Self::new(facts, conn, client_id, conn_id.to_string(), last_resp_ts))
+            let config = facts.get_config();
+            let negotiated = if config.handshake {
+                let mut local = proto::Handshake {
+                    codecs: vec![F::Codec::FORMAT],
+                    compressions: crate::compress::preference(config.compression),
+                    ..Default::default()
+                };
+                if !config.checksum {
+                    local.features &= !proto::RPC_FEATURE_CHECKSUM;
+                }
+                if !facts.accept_push() {
+                    local.features &= !proto::RPC_FEATURE_PUSH;
+                }
+                conn.handshake::<F>(&facts.new_logger(), &local).await?
+            } else {
+                proto::Negotiated::default()
+            };
+            Ok(Self::new(facts, conn, client_id, conn_id.to_string(), last_resp_ts, negotiated))
         }
     }
 
     #[inline]
     fn new(
         facts: Arc<F>, conn: P, client_id: u64, conn_id: String,
-        last_resp_ts: Option<Arc<AtomicU64>>,
+        last_resp_ts: Option<Arc<AtomicU64>>, negotiated: proto::Negotiated,
     ) -> Self {
         let (_close_tx, _close_rx) = mpmc::unbounded_async::<()>();
         let inner = Arc::new(ClientStreamInner::new(
@@ -69,6 +86,7 @@ impl<F: ClientFacts, P: ClientTransport> ClientStream<F, P> {
             conn_id,
             _close_rx,
             last_resp_ts,
+            negotiated,
         ));
         logger_debug!(inner.logger, "{:?} connected", inner);
         let _inner = inner.clone();
@@ -83,6 +101,12 @@ impl<F: ClientFacts, P: ClientTransport> ClientStream<F, P> {
         &self.inner.codec
     }
 
+    /// The protocol settings agreed with the server by handshake
+    #[inline]
+    pub fn get_negotiated(&self) -> &proto::Negotiated {
+        &self.inner.negotiated
+    }
+
     /// Should be call in sender threads
     ///
     /// NOTE: will skip if throttler is full
@@ -102,6 +126,13 @@ impl<F: ClientFacts, P: ClientTransport> ClientStream<F, P> {
         self.inner.closed.load(Ordering::SeqCst)
     }
 
+    /// The server is draining this connection, new tasks should be sent through another
+    /// connection. The in-flight tasks will still finish normally.
+    #[inline(always)]
+    pub fn is_goaway(&self) -> bool {
+        self.inner.get_timer_mut().is_goaway()
+    }
+
     /// Force the receiver to exit.
     ///
     /// You can call it when connectivity probes detect that a server is unreachable.
@@ -125,6 +156,20 @@ impl<F: ClientFacts, P: ClientTransport> ClientStream<F, P> {
         self.inner.send_task(task, need_flush).await
     }
 
+    /// Send a task expecting no response (fire-and-forget), like send_task().
+    ///
+    /// The task is not registered in the timer nor counted by the throttler, it's done with
+    /// `set_ok()` right after written. When the server does not negotiate
+    /// [RPC_FEATURE_ONEWAY](proto::RPC_FEATURE_ONEWAY), or the task is streaming, it's sent as
+    /// a normal task and done on response.
+    #[inline(always)]
+    pub async fn send_oneway(
+        &mut self, mut task: F::Task, need_flush: bool,
+    ) -> Result<(), RpcIntErr> {
+        task.oneway = true;
+        self.inner.send_task(task, need_flush).await
+    }
+
     /// Since the transport layer might have buffer, user should always call flush explicitly.
     /// you can set `need_flush` = true for some urgent message, or call flush_req() explicitly.
     #[inline(always)]
@@ -132,6 +177,28 @@ impl<F: ClientFacts, P: ClientTransport> ClientStream<F, P> {
         self.inner.flush_req().await
     }
 
+    /// Write and flush the frames of the streaming tasks, queued by
+    /// [ClientTaskSink](crate::client::task::ClientTaskSink), and the cancel frames.
+    ///
+    /// ClientPool does it on its own, you only need it when using ClientStream directly.
+    #[inline]
+    pub async fn flush_frames(&mut self) -> Result<(), RpcIntErr> {
+        self.inner.send_frames().await?;
+        self.inner.flush_req().await
+    }
+
+    /// Wait for a frame queued by the streaming tasks or the cancelled tasks
+    #[inline(always)]
+    pub(crate) async fn recv_frame(&self) -> TaskFrame {
+        self.inner.recv_frame().await
+    }
+
+    /// Write a frame received by recv_frame(), without flush
+    #[inline(always)]
+    pub(crate) async fn send_frame(&mut self, frame: TaskFrame) -> Result<(), RpcIntErr> {
+        self.inner.send_frame(frame).await
+    }
+
     /// Check the throttler and see if future send_task() might be blocked
     #[inline]
     pub fn will_block(&self) -> bool {
@@ -173,6 +240,14 @@ struct ClientStreamInner<F: ClientFacts, P: ClientTransport> {
     throttler: Throttler,
     last_resp_ts: Option<Arc<AtomicU64>>,
     encode_buf: UnsafeCell<Vec<u8>>,
+    /// for the compressed blob
+    blob_buf: UnsafeCell<Vec<u8>>,
+    /// Frames of the streaming tasks queued by ClientTaskSink, and the cancel frames queued by
+    /// ClientTaskCancel or the timer, written by the sender
+    frame_tx: MTx<TaskFrame>,
+    frame_rx: AsyncRx<TaskFrame>,
+    negotiated: proto::Negotiated,
+    compressor: Compressor,
     codec: F::Codec,
     logger: Arc<LogFilter>,
     facts: Arc<F>,
@@ -191,13 +266,14 @@ impl<F: ClientFacts, P: ClientTransport> fmt::Debug for ClientStreamInner<F, P>
 impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
     pub fn new(
         facts: Arc<F>, conn: P, client_id: u64, conn_id: String, close_rx: MAsyncRx<()>,
-        last_resp_ts: Option<Arc<AtomicU64>>,
+        last_resp_ts: Option<Arc<AtomicU64>>, negotiated: proto::Negotiated,
     ) -> Self {
         let config = facts.get_config();
         let mut thresholds = config.thresholds;
         if thresholds == 0 {
             thresholds = 128;
         }
+        let (frame_tx, frame_rx) = mpsc::unbounded_async();
         let client_inner = Self {
             client_id,
             conn,
@@ -205,6 +281,15 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
             closed: AtomicBool::new(false),
             seq: AtomicU64::new(1),
             encode_buf: UnsafeCell::new(Vec::with_capacity(1024)),
+            blob_buf: UnsafeCell::new(Vec::new()),
+            frame_tx,
+            frame_rx,
+            compressor: Compressor::new(
+                config.compression,
+                negotiated.compression,
+                config.compress_threshold,
+            ),
+            negotiated,
             throttler: Throttler::new(thresholds),
             last_resp_ts,
             has_err: AtomicBool::new(false),
@@ -214,6 +299,7 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
             facts,
         };
         logger_trace!(client_inner.logger, "{:?} throttler is set to {}", client_inner, thresholds,);
+        logger_trace!(client_inner.logger, "{:?} {:?}", client_inner, client_inner.negotiated);
         client_inner
     }
 
@@ -227,6 +313,12 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
         unsafe { transmute(self.encode_buf.get()) }
     }
 
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_blob_buf(&self) -> &mut Vec<u8> {
+        unsafe { transmute(self.blob_buf.get()) }
+    }
+
     /// Directly work on the socket steam, when failed
     async fn send_task(&self, mut task: F::Task, mut need_flush: bool) -> Result<(), RpcIntErr> {
         if self.throttler.nearly_full() {
@@ -249,6 +341,26 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
             timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
             return Err(RpcIntErr::IO);
         }
+        if timer.is_goaway() {
+            logger_debug!(self.logger, "{:?} reject task {:?} due to goaway", self, task);
+            task.set_rpc_error(RpcIntErr::Unreachable);
+            self.facts.error_handle(task);
+            timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
+            return Err(RpcIntErr::Unreachable);
+        }
+        if let Err(e) = self.send_frames().await {
+            task.set_rpc_error(RpcIntErr::IO);
+            self.facts.error_handle(task);
+            timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
+            return Err(e);
+        }
+        if task.oneway && (!self.negotiated.has(proto::RPC_FEATURE_ONEWAY) || task.sink.is_some()) {
+            // An old server answers it anyway
+            task.oneway = false;
+        }
+        // The chunks received on a previous connection are dropped with it
+        task.resp_blob_received = 0;
+        let oneway = task.oneway;
         match self.send_request(task, need_flush).await {
             Err(_) => {
                 self.closed.store(true, Ordering::SeqCst);
@@ -256,8 +368,10 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
                 return Err(RpcIntErr::IO);
             }
             Ok(_) => {
-                // register task to norifier
-                self.throttler.throttle().await;
+                if !oneway {
+                    // register task to norifier
+                    self.throttler.throttle().await;
+                }
                 return Ok(());
             }
         }
@@ -278,10 +392,72 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
 
     #[inline(always)]
     async fn send_request(&self, mut task: F::Task, need_flush: bool) -> Result<(), RpcIntErr> {
+        if task.is_cancelled() {
+            logger_debug!(self.logger, "{:?} task {:?} cancelled before sending", self, task);
+            let timer = self.get_timer_mut();
+            timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
+            task.set_rpc_error(RpcIntErr::Cancelled);
+            self.facts.error_handle(task);
+            return Ok(());
+        }
+        // The v2 server decodes the meta, even without RPC_FEATURE_META agreed
+        let meta_ok = self.negotiated.has(proto::RPC_FEATURE_META)
+            || self.negotiated.version >= proto::RPC_VERSION_2;
+        if let Some(deadline) = task.deadline {
+            let now = Instant::now();
+            if deadline <= now {
+                logger_debug!(self.logger, "{:?} task {:?} expired before sending", self, task);
+                let timer = self.get_timer_mut();
+                timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
+                task.set_rpc_error(RpcIntErr::Deadline);
+                self.facts.error_handle(task);
+                // Not an error of the connection
+                return Ok(());
+            }
+            if meta_ok {
+                task.meta.set_timeout(deadline - now);
+            }
+        }
+        if task.sink.is_some() && !self.negotiated.has(proto::RPC_FEATURE_STREAM) {
+            logger_debug!(self.logger, "{:?} server does not support stream {:?}", self, task);
+            let timer = self.get_timer_mut();
+            timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
+            task.set_rpc_error(RpcIntErr::Version);
+            self.facts.error_handle(task);
+            return Ok(());
+        }
+        if !meta_ok && (!task.meta.is_empty() || task.deadline.is_some()) {
+            // An old server will reject the v2 header and close the connection
+            logger_warn!(self.logger, "{:?} server does not support meta {:?}", self, task);
+            let timer = self.get_timer_mut();
+            timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst); // rollback
+            task.set_rpc_error(RpcIntErr::Version);
+            self.facts.error_handle(task);
+            return Ok(());
+        }
         let seq = self.seq_update();
         task.set_seq(seq);
+        let action = self.negotiated.resolve_action(task.get_action());
+        if let Some(sink) = task.sink.as_ref() {
+            // Before written, the frames must follow the request
+            sink.bind(seq, action, self.frame_tx.clone());
+        }
+        if let Some(cancel) = task.cancel.as_ref() {
+            if self.negotiated.has(proto::RPC_FEATURE_CANCEL) {
+                cancel.bind(seq, self.frame_tx.clone());
+            }
+        }
         let buf = self.get_encoded_buf();
-        match proto::ReqHead::encode(&self.codec, buf, self.client_id, &task) {
+        let blob_buf = self.get_blob_buf();
+        match proto::ReqHead::encode(
+            &self.codec,
+            buf,
+            self.client_id,
+            &task,
+            action,
+            &self.compressor,
+            blob_buf,
+        ) {
             Err(_) => {
                 logger_warn!(&self.logger, "{:?} send_req encode req {:?} err", self, task);
                 return Err(RpcIntErr::Encode);
@@ -308,6 +484,13 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
                     task.set_rpc_error(RpcIntErr::IO);
                     self.facts.error_handle(task);
                     return Err(RpcIntErr::IO);
+                } else if task.oneway {
+                    // No response to wait for
+                    let timer = self.get_timer_mut();
+                    timer.pending_task_count_ref().fetch_sub(1, Ordering::SeqCst);
+                    logger_trace!(self.logger, "{:?} send oneway task {:?} ok", self, task);
+                    task.set_ok();
+                    task.done();
                 } else {
                     let wg = self.throttler.add_task();
                     let timer = self.get_timer_mut();
@@ -319,12 +502,80 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
         }
     }
 
+    #[inline(always)]
+    async fn recv_frame(&self) -> TaskFrame {
+        // frame_tx is kept by ourselves, never disconnect
+        self.frame_rx.recv().await.expect("frame channel")
+    }
+
+    /// Write the frames queued by the streaming tasks, without flush
+    async fn send_frames(&self) -> Result<(), RpcIntErr> {
+        while let Ok(frame) = self.frame_rx.try_recv() {
+            self.send_frame(frame).await?;
+        }
+        Ok(())
+    }
+
+    /// Write a frame of a streaming task, without flush
+    async fn send_frame(&self, frame: TaskFrame) -> Result<(), RpcIntErr> {
+        if self.closed.load(Ordering::Acquire) {
+            if let TaskFrame::Data { sink, .. } = frame {
+                sink.release();
+            }
+            return Err(RpcIntErr::IO);
+        }
+        let buf = self.get_encoded_buf();
+        let (seq, sink) = match frame {
+            TaskFrame::Cancel { seq } => {
+                proto::ReqHead::encode_cancel(buf, self.client_id, seq);
+                (seq, None)
+            }
+            TaskFrame::Credit { seq, credit } => {
+                proto::ReqHead::encode_credit(buf, self.client_id, seq, credit);
+                (seq, None)
+            }
+            TaskFrame::Data { seq, action, msg, more, sink } => {
+                if proto::ReqHead::encode_frame(
+                    buf,
+                    self.client_id,
+                    F::Codec::FORMAT,
+                    action.to_action(),
+                    seq,
+                    &msg,
+                    more,
+                    &self.compressor,
+                )
+                .is_err()
+                {
+                    logger_warn!(self.logger, "{:?} encode frame of seq={} err", self, seq);
+                    sink.release();
+                    return Err(RpcIntErr::Encode);
+                }
+                (seq, Some(sink))
+            }
+        };
+        let r = self.conn.write_req::<F>(&self.logger, buf, None, false).await;
+        if let Err(e) = r {
+            // The slot of a written frame is returned by the credit of the server
+            if let Some(sink) = sink {
+                sink.release();
+            }
+            logger_warn!(self.logger, "{:?} send frame of seq={} err: {:?}", self, seq, e);
+            self.closed.store(true, Ordering::SeqCst);
+            self.has_err.store(true, Ordering::SeqCst);
+            return Err(RpcIntErr::IO);
+        }
+        logger_trace!(self.logger, "{:?} send frame of seq={}", self, seq);
+        Ok(())
+    }
+
     #[inline(always)]
     async fn send_ping_req(&self) -> Result<(), RpcIntErr> {
         if self.closed.load(Ordering::Acquire) {
             logger_warn!(self.logger, "{:?} send_ping_req skip as conn closed", self);
             return Err(RpcIntErr::IO);
         }
+        self.send_frames().await?;
         // PING does not counted in throttler
         let buf = self.get_encoded_buf();
         proto::ReqHead::encode_ping(buf, self.client_id, self.seq_update());
@@ -349,7 +600,8 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
                 }
                 Ok(_) => {
                     if let Some(last_resp_ts) = self.last_resp_ts.as_ref() {
-                        last_resp_ts.store(DelayedTime::get(), Ordering::Relaxed);
+                        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+                        last_resp_ts.store(now.as_secs(), Ordering::Relaxed);
                     }
                 }
             }
@@ -438,7 +690,14 @@ impl<F: ClientFacts, P: ClientTransport> ClientStreamInner<F, P> {
             self.throttler.get_inflight_count()
         );
         let timer = self.get_timer_mut();
-        timer.adjust_task_queue(self.facts.as_ref());
+        let mut cancel_seqs = Vec::new();
+        timer.adjust_task_queue(self.facts.as_ref(), &mut cancel_seqs);
+        if self.negotiated.has(proto::RPC_FEATURE_CANCEL) {
+            // Only the sender can write to the connection
+            for seq in cancel_seqs {
+                let _ = self.frame_tx.send(TaskFrame::Cancel { seq });
+            }
+        }
         return;
     }
 
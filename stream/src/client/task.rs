@@ -5,13 +5,18 @@
 //! - [`#[client_task]`](macro@client_task): For defining a client-side RPC task on a struct. It will not generate ClientTask trait (it's optional to you to define ClientTaskAction with it)
 //! - [`#[client_task_enum]`](macro@client_task_enum): For creating an enum that delegates to client task variants. It will generate ClientTask trait for the enum
 
-use crate::proto::RpcAction;
+use crate::proto::{RPC_STREAM_WINDOW, RpcAction, RpcActionOwned, RpcMeta};
 use crate::{
     Codec,
-    error::{EncodedErr, RpcErrCodec, RpcError, RpcIntErr},
+    error::{CodecError, EncodedErr, RpcErrCodec, RpcError, RpcIntErr},
 };
 use std::fmt;
 use std::ops::DerefMut;
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+use std::time::{Duration, Instant};
 
 pub use razor_stream_macros::{client_task, client_task_enum};
 
@@ -33,7 +38,7 @@ pub trait ClientTask:
 /// Encode the request to buffer that can be send to server
 pub trait ClientTaskEncode {
     /// sererialized the msg into buf (with std::io::Writer), and return the size written
-    fn encode_req<C: Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, ()>;
+    fn encode_req<C: Codec>(&self, codec: &C, buf: &mut Vec<u8>) -> Result<usize, CodecError>;
 
     /// Contain optional extra data to send to server side.
     ///
@@ -46,7 +51,8 @@ pub trait ClientTaskEncode {
 
 /// Decode the response from server and assign to the task struct
 pub trait ClientTaskDecode {
-    fn decode_resp<C: Codec>(&mut self, codec: &C, buf: &[u8]) -> Result<(), ()>;
+    /// The CodecError is logged by the framework, and the task fails with RpcIntErr::Decode.
+    fn decode_resp<C: Codec>(&mut self, codec: &C, buf: &[u8]) -> Result<(), CodecError>;
 
     /// You can call crate::io::AllocateBuf::reserve(_size) on the following types:
     /// `Option<Vec<u8>>`, `Vec<u8>`, `Option<io_buffer::Buffer>`, `io_buffer::Buffer`
@@ -56,6 +62,32 @@ pub trait ClientTaskDecode {
     fn reserve_resp_blob(&mut self, _size: i32) -> Option<&mut [u8]> {
         None
     }
+
+    /// Called for each chunk of the response blob in order, when the blob is larger than
+    /// [RPC_CHUNK_SIZE](crate::proto::RPC_CHUNK_SIZE), before the final response is decoded by
+    /// decode_resp(). `offset` is the number of bytes received before the chunk.
+    ///
+    /// You can call crate::buffer::AllocateBuf::write_chunk() on the types of reserve_resp_blob(),
+    /// or a sink of your own.
+    ///
+    /// The task fails with the error returned. By Default, return RpcIntErr::Decode when client
+    /// task does not have a resp_blob field.
+    #[inline(always)]
+    fn write_resp_blob(&mut self, _offset: u64, _chunk: &[u8]) -> Result<(), RpcIntErr> {
+        Err(RpcIntErr::Decode)
+    }
+
+    /// Called for each partial response of server-streaming, before the final response is
+    /// decoded by decode_resp(). `blob` is the blob carried by the frame, if any.
+    ///
+    /// By Default, return Err as the task does not expect a stream, the task fails with
+    /// RpcIntErr::Decode.
+    #[inline(always)]
+    fn decode_frame<C: Codec>(
+        &mut self, _codec: &C, _buf: &[u8], _blob: Option<&[u8]>,
+    ) -> Result<(), CodecError> {
+        Err(CodecError::decode::<Self>("partial response not expected"))
+    }
 }
 
 /// client_task_enum should impl this for user, not used by framework
@@ -92,6 +124,22 @@ pub trait ClientTaskAction {
 pub struct ClientTaskCommon {
     /// Every task should be assigned an ID which is unique inside a socket connection
     pub seq: u64,
+    /// Optional metadata headers send along with the request,
+    /// the task fails with RpcIntErr::Version when the server does not understand RPC_VERSION_2
+    pub meta: RpcMeta,
+    /// Metadata headers returned by the server, filled before decode_resp()
+    pub resp_meta: RpcMeta,
+    /// Optional deadline of the task, the remaining budget is propagated to the server on sending
+    /// within the meta
+    pub deadline: Option<Instant>,
+    /// Optional flag for the caller to abandon the task
+    pub cancel: Option<ClientTaskCancel>,
+    /// Optional handle to send more frames of a streaming task
+    pub sink: Option<ClientTaskSink>,
+    /// Expect no response, set by [ClientStream::send_oneway()](crate::client::stream::ClientStream::send_oneway)
+    pub oneway: bool,
+    /// The number of bytes of the response blob received in chunks
+    pub resp_blob_received: u64,
 }
 
 impl ClientTaskCommon {
@@ -101,4 +149,235 @@ impl ClientTaskCommon {
     pub fn set_seq(&mut self, seq: u64) {
         self.seq = seq;
     }
+    pub fn meta(&self) -> &RpcMeta {
+        &self.meta
+    }
+    pub fn meta_mut(&mut self) -> &mut RpcMeta {
+        &mut self.meta
+    }
+    pub fn resp_meta(&self) -> &RpcMeta {
+        &self.resp_meta
+    }
+    pub fn set_deadline(&mut self, deadline: Instant) {
+        self.deadline = Some(deadline);
+    }
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.deadline = Some(Instant::now() + timeout);
+    }
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        if let Some(cancel) = self.cancel.as_ref() { cancel.is_cancelled() } else { false }
+    }
+}
+
+/// A shared flag to abandon a task which might be in-flight.
+///
+/// A cancelled task is failed with RpcIntErr::Cancelled by ClientStream before sending,
+/// or on the next timer tick after sending.
+///
+/// When the server negotiates [RPC_FEATURE_CANCEL](crate::proto::RPC_FEATURE_CANCEL), the flag is
+/// bound to the connection on sending, and cancel() queues a cancel frame to the same ClientStream
+/// right away, which is written like the frames of [ClientTaskSink]. Don't cancel a finished task,
+/// the frame is sent anyway (and ignored by the server).
+#[derive(Clone, Default)]
+pub struct ClientTaskCancel(Arc<CancelInner>);
+
+#[derive(Default)]
+struct CancelInner {
+    flag: AtomicBool,
+    /// The seq and the frame channel of the connection, taken by the first notification
+    bound: Mutex<Option<(u64, crossfire::MTx<TaskFrame>)>>,
+    /// Set once bound, the timer does not send the cancel frame again
+    has_bound: AtomicBool,
+}
+
+impl ClientTaskCancel {
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn cancel(&self) {
+        if !self.0.flag.swap(true, Ordering::AcqRel) {
+            self.notify();
+        }
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.flag.load(Ordering::Acquire)
+    }
+
+    /// Whether the cancel frame is sent on cancel(), instead of by the timer
+    #[inline]
+    pub(crate) fn is_bound(&self) -> bool {
+        self.0.has_bound.load(Ordering::Acquire)
+    }
+
+    pub(crate) fn bind(&self, seq: u64, tx: crossfire::MTx<TaskFrame>) {
+        self.0.bound.lock().unwrap().replace((seq, tx));
+        self.0.has_bound.store(true, Ordering::Release);
+        // Cancelled during sending
+        if self.is_cancelled() {
+            self.notify();
+        }
+    }
+
+    #[inline]
+    fn notify(&self) {
+        if let Some((seq, tx)) = self.0.bound.lock().unwrap().take() {
+            let _ = tx.send(TaskFrame::Cancel { seq });
+        }
+    }
+}
+
+impl fmt::Debug for ClientTaskCancel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ClientTaskCancel(cancelled={})", self.is_cancelled())
+    }
+}
+
+/// Frames of streaming tasks following the request, written by the ClientStream of the task.
+pub(crate) enum TaskFrame {
+    /// Abandon the task in-flight, queued by [ClientTaskCancel]
+    Cancel { seq: u64 },
+    /// Grant the server more partial responses
+    Credit { seq: u64, credit: u32 },
+    /// An item of client-streaming, the slot of the sink is returned by the credit of the server
+    Data { seq: u64, action: RpcActionOwned, msg: Vec<u8>, more: bool, sink: ClientTaskSink },
+}
+
+struct SinkBound {
+    seq: u64,
+    action: RpcActionOwned,
+    tx: crossfire::MTx<TaskFrame>,
+}
+
+struct SinkInner {
+    input: bool,
+    bound: Mutex<Option<SinkBound>>,
+    /// Drop the sender to wake up the waiters on bind
+    bind_tx: Mutex<Option<crossfire::MTx<()>>>,
+    bind_rx: crossfire::MAsyncRx<()>,
+    /// One unit for each frame the server allows, refer to [RPC_STREAM_WINDOW]
+    slot_tx: crossfire::MTx<()>,
+    slot_rx: crossfire::MAsyncRx<()>,
+}
+
+/// A handle to send more frames of a streaming task after its request, refer to `Stream` and
+/// `Request stream` in [crate::proto].
+///
+/// The sink is bound to the connection by ClientStream when the request is sent,
+/// and the frames are written by the same ClientStream. ClientPool wakes up for them;
+/// when you use ClientStream directly, call
+/// [flush_frames()](crate::client::stream::ClientStream::flush_frames).
+///
+/// A task with sink fails with RpcIntErr::Version when the server does not negotiate
+/// [RPC_FEATURE_STREAM](crate::proto::RPC_FEATURE_STREAM).
+#[derive(Clone)]
+pub struct ClientTaskSink(Arc<SinkInner>);
+
+impl ClientTaskSink {
+    /// `input`: the request opens a client-streaming, the items follow with [send()](Self::send).
+    pub fn new(input: bool) -> Self {
+        let (bind_tx, bind_rx) = crossfire::mpmc::unbounded_async();
+        let (slot_tx, slot_rx) = crossfire::mpmc::unbounded_async();
+        for _ in 0..RPC_STREAM_WINDOW {
+            let _ = slot_tx.send(());
+        }
+        Self(Arc::new(SinkInner {
+            input,
+            bound: Mutex::new(None),
+            bind_tx: Mutex::new(Some(bind_tx)),
+            bind_rx,
+            slot_tx,
+            slot_rx,
+        }))
+    }
+
+    #[inline]
+    pub fn has_input(&self) -> bool {
+        self.0.input
+    }
+
+    pub(crate) fn bind(&self, seq: u64, action: RpcAction, tx: crossfire::MTx<TaskFrame>) {
+        self.0.bound.lock().unwrap().replace(SinkBound { seq, action: action.into(), tx });
+        let _ = self.0.bind_tx.lock().unwrap().take();
+    }
+
+    /// Wait until the request is sent. It never returns if the task fails before sending,
+    /// you can select it with the completion of the task.
+    pub async fn sent(&self) {
+        if self.0.bound.lock().unwrap().is_some() {
+            return;
+        }
+        // Nothing is ever sent, recv() only returns on disconnect
+        let _ = self.0.bind_rx.recv().await;
+    }
+
+    /// Grant the server `credit` more partial responses of server-streaming.
+    ///
+    /// Returns Err(RpcIntErr::Internal) before the request is sent,
+    /// Err(RpcIntErr::IO) if the connection has closed.
+    pub fn credit(&self, credit: u32) -> Result<(), RpcIntErr> {
+        let guard = self.0.bound.lock().unwrap();
+        let Some(bound) = guard.as_ref() else {
+            return Err(RpcIntErr::Internal);
+        };
+        if bound.tx.send(TaskFrame::Credit { seq: bound.seq, credit }).is_err() {
+            return Err(RpcIntErr::IO);
+        }
+        Ok(())
+    }
+
+    /// Send an encoded item of client-streaming, the last frame should have `more` = false.
+    ///
+    /// Waits when [RPC_STREAM_WINDOW] frames are not taken by the server yet, the server grants
+    /// more as its handler takes them.
+    /// Returns Err(RpcIntErr::Internal) before the request is sent,
+    /// Err(RpcIntErr::IO) if the connection has closed.
+    pub async fn send(&self, msg: Vec<u8>, more: bool) -> Result<(), RpcIntErr> {
+        // slot_tx is kept by ourselves, never disconnect
+        let _ = self.0.slot_rx.recv().await;
+        let guard = self.0.bound.lock().unwrap();
+        let Some(bound) = guard.as_ref() else {
+            self.release();
+            return Err(RpcIntErr::Internal);
+        };
+        let frame = TaskFrame::Data {
+            seq: bound.seq,
+            action: bound.action.clone(),
+            msg,
+            more,
+            sink: self.clone(),
+        };
+        if bound.tx.send(frame).is_err() {
+            return Err(RpcIntErr::IO);
+        }
+        Ok(())
+    }
+
+    /// Return the slot of a frame failed to write
+    #[inline]
+    pub(crate) fn release(&self) {
+        let _ = self.0.slot_tx.send(());
+    }
+
+    /// The server has taken `credit` more frames
+    #[inline]
+    pub(crate) fn grant(&self, credit: u32) {
+        for _ in 0..credit {
+            let _ = self.0.slot_tx.send(());
+        }
+    }
+}
+
+impl fmt::Debug for ClientTaskSink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.bound.lock().unwrap().as_ref() {
+            Some(bound) => write!(f, "ClientTaskSink(seq={}, input={})", bound.seq, self.0.input),
+            None => write!(f, "ClientTaskSink(input={})", self.0.input),
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
-use sync_utils::waitgroup::{WaitGroup, WaitGroupGuard};
+use atomic_waitgroup::{WaitGroup, WaitGroupGuard};
 
 pub struct Throttler {
     wg: WaitGroup,
@@ -11,11 +11,13 @@ use std::{
     task::*,
 };
 
-use crate::client::task::ClientTaskDone;
+use crate::client::task::{ClientTaskDecode, ClientTaskDone};
 use crate::client::*;
+use crate::error::{EncodedErr, RPC_ERR_PREFIX};
+use crate::proto::{RESP_FLAG_HAS_ERR_STRING, RESP_FLAG_HAS_ERRNO, RPC_STREAM_WINDOW, RespFrame};
+use atomic_waitgroup::WaitGroupGuard;
 use crossfire::{stream::AsyncStream, *};
 use rustc_hash::FxHashMap;
-use sync_utils::waitgroup::WaitGroupGuard;
 
 pub struct ClientTaskItem<T: ClientTask> {
     pub task: Option<T>,
@@ -40,6 +42,12 @@ pub struct ClientTaskTimer<F: ClientFacts> {
     // TODO what if seq reach max u64, should exit client
     processed_seq: u64,
     reg_stopped_flag: AtomicBool,
+    /// Set when received GOAWAY from server, checked by the sender
+    goaway_flag: AtomicBool,
+    /// The last seq accepted by server, valid when goaway_flag is set
+    goaway_seq: u64,
+    /// Set when any task is put back by keep_task(), which may be older than min_delay_seq
+    has_kept: bool,
 }
 
 unsafe impl<T: ClientFacts> Send for ClientTaskTimer<T> {}
@@ -62,6 +70,9 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
             delay_tasks_queue: VecDeque::with_capacity(task_timeout),
             processed_seq: 0,
             reg_stopped_flag: AtomicBool::new(false),
+            goaway_flag: AtomicBool::new(false),
+            goaway_seq: 0,
+            has_kept: false,
         }
     }
 
@@ -80,6 +91,11 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
                 }
             }
         }
+        // The tasks not accepted by a draining server are retriable
+        let goaway_seq =
+            if self.goaway_flag.load(Ordering::Relaxed) { self.goaway_seq } else { u64::MAX };
+        let clean_err =
+            |seq: u64| if seq > goaway_seq { RpcIntErr::Unreachable } else { RpcIntErr::IO };
         let mut task_seqs: Vec<u64> = Vec::with_capacity(self.sent_tasks.len());
         for (key, _) in self.sent_tasks.iter() {
             task_seqs.push(*key);
@@ -87,7 +103,7 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
         for key in task_seqs {
             let mut task_item = self.sent_tasks.remove(&key).unwrap();
             let mut task = task_item.task.take().unwrap();
-            task.set_rpc_error(RpcIntErr::IO);
+            task.set_rpc_error(clean_err(key));
             facts.error_handle(task);
         }
         for tasks_batch_in_second in self.delay_tasks_queue.iter_mut() {
@@ -98,12 +114,62 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
             for key in task_seqs {
                 let mut task_item = tasks_batch_in_second.tasks.remove(&key).unwrap();
                 let mut task = task_item.task.take().unwrap();
-                task.set_rpc_error(RpcIntErr::IO);
+                task.set_rpc_error(clean_err(key));
                 facts.error_handle(task);
             }
         }
     }
 
+    /// Called by transport on receiving GOAWAY from server.
+    ///
+    /// The tasks after `last_seq` will never have a response, they are failed with
+    /// RpcIntErr::Unreachable, which is retriable.
+    pub fn set_goaway(&mut self, facts: &F, last_seq: u64) {
+        loop {
+            match self.pending_tasks_recv.try_recv() {
+                Ok(task) => {
+                    self.got_pending_task(task);
+                }
+                Err(_) => {
+                    break;
+                }
+            }
+        }
+        self.goaway_seq = last_seq;
+        self.goaway_flag.store(true, Ordering::SeqCst);
+        let mut task_seqs: Vec<u64> = Vec::new();
+        for (key, _) in self.sent_tasks.iter() {
+            if *key > last_seq {
+                task_seqs.push(*key);
+            }
+        }
+        for key in task_seqs.drain(..) {
+            let mut task_item = self.sent_tasks.remove(&key).unwrap();
+            let mut task = task_item.task.take().unwrap();
+            task.set_rpc_error(RpcIntErr::Unreachable);
+            facts.error_handle(task);
+        }
+        for tasks_batch_in_second in self.delay_tasks_queue.iter_mut() {
+            for (key, _) in tasks_batch_in_second.tasks.iter() {
+                if *key > last_seq {
+                    task_seqs.push(*key);
+                }
+            }
+            for key in task_seqs.drain(..) {
+                let mut task_item = tasks_batch_in_second.tasks.remove(&key).unwrap();
+                let mut task = task_item.task.take().unwrap();
+                task.set_rpc_error(RpcIntErr::Unreachable);
+                facts.error_handle(task);
+            }
+        }
+    }
+
+    /// Whether the server has sent GOAWAY, the sender should stop sending new tasks
+    #[inline(always)]
+    pub fn is_goaway(&self) -> bool {
+        self.goaway_flag.load(Ordering::SeqCst)
+    }
+
     pub fn check_pending_tasks_empty(&mut self) -> bool {
         loop {
             match self.pending_tasks_recv.try_recv() {
@@ -142,7 +208,7 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
 
     pub async fn take_task(&mut self, seq: u64) -> Option<ClientTaskItem<F::Task>> {
         // ping resp won't readh here
-        if seq < self.min_delay_seq {
+        if seq < self.min_delay_seq && !self.has_kept {
             return None; // Task is already timeouted by us
         }
         if seq > self.processed_seq {
@@ -163,6 +229,240 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
         return None;
     }
 
+    /// The task of seq waiting for the response, without taking it
+    fn get_task(&self, seq: u64) -> Option<&F::Task> {
+        if let Some(task_item) = self.sent_tasks.get(&seq) {
+            return task_item.task.as_ref();
+        }
+        for tasks_batch_in_second in self.delay_tasks_queue.iter() {
+            if let Some(task_item) = tasks_batch_in_second.tasks.get(&seq) {
+                return task_item.task.as_ref();
+            }
+        }
+        None
+    }
+
+    /// Put back the task taken on a partial response (server-streaming).
+    ///
+    /// The frame is a keepalive of the task, the timeout restarts from now.
+    #[inline]
+    pub fn keep_task(&mut self, task_item: ClientTaskItem<F::Task>) {
+        let seq = task_item.task.as_ref().unwrap().seq();
+        self.has_kept = true;
+        self.sent_tasks.insert(seq, task_item);
+    }
+
+    /// Deliver a response decoded by [FrameDecoder](crate::proto::FrameDecoder) to the task of the
+    /// seq, or deliver the GOAWAY, push or credit frame.
+    ///
+    /// The chunks of the response blob are written to the task as they arrive, and the task is
+    /// kept until the final response.
+    ///
+    /// A frame failing to decode only fails its task, the connection is still healthy.
+    pub async fn recv_resp(
+        &mut self, facts: &F, logger: &LogFilter, codec: &F::Codec, frame: RespFrame<'_>,
+    ) {
+        let head = frame.head;
+        let seq = head.seq.get();
+        if head.is_goaway() {
+            logger_debug!(logger, "{} rpc client recv goaway {}", self.conn_id, head);
+            self.set_goaway(facts, seq);
+            return;
+        }
+        if head.is_push() {
+            return self._recv_push(facts, logger, frame);
+        }
+        if head.is_credit() {
+            // Not a response, the task keeps waiting
+            let credit = head.msg_len.get();
+            if credit > RPC_STREAM_WINDOW {
+                // The server grants the items taken, never more than the window
+                logger_warn!(logger, "{} rpc client recv invalid credit {}", self.conn_id, head);
+                return;
+            }
+            if let Some(sink) = self.get_task(seq).and_then(|task| task.sink.as_ref()) {
+                logger_trace!(logger, "{} recv credit {}", self.conn_id, head);
+                sink.grant(credit);
+            }
+            return;
+        }
+        let Some(mut task_item) = self.take_task(seq).await else {
+            logger_trace!(logger, "{} timer take_task(seq={}) return None", self.conn_id, seq);
+            return;
+        };
+        let mut task = task_item.task.take().unwrap();
+        if head.is_chunk() {
+            let offset = task.resp_blob_received;
+            if let Err(e) = task.write_resp_blob(offset, frame.blob) {
+                logger_warn!(
+                    logger,
+                    "{} rpc client write resp blob chunk of {:?} at {} err: {}",
+                    self.conn_id,
+                    task,
+                    offset,
+                    e
+                );
+                task.set_rpc_error(e);
+                facts.error_handle(task);
+                return;
+            }
+            task.resp_blob_received += frame.blob.len() as u64;
+            logger_trace!(logger, "{} recv resp chunk of {:?}", self.conn_id, task);
+            task_item.task = Some(task);
+            self.keep_task(task_item);
+            return;
+        }
+        let meta_ok = match frame.decode_meta() {
+            Ok(meta) => {
+                task.resp_meta = meta;
+                true
+            }
+            Err(_) => {
+                logger_warn!(logger, "{} rpc client decode resp meta err", self.conn_id);
+                false
+            }
+        };
+        if head.is_more() {
+            let decoded = meta_ok
+                && match Self::_frame_payload(frame) {
+                    Ok((msg, blob)) => match task.decode_frame(codec, msg, blob.as_deref()) {
+                        Ok(_) => true,
+                        Err(e) => {
+                            logger_warn!(logger, "{} rpc client {:?} {}", self.conn_id, task, e);
+                            false
+                        }
+                    },
+                    Err(_) => false,
+                };
+            if !decoded {
+                logger_warn!(
+                    logger,
+                    "{} rpc client decode resp frame of {:?} err",
+                    self.conn_id,
+                    task
+                );
+                task.set_rpc_error(RpcIntErr::Decode);
+                facts.error_handle(task);
+                return;
+            }
+            logger_trace!(logger, "{} recv resp frame of {:?}", self.conn_id, task);
+            task_item.task = Some(task);
+            self.keep_task(task_item);
+            return;
+        }
+        match head.flag {
+            0 => {}
+            RESP_FLAG_HAS_ERRNO => {
+                task.set_custom_error(codec, EncodedErr::Num(head.msg_len.get()));
+                facts.error_handle(task);
+                return;
+            }
+            RESP_FLAG_HAS_ERR_STRING => {
+                // Only prefix by rpc_
+                if frame.blob.starts_with(RPC_ERR_PREFIX.as_bytes()) {
+                    if let Ok(s) = str::from_utf8(frame.blob) {
+                        if let Some((e, detail)) = RpcIntErr::parse_detail(s) {
+                            if let Some(detail) = detail {
+                                logger_warn!(
+                                    logger,
+                                    "{} rpc client {:?} server err {}: {}",
+                                    self.conn_id,
+                                    task,
+                                    e,
+                                    detail
+                                );
+                            }
+                            task.set_rpc_error(e);
+                            facts.error_handle(task);
+                            return;
+                        }
+                    }
+                }
+                task.set_custom_error(codec, EncodedErr::Buf(frame.blob.to_vec()));
+                facts.error_handle(task);
+                return;
+            }
+            flag => {
+                logger_warn!(logger, "{} rpc client unknown resp flag {}", self.conn_id, flag);
+                task.set_rpc_error(RpcIntErr::Decode);
+                facts.error_handle(task);
+                return;
+            }
+        }
+        let mut err = if meta_ok { None } else { Some(RpcIntErr::Decode) };
+        if !frame.blob.is_empty() {
+            let r = match frame.blob_len() {
+                Ok(len) if len <= i32::MAX as usize => match task.reserve_resp_blob(len as i32) {
+                    Some(buf) => frame.copy_blob(buf),
+                    None => {
+                        logger_error!(
+                            logger,
+                            "{} rpc client task {:?} has no ext_buf",
+                            self.conn_id,
+                            task
+                        );
+                        Err(RpcIntErr::Decode)
+                    }
+                },
+                _ => Err(RpcIntErr::Decode),
+            };
+            if r.is_err() {
+                err = Some(RpcIntErr::Decode);
+            }
+        }
+        if err.is_none() && !frame.msg.is_empty() {
+            match frame.into_msg() {
+                Ok(msg) => {
+                    // set result of task, and notify task completed
+                    if let Err(e) = task.decode_resp(codec, msg) {
+                        logger_warn!(logger, "{} rpc client decode resp err: {}", self.conn_id, e);
+                        err = Some(RpcIntErr::Decode);
+                    }
+                }
+                Err(_) => {
+                    logger_warn!(logger, "{} rpc client decompress resp msg err", self.conn_id);
+                    err = Some(RpcIntErr::Decode);
+                }
+            }
+        }
+        if let Some(e) = err {
+            task.set_rpc_error(e);
+            facts.error_handle(task);
+            return;
+        }
+        logger_trace!(logger, "{} recv task {:?} ok", self.conn_id, task);
+        task.set_ok();
+        task.done();
+    }
+
+    /// Deliver a push frame to the ClientFacts
+    #[inline]
+    fn _recv_push(&self, facts: &F, logger: &LogFilter, frame: RespFrame<'_>) {
+        if !facts.accept_push() {
+            logger_debug!(logger, "{} rpc client drop push {}", self.conn_id, frame.head);
+            return;
+        }
+        let seq = frame.head.seq.get();
+        match Self::_frame_payload(frame) {
+            Ok((msg, blob)) => facts.on_push(RpcPush { seq, msg: msg.to_vec(), blob }),
+            Err(_) => {
+                logger_warn!(logger, "{} rpc client decompress push seq={} err", self.conn_id, seq);
+            }
+        }
+    }
+
+    /// The decompressed msg and blob of a push or partial response
+    #[inline]
+    fn _frame_payload(frame: RespFrame<'_>) -> Result<(&[u8], Option<Vec<u8>>), RpcIntErr> {
+        let mut blob = None;
+        if !frame.blob.is_empty() {
+            let mut buf = vec![0u8; frame.blob_len()?];
+            frame.copy_blob(&mut buf)?;
+            blob = Some(buf);
+        }
+        Ok((frame.into_msg()?, blob))
+    }
+
     #[inline]
     pub fn poll_sent_task<'a>(&mut self, ctx: &mut Context) -> bool {
         let mut got = false;
@@ -190,7 +490,17 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
         self.sent_tasks.insert(task_seq, task_item);
     }
 
-    pub fn adjust_task_queue(&mut self, facts: &F) {
+    /// Called every second, move the sent tasks into delay queue and check timeout.
+    ///
+    /// The seq of timeout or cancelled tasks are pushed into `cancel_seqs`,
+    /// so the sender can notify the server with cancel frames.
+    pub fn adjust_task_queue(&mut self, facts: &F, cancel_seqs: &mut Vec<u64>) {
+        // 0. remove the tasks abandoned by the caller
+        Self::take_cancelled(facts, &mut self.sent_tasks, cancel_seqs);
+        for tasks_batch_in_second in self.delay_tasks_queue.iter_mut() {
+            Self::take_cancelled(facts, &mut tasks_batch_in_second.tasks, cancel_seqs);
+        }
+
         // 1. move wait_confirmed to overtime
         let mut tasks_batch_in_second = FxHashMap::default();
         swap(&mut self.sent_tasks, &mut tasks_batch_in_second);
@@ -213,12 +523,46 @@ impl<F: ClientFacts> ClientTaskTimer<F> {
                         }
                     }
                     warn!("{} task {:?} is timeout", self.conn_id, task,);
+                    cancel_seqs.push(seq);
                     task.set_rpc_error(RpcIntErr::Timeout);
                     facts.error_handle(task);
                 }
                 self.min_delay_seq = min_seq;
             }
         }
+
+        // 3. stop looking up the seq older than min_delay_seq when the kept tasks are all done
+        if self.has_kept {
+            let min_delay_seq = self.min_delay_seq;
+            self.has_kept = self
+                .delay_tasks_queue
+                .iter()
+                .any(|batch| batch.tasks.keys().any(|seq| *seq < min_delay_seq));
+        }
+    }
+}
+
+impl<F: ClientFacts> ClientTaskTimer<F> {
+    #[inline]
+    fn take_cancelled(
+        facts: &F, tasks: &mut FxHashMap<u64, ClientTaskItem<F::Task>>, cancel_seqs: &mut Vec<u64>,
+    ) {
+        let mut cancelled = Vec::new();
+        for (seq, task_item) in tasks.iter() {
+            if task_item.task.as_ref().unwrap().is_cancelled() {
+                cancelled.push(*seq);
+            }
+        }
+        for seq in cancelled {
+            let mut task_item = tasks.remove(&seq).unwrap();
+            let mut task = task_item.task.take().unwrap();
+            // Otherwise the cancel frame has been queued on cancel()
+            if !task.cancel.as_ref().is_some_and(|cancel| cancel.is_bound()) {
+                cancel_seqs.push(seq);
+            }
+            task.set_rpc_error(RpcIntErr::Cancelled);
+            facts.error_handle(task);
+        }
     }
 }
 
@@ -0,0 +1,204 @@
+//! Transparent compression for msg and blob.
+//!
+//! The algorithms are enabled by the cargo features `lz4` and `zstd`.
+//! Which algorithm to use on a connection is agreed by the handshake,
+//! a side only compresses when its own config enables compression,
+//! and only for the msg or blob larger than `compress_threshold`.
+//!
+//! A compressed payload is the original length (u32 little endian) followed by the
+//! compressed data, flagged by [EXT_FLAG_COMPRESS_MSG](crate::proto::EXT_FLAG_COMPRESS_MSG) and
+//! [EXT_FLAG_COMPRESS_BLOB](crate::proto::EXT_FLAG_COMPRESS_BLOB) in the [ExtHead](crate::proto::ExtHead).
+
+use crate::error::RpcIntErr;
+pub use crate::proto::COMPRESS_NONE;
+
+/// Compression id of lz4 (block format)
+pub const COMPRESS_LZ4: u8 = 1;
+/// Compression id of zstd
+pub const COMPRESS_ZSTD: u8 = 2;
+
+const ORIG_LEN_SIZE: usize = 4;
+
+/// The compression ids supported by this build, with [COMPRESS_NONE] at last
+pub fn supported() -> Vec<u8> {
+    let mut ids = Vec::with_capacity(3);
+    #[cfg(feature = "lz4")]
+    ids.push(COMPRESS_LZ4);
+    #[cfg(feature = "zstd")]
+    ids.push(COMPRESS_ZSTD);
+    ids.push(COMPRESS_NONE);
+    ids
+}
+
+/// The compression ids for handshake, put `preferred` at first if supported.
+///
+/// [COMPRESS_NONE] is kept at last, so that the peer can still compress what it sends.
+pub fn preference(preferred: u8) -> Vec<u8> {
+    let mut ids = supported();
+    if preferred == COMPRESS_NONE {
+        return ids;
+    }
+    if let Some(pos) = ids.iter().position(|id| *id == preferred) {
+        ids.remove(pos);
+        ids.insert(0, preferred);
+    }
+    ids
+}
+
+/// The compression setting of the sender side of a connection
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Compressor {
+    pub algo: u8,
+    pub threshold: usize,
+}
+
+impl Compressor {
+    /// `configured` is the compression set by config, `negotiated` is agreed by the handshake.
+    #[inline]
+    pub fn new(configured: u8, negotiated: u8, threshold: usize) -> Self {
+        let algo = if configured == COMPRESS_NONE { COMPRESS_NONE } else { negotiated };
+        Self { algo, threshold }
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.algo != COMPRESS_NONE
+    }
+
+    /// Append the compressed `src` to `dst`.
+    ///
+    /// Returns false and leave `dst` untouched when `src` is below the threshold,
+    /// or the compressed data is not smaller.
+    pub fn compress(&self, src: &[u8], dst: &mut Vec<u8>) -> bool {
+        if !self.is_enabled() || src.len() < self.threshold || src.len() > u32::MAX as usize {
+            return false;
+        }
+        let pre_len = dst.len();
+        dst.extend_from_slice(&(src.len() as u32).to_le_bytes());
+        if _compress(self.algo, src, dst) && dst.len() - pre_len < src.len() {
+            return true;
+        }
+        dst.truncate(pre_len);
+        false
+    }
+
+    /// Compress the tail of `buf` starting at `start` in place,
+    /// returns the new length of the tail if compressed.
+    pub fn compress_tail(&self, buf: &mut Vec<u8>, start: usize) -> Option<usize> {
+        if !self.is_enabled() || buf.len() - start < self.threshold {
+            return None;
+        }
+        let mut compressed = Vec::new();
+        if self.compress(&buf[start..], &mut compressed) {
+            buf.truncate(start);
+            buf.extend_from_slice(&compressed);
+            return Some(compressed.len());
+        }
+        None
+    }
+}
+
+/// Get the original length of a compressed payload
+#[inline]
+pub fn decompressed_len(src: &[u8]) -> Result<usize, RpcIntErr> {
+    if src.len() < ORIG_LEN_SIZE {
+        return Err(RpcIntErr::Decode);
+    }
+    Ok(u32::from_le_bytes(src[0..ORIG_LEN_SIZE].try_into().unwrap()) as usize)
+}
+
+/// Decompress into `dst`, which should have the size of [decompressed_len]
+///
+/// Returns [RpcIntErr::Decode] on corrupted data, or when the algorithm is not supported.
+#[allow(unused_variables)]
+pub fn decompress_into(algo: u8, src: &[u8], dst: &mut [u8]) -> Result<(), RpcIntErr> {
+    if decompressed_len(src)? != dst.len() {
+        return Err(RpcIntErr::Decode);
+    }
+    let src = &src[ORIG_LEN_SIZE..];
+    let r: Option<usize> = match algo {
+        #[cfg(feature = "lz4")]
+        COMPRESS_LZ4 => lz4_flex::block::decompress_into(src, dst).ok(),
+        #[cfg(feature = "zstd")]
+        COMPRESS_ZSTD => zstd::bulk::decompress_to_buffer(src, dst).ok(),
+        _ => {
+            warn!("decompress: compression id {} not supported", algo);
+            None
+        }
+    };
+    match r {
+        Some(size) if size == dst.len() => Ok(()),
+        _ => Err(RpcIntErr::Decode),
+    }
+}
+
+/// Decompress into a new vec
+#[inline]
+pub fn decompress(algo: u8, src: &[u8]) -> Result<Vec<u8>, RpcIntErr> {
+    let mut dst = vec![0u8; decompressed_len(src)?];
+    decompress_into(algo, src, &mut dst)?;
+    Ok(dst)
+}
+
+/// Returns false when the compression failed or not supported
+#[allow(unused_variables)]
+fn _compress(algo: u8, src: &[u8], dst: &mut Vec<u8>) -> bool {
+    match algo {
+        #[cfg(feature = "lz4")]
+        COMPRESS_LZ4 => {
+            let pre_len = dst.len();
+            dst.resize(pre_len + lz4_flex::block::get_maximum_output_size(src.len()), 0);
+            match lz4_flex::block::compress_into(src, &mut dst[pre_len..]) {
+                Ok(size) => {
+                    dst.truncate(pre_len + size);
+                    true
+                }
+                Err(_) => false,
+            }
+        }
+        #[cfg(feature = "zstd")]
+        COMPRESS_ZSTD => match zstd::bulk::compress(src, zstd::DEFAULT_COMPRESSION_LEVEL) {
+            Ok(data) => {
+                dst.extend_from_slice(&data);
+                true
+            }
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_compress() {
+        let data: Vec<u8> = (0..8192).map(|i| (i % 16) as u8).collect();
+        assert!(!Compressor::default().compress(&data, &mut Vec::new()));
+        assert_eq!(Compressor::new(COMPRESS_NONE, COMPRESS_LZ4, 0), Compressor::default());
+        assert_eq!(preference(COMPRESS_NONE).last(), Some(&COMPRESS_NONE));
+        for algo in supported() {
+            if algo == COMPRESS_NONE {
+                continue;
+            }
+            assert_eq!(preference(algo)[0], algo);
+            let compressor = Compressor::new(algo, algo, 1024);
+            let mut buf = Vec::new();
+            assert!(!compressor.compress(&data[0..100], &mut buf));
+            assert!(buf.is_empty());
+            assert!(compressor.compress(&data, &mut buf));
+            assert!(buf.len() < data.len());
+            assert_eq!(decompressed_len(&buf), Ok(data.len()));
+            assert_eq!(decompress(algo, &buf).expect("decompress"), data);
+            assert!(decompress(algo, &buf[0..buf.len() - 1]).is_err());
+
+            let mut buf = vec![1u8, 2, 3];
+            buf.extend_from_slice(&data);
+            let len = compressor.compress_tail(&mut buf, 3).expect("compress");
+            assert_eq!(buf.len(), 3 + len);
+            assert_eq!(decompress(algo, &buf[3..]).expect("decompress"), data);
+        }
+    }
+}
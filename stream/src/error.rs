@@ -1,5 +1,7 @@
 use crate::Codec;
+pub use razor_rpc_codec::CodecError;
 use std::fmt;
+use std::str::FromStr;
 
 /// "rpc_" prefix is reserved for internal error, you should avoid conflict with it
 pub const RPC_ERR_PREFIX: &'static str = "rpc_";
@@ -120,14 +122,14 @@ impl<E: RpcErrCodec> From<RpcIntErr> for RpcError<E> {
 ///     fn encode<C: Codec>(&self, codec: &C) -> EncodedErr {
 ///         match codec.encode(self) {
 ///             Ok(buf)=>EncodedErr::Buf(buf),
-///             Err(())=>EncodedErr::Rpc(RpcIntErr::Encode),
+///             Err(_)=>EncodedErr::Rpc(RpcIntErr::Encode),
 ///         }
 ///     }
 ///
 ///     #[inline(always)]
 ///     fn decode<C: Codec>(codec: &C, buf: Result<u32, &[u8]>) -> Result<Self, ()> {
 ///         if let Err(b) = buf {
-///             return codec.decode(b);
+///             return codec.decode(b).map_err(|_| ());
 ///         } else {
 ///             Err(())
 ///         }
@@ -336,6 +338,18 @@ pub enum RpcIntErr {
     /// invalid version number in rpc header
     #[strum(serialize = "rpc_invalid_ver")]
     Version = 8,
+    /// The deadline of request has passed before the server processing it
+    #[strum(serialize = "rpc_deadline_exceeded")]
+    Deadline = 9,
+    /// The task is abandoned by the caller
+    #[strum(serialize = "rpc_cancelled")]
+    Cancelled = 10,
+    /// The server is draining the connection, only used internally to write GOAWAY frame
+    #[strum(serialize = "rpc_goaway")]
+    GoAway = 11,
+    /// The checksum trailer of a frame mismatch, the connection is closed
+    #[strum(serialize = "rpc_checksum")]
+    Checksum = 12,
 }
 
 // The default Debug derive just ignore strum customized string, by strum only have a Display derive
@@ -351,6 +365,16 @@ impl RpcIntErr {
     pub fn as_bytes<'a>(&'a self) -> &'a [u8] {
         self.as_ref().as_bytes()
     }
+
+    /// Parse the error string from the transport, which might be followed by a detail like
+    /// "rpc_decode: <detail>", refer to [EncodedErr::decode_err()].
+    pub fn parse_detail<'a>(s: &'a str) -> Option<(Self, Option<&'a str>)> {
+        if let Ok(e) = Self::from_str(s) {
+            return Some((e, None));
+        }
+        let (code, detail) = s.split_once(": ")?;
+        Some((Self::from_str(code).ok()?, Some(detail)))
+    }
 }
 
 impl From<std::io::Error> for RpcIntErr {
@@ -374,6 +398,18 @@ pub enum EncodedErr {
 }
 
 impl EncodedErr {
+    /// RpcIntErr::Decode for the request failed to decode, with the detail of CodecError
+    /// appended when `detail` is true, refer to
+    /// [ServerConfig::decode_err_detail](crate::server::ServerConfig::decode_err_detail).
+    #[inline]
+    pub fn decode_err(e: &CodecError, detail: bool) -> Self {
+        if detail {
+            Self::Buf(format!("{}: {}", RpcIntErr::Decode, e).into_bytes())
+        } else {
+            Self::Rpc(RpcIntErr::Decode)
+        }
+    }
+
     #[inline]
     pub fn try_as_str<'a>(&'a self) -> Result<&'a str, ()> {
         match self {
@@ -448,6 +484,36 @@ impl From<RpcIntErr> for EncodedErr {
     }
 }
 
+/// The error of [Dispatch::dispatch_req()](crate::server::dispatch::Dispatch::dispatch_req),
+/// answered to the client by RpcServer
+#[derive(Debug, thiserror::Error)]
+pub enum DispatchErr {
+    /// The request failed to decode, answered with [EncodedErr::decode_err()]
+    #[error("{0}")]
+    Decode(#[from] CodecError),
+    /// The request is decoded but not handled, answered with the error
+    #[error("{0}")]
+    Rpc(RpcIntErr),
+}
+
+impl DispatchErr {
+    /// The error to answer, `detail` refers to [EncodedErr::decode_err()]
+    #[inline]
+    pub fn to_encoded(&self, detail: bool) -> EncodedErr {
+        match self {
+            Self::Decode(e) => EncodedErr::decode_err(e, detail),
+            Self::Rpc(e) => EncodedErr::Rpc(e.clone()),
+        }
+    }
+}
+
+impl From<RpcIntErr> for DispatchErr {
+    #[inline(always)]
+    fn from(e: RpcIntErr) -> Self {
+        Self::Rpc(e)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -466,6 +532,25 @@ mod tests {
         assert!(RpcIntErr::Timeout < RpcIntErr::Method);
         assert!(RpcIntErr::IO < RpcIntErr::Method);
         assert!(RpcIntErr::Unreachable < RpcIntErr::Method);
+        assert!(RpcIntErr::Deadline > RpcIntErr::Method);
+        let e = RpcIntErr::from_str(RpcIntErr::Deadline.as_ref()).expect("parse");
+        assert_eq!(e, RpcIntErr::Deadline);
+    }
+
+    #[test]
+    fn test_decode_err_detail() {
+        let e = CodecError::decode::<u32>("invalid type");
+        assert_eq!(EncodedErr::decode_err(&e, false), EncodedErr::Rpc(RpcIntErr::Decode));
+        let encoded = EncodedErr::decode_err(&e, true);
+        let s = encoded.try_as_str().expect("str");
+        assert_eq!(s, "rpc_decode: decode u32: invalid type");
+        assert_eq!(
+            RpcIntErr::parse_detail(s),
+            Some((RpcIntErr::Decode, Some("decode u32: invalid type")))
+        );
+        assert_eq!(RpcIntErr::parse_detail("rpc_timeout"), Some((RpcIntErr::Timeout, None)));
+        assert_eq!(RpcIntErr::parse_detail("rpc_unknown: x"), None);
+        assert_eq!(RpcIntErr::parse_detail("user error"), None);
     }
 
     #[test]
@@ -68,8 +68,27 @@ extern crate captains_log;
 
 pub mod buffer;
 pub mod client;
+pub mod compress;
 pub mod error;
 pub mod proto;
 pub mod server;
 // re-export for macros, so that user don't need to use multiple crates
 pub use razor_rpc_codec::Codec;
+
+use orb::prelude::*;
+use std::{future::Future, io, time::Duration};
+
+/// Run the IO of a transport within `timeout`, zero for no timeout
+#[inline]
+pub async fn io_with_timeout<RT: AsyncRuntime, T>(
+    timeout: Duration, f: impl Future<Output = io::Result<T>> + Send,
+) -> io::Result<T> {
+    if timeout == Duration::from_secs(0) {
+        f.await
+    } else {
+        match RT::timeout(timeout, f).await {
+            Ok(r) => r,
+            Err(_) => Err(io::ErrorKind::TimedOut.into()),
+        }
+    }
+}
@@ -8,7 +8,7 @@
 //! |-----------|------|-------------------------------------------|
 //! | `magic`   | 2B   | Magic number                              |
 //! | `ver`     | 1B   | Protocol version                          |
-//! | `format`  | 1B   | Encoder-decoder format                    |
+//! | `format`  | 1B   | Codec format id, refer to [Codec::FORMAT] |
 //! | `action`  | 4B   | Action type (numeric or length if string) |
 //! | `seq`     | 8B   | Increased ID of request message           |
 //! | `client_id`| 8B   | Client identifier                         |
@@ -17,6 +17,7 @@
 //!
 //! Variable length message components:
 //! - `action_len` (if `action` is a string)
+//! - `meta_len` (since [RPC_VERSION_2], see below)
 //! - `msg_len`
 //! - `blob_len`
 //!
@@ -34,27 +35,198 @@
 //! | `blob_len`| 4B   | Unstructured message (blob) length        |
 //!
 //! Variable length message components:
+//! - `meta_len` (since [RPC_VERSION_2], see below)
 //! - `msg_len`
 //! - `blob_len`
+//!
+//! ## Extended header
+//!
+//! When `ver` >= [RPC_VERSION_2], the fixed header of both request and response is followed by
+//! `ExtHead` = 4B
+//!
+//! | Field     | Size | Description                               |
+//! |-----------|------|-------------------------------------------|
+//! | `flag`    | 1B   | Extended flags, 0 if not used             |
+//! | `compress`| 1B   | Compression id, 0 if not compressed       |
+//! | `meta_len`| 2B   | Length of the metadata section            |
+//!
+//! The metadata section is a sequence of key/value entries, refer to [RpcMeta].
+//!
+//! When [EXT_FLAG_COMPRESS_MSG] or [EXT_FLAG_COMPRESS_BLOB] is set, the msg or blob is
+//! compressed with the algorithm of `compress`, and `msg_len` / `blob_len` in the fixed header are
+//! the compressed length, refer to [crate::compress]. The compression is agreed by handshake,
+//! so a peer never receives compressed payload it does not understand.
+//!
+//! A peer only writes v2 headers when there's something to carry in the extended part,
+//! so that peers speaking only v1 can still talk to each other.
+//!
+//! ## Control frames
+//!
+//! - Ping: request with action [PING_ACTION] and empty msg, the server answers with empty response.
+//! - Cancel: request with action [CANCEL_ACTION] and empty msg, `seq` is the request to abandon.
+//! There's no response to the cancel frame itself.
+//! - GOAWAY: response with flag [RESP_FLAG_GOAWAY], `seq` is the last request accepted by the server.
+//! The server will answer the requests up to `seq` and then close the connection,
+//! the client should not send new requests on this connection.
+//! - Push: response with flag [RESP_FLAG_PUSH], sent by the server without a request when
+//! [RPC_FEATURE_PUSH] is negotiated. `seq` is numbered by the server from 1 on each connection,
+//! unrelated to the request seq. The msg and blob are carried (and compressed) like a normal
+//! response, but there's no metadata.
+//! - Stream: response with flag [RESP_FLAG_MORE] is a partial response of the request `seq`,
+//! more frames of the same `seq` follow, until a final response (without the flag) or an error.
+//! Only sent when [RPC_FEATURE_STREAM] is negotiated.
+//! The server sends at most [RPC_STREAM_WINDOW] partial responses ahead of the client,
+//! the client grants more with credit frames as it consumes them.
+//! - Credit: request with action [CREDIT_ACTION], `seq` is the request of a server-streaming,
+//! msg is the number of partial responses granted (u32 little endian). There's no response to it.
+//! - Request stream: a request flagged with [EXT_FLAG_MORE] in ExtHead opens a client-streaming,
+//! the following requests with the same `seq` and action carry one item each in msg, flagged
+//! with [EXT_FLAG_MORE] as well. The input ends with a request of the same `seq` without the flag
+//! and empty msg, the response is sent as usual. Only sent when [RPC_FEATURE_STREAM] is negotiated.
+//! The client sends at most [RPC_STREAM_WINDOW] items ahead of the server, the server grants
+//! more as the handler consumes them.
+//! - Request credit: response with flag [RESP_FLAG_CREDIT], `seq` is the request of a
+//! client-streaming, `msg_len` is the number of items granted, without msg and blob.
+//! - One-way: a request flagged with [EXT_FLAG_ONEWAY] in ExtHead expects no response, the server
+//! does not answer it, even on error. Only sent when [RPC_FEATURE_ONEWAY] is negotiated.
+//! - Chunk: response with flag [RESP_FLAG_CHUNK] carries the next part of the blob of the request
+//! `seq`, with empty msg and without ExtHead. The chunks arrive in order, followed by the final
+//! response of `seq` with meta and msg as usual and empty blob. Only sent when [RPC_FEATURE_CHUNK]
+//! is negotiated, for a blob larger than [RPC_CHUNK_SIZE], which can exceed the i32 limit of
+//! `blob_len`. The chunks are never compressed, refer to [BlobChunks]. Only the response blob is
+//! chunked, the request blob is still limited to i32. The size of the blob received is limited by
+//! the sink of the client task: `Vec<u8>` is not limited, while `io_buffer::Buffer` is limited to
+//! i32, refer to [AllocateBuf::write_chunk](crate::buffer::AllocateBuf::write_chunk).
+//!
+//! ## Handshake
+//!
+//! Optionally, right after the connection is established and before any request, the client
+//! sends a v1 request with action [HANDSHAKE_ACTION] and `seq` 0, the msg is an encoded [Handshake]
+//! listing what the client supports. The server answers with `seq` 0 and an encoded
+//! [Handshake] holding the chosen settings, refer to [Handshake::negotiate].
+//!
+//! An old server treats the handshake as an unknown action and answers with an error,
+//! in which case the client falls back to v1 behavior ([Negotiated::default]).
+//! A server which does not receive a handshake also assumes v1 behavior from the client.
+//!
+//! ## Method ids
+//!
+//! A string action like "Service.method" can be sent as a numeric id instead, to save the
+//! action bytes on the wire and the string handling on the server. The id is derived from the name
+//! by [method_id()], so both sides assign the same id without coordination.
+//!
+//! When [RPC_FEATURE_METHOD_ID] is negotiated, the server advertises the names it serves along
+//! with their ids in the handshake answer ([MethodTable]). The client only sends the ids found in
+//! the table, other actions are still sent as string.
+//!
+//! ## Checksum
+//!
+//! When [RPC_FEATURE_CHECKSUM] is negotiated, every frame after the handshake in both directions
+//! is followed by a 4B trailer, the CRC32C (little endian) over all the bytes of the frame, from
+//! the fixed header to the end of blob, refer to [FrameCrc]. The receiver closes the connection
+//! with [RpcIntErr::Checksum] on mismatch.
+//!
+//! ## Frame codec
+//!
+//! [FrameDecoder] and [FrameEncoder] implement the framing above without IO, shared by all the
+//! transports, refer to [frame].
 ///
 use crate::client::task::ClientTask;
+use crate::compress::Compressor;
+use crate::server::RpcSvrPush;
 use crate::server::task::ServerTaskEncode;
 use crate::{Codec, error::*};
+use rustc_hash::FxHashMap;
 use std::fmt;
 use std::io::Write;
 use std::mem::size_of;
 use std::ptr::addr_of;
+use std::time::Duration;
 use zerocopy::byteorder::little_endian;
 use zerocopy::{FromBytes, Immutable, IntoBytes, KnownLayout, Unaligned};
 
+pub mod frame;
+pub use frame::{BlobChunks, EncodedFrame, FrameDecoder, FrameEncoder, ReqFrame, RespFrame};
+
 pub const PING_ACTION: u32 = 0;
+/// Reserved action for the cancel frame, user should not define numeric action with i32::MAX
+pub const CANCEL_ACTION: u32 = i32::MAX as u32;
+/// Reserved action for the handshake frame, user should not define numeric action with i32::MAX - 1
+pub const HANDSHAKE_ACTION: u32 = i32::MAX as u32 - 1;
+/// Reserved action for the credit frame, user should not define numeric action with i32::MAX - 2
+pub const CREDIT_ACTION: u32 = i32::MAX as u32 - 2;
 
 pub const RPC_MAGIC: little_endian::U16 = little_endian::U16::new(19749);
 pub const U32_HIGH_MASK: u32 = 1 << 31;
 
 pub const RESP_FLAG_HAS_ERRNO: u8 = 1;
 pub const RESP_FLAG_HAS_ERR_STRING: u8 = 2;
+/// The server is draining the connection, refer to `GOAWAY` in module doc
+pub const RESP_FLAG_GOAWAY: u8 = 4;
+/// A message pushed by the server, refer to `Push` in module doc
+pub const RESP_FLAG_PUSH: u8 = 8;
+/// A partial response, more frames of the seq follow, refer to `Stream` in module doc
+pub const RESP_FLAG_MORE: u8 = 16;
+/// A part of the response blob, refer to `Chunk` in module doc
+pub const RESP_FLAG_CHUNK: u8 = 32;
+/// Credit for the items of a client-streaming, refer to `Request credit` in module doc
+pub const RESP_FLAG_CREDIT: u8 = 64;
 pub const RPC_VERSION_1: u8 = 1;
+/// Adds [ExtHead] and the metadata section
+pub const RPC_VERSION_2: u8 = 2;
+/// The highest protocol version of this build
+pub const RPC_VERSION_MAX: u8 = RPC_VERSION_2;
+
+/// Feature bit: metadata section and deadline propagation (requires [RPC_VERSION_2])
+pub const RPC_FEATURE_META: u64 = 1 << 0;
+/// Feature bit: the server accepts cancel frames
+pub const RPC_FEATURE_CANCEL: u64 = 1 << 1;
+/// Feature bit: the client understands GOAWAY
+pub const RPC_FEATURE_GOAWAY: u64 = 1 << 2;
+/// Feature bit: CRC32C trailer on every frame, refer to `Checksum` in module doc
+pub const RPC_FEATURE_CHECKSUM: u64 = 1 << 3;
+/// Feature bit: the client accepts messages pushed by the server
+pub const RPC_FEATURE_PUSH: u64 = 1 << 4;
+/// Feature bit: multiple response (or request) frames for a request, refer to `Stream` in module doc
+pub const RPC_FEATURE_STREAM: u64 = 1 << 5;
+/// Feature bit: numeric ids for the string actions, refer to `Method ids` in module doc
+pub const RPC_FEATURE_METHOD_ID: u64 = 1 << 6;
+/// Feature bit: requests without response, refer to `One-way` in module doc
+pub const RPC_FEATURE_ONEWAY: u64 = 1 << 7;
+/// Feature bit: the response blob in chunks, refer to `Chunk` in module doc
+pub const RPC_FEATURE_CHUNK: u64 = 1 << 8;
+/// All the features supported by this build
+pub const RPC_FEATURES_ALL: u64 = RPC_FEATURE_META
+    | RPC_FEATURE_CANCEL
+    | RPC_FEATURE_GOAWAY
+    | RPC_FEATURE_CHECKSUM
+    | RPC_FEATURE_PUSH
+    | RPC_FEATURE_STREAM
+    | RPC_FEATURE_METHOD_ID
+    | RPC_FEATURE_ONEWAY
+    | RPC_FEATURE_CHUNK;
+
+/// Format id of the default codec of the server, refer to [Codec::FORMAT]
+pub const CODEC_DEFAULT: u8 = razor_rpc_codec::FORMAT_DEFAULT;
+/// Compression id for no compression, always supported
+pub const COMPRESS_NONE: u8 = 0;
+
+/// ExtHead flag: msg is compressed
+pub const EXT_FLAG_COMPRESS_MSG: u8 = 1;
+/// ExtHead flag: blob is compressed
+pub const EXT_FLAG_COMPRESS_BLOB: u8 = 2;
+/// ExtHead flag: more request frames of the seq follow, refer to `Request stream` in module doc
+pub const EXT_FLAG_MORE: u8 = 4;
+/// ExtHead flag: the request expects no response, refer to `One-way` in module doc
+pub const EXT_FLAG_ONEWAY: u8 = 8;
+
+/// The initial number of partial responses the server can send ahead of the client's credit,
+/// and the items of client-streaming the client can send ahead of the server's credit
+pub const RPC_STREAM_WINDOW: u32 = 32;
+
+/// The response blob larger than this is sent in chunks of this size, when [RPC_FEATURE_CHUNK]
+/// is negotiated
+pub const RPC_CHUNK_SIZE: usize = 4 * 1024 * 1024;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RpcAction<'a> {
@@ -86,14 +258,577 @@ impl RpcActionOwned {
     }
 }
 
+/// Key/value metadata carried along with a request or a response, like trace id, auth token,
+/// or routing key.
+///
+/// On the wire, each entry is encoded as:
+///
+/// | Field     | Size      | Description              |
+/// |-----------|-----------|--------------------------|
+/// | `key_len` | 1B        | Length of key            |
+/// | `key`     | `key_len` | utf8 key                 |
+/// | `val_len` | 2B        | Length of value          |
+/// | `val`     | `val_len` | value in bytes           |
+///
+/// The total encoded size must not exceed `u16::MAX`.
+///
+/// NOTE: "rpc-" prefix of key is reserved for the framework.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RpcMeta(Vec<(String, Vec<u8>)>);
+
+/// "rpc-" prefix is reserved for metadata keys used by the framework
+pub const RPC_META_PREFIX: &'static str = "rpc-";
+
+/// Reserved metadata key for the remaining time budget of a request, see [RpcMeta::set_timeout]
+pub const RPC_META_TIMEOUT: &'static str = "rpc-timeout";
+
+impl RpcMeta {
+    #[inline]
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Insert or replace the value of key
+    #[inline]
+    pub fn insert<K: Into<String>, V: Into<Vec<u8>>>(&mut self, key: K, val: V) {
+        let key = key.into();
+        let val = val.into();
+        for entry in self.0.iter_mut() {
+            if entry.0 == key {
+                entry.1 = val;
+                return;
+            }
+        }
+        self.0.push((key, val));
+    }
+
+    #[inline]
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        for (k, v) in self.0.iter() {
+            if k == key {
+                return Some(v.as_slice());
+            }
+        }
+        None
+    }
+
+    /// Return None when key not exists or the value is not utf8
+    #[inline]
+    pub fn get_str(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(|v| str::from_utf8(v).ok())
+    }
+
+    #[inline]
+    pub fn remove(&mut self, key: &str) -> Option<Vec<u8>> {
+        if let Some(i) = self.0.iter().position(|(k, _)| k == key) {
+            return Some(self.0.remove(i).1);
+        }
+        None
+    }
+
+    /// Set the time budget of the request, encoded as u64 milliseconds.
+    ///
+    /// The budget is relative, so it's not affected by clock skew between hosts,
+    /// the receiver turns it into a deadline on arrival.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        let ms = timeout.as_millis().min(u64::MAX as u128) as u64;
+        self.insert(RPC_META_TIMEOUT, ms.to_le_bytes().to_vec());
+    }
+
+    #[inline]
+    pub fn get_timeout(&self) -> Option<Duration> {
+        let v = self.get(RPC_META_TIMEOUT)?;
+        let ms = u64::from_le_bytes(v.try_into().ok()?);
+        Some(Duration::from_millis(ms))
+    }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.0.iter().map(|(k, v)| (k.as_str(), v.as_slice()))
+    }
+
+    /// Append the encoded entries to buf, return the size written
+    pub fn encode_into(&self, buf: &mut Vec<u8>) -> Result<usize, ()> {
+        let pre_len = buf.len();
+        for (k, v) in self.0.iter() {
+            if k.len() > u8::MAX as usize {
+                error!("RpcMeta: key {:?} len cannot larger than u8", k);
+                return Err(());
+            }
+            if v.len() > u16::MAX as usize {
+                error!("RpcMeta: value of {:?} len {} cannot larger than u16", k, v.len());
+                return Err(());
+            }
+            buf.push(k.len() as u8);
+            buf.write_all(k.as_bytes()).expect("fill meta key");
+            buf.write_all(&(v.len() as u16).to_le_bytes()).expect("fill meta val_len");
+            buf.write_all(v).expect("fill meta val");
+        }
+        let meta_len = buf.len() - pre_len;
+        if meta_len > u16::MAX as usize {
+            error!("RpcMeta: encoded len {} cannot larger than u16", meta_len);
+            return Err(());
+        }
+        Ok(meta_len)
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self, ()> {
+        let mut entries = Vec::new();
+        while !buf.is_empty() {
+            let key_len = buf[0] as usize;
+            if buf.len() < 1 + key_len + 2 {
+                return Err(());
+            }
+            let key = match str::from_utf8(&buf[1..1 + key_len]) {
+                Ok(k) => k.to_string(),
+                Err(_) => return Err(()),
+            };
+            buf = &buf[1 + key_len..];
+            let val_len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+            if buf.len() < 2 + val_len {
+                return Err(());
+            }
+            entries.push((key, buf[2..2 + val_len].to_vec()));
+            buf = &buf[2 + val_len..];
+        }
+        Ok(Self(entries))
+    }
+}
+
+/// The settings supported by a peer, exchanged on connection establishment.
+///
+/// On the wire:
+///
+/// | Field           | Size              | Description                          |
+/// |-----------------|-------------------|--------------------------------------|
+/// | `version`       | 1B                | Highest protocol version supported   |
+/// | `reserved`      | 1B                | Must be 0                            |
+/// | `codec_count`   | 1B                | Number of codec format ids           |
+/// | `compress_count`| 1B                | Number of compression ids            |
+/// | `features`      | 8B                | Feature bitmap, `RPC_FEATURE_*`      |
+/// | `codecs`        | `codec_count`     | Codec format ids by preference       |
+/// | `compressions`  | `compress_count`  | Compression ids by preference        |
+/// | `methods`       | variable, optional | Method table, refer to [MethodTable] |
+///
+/// Unknown feature bits and ids are ignored by the receiver, so they can be extended freely.
+/// The method table is only written when it's not empty, an old receiver ignores it as trailing
+/// bytes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub version: u8,
+    pub features: u64,
+    /// Codec format ids in the order of preference
+    pub codecs: Vec<u8>,
+    /// Compression ids in the order of preference, [COMPRESS_NONE] is implied
+    pub compressions: Vec<u8>,
+    /// The methods served, only advertised by the server
+    pub methods: MethodTable,
+}
+
+const HANDSHAKE_FIXED_LEN: usize = 12;
+
+impl Default for Handshake {
+    /// Everything supported by this build
+    #[inline]
+    fn default() -> Self {
+        Self {
+            version: RPC_VERSION_MAX,
+            features: RPC_FEATURES_ALL,
+            codecs: vec![CODEC_DEFAULT],
+            compressions: crate::compress::supported(),
+            methods: MethodTable::default(),
+        }
+    }
+}
+
+impl Handshake {
+    /// Append the encoded handshake to buf, return the size written
+    pub fn encode_into(&self, buf: &mut Vec<u8>) -> Result<usize, ()> {
+        if self.codecs.len() > u8::MAX as usize || self.compressions.len() > u8::MAX as usize {
+            error!("Handshake: too many codecs or compressions");
+            return Err(());
+        }
+        let pre_len = buf.len();
+        buf.push(self.version);
+        buf.push(0);
+        buf.push(self.codecs.len() as u8);
+        buf.push(self.compressions.len() as u8);
+        buf.write_all(&self.features.to_le_bytes()).expect("fill features");
+        buf.write_all(&self.codecs).expect("fill codecs");
+        buf.write_all(&self.compressions).expect("fill compressions");
+        if !self.methods.is_empty() {
+            self.methods.encode_into(buf)?;
+        }
+        Ok(buf.len() - pre_len)
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, ()> {
+        if buf.len() < HANDSHAKE_FIXED_LEN {
+            return Err(());
+        }
+        let codec_count = buf[2] as usize;
+        let compress_count = buf[3] as usize;
+        if buf.len() < HANDSHAKE_FIXED_LEN + codec_count + compress_count {
+            return Err(());
+        }
+        let features = u64::from_le_bytes(buf[4..12].try_into().unwrap());
+        let codecs_end = HANDSHAKE_FIXED_LEN + codec_count;
+        let compressions_end = codecs_end + compress_count;
+        let methods = if buf.len() > compressions_end {
+            MethodTable::decode(&buf[compressions_end..])?
+        } else {
+            MethodTable::default()
+        };
+        Ok(Self {
+            version: buf[0],
+            features,
+            codecs: buf[HANDSHAKE_FIXED_LEN..codecs_end].to_vec(),
+            compressions: buf[codecs_end..compressions_end].to_vec(),
+            methods,
+        })
+    }
+
+    /// Choose the common settings of both sides, in the order of preference of `self`.
+    ///
+    /// Returns RpcIntErr::Version when there's no common codec.
+    pub fn negotiate(&self, peer: &Self) -> Result<Negotiated, RpcIntErr> {
+        let version = self.version.min(peer.version);
+        if version < RPC_VERSION_1 {
+            return Err(RpcIntErr::Version);
+        }
+        let Some(codec) = self.codecs.iter().find(|c| peer.codecs.contains(c)) else {
+            return Err(RpcIntErr::Version);
+        };
+        // Compressed payload is flagged in ExtHead
+        let compression = if version < RPC_VERSION_2 {
+            COMPRESS_NONE
+        } else {
+            self.compressions
+                .iter()
+                .find(|c| peer.compressions.contains(c))
+                .copied()
+                .unwrap_or(COMPRESS_NONE)
+        };
+        let mut features = self.features & peer.features;
+        if version < RPC_VERSION_2 {
+            features &= !RPC_FEATURE_META;
+        }
+        // Only the server advertises the methods
+        let methods = if features & RPC_FEATURE_METHOD_ID == 0 {
+            MethodTable::default()
+        } else if self.methods.is_empty() {
+            peer.methods.clone()
+        } else {
+            self.methods.clone()
+        };
+        Ok(Negotiated { version, codec: *codec, compression, features, methods })
+    }
+}
+
+/// The settings agreed by both sides of a connection.
+///
+/// The default is the v1 behavior, used when the peer does not support handshake.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negotiated {
+    pub version: u8,
+    pub codec: u8,
+    pub compression: u8,
+    pub features: u64,
+    /// The methods advertised by the server, empty unless [RPC_FEATURE_METHOD_ID] is agreed
+    pub methods: MethodTable,
+}
+
+impl Default for Negotiated {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            version: RPC_VERSION_1,
+            codec: CODEC_DEFAULT,
+            compression: COMPRESS_NONE,
+            features: 0,
+            methods: MethodTable::default(),
+        }
+    }
+}
+
+impl Negotiated {
+    /// Whether the `RPC_FEATURE_*` bit is agreed
+    #[inline(always)]
+    pub fn has(&self, feature: u64) -> bool {
+        self.features & feature == feature
+    }
+
+    /// The handshake answered by the server
+    #[inline]
+    pub fn to_handshake(&self) -> Handshake {
+        Handshake {
+            version: self.version,
+            features: self.features,
+            codecs: vec![self.codec],
+            compressions: vec![self.compression],
+            methods: self.methods.clone(),
+        }
+    }
+
+    /// The action to send for `action`, the string is replaced by its numeric id when the
+    /// server advertises it.
+    #[inline]
+    pub fn resolve_action<'a>(&self, action: RpcAction<'a>) -> RpcAction<'a> {
+        if let RpcAction::Str(name) = action {
+            if let Some(id) = self.methods.find(name) {
+                return RpcAction::Num(id);
+            }
+        }
+        action
+    }
+}
+
+/// The ids of method are in `[METHOD_ID_BASE, METHOD_ID_BASE + METHOD_ID_MASK]`, away from the
+/// reserved actions
+pub const METHOD_ID_BASE: i32 = 0x4000_0000;
+const METHOD_ID_MASK: u32 = 0x0fff_ffff;
+
+/// The stable numeric id of a method name like "Service.method" (FNV-1a hash).
+///
+/// Different names might get the same id, which is resolved by [method_ids()].
+pub const fn method_id(name: &str) -> i32 {
+    let bytes = name.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    METHOD_ID_BASE | (hash & METHOD_ID_MASK) as i32
+}
+
+/// Assign the ids to the method names by [method_id()].
+///
+/// The names sharing an id are left out, so they are always sent as string.
+pub fn method_ids<'a>(names: &[&'a str]) -> Vec<(i32, &'a str)> {
+    let mut ids: Vec<(i32, &'a str)> = names.iter().map(|name| (method_id(name), *name)).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    let mut res = Vec::with_capacity(ids.len());
+    let mut i = 0;
+    while i < ids.len() {
+        let mut j = i + 1;
+        while j < ids.len() && ids[j].0 == ids[i].0 {
+            j += 1;
+        }
+        if j == i + 1 {
+            res.push(ids[i]);
+        } else {
+            warn!("method id {:#x} is shared by {:?}, sent as string", ids[i].0, &ids[i..j]);
+        }
+        i = j;
+    }
+    res
+}
+
+/// The method names served along with their numeric ids, advertised by the server in the
+/// handshake answer, refer to `Method ids` in module doc.
+///
+/// On the wire:
+///
+/// | Field          | Size       | Description                  |
+/// |----------------|------------|------------------------------|
+/// | `method_count` | 2B         | Number of entries            |
+/// | `id`           | 4B         | Method id (little endian)    |
+/// | `name_len`     | 1B         | Length of name               |
+/// | `name`         | `name_len` | utf8 name                    |
+///
+/// The `id`, `name_len`, `name` repeat for each entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodTable(FxHashMap<i32, String>);
+
+impl MethodTable {
+    /// Build the table of the names served, refer to [method_ids()]
+    pub fn new(names: &[&str]) -> Self {
+        Self(method_ids(names).into_iter().map(|(id, name)| (id, name.to_string())).collect())
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// The name of the id
+    #[inline]
+    pub fn get(&self, id: i32) -> Option<&str> {
+        self.0.get(&id).map(|name| name.as_str())
+    }
+
+    /// The id of the name, if it's in the table
+    #[inline]
+    pub fn find(&self, name: &str) -> Option<i32> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let id = method_id(name);
+        if self.get(id)? == name { Some(id) } else { None }
+    }
+
+    fn encode_into(&self, buf: &mut Vec<u8>) -> Result<(), ()> {
+        if self.0.len() > u16::MAX as usize {
+            error!("MethodTable: too many methods");
+            return Err(());
+        }
+        let mut entries: Vec<_> = self.0.iter().collect();
+        entries.sort_unstable();
+        buf.write_all(&(entries.len() as u16).to_le_bytes()).expect("fill method count");
+        for (id, name) in entries {
+            if name.len() > u8::MAX as usize {
+                error!("MethodTable: name {:?} too long", name);
+                return Err(());
+            }
+            buf.write_all(&id.to_le_bytes()).expect("fill method id");
+            buf.push(name.len() as u8);
+            buf.write_all(name.as_bytes()).expect("fill method name");
+        }
+        Ok(())
+    }
+
+    fn decode(mut buf: &[u8]) -> Result<Self, ()> {
+        if buf.len() < 2 {
+            return Err(());
+        }
+        let count = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+        buf = &buf[2..];
+        let mut table = FxHashMap::default();
+        for _ in 0..count {
+            if buf.len() < 5 {
+                return Err(());
+            }
+            let id = i32::from_le_bytes(buf[0..4].try_into().unwrap());
+            let name_len = buf[4] as usize;
+            if buf.len() < 5 + name_len {
+                return Err(());
+            }
+            let Ok(name) = std::str::from_utf8(&buf[5..5 + name_len]) else {
+                return Err(());
+            };
+            table.insert(id, name.to_string());
+            buf = &buf[5 + name_len..];
+        }
+        Ok(Self(table))
+    }
+}
+
+/// Length of the checksum trailer
+pub const RPC_CHECKSUM_LEN: usize = 4;
+
+/// The running CRC32C of a frame, for the trailer when [RPC_FEATURE_CHECKSUM] is negotiated.
+///
+/// Does nothing when disabled (the default).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameCrc {
+    enabled: bool,
+    crc: u32,
+}
+
+impl FrameCrc {
+    #[inline(always)]
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled, crc: 0 }
+    }
+
+    #[inline(always)]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline(always)]
+    pub fn update(&mut self, data: &[u8]) {
+        if self.enabled {
+            self.crc = crc32c::crc32c_append(self.crc, data);
+        }
+    }
+
+    /// The trailer to write after the frame
+    #[inline(always)]
+    pub fn trailer(&self) -> [u8; RPC_CHECKSUM_LEN] {
+        self.crc.to_le_bytes()
+    }
+
+    /// Whether the trailer received matches the frame
+    #[inline(always)]
+    pub fn check(&self, trailer: &[u8; RPC_CHECKSUM_LEN]) -> bool {
+        u32::from_le_bytes(*trailer) == self.crc
+    }
+}
+
+/// Extended header following [ReqHead] or [RespHead], since [RPC_VERSION_2]
+#[derive(
+    FromBytes, IntoBytes, Unaligned, Immutable, KnownLayout, PartialEq, Clone, Debug, Default,
+)]
+#[repr(packed)]
+pub struct ExtHead {
+    /// Extended flags, `EXT_FLAG_*`, 0 if not used
+    pub flag: u8,
+    /// Compression id of the flagged payload, refer to [crate::compress]
+    pub compress: u8,
+    /// Length of the metadata section
+    pub meta_len: little_endian::U16,
+}
+
+pub const RPC_EXT_HEADER_LEN: usize = size_of::<ExtHead>();
+
+impl ExtHead {
+    #[inline(always)]
+    fn _write_head(buf: &mut [u8], flag: u8, compress: u8, meta_len: u16) {
+        let header = Self::mut_from_bytes(buf).expect("fill ext header");
+        header.flag = flag;
+        header.compress = compress;
+        header.meta_len.set(meta_len);
+    }
+
+    #[inline(always)]
+    pub fn decode_head(head_buf: &[u8]) -> &Self {
+        Self::ref_from_bytes(head_buf).expect("decode ext header")
+    }
+
+    #[inline(always)]
+    pub fn is_msg_compressed(&self) -> bool {
+        self.flag & EXT_FLAG_COMPRESS_MSG != 0
+    }
+
+    #[inline(always)]
+    pub fn is_blob_compressed(&self) -> bool {
+        self.flag & EXT_FLAG_COMPRESS_BLOB != 0
+    }
+}
+
 /// Fixed-length header for request
 #[derive(FromBytes, IntoBytes, Unaligned, Immutable, KnownLayout, PartialEq, Clone)]
 #[repr(packed)]
 pub struct ReqHead {
     pub magic: little_endian::U16,
     pub ver: u8,
+    /// Codec format id of msg, refer to [Codec::FORMAT]
     pub format: u8,
-    /// encoder-decoder format
 
     /// If highest bit is 0, the rest will be i32 action_num.
     ///
@@ -117,20 +852,114 @@ impl ReqHead {
     pub fn encode_ping(buf: &mut Vec<u8>, client_id: u64, seq: u64) {
         debug_assert!(buf.capacity() > RPC_REQ_HEADER_LEN);
         unsafe { buf.set_len(RPC_REQ_HEADER_LEN) };
-        Self::_write_head(buf, client_id, PING_ACTION, seq, 0, 0);
+        Self::_write_head(buf, RPC_VERSION_1, CODEC_DEFAULT, client_id, PING_ACTION, seq, 0, 0);
+    }
+
+    /// Write a cancel frame for a previous request `seq`
+    #[inline(always)]
+    pub fn encode_cancel(buf: &mut Vec<u8>, client_id: u64, seq: u64) {
+        debug_assert!(buf.capacity() > RPC_REQ_HEADER_LEN);
+        unsafe { buf.set_len(RPC_REQ_HEADER_LEN) };
+        Self::_write_head(buf, RPC_VERSION_1, CODEC_DEFAULT, client_id, CANCEL_ACTION, seq, 0, 0);
+    }
+
+    /// Write a credit frame to grant `credit` more partial responses of the request `seq`
+    #[inline]
+    pub fn encode_credit(buf: &mut Vec<u8>, client_id: u64, seq: u64, credit: u32) {
+        buf.resize(RPC_REQ_HEADER_LEN, 0);
+        buf.extend_from_slice(&credit.to_le_bytes());
+        Self::_write_head(buf, RPC_VERSION_1, CODEC_DEFAULT, client_id, CREDIT_ACTION, seq, 4, 0);
+    }
+
+    /// Decode the msg of a credit frame
+    #[inline]
+    pub fn decode_credit(msg: &[u8]) -> Result<u32, ()> {
+        match <[u8; 4]>::try_from(msg) {
+            Ok(b) => Ok(u32::from_le_bytes(b)),
+            Err(_) => Err(()),
+        }
+    }
+
+    /// Write a frame following the client-streaming request `seq`, refer to `Request stream` in
+    /// module doc. The last frame should be `more` = false with empty msg.
+    ///
+    /// The msg is compressed as a normal request.
+    #[inline]
+    pub fn encode_frame(
+        buf: &mut Vec<u8>, client_id: u64, format: u8, action: RpcAction, seq: u64, msg: &[u8],
+        more: bool, compressor: &Compressor,
+    ) -> Result<(), ()> {
+        buf.resize(RPC_REQ_HEADER_LEN + RPC_EXT_HEADER_LEN, 0);
+        let action_flag = match action {
+            RpcAction::Num(num) => num as u32,
+            RpcAction::Str(s) => {
+                buf.write_all(s.as_bytes()).expect("fill action buffer");
+                s.len() as u32 | U32_HIGH_MASK
+            }
+        };
+        let msg_start = buf.len();
+        buf.write_all(msg).expect("fill msg buffer");
+        let mut msg_len = msg.len();
+        let mut ext_flag = if more { EXT_FLAG_MORE } else { 0 };
+        let mut compress = COMPRESS_NONE;
+        if let Some(compressed_len) = compressor.compress_tail(buf, msg_start) {
+            msg_len = compressed_len;
+            ext_flag |= EXT_FLAG_COMPRESS_MSG;
+            compress = compressor.algo;
+        }
+        if msg_len > u32::MAX as usize {
+            error!("ReqHead: frame len {} cannot larger than u32", msg_len);
+            return Err(());
+        }
+        ExtHead::_write_head(
+            &mut buf[RPC_REQ_HEADER_LEN..RPC_REQ_HEADER_LEN + RPC_EXT_HEADER_LEN],
+            ext_flag,
+            compress,
+            0,
+        );
+        Self::_write_head(
+            buf,
+            RPC_VERSION_2,
+            format,
+            client_id,
+            action_flag,
+            seq,
+            msg_len as u32,
+            0,
+        );
+        Ok(())
+    }
+
+    /// Write the handshake frame, refer to `Handshake` in module doc
+    #[inline]
+    pub fn encode_handshake(buf: &mut Vec<u8>, client_id: u64, hs: &Handshake) -> Result<(), ()> {
+        buf.resize(RPC_REQ_HEADER_LEN, 0);
+        let msg_len = hs.encode_into(buf)?;
+        Self::_write_head(
+            buf,
+            RPC_VERSION_1,
+            CODEC_DEFAULT,
+            client_id,
+            HANDSHAKE_ACTION,
+            0,
+            msg_len as u32,
+            0,
+        );
+        Ok(())
     }
 
     #[inline(always)]
     fn _write_head(
-        buf: &mut Vec<u8>, client_id: u64, action: u32, seq: u64, msg_len: u32, blob_len: i32,
+        buf: &mut Vec<u8>, ver: u8, format: u8, client_id: u64, action: u32, seq: u64,
+        msg_len: u32, blob_len: i32,
     ) {
         // NOTE: We are directly init ReqHead on the buffer with unsafe, check carefully don't miss
         // a field
         let header: &mut Self =
             Self::mut_from_bytes(&mut buf[0..RPC_REQ_HEADER_LEN]).expect("fill header buf");
         header.magic = RPC_MAGIC;
-        header.ver = RPC_VERSION_1;
-        header.format = 0;
+        header.ver = ver;
+        header.format = format;
         header.action.set(action);
         header.seq.set(seq);
         header.client_id.set(client_id);
@@ -138,39 +967,96 @@ impl ReqHead {
         header.blob_len.set(blob_len as u32);
     }
 
-    /// write header, action, msg into `buf`, return reference to blob if there's any
+    /// write header, action, meta, msg into `buf`, return reference to blob if there's any
+    ///
+    /// `action` is the one of the task, or its numeric id refer to [Negotiated::resolve_action].
+    /// The v2 header is only written when the task carries metadata or compression is enabled.
+    /// The compressed blob is written into `blob_buf`.
     #[inline(always)]
     pub fn encode<'a, T, C>(
-        codec: &C, buf: &mut Vec<u8>, client_id: u64, task: &'a T,
+        codec: &C, buf: &mut Vec<u8>, client_id: u64, task: &'a T, action: RpcAction<'_>,
+        compressor: &Compressor, blob_buf: &'a mut Vec<u8>,
     ) -> Result<Option<&'a [u8]>, ()>
     where
         T: ClientTask,
         C: Codec,
     {
-        debug_assert!(buf.capacity() >= RPC_REQ_HEADER_LEN);
-        // Leave a room at the beginning of buffer for ReqHead
-        unsafe { buf.set_len(RPC_REQ_HEADER_LEN) };
+        // A client-streaming or one-way request is flagged in ExtHead
+        let more = task.sink.as_ref().map_or(false, |sink| sink.has_input());
+        let (ver, head_len) =
+            if task.meta.is_empty() && !compressor.is_enabled() && !more && !task.oneway {
+                (RPC_VERSION_1, RPC_REQ_HEADER_LEN)
+            } else {
+                (RPC_VERSION_2, RPC_REQ_HEADER_LEN + RPC_EXT_HEADER_LEN)
+            };
+        debug_assert!(buf.capacity() >= head_len);
+        // Leave a room at the beginning of buffer for ReqHead (and ExtHead)
+        unsafe { buf.set_len(head_len) };
         // But we have to write action str and encode the msg first to get message data len
         let action_flag: u32;
-        match task.get_action() {
+        match action {
             RpcAction::Num(num) => action_flag = num as u32,
             RpcAction::Str(s) => {
                 action_flag = s.len() as u32 | U32_HIGH_MASK;
                 buf.write_all(s.as_bytes()).expect("fill action buffer");
             }
         }
-        let msg_len = task.encode_req(codec, buf)?;
+        let meta_len = if ver >= RPC_VERSION_2 { task.meta.encode_into(buf)? } else { 0 };
+        let msg_start = buf.len();
+        let mut msg_len = match task.encode_req(codec, buf) {
+            Ok(len) => len,
+            Err(e) => {
+                error!("ReqHead: {}", e);
+                return Err(());
+            }
+        };
+        let mut ext_flag = if more { EXT_FLAG_MORE } else { 0 };
+        if task.oneway {
+            ext_flag |= EXT_FLAG_ONEWAY;
+        }
+        if let Some(compressed_len) = compressor.compress_tail(buf, msg_start) {
+            msg_len = compressed_len;
+            ext_flag |= EXT_FLAG_COMPRESS_MSG;
+        }
         if msg_len > u32::MAX as usize {
             error!("ReqHead: req len {} cannot larger than u32", msg_len);
             return Err(());
         }
-        let blob = task.get_req_blob();
+        let mut blob = task.get_req_blob();
+        if let Some(_blob) = blob {
+            blob_buf.clear();
+            if compressor.compress(_blob, blob_buf) {
+                blob = Some(blob_buf.as_slice());
+                ext_flag |= EXT_FLAG_COMPRESS_BLOB;
+            }
+        }
+        if ver >= RPC_VERSION_2 {
+            ExtHead::_write_head(
+                &mut buf[RPC_REQ_HEADER_LEN..RPC_REQ_HEADER_LEN + RPC_EXT_HEADER_LEN],
+                ext_flag,
+                if ext_flag & (EXT_FLAG_COMPRESS_MSG | EXT_FLAG_COMPRESS_BLOB) != 0 {
+                    compressor.algo
+                } else {
+                    COMPRESS_NONE
+                },
+                meta_len as u16,
+            );
+        }
         let blob_len = if let Some(blob) = blob { blob.len() } else { 0 };
         if blob_len > i32::MAX as usize {
             error!("ReqHead: blob_len {} cannot larger than i32", blob_len);
             return Err(());
         }
-        Self::_write_head(buf, client_id, action_flag, task.seq(), msg_len as u32, blob_len as i32);
+        Self::_write_head(
+            buf,
+            ver,
+            C::FORMAT,
+            client_id,
+            action_flag,
+            task.seq(),
+            msg_len as u32,
+            blob_len as i32,
+        );
         Ok(blob)
     }
 
@@ -181,13 +1067,19 @@ impl ReqHead {
             warn!("rpc server: wrong magic receive {:?}", head.magic);
             return Err(RpcIntErr::IO);
         }
-        if head.ver != RPC_VERSION_1 {
+        if head.ver < RPC_VERSION_1 || head.ver > RPC_VERSION_MAX {
             warn!("rpc server: version {} not supported", head.ver);
             return Err(RpcIntErr::Version);
         }
         return Ok(head);
     }
 
+    /// Whether an [ExtHead] follows this header
+    #[inline(always)]
+    pub fn has_ext(&self) -> bool {
+        self.ver >= RPC_VERSION_2
+    }
+
     #[inline]
     pub fn get_action(&self) -> Result<i32, i32> {
         if self.action & U32_HIGH_MASK == 0 {
@@ -237,6 +1129,7 @@ pub struct RespHead {
 
     /// when flag == RESP_FLAG_HAS_ERRNO: msg_len is posix errno; blob_len = 0
     /// when flag == RESP_FLAG_HAS_ERR_STRING: msg_len=0, blob_len > 0 and follow an error string
+    /// when flag == RESP_FLAG_MORE: a partial response, with msg and blob as a normal response
     pub flag: u8,
 
     /// structured msg_len or errno
@@ -251,52 +1144,157 @@ pub struct RespHead {
 pub const RPC_RESP_HEADER_LEN: usize = size_of::<RespHead>();
 
 impl RespHead {
+    /// Write header, meta, msg into `buf`, return seq and reference to blob if there's any.
+    ///
+    /// `peer_ver` is the highest protocol version seen from the peer,
+    /// the response metadata is dropped when the peer does not understand [RPC_VERSION_2].
+    /// The msg and blob are compressed by `compressor` if enabled, the compressed blob is written
+    /// into `blob_buf`. Error responses are never compressed.
+    /// The response is flagged with [RESP_FLAG_MORE] when [ServerTaskEncode::has_more()].
+    ///
+    /// When `chunk` is set and the blob of the final response is larger than [RPC_CHUNK_SIZE],
+    /// the blob is left out of the frame and returned with `true`, to be sent by [BlobChunks]
+    /// before the frame.
     #[inline]
     pub fn encode<'a, 'b, L, C, T>(
-        logger: &'b L, codec: &'b C, buf: &'b mut Vec<u8>, task: &'a mut T,
-    ) -> (u64, Option<&'a [u8]>)
+        logger: &'b L, codec: &'b C, buf: &'b mut Vec<u8>, task: &'a mut T, peer_ver: u8,
+        compressor: &Compressor, blob_buf: &'a mut Vec<u8>, chunk: bool,
+    ) -> (u64, Option<&'a [u8]>, bool)
     where
         L: captains_log::filter::Filter,
         C: Codec,
         T: ServerTaskEncode,
     {
-        debug_assert!(buf.capacity() >= RPC_RESP_HEADER_LEN);
+        debug_assert!(buf.capacity() >= RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN);
         // Leave a room at the beginning of buffer for RespHead
         unsafe { buf.set_len(RPC_RESP_HEADER_LEN) };
+        // NOTE: meta should be written before encode_resp(), which borrows the task for blob
+        let mut meta_len = if peer_ver >= RPC_VERSION_2 {
+            Self::_encode_meta::<L>(logger, buf, task.get_resp_meta())
+        } else {
+            None
+        };
+        let compressor =
+            if peer_ver >= RPC_VERSION_2 { *compressor } else { Compressor::default() };
+        if meta_len.is_none() && compressor.is_enabled() {
+            // Leave the room for ExtHead to flag the compression
+            unsafe { buf.set_len(RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN) };
+            meta_len = Some(0);
+        }
+        let msg_start = buf.len();
+        let mut ext_flag = 0;
+        let mut chunked = false;
+        let flag = if task.has_more() { RESP_FLAG_MORE } else { 0 };
         let (seq, r) = task.encode_resp(codec, buf);
-        match r {
-            Ok((msg_len, None)) => {
-                if msg_len > u32::MAX as usize {
-                    error!("write_resp: encoded msg len {} exceed u32 limit", msg_len);
-                    Self::_encode_error::<L>(logger, buf, seq, EncodedErr::Rpc(RpcIntErr::Encode));
-                } else {
-                    Self::_write_head(logger, buf, 0, seq, msg_len as u32, 0);
+        let blob = match r {
+            Ok((mut msg_len, mut blob)) => {
+                if let Some(compressed_len) = compressor.compress_tail(buf, msg_start) {
+                    msg_len = compressed_len;
+                    ext_flag |= EXT_FLAG_COMPRESS_MSG;
                 }
-                return (seq, None);
-            }
-            Ok((msg_len, Some(blob))) => {
+                if let Some(_blob) = blob {
+                    if chunk && flag == 0 && _blob.len() > RPC_CHUNK_SIZE {
+                        chunked = true;
+                    } else {
+                        blob_buf.clear();
+                        if compressor.compress(_blob, blob_buf) {
+                            blob = Some(blob_buf.as_slice());
+                            ext_flag |= EXT_FLAG_COMPRESS_BLOB;
+                        }
+                    }
+                }
+                let blob_len = match blob {
+                    Some(blob) if !chunked => blob.len(),
+                    _ => 0,
+                };
                 if msg_len > u32::MAX as usize {
                     error!("write_resp: encoded msg len {} exceed u32 limit", msg_len);
+                    ext_flag = 0;
+                    chunked = false;
                     Self::_encode_error::<L>(logger, buf, seq, EncodedErr::Rpc(RpcIntErr::Encode));
-                    return (seq, None);
-                } else if blob.len() > i32::MAX as usize {
-                    error!("write_resp: blob len {} exceed i32 limit", blob.len());
+                    None
+                } else if blob_len > i32::MAX as usize {
+                    error!("write_resp: blob len {} exceed i32 limit", blob_len);
+                    ext_flag = 0;
                     Self::_encode_error::<L>(logger, buf, seq, EncodedErr::Rpc(RpcIntErr::Encode));
-                    return (seq, None);
+                    None
+                } else {
+                    Self::_write_head::<L>(logger, buf, flag, seq, msg_len as u32, blob_len as i32);
+                    blob
                 }
-                Self::_write_head::<L>(logger, buf, 0, seq, msg_len as u32, blob.len() as i32);
-                return (seq, Some(blob));
             }
             Err(e) => {
                 Self::_encode_error::<L>(logger, buf, seq, e);
-                return (seq, None);
+                None
+            }
+        };
+        if let Some(meta_len) = meta_len {
+            Self::_write_ext(buf, ext_flag, compressor.algo, meta_len);
+        }
+        return (seq, blob, chunked);
+    }
+
+    /// Write the header of a chunk frame, refer to `Chunk` in module doc
+    #[inline(always)]
+    pub fn encode_chunk(buf: &mut [u8; RPC_RESP_HEADER_LEN], seq: u64, chunk_len: usize) {
+        debug_assert!(chunk_len <= RPC_CHUNK_SIZE);
+        let header = Self::mut_from_bytes(buf).expect("fill header");
+        header.magic = RPC_MAGIC;
+        header.ver = RPC_VERSION_1;
+        header.flag = RESP_FLAG_CHUNK;
+        header.msg_len.set(0);
+        header.seq.set(seq);
+        header.blob_len.set(chunk_len as i32);
+    }
+
+    /// Return the meta_len when there's meta written after the room of RespHead and ExtHead
+    #[inline]
+    fn _encode_meta<L>(logger: &L, buf: &mut Vec<u8>, meta: Option<&RpcMeta>) -> Option<u16>
+    where
+        L: captains_log::filter::Filter,
+    {
+        if let Some(meta) = meta {
+            if !meta.is_empty() {
+                unsafe { buf.set_len(RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN) };
+                match meta.encode_into(buf) {
+                    Ok(meta_len) => return Some(meta_len as u16),
+                    Err(_) => {
+                        logger_warn!(logger, "write_resp: drop resp meta due to encode error");
+                        unsafe { buf.set_len(RPC_RESP_HEADER_LEN) };
+                    }
+                }
             }
         }
+        None
+    }
+
+    /// Upgrade the written header to v2, and fill the ExtHead
+    #[inline(always)]
+    fn _write_ext(buf: &mut Vec<u8>, flag: u8, compress: u8, meta_len: u16) {
+        let header = Self::mut_from_bytes(&mut buf[0..RPC_RESP_HEADER_LEN]).expect("fill header");
+        header.ver = RPC_VERSION_2;
+        ExtHead::_write_head(
+            &mut buf[RPC_RESP_HEADER_LEN..RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN],
+            flag,
+            if flag != 0 { compress } else { COMPRESS_NONE },
+            meta_len,
+        );
+    }
+
+    /// Grant `credit` more items of the client-streaming request `seq`
+    #[inline]
+    pub fn encode_credit<L>(logger: &L, buf: &mut Vec<u8>, seq: u64, credit: u32)
+    where
+        L: captains_log::filter::Filter,
+    {
+        debug_assert!(buf.capacity() >= RPC_RESP_HEADER_LEN);
+        unsafe { buf.set_len(RPC_RESP_HEADER_LEN) };
+        Self::_write_head::<L>(logger, buf, RESP_FLAG_CREDIT, seq, credit, 0);
     }
 
     #[inline]
     pub fn encode_internal<'a, L>(
-        logger: &'a L, buf: &'a mut Vec<u8>, seq: u64, err: Option<RpcIntErr>,
+        logger: &'a L, buf: &'a mut Vec<u8>, seq: u64, err: Option<EncodedErr>,
     ) -> u64
     where
         L: captains_log::filter::Filter,
@@ -304,8 +1302,12 @@ impl RespHead {
         debug_assert!(buf.capacity() >= RPC_RESP_HEADER_LEN);
         // Leave a room at the beginning of buffer for RespHead
         unsafe { buf.set_len(RPC_RESP_HEADER_LEN) };
-        if let Some(e) = err {
-            Self::_encode_error::<L>(logger, buf, seq, e.into());
+        if let Some(EncodedErr::Rpc(RpcIntErr::GoAway)) = err {
+            // seq is the last accepted
+            Self::_write_head::<L>(logger, buf, RESP_FLAG_GOAWAY, seq, 0, 0);
+            return seq;
+        } else if let Some(e) = err {
+            Self::_encode_error::<L>(logger, buf, seq, e);
             return seq;
         } else {
             // ping
@@ -314,6 +1316,76 @@ impl RespHead {
         }
     }
 
+    /// Write the header and msg of a push frame into `buf`, return reference to blob if there's any.
+    ///
+    /// The msg and blob are compressed by `compressor` if enabled and `peer_ver` >= [RPC_VERSION_2],
+    /// the compressed blob is written into `blob_buf`.
+    /// Returns Err(()) when the msg or blob exceeds the length limit.
+    #[inline]
+    pub fn encode_push<'a, L>(
+        logger: &L, buf: &mut Vec<u8>, push: &'a RpcSvrPush, peer_ver: u8, compressor: &Compressor,
+        blob_buf: &'a mut Vec<u8>,
+    ) -> Result<Option<&'a [u8]>, ()>
+    where
+        L: captains_log::filter::Filter,
+    {
+        let compressor =
+            if peer_ver >= RPC_VERSION_2 { *compressor } else { Compressor::default() };
+        let head_len = if compressor.is_enabled() {
+            RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN
+        } else {
+            RPC_RESP_HEADER_LEN
+        };
+        buf.clear();
+        buf.resize(head_len, 0);
+        let mut ext_flag = 0;
+        if compressor.compress(&push.msg, buf) {
+            ext_flag |= EXT_FLAG_COMPRESS_MSG;
+        } else {
+            buf.extend_from_slice(&push.msg);
+        }
+        let msg_len = buf.len() - head_len;
+        let mut blob = push.blob.as_deref();
+        if let Some(_blob) = blob {
+            blob_buf.clear();
+            if compressor.compress(_blob, blob_buf) {
+                blob = Some(blob_buf.as_slice());
+                ext_flag |= EXT_FLAG_COMPRESS_BLOB;
+            }
+        }
+        let blob_len = if let Some(blob) = blob { blob.len() } else { 0 };
+        if msg_len > u32::MAX as usize || blob_len > i32::MAX as usize {
+            logger_warn!(logger, "write_push: msg {} or blob {} too large", msg_len, blob_len);
+            return Err(());
+        }
+        Self::_write_head::<L>(
+            logger,
+            buf,
+            RESP_FLAG_PUSH,
+            push.seq,
+            msg_len as u32,
+            blob_len as i32,
+        );
+        if compressor.is_enabled() {
+            Self::_write_ext(buf, ext_flag, compressor.algo, 0);
+        }
+        Ok(blob)
+    }
+
+    /// Write the answer to the handshake frame, refer to `Handshake` in module doc
+    #[inline]
+    pub fn encode_handshake<'a, L>(
+        logger: &'a L, buf: &'a mut Vec<u8>, seq: u64, hs: &Handshake,
+    ) -> Result<(), ()>
+    where
+        L: captains_log::filter::Filter,
+    {
+        buf.resize(RPC_RESP_HEADER_LEN, 0);
+        let msg_len = hs.encode_into(buf)?;
+        Self::_write_head::<L>(logger, buf, 0, seq, msg_len as u32, 0);
+        Ok(())
+    }
+
     #[inline(always)]
     fn _encode_error<'b, L>(logger: &'b L, buf: &'b mut Vec<u8>, seq: u64, e: EncodedErr)
     where
@@ -371,12 +1443,43 @@ impl RespHead {
             warn!("rpc server: wrong magic receive {:?}", head.magic);
             return Err(RpcIntErr::IO);
         }
-        if head.ver != RPC_VERSION_1 {
+        if head.ver < RPC_VERSION_1 || head.ver > RPC_VERSION_MAX {
             warn!("rpc server: version {} not supported", head.ver);
             return Err(RpcIntErr::Version);
         }
         return Ok(head);
     }
+
+    /// Whether an [ExtHead] follows this header
+    #[inline(always)]
+    pub fn has_ext(&self) -> bool {
+        self.ver >= RPC_VERSION_2
+    }
+
+    #[inline(always)]
+    pub fn is_goaway(&self) -> bool {
+        self.flag == RESP_FLAG_GOAWAY
+    }
+
+    #[inline(always)]
+    pub fn is_push(&self) -> bool {
+        self.flag == RESP_FLAG_PUSH
+    }
+
+    #[inline(always)]
+    pub fn is_more(&self) -> bool {
+        self.flag == RESP_FLAG_MORE
+    }
+
+    #[inline(always)]
+    pub fn is_chunk(&self) -> bool {
+        self.flag == RESP_FLAG_CHUNK
+    }
+
+    #[inline(always)]
+    pub fn is_credit(&self) -> bool {
+        self.flag == RESP_FLAG_CREDIT
+    }
 }
 
 impl fmt::Display for RespHead {
@@ -409,5 +1512,345 @@ mod tests {
     fn test_header_len() {
         assert_eq!(RPC_REQ_HEADER_LEN, 32);
         assert_eq!(RPC_RESP_HEADER_LEN, 20);
+        assert_eq!(RPC_EXT_HEADER_LEN, 4);
+    }
+
+    #[test]
+    fn test_meta() {
+        let mut meta = RpcMeta::new();
+        assert!(meta.is_empty());
+        meta.insert("trace-id", "abc");
+        meta.insert("token", vec![1u8, 2, 3]);
+        meta.insert("trace-id", "def");
+        assert_eq!(meta.len(), 2);
+        assert_eq!(meta.get_str("trace-id"), Some("def"));
+        let mut buf = Vec::new();
+        let meta_len = meta.encode_into(&mut buf).expect("encode");
+        assert_eq!(meta_len, buf.len());
+        let decoded = RpcMeta::decode(&buf).expect("decode");
+        assert_eq!(decoded, meta);
+        assert_eq!(decoded.get("token"), Some(&[1u8, 2, 3][..]));
+        assert!(RpcMeta::decode(&buf[0..buf.len() - 1]).is_err());
+        meta.remove("token");
+        assert!(meta.get("token").is_none());
+
+        assert!(meta.get_timeout().is_none());
+        meta.set_timeout(Duration::from_millis(1500));
+        assert_eq!(meta.get_timeout(), Some(Duration::from_millis(1500)));
+    }
+
+    #[test]
+    fn test_goaway() {
+        let logger = captains_log::filter::LogFilter::new();
+        let mut buf = Vec::with_capacity(RPC_RESP_HEADER_LEN);
+        RespHead::encode_internal(&logger, &mut buf, 10, Some(RpcIntErr::GoAway.into()));
+        assert_eq!(buf.len(), RPC_RESP_HEADER_LEN);
+        let head = RespHead::decode_head(&buf).expect("decode");
+        assert!(head.is_goaway());
+        assert_eq!(head.seq.get(), 10);
+        assert_eq!(head.msg_len.get(), 0);
+        assert_eq!(head.blob_len.get(), 0);
+
+        RespHead::encode_internal(&logger, &mut buf, 11, None);
+        let head = RespHead::decode_head(&buf).expect("decode");
+        assert!(!head.is_goaway());
+
+        RespHead::encode_credit(&logger, &mut buf, 12, 16);
+        assert_eq!(buf.len(), RPC_RESP_HEADER_LEN);
+        let head = RespHead::decode_head(&buf).expect("decode");
+        assert!(head.is_credit() && !head.has_ext());
+        assert_eq!(head.seq.get(), 12);
+        assert_eq!(head.msg_len.get(), 16);
+        assert_eq!(head.blob_len.get(), 0);
+    }
+
+    #[test]
+    fn test_handshake() {
+        let client = Handshake {
+            version: RPC_VERSION_MAX + 1,
+            features: RPC_FEATURES_ALL | (1 << 63),
+            codecs: vec![3, CODEC_DEFAULT],
+            compressions: vec![0xfe, COMPRESS_NONE],
+            methods: MethodTable::default(),
+        };
+        let mut buf = Vec::new();
+        let len = client.encode_into(&mut buf).expect("encode");
+        assert_eq!(len, buf.len());
+        assert_eq!(Handshake::decode(&buf).expect("decode"), client);
+        assert!(Handshake::decode(&buf[0..buf.len() - 1]).is_err());
+
+        let server = Handshake::default();
+        let negotiated = server.negotiate(&client).expect("negotiate");
+        assert_eq!(negotiated.version, RPC_VERSION_MAX);
+        assert_eq!(negotiated.codec, CODEC_DEFAULT);
+        assert_eq!(negotiated.compression, COMPRESS_NONE);
+        assert_eq!(negotiated.features, RPC_FEATURES_ALL);
+        assert!(negotiated.has(RPC_FEATURE_META | RPC_FEATURE_GOAWAY));
+        // The client verifies the answer of server
+        assert_eq!(client.negotiate(&negotiated.to_handshake()), Ok(negotiated));
+
+        let old = Handshake {
+            version: RPC_VERSION_1,
+            compressions: vec![0xfe, COMPRESS_NONE],
+            ..Default::default()
+        };
+        let negotiated = Handshake { compressions: vec![0xfe], ..Default::default() }
+            .negotiate(&old)
+            .expect("negotiate");
+        // Compression requires ExtHead
+        assert_eq!(negotiated.compression, COMPRESS_NONE);
+        assert!(negotiated.has(RPC_FEATURE_CHECKSUM));
+        assert!(!negotiated.has(RPC_FEATURE_META));
+        assert!(negotiated.has(RPC_FEATURE_CANCEL));
+
+        let other = Handshake { codecs: vec![5], ..Default::default() };
+        assert!(server.negotiate(&other).is_err());
+        assert_eq!(Negotiated::default().features, 0);
+    }
+
+    #[test]
+    fn test_method_table() {
+        assert_eq!(method_id("Svc.sum"), method_id("Svc.sum"));
+        assert_ne!(method_id("Svc.sum"), method_id("Svc.sub"));
+        for name in ["", "Svc.sum", "a.very.long.service.name.method"] {
+            let id = method_id(name);
+            assert!(id >= METHOD_ID_BASE && id < CREDIT_ACTION as i32);
+        }
+        // Duplicated names are fine, the colliding ones are left out
+        let ids = method_ids(&["Svc.sum", "Svc.sub", "Svc.sum"]);
+        assert_eq!(ids.len(), 2);
+
+        let server =
+            Handshake { methods: MethodTable::new(&["Svc.sum", "Svc.sub"]), ..Default::default() };
+        let mut buf = Vec::new();
+        server.encode_into(&mut buf).expect("encode");
+        let answer = Handshake::decode(&buf).expect("decode");
+        assert_eq!(answer, server);
+        assert!(Handshake::decode(&buf[0..buf.len() - 1]).is_err());
+
+        let negotiated = Handshake::default().negotiate(&answer).expect("negotiate");
+        assert_eq!(negotiated.methods.len(), 2);
+        assert_eq!(
+            negotiated.resolve_action(RpcAction::Str("Svc.sum")),
+            RpcAction::Num(method_id("Svc.sum"))
+        );
+        assert_eq!(negotiated.methods.get(method_id("Svc.sub")), Some("Svc.sub"));
+        // Not served by the server
+        assert_eq!(negotiated.resolve_action(RpcAction::Str("Svc.mul")), RpcAction::Str("Svc.mul"));
+        assert_eq!(negotiated.resolve_action(RpcAction::Num(3)), RpcAction::Num(3));
+
+        // The client does not support method ids
+        let old = Handshake { features: RPC_FEATURE_META, ..Default::default() };
+        let negotiated = server.negotiate(&old).expect("negotiate");
+        assert!(negotiated.methods.is_empty());
+        assert_eq!(negotiated.resolve_action(RpcAction::Str("Svc.sum")), RpcAction::Str("Svc.sum"));
+    }
+
+    #[test]
+    fn test_frame_crc() {
+        let mut buf = Vec::with_capacity(RPC_RESP_HEADER_LEN);
+        let logger = captains_log::filter::LogFilter::new();
+        RespHead::encode_internal(&logger, &mut buf, 10, None);
+        let mut crc = FrameCrc::new(true);
+        crc.update(&buf);
+        crc.update(b"blob");
+        let trailer = crc.trailer();
+
+        let mut crc = FrameCrc::new(true);
+        crc.update(&buf[0..8]);
+        crc.update(&buf[8..]);
+        crc.update(b"blob");
+        assert!(crc.check(&trailer));
+
+        buf[12] ^= 1;
+        let mut crc = FrameCrc::new(true);
+        crc.update(&buf);
+        crc.update(b"blob");
+        assert!(!crc.check(&trailer));
+
+        let mut crc = FrameCrc::default();
+        assert!(!crc.is_enabled());
+        crc.update(&buf);
+        assert_eq!(crc.trailer(), [0u8; RPC_CHECKSUM_LEN]);
+    }
+
+    #[test]
+    fn test_compress_resp() {
+        use crate::compress::{self, Compressor};
+        use crate::server::RpcSvrResp;
+        use io_buffer::Buffer;
+
+        let logger = captains_log::filter::LogFilter::new();
+        let codec = razor_rpc_codec::MsgpCodec::default();
+        let data: Vec<u8> = (0..8192).map(|i| (i % 16) as u8).collect();
+        for algo in compress::supported() {
+            let new_resp = || {
+                let mut blob = Buffer::alloc(data.len() as i32).expect("alloc");
+                blob.copy_from_slice(&data);
+                RpcSvrResp {
+                    seq: 1,
+                    msg: Some(data.clone()),
+                    blob: Some(blob),
+                    res: Some(Ok(())),
+                    meta: None,
+                    more: false,
+                }
+            };
+            let compressor = Compressor::new(algo, algo, 1024);
+            let mut buf = Vec::with_capacity(1024);
+            let mut blob_buf = Vec::new();
+            // The peer does not understand ExtHead
+            let mut resp = new_resp();
+            let (_, blob, chunked) = RespHead::encode(
+                &logger,
+                &codec,
+                &mut buf,
+                &mut resp,
+                RPC_VERSION_1,
+                &compressor,
+                &mut blob_buf,
+                true,
+            );
+            assert!(!chunked);
+            assert_eq!(blob.expect("blob"), &data[..]);
+            assert!(!RespHead::decode_head(&buf[0..RPC_RESP_HEADER_LEN]).unwrap().has_ext());
+
+            let mut resp = new_resp();
+            let (_, blob, chunked) = RespHead::encode(
+                &logger,
+                &codec,
+                &mut buf,
+                &mut resp,
+                RPC_VERSION_2,
+                &compressor,
+                &mut blob_buf,
+                true,
+            );
+            assert!(!chunked);
+            let blob = blob.expect("blob");
+            let head = RespHead::decode_head(&buf[0..RPC_RESP_HEADER_LEN]).unwrap();
+            if algo == COMPRESS_NONE {
+                assert!(!head.has_ext());
+                assert_eq!(blob, &data[..]);
+                continue;
+            }
+            assert!(head.has_ext());
+            let msg_len = head.msg_len.get() as usize;
+            assert_eq!(head.blob_len.get() as usize, blob.len());
+            let ext_start = RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN;
+            let ext = ExtHead::decode_head(&buf[RPC_RESP_HEADER_LEN..ext_start]);
+            assert!(ext.is_msg_compressed() && ext.is_blob_compressed());
+            assert_eq!(ext.compress, algo);
+            assert_eq!(buf.len(), ext_start + msg_len);
+            assert_eq!(compress::decompress(algo, &buf[ext_start..]).unwrap(), data);
+            assert_eq!(compress::decompress(algo, blob).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_encode_push() {
+        use crate::compress::{self, Compressor};
+        use io_buffer::Buffer;
+
+        let logger = captains_log::filter::LogFilter::new();
+        let data: Vec<u8> = (0..8192).map(|i| (i % 16) as u8).collect();
+        let mut blob = Buffer::alloc(data.len() as i32).expect("alloc");
+        blob.copy_from_slice(&data);
+        let push = RpcSvrPush { seq: 3, msg: data.clone(), blob: Some(blob) };
+        let mut buf = Vec::new();
+        let mut blob_buf = Vec::new();
+        for algo in compress::supported() {
+            let compressor = Compressor::new(algo, algo, 1024);
+            // The peer does not understand ExtHead
+            let blob = RespHead::encode_push(
+                &logger,
+                &mut buf,
+                &push,
+                RPC_VERSION_1,
+                &compressor,
+                &mut blob_buf,
+            )
+            .expect("encode");
+            assert_eq!(blob.expect("blob"), &data[..]);
+            let head = RespHead::decode_head(&buf[0..RPC_RESP_HEADER_LEN]).unwrap();
+            assert!(head.is_push() && !head.has_ext());
+            assert_eq!(head.seq.get(), 3);
+            assert_eq!(&buf[RPC_RESP_HEADER_LEN..], &data[..]);
+
+            let blob = RespHead::encode_push(
+                &logger,
+                &mut buf,
+                &push,
+                RPC_VERSION_2,
+                &compressor,
+                &mut blob_buf,
+            )
+            .expect("encode")
+            .expect("blob");
+            let head = RespHead::decode_head(&buf[0..RPC_RESP_HEADER_LEN]).unwrap();
+            assert!(head.is_push());
+            assert_eq!(head.blob_len.get() as usize, blob.len());
+            if algo == COMPRESS_NONE {
+                assert!(!head.has_ext());
+                assert_eq!(blob, &data[..]);
+                continue;
+            }
+            let ext_start = RPC_RESP_HEADER_LEN + RPC_EXT_HEADER_LEN;
+            let ext = ExtHead::decode_head(&buf[RPC_RESP_HEADER_LEN..ext_start]);
+            assert!(ext.is_msg_compressed() && ext.is_blob_compressed());
+            assert_eq!(ext.meta_len.get(), 0);
+            assert_eq!(buf.len(), ext_start + head.msg_len.get() as usize);
+            assert_eq!(compress::decompress(algo, &buf[ext_start..]).unwrap(), data);
+            assert_eq!(compress::decompress(algo, blob).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_encode_credit_frame() {
+        use crate::compress::{self, Compressor};
+
+        let mut buf = Vec::new();
+        ReqHead::encode_credit(&mut buf, 1, 5, 16);
+        let head = ReqHead::decode_head(&buf[0..RPC_REQ_HEADER_LEN]).unwrap();
+        assert!(!head.has_ext());
+        assert_eq!(head.get_action(), Ok(CREDIT_ACTION as i32));
+        assert_eq!(head.seq.get(), 5);
+        assert_eq!(head.msg_len.get(), 4);
+        assert_eq!(ReqHead::decode_credit(&buf[RPC_REQ_HEADER_LEN..]), Ok(16));
+        assert!(ReqHead::decode_credit(&[0u8; 3]).is_err());
+
+        let data: Vec<u8> = (0..8192).map(|i| (i % 16) as u8).collect();
+        let ext_start = RPC_REQ_HEADER_LEN + RPC_EXT_HEADER_LEN;
+        for algo in compress::supported() {
+            let compressor = Compressor::new(algo, algo, 1024);
+            let action = RpcAction::Str("Svc.sum");
+            ReqHead::encode_frame(&mut buf, 1, CODEC_DEFAULT, action, 7, &data, true, &compressor)
+                .expect("encode");
+            let head = ReqHead::decode_head(&buf[0..RPC_REQ_HEADER_LEN]).unwrap();
+            assert!(head.has_ext());
+            assert_eq!(head.get_action(), Err(7));
+            assert_eq!(head.seq.get(), 7);
+            let ext = ExtHead::decode_head(&buf[RPC_REQ_HEADER_LEN..ext_start]);
+            assert!(ext.flag & EXT_FLAG_MORE != 0);
+            assert_eq!(&buf[ext_start..ext_start + 7], b"Svc.sum");
+            let msg = &buf[ext_start + 7..];
+            assert_eq!(msg.len(), head.msg_len.get() as usize);
+            if algo == COMPRESS_NONE {
+                assert!(!ext.is_msg_compressed());
+                assert_eq!(msg, &data[..]);
+            } else {
+                assert!(ext.is_msg_compressed());
+                assert_eq!(compress::decompress(algo, msg).unwrap(), data);
+            }
+
+            // The end of input
+            ReqHead::encode_frame(&mut buf, 1, CODEC_DEFAULT, action, 7, &[], false, &compressor)
+                .expect("encode");
+            let head = ReqHead::decode_head(&buf[0..RPC_REQ_HEADER_LEN]).unwrap();
+            assert_eq!(head.msg_len.get(), 0);
+            let ext = ExtHead::decode_head(&buf[RPC_REQ_HEADER_LEN..ext_start]);
+            assert_eq!(ext.flag, 0);
+            assert_eq!(ext.compress, COMPRESS_NONE);
+        }
     }
 }
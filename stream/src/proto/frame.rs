@@ -0,0 +1,946 @@
+//! Sans-IO decoder and encoder of the frames
+//!
+//! [FrameDecoder] takes the bytes received from the connection in any size, and yields the
+//! complete request ([ReqFrame]) or response ([RespFrame]) with slices borrowed from its buffer.
+//! The checksum trailer is verified before a frame is yielded, so the payload is never decoded
+//! from corrupted bytes.
+//!
+//! [FrameEncoder] writes the responses into its buffer, the blob is left as a separated slice to
+//! avoid copy, refer to [EncodedFrame]. A large blob is split into chunk frames written ahead of
+//! the response, refer to [BlobChunks]. The requests are encoded by
+//! [ClientStream](crate::client::stream::ClientStream), the transport only seals them with
+//! [EncodedFrame::new].
+//!
+//! A transport only moves the bytes between the connection and them, for example:
+//!
+//! ``` ignore
+//! loop {
+//!     let want = decoder.want_req()?;
+//!     if want == 0 {
+//!         break;
+//!     }
+//!     conn.read_exact(decoder.read_buf(want)).await?;
+//!     decoder.advance(want);
+//! }
+//! let req = decoder.decode_req()?.expect("complete").into_req()?;
+//! ```
+
+use super::*;
+use crate::compress;
+use crate::server::RpcSvrReq;
+use io_buffer::Buffer;
+
+/// The buffer of the decoder shrinks when emptied after growing over this size (by a large blob)
+const DECODER_SHRINK_SIZE: usize = 1024 * 1024;
+
+const DECODER_INIT_SIZE: usize = 8 * 1024;
+
+/// The lengths of the sections of a frame, from the fixed header to the end of blob
+struct Layout {
+    /// Fixed header and the ExtHead
+    head_len: usize,
+    action_len: usize,
+    meta_len: usize,
+    msg_len: usize,
+    blob_len: usize,
+}
+
+impl Layout {
+    #[inline(always)]
+    fn body_end(&self) -> usize {
+        self.head_len + self.action_len + self.meta_len + self.msg_len + self.blob_len
+    }
+}
+
+/// Incremental decoder of the frames received from one connection, refer to the module doc.
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    /// The start of the bytes not yielded
+    pos: usize,
+    /// The end of the bytes received
+    end: usize,
+    /// The length of the frame yielded last time, consumed on the next call
+    last_len: usize,
+    checksum: bool,
+    /// For the decompressed msg
+    msg_buf: Vec<u8>,
+}
+
+impl Default for FrameDecoder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self {
+            buf: Vec::with_capacity(DECODER_INIT_SIZE),
+            pos: 0,
+            end: 0,
+            last_len: 0,
+            checksum: false,
+            msg_buf: Vec::new(),
+        }
+    }
+
+    /// Expect the checksum trailer on the following frames, when [RPC_FEATURE_CHECKSUM] is
+    /// negotiated.
+    #[inline(always)]
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.checksum = enabled;
+    }
+
+    /// The number of bytes received but not yielded
+    #[inline]
+    pub fn pending(&mut self) -> usize {
+        self.consume();
+        self.end - self.pos
+    }
+
+    /// Append the bytes received
+    #[inline]
+    pub fn feed(&mut self, data: &[u8]) {
+        self.read_buf(data.len()).copy_from_slice(data);
+        self.advance(data.len());
+    }
+
+    /// Return a buffer of `len` bytes after the bytes received, for the transport to read into
+    /// without copy. Call [advance()](Self::advance) with the number of bytes filled.
+    pub fn read_buf(&mut self, len: usize) -> &mut [u8] {
+        self.consume();
+        if self.pos > 0 {
+            self.buf.copy_within(self.pos..self.end, 0);
+            self.end -= self.pos;
+            self.pos = 0;
+        }
+        if self.buf.len() < self.end + len {
+            self.buf.resize(self.end + len, 0);
+        }
+        &mut self.buf[self.end..self.end + len]
+    }
+
+    /// Mark `n` bytes of [read_buf()](Self::read_buf) as received
+    #[inline]
+    pub fn advance(&mut self, n: usize) {
+        assert!(self.end + n <= self.buf.len(), "advance beyond read_buf");
+        self.end += n;
+    }
+
+    /// The number of bytes missing to complete the next request frame,
+    /// 0 when [decode_req()](Self::decode_req) is ready to yield it.
+    ///
+    /// Returns Err on a malformed header, the connection should be closed.
+    #[inline]
+    pub fn want_req(&mut self) -> Result<usize, RpcIntErr> {
+        self.consume();
+        let data = &self.buf[self.pos..self.end];
+        let total = match Self::req_layout(data)? {
+            Ok(layout) => layout.body_end() + self.trailer_len(),
+            Err(need) => need,
+        };
+        Ok(total.saturating_sub(data.len()))
+    }
+
+    /// The number of bytes missing to complete the next response frame,
+    /// 0 when [decode_resp()](Self::decode_resp) is ready to yield it.
+    ///
+    /// Returns Err on a malformed header, the connection should be closed.
+    #[inline]
+    pub fn want_resp(&mut self) -> Result<usize, RpcIntErr> {
+        self.consume();
+        let data = &self.buf[self.pos..self.end];
+        let total = match Self::resp_layout(data)? {
+            Ok(layout) => layout.body_end() + self.trailer_len(),
+            Err(need) => need,
+        };
+        Ok(total.saturating_sub(data.len()))
+    }
+
+    /// Yield the next request frame when it's complete, the frame is consumed on the next call.
+    ///
+    /// Returns Err on a malformed frame or checksum mismatch, the connection should be closed.
+    pub fn decode_req(&mut self) -> Result<Option<ReqFrame<'_>>, RpcIntErr> {
+        self.consume();
+        let data = &self.buf[self.pos..self.end];
+        let Ok(layout) = Self::req_layout(data)? else {
+            return Ok(None);
+        };
+        let Some(data) = Self::take_frame(data, &layout, self.checksum)? else {
+            return Ok(None);
+        };
+        self.last_len = data.len();
+        let head = ReqHead::decode_head(&data[..RPC_REQ_HEADER_LEN])?;
+        let ext = Self::ext_head(data, RPC_REQ_HEADER_LEN, layout.head_len);
+        let action_end = layout.head_len + layout.action_len;
+        let meta_end = action_end + layout.meta_len;
+        let msg_end = meta_end + layout.msg_len;
+        let action = match head.get_action() {
+            Ok(num) => RpcAction::Num(num),
+            Err(_) => match std::str::from_utf8(&data[layout.head_len..action_end]) {
+                Ok(s) => RpcAction::Str(s),
+                Err(_) => {
+                    warn!("decode req: action string is not utf8");
+                    return Err(RpcIntErr::Decode);
+                }
+            },
+        };
+        Ok(Some(ReqFrame {
+            head,
+            ext,
+            action,
+            meta: &data[action_end..meta_end],
+            msg: &data[meta_end..msg_end],
+            blob: &data[msg_end..msg_end + layout.blob_len],
+            msg_buf: &mut self.msg_buf,
+        }))
+    }
+
+    /// Yield the next response frame when it's complete, the frame is consumed on the next call.
+    ///
+    /// Returns Err on a malformed frame or checksum mismatch, the connection should be closed.
+    pub fn decode_resp(&mut self) -> Result<Option<RespFrame<'_>>, RpcIntErr> {
+        self.consume();
+        let data = &self.buf[self.pos..self.end];
+        let Ok(layout) = Self::resp_layout(data)? else {
+            return Ok(None);
+        };
+        let Some(data) = Self::take_frame(data, &layout, self.checksum)? else {
+            return Ok(None);
+        };
+        self.last_len = data.len();
+        let head = RespHead::decode_head(&data[..RPC_RESP_HEADER_LEN])?;
+        let ext = Self::ext_head(data, RPC_RESP_HEADER_LEN, layout.head_len);
+        let meta_end = layout.head_len + layout.meta_len;
+        let msg_end = meta_end + layout.msg_len;
+        Ok(Some(RespFrame {
+            head,
+            ext,
+            meta: &data[layout.head_len..meta_end],
+            msg: &data[meta_end..msg_end],
+            blob: &data[msg_end..msg_end + layout.blob_len],
+            msg_buf: &mut self.msg_buf,
+        }))
+    }
+
+    /// Drop the frame yielded last time
+    #[inline(always)]
+    fn consume(&mut self) {
+        if self.last_len > 0 {
+            self.pos += self.last_len;
+            self.last_len = 0;
+            if self.pos == self.end {
+                self.pos = 0;
+                self.end = 0;
+                if self.buf.len() > DECODER_SHRINK_SIZE {
+                    self.buf = Vec::with_capacity(DECODER_INIT_SIZE);
+                }
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn trailer_len(&self) -> usize {
+        if self.checksum { RPC_CHECKSUM_LEN } else { 0 }
+    }
+
+    /// Return the frame with its trailer when complete, after the checksum is verified
+    #[inline]
+    fn take_frame<'a>(
+        data: &'a [u8], layout: &Layout, checksum: bool,
+    ) -> Result<Option<&'a [u8]>, RpcIntErr> {
+        let body_end = layout.body_end();
+        let total = if checksum { body_end + RPC_CHECKSUM_LEN } else { body_end };
+        if data.len() < total {
+            return Ok(None);
+        }
+        if checksum {
+            let mut crc = FrameCrc::new(true);
+            crc.update(&data[..body_end]);
+            let trailer: &[u8; RPC_CHECKSUM_LEN] = data[body_end..total].try_into().unwrap();
+            if !crc.check(trailer) {
+                warn!("decode frame: checksum mismatch");
+                return Err(RpcIntErr::Checksum);
+            }
+        }
+        Ok(Some(&data[..total]))
+    }
+
+    #[inline(always)]
+    fn ext_head(data: &[u8], fixed_len: usize, head_len: usize) -> ExtHead {
+        if head_len > fixed_len {
+            ExtHead::decode_head(&data[fixed_len..head_len]).clone()
+        } else {
+            ExtHead::default()
+        }
+    }
+
+    /// Return Ok(Err(len)) when `len` bytes are needed to know the layout
+    fn req_layout(data: &[u8]) -> Result<Result<Layout, usize>, RpcIntErr> {
+        if data.len() < RPC_REQ_HEADER_LEN {
+            return Ok(Err(RPC_REQ_HEADER_LEN));
+        }
+        let head = ReqHead::decode_head(&data[..RPC_REQ_HEADER_LEN])?;
+        let mut layout = Layout {
+            head_len: RPC_REQ_HEADER_LEN,
+            action_len: 0,
+            meta_len: 0,
+            msg_len: head.msg_len.get() as usize,
+            blob_len: head.blob_len.get() as usize,
+        };
+        if let Err(action_len) = head.get_action() {
+            layout.action_len = action_len as usize;
+        }
+        if head.has_ext() {
+            layout.head_len += RPC_EXT_HEADER_LEN;
+            if data.len() < layout.head_len {
+                return Ok(Err(layout.head_len));
+            }
+            let ext = ExtHead::decode_head(&data[RPC_REQ_HEADER_LEN..layout.head_len]);
+            layout.meta_len = ext.meta_len.get() as usize;
+        }
+        Ok(Ok(layout))
+    }
+
+    /// Return Ok(Err(len)) when `len` bytes are needed to know the layout
+    fn resp_layout(data: &[u8]) -> Result<Result<Layout, usize>, RpcIntErr> {
+        if data.len() < RPC_RESP_HEADER_LEN {
+            return Ok(Err(RPC_RESP_HEADER_LEN));
+        }
+        let head = RespHead::decode_head(&data[..RPC_RESP_HEADER_LEN])?;
+        let blob_len = head.blob_len.get();
+        if blob_len < 0 {
+            warn!("decode resp: invalid blob_len {}", blob_len);
+            return Err(RpcIntErr::Decode);
+        }
+        let mut layout = Layout {
+            head_len: RPC_RESP_HEADER_LEN,
+            action_len: 0,
+            meta_len: 0,
+            // msg_len is the errno when flagged with RESP_FLAG_HAS_ERRNO, or the credit
+            msg_len: match head.flag {
+                RESP_FLAG_HAS_ERRNO | RESP_FLAG_CREDIT => 0,
+                _ => head.msg_len.get() as usize,
+            },
+            blob_len: blob_len as usize,
+        };
+        if head.has_ext() {
+            layout.head_len += RPC_EXT_HEADER_LEN;
+            if data.len() < layout.head_len {
+                return Ok(Err(layout.head_len));
+            }
+            let ext = ExtHead::decode_head(&data[RPC_RESP_HEADER_LEN..layout.head_len]);
+            layout.meta_len = ext.meta_len.get() as usize;
+        }
+        Ok(Ok(layout))
+    }
+}
+
+/// The length of the payload after decompression
+#[inline]
+fn payload_len(ext: &ExtHead, flag: u8, data: &[u8]) -> Result<usize, RpcIntErr> {
+    if ext.flag & flag != 0 { compress::decompressed_len(data) } else { Ok(data.len()) }
+}
+
+/// Copy the payload into `dst` of [payload_len()], decompress if flagged
+#[inline]
+fn copy_payload(ext: &ExtHead, flag: u8, data: &[u8], dst: &mut [u8]) -> Result<(), RpcIntErr> {
+    if ext.flag & flag != 0 {
+        compress::decompress_into(ext.compress, data, dst)
+    } else if data.len() == dst.len() {
+        dst.copy_from_slice(data);
+        Ok(())
+    } else {
+        Err(RpcIntErr::Decode)
+    }
+}
+
+/// Return the msg, decompressed into `buf` if flagged
+#[inline]
+fn unpack_msg<'a>(
+    ext: &ExtHead, msg: &'a [u8], buf: &'a mut Vec<u8>,
+) -> Result<&'a [u8], RpcIntErr> {
+    if msg.is_empty() || !ext.is_msg_compressed() {
+        return Ok(msg);
+    }
+    buf.resize(compress::decompressed_len(msg)?, 0);
+    compress::decompress_into(ext.compress, msg, buf)?;
+    Ok(buf)
+}
+
+#[inline]
+fn decode_meta(meta: &[u8]) -> Result<RpcMeta, RpcIntErr> {
+    if meta.is_empty() {
+        Ok(RpcMeta::new())
+    } else {
+        RpcMeta::decode(meta).map_err(|_| RpcIntErr::Decode)
+    }
+}
+
+/// A complete request frame yielded by [FrameDecoder::decode_req]
+///
+/// The payload is as received, call [into_req()](Self::into_req) to decompress and decode.
+pub struct ReqFrame<'a> {
+    pub head: &'a ReqHead,
+    /// All zero for a v1 frame
+    pub ext: ExtHead,
+    pub action: RpcAction<'a>,
+    /// The encoded metadata section, refer to [RpcMeta]
+    pub meta: &'a [u8],
+    pub msg: &'a [u8],
+    pub blob: &'a [u8],
+    msg_buf: &'a mut Vec<u8>,
+}
+
+impl<'a> ReqFrame<'a> {
+    /// Flagged with [EXT_FLAG_MORE], refer to `Request stream` in module doc
+    #[inline(always)]
+    pub fn is_more(&self) -> bool {
+        self.ext.flag & EXT_FLAG_MORE != 0
+    }
+
+    /// Flagged with [EXT_FLAG_ONEWAY], refer to `One-way` in module doc
+    #[inline(always)]
+    pub fn is_oneway(&self) -> bool {
+        self.ext.flag & EXT_FLAG_ONEWAY != 0
+    }
+
+    #[inline]
+    pub fn decode_meta(&self) -> Result<RpcMeta, RpcIntErr> {
+        decode_meta(self.meta)
+    }
+
+    /// The length of the blob after decompression
+    #[inline]
+    pub fn blob_len(&self) -> Result<usize, RpcIntErr> {
+        payload_len(&self.ext, EXT_FLAG_COMPRESS_BLOB, self.blob)
+    }
+
+    /// Copy the blob into `dst` of [blob_len()](Self::blob_len), decompress if flagged
+    #[inline]
+    pub fn copy_blob(&self, dst: &mut [u8]) -> Result<(), RpcIntErr> {
+        copy_payload(&self.ext, EXT_FLAG_COMPRESS_BLOB, self.blob, dst)
+    }
+
+    /// Return the msg, decompress if flagged
+    #[inline]
+    pub fn into_msg(self) -> Result<&'a [u8], RpcIntErr> {
+        unpack_msg(&self.ext, self.msg, self.msg_buf)
+    }
+
+    /// Decompress the payload, decode the metadata, and convert to [RpcSvrReq]
+    pub fn into_req(self) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        let Ok(meta) = self.decode_meta() else {
+            warn!("decode req meta error");
+            return Err(RpcIntErr::Decode);
+        };
+        let mut blob = None;
+        if !self.blob.is_empty() {
+            let r = match self.blob_len() {
+                Ok(len) if len <= i32::MAX as usize => match Buffer::alloc(len as i32) {
+                    Ok(mut buf) => self.copy_blob(&mut buf).map(|_| buf),
+                    Err(_) => Err(RpcIntErr::Decode),
+                },
+                _ => Err(RpcIntErr::Decode),
+            };
+            match r {
+                Ok(buf) => blob = Some(buf),
+                Err(_) => {
+                    warn!("decompress req blob error");
+                    return Err(RpcIntErr::Decode);
+                }
+            }
+        }
+        let (seq, format, action) = (self.head.seq.get(), self.head.format, self.action);
+        let (more, oneway) = (self.is_more(), self.is_oneway());
+        let Ok(msg) = self.into_msg() else {
+            warn!("decompress req msg error");
+            return Err(RpcIntErr::Decode);
+        };
+        Ok(RpcSvrReq {
+            seq,
+            action,
+            format,
+            msg,
+            blob,
+            meta,
+            deadline: None,
+            more,
+            oneway,
+            decode_err_detail: false,
+            peer: None,
+        })
+    }
+}
+
+/// A complete response frame yielded by [FrameDecoder::decode_resp]
+///
+/// The payload is as received. When flagged with [RESP_FLAG_HAS_ERR_STRING], the blob is the error
+/// string, which is never compressed.
+pub struct RespFrame<'a> {
+    pub head: &'a RespHead,
+    /// All zero for a v1 frame
+    pub ext: ExtHead,
+    /// The encoded metadata section, refer to [RpcMeta]
+    pub meta: &'a [u8],
+    pub msg: &'a [u8],
+    pub blob: &'a [u8],
+    msg_buf: &'a mut Vec<u8>,
+}
+
+impl<'a> RespFrame<'a> {
+    #[inline]
+    pub fn decode_meta(&self) -> Result<RpcMeta, RpcIntErr> {
+        decode_meta(self.meta)
+    }
+
+    /// The length of the blob after decompression
+    #[inline]
+    pub fn blob_len(&self) -> Result<usize, RpcIntErr> {
+        payload_len(&self.ext, EXT_FLAG_COMPRESS_BLOB, self.blob)
+    }
+
+    /// Copy the blob into `dst` of [blob_len()](Self::blob_len), decompress if flagged
+    #[inline]
+    pub fn copy_blob(&self, dst: &mut [u8]) -> Result<(), RpcIntErr> {
+        copy_payload(&self.ext, EXT_FLAG_COMPRESS_BLOB, self.blob, dst)
+    }
+
+    /// Return the msg, decompress if flagged
+    #[inline]
+    pub fn into_msg(self) -> Result<&'a [u8], RpcIntErr> {
+        unpack_msg(&self.ext, self.msg, self.msg_buf)
+    }
+}
+
+/// An encoded frame, to be written in the order of `head`, `blob` and `trailer`
+pub struct EncodedFrame<'a> {
+    /// The headers, action, meta and msg
+    pub head: &'a [u8],
+    pub blob: Option<&'a [u8]>,
+    /// The checksum trailer when [RPC_FEATURE_CHECKSUM] is negotiated
+    pub trailer: Option<[u8; RPC_CHECKSUM_LEN]>,
+    /// The chunk frames of the response blob, to be written before this frame
+    pub chunks: Option<BlobChunks<'a>>,
+}
+
+impl<'a> EncodedFrame<'a> {
+    /// Seal the frame with the checksum trailer if enabled
+    #[inline]
+    pub fn new(checksum: bool, head: &'a [u8], blob: Option<&'a [u8]>) -> Self {
+        let trailer = if checksum {
+            let mut crc = FrameCrc::new(true);
+            crc.update(head);
+            if let Some(blob) = blob {
+                crc.update(blob);
+            }
+            Some(crc.trailer())
+        } else {
+            None
+        };
+        Self { head, blob, trailer, chunks: None }
+    }
+
+    /// The total length to write
+    #[inline]
+    pub fn total_len(&self) -> usize {
+        self.head.len()
+            + self.blob.map_or(0, |blob| blob.len())
+            + if self.trailer.is_some() { RPC_CHECKSUM_LEN } else { 0 }
+    }
+
+    /// The parts to write in order
+    #[inline]
+    pub fn parts(&self) -> impl Iterator<Item = &[u8]> {
+        [Some(self.head), self.blob, self.trailer.as_ref().map(|t| t.as_slice())]
+            .into_iter()
+            .flatten()
+            .filter(|part| !part.is_empty())
+    }
+}
+
+/// The chunk frames of a response blob larger than [RPC_CHUNK_SIZE], refer to `Chunk` in
+/// [proto](crate::proto) doc.
+///
+/// The blob is borrowed from the task, only the header of the current chunk is written.
+pub struct BlobChunks<'a> {
+    seq: u64,
+    /// The part not yielded
+    blob: &'a [u8],
+    checksum: bool,
+    head: [u8; RPC_RESP_HEADER_LEN],
+}
+
+impl<'a> BlobChunks<'a> {
+    #[inline]
+    pub fn new(checksum: bool, seq: u64, blob: &'a [u8]) -> Self {
+        Self { seq, blob, checksum, head: [0u8; RPC_RESP_HEADER_LEN] }
+    }
+
+    /// The number of blob bytes not yielded
+    #[inline(always)]
+    pub fn remaining(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// Return the next chunk frame to write, None when the whole blob is yielded
+    #[inline]
+    pub fn next_frame(&mut self) -> Option<EncodedFrame<'_>> {
+        if self.blob.is_empty() {
+            return None;
+        }
+        let (chunk, rest) = self.blob.split_at(self.blob.len().min(RPC_CHUNK_SIZE));
+        self.blob = rest;
+        RespHead::encode_chunk(&mut self.head, self.seq, chunk.len());
+        Some(EncodedFrame::new(self.checksum, &self.head, Some(chunk)))
+    }
+}
+
+/// Encoder of the responses written to one connection, refer to the module doc.
+pub struct FrameEncoder {
+    buf: Vec<u8>,
+    /// For the compressed blob
+    blob_buf: Vec<u8>,
+    checksum: bool,
+    chunk: bool,
+}
+
+impl Default for FrameEncoder {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameEncoder {
+    pub fn new() -> Self {
+        Self { buf: Vec::with_capacity(512), blob_buf: Vec::new(), checksum: false, chunk: false }
+    }
+
+    /// Write the checksum trailer on the following frames, when [RPC_FEATURE_CHECKSUM] is
+    /// negotiated.
+    #[inline(always)]
+    pub fn set_checksum(&mut self, enabled: bool) {
+        self.checksum = enabled;
+    }
+
+    /// Send the blob larger than [RPC_CHUNK_SIZE] in chunks, when [RPC_FEATURE_CHUNK] is
+    /// negotiated.
+    #[inline(always)]
+    pub fn set_chunk(&mut self, enabled: bool) {
+        self.chunk = enabled;
+    }
+
+    /// Encode the response of the task, return with its seq, refer to [RespHead::encode].
+    ///
+    /// The chunk frames in [EncodedFrame::chunks] should be written before the frame.
+    #[inline]
+    pub fn encode_resp<'a, L, C, T>(
+        &'a mut self, logger: &L, codec: &C, task: &'a mut T, peer_ver: u8, compressor: &Compressor,
+    ) -> (u64, EncodedFrame<'a>)
+    where
+        L: captains_log::filter::Filter,
+        C: Codec,
+        T: ServerTaskEncode,
+    {
+        let (seq, blob, chunked) = RespHead::encode(
+            logger,
+            codec,
+            &mut self.buf,
+            task,
+            peer_ver,
+            compressor,
+            &mut self.blob_buf,
+            self.chunk,
+        );
+        if chunked {
+            let mut frame = EncodedFrame::new(self.checksum, &self.buf, None);
+            frame.chunks = blob.map(|blob| BlobChunks::new(self.checksum, seq, blob));
+            (seq, frame)
+        } else {
+            (seq, EncodedFrame::new(self.checksum, &self.buf, blob))
+        }
+    }
+
+    /// Encode the ping response, an error or GOAWAY, refer to [RespHead::encode_internal]
+    #[inline]
+    pub fn encode_internal<L>(
+        &mut self, logger: &L, seq: u64, err: Option<EncodedErr>,
+    ) -> EncodedFrame<'_>
+    where
+        L: captains_log::filter::Filter,
+    {
+        RespHead::encode_internal(logger, &mut self.buf, seq, err);
+        EncodedFrame::new(self.checksum, &self.buf, None)
+    }
+
+    /// Encode the credit of a client-streaming, refer to [RespHead::encode_credit]
+    #[inline]
+    pub fn encode_credit<L>(&mut self, logger: &L, seq: u64, credit: u32) -> EncodedFrame<'_>
+    where
+        L: captains_log::filter::Filter,
+    {
+        RespHead::encode_credit(logger, &mut self.buf, seq, credit);
+        EncodedFrame::new(self.checksum, &self.buf, None)
+    }
+
+    /// Encode the push frame, refer to [RespHead::encode_push]
+    #[inline]
+    pub fn encode_push<'a, L>(
+        &'a mut self, logger: &L, push: &'a RpcSvrPush, peer_ver: u8, compressor: &Compressor,
+    ) -> Result<EncodedFrame<'a>, RpcIntErr>
+    where
+        L: captains_log::filter::Filter,
+    {
+        let blob = RespHead::encode_push(
+            logger,
+            &mut self.buf,
+            push,
+            peer_ver,
+            compressor,
+            &mut self.blob_buf,
+        )
+        .map_err(|_| RpcIntErr::Encode)?;
+        Ok(EncodedFrame::new(self.checksum, &self.buf, blob))
+    }
+
+    /// Encode the answer to the handshake, which is never sealed with the checksum trailer.
+    #[inline]
+    pub fn encode_handshake<L>(
+        &mut self, logger: &L, seq: u64, hs: &Handshake,
+    ) -> Result<EncodedFrame<'_>, RpcIntErr>
+    where
+        L: captains_log::filter::Filter,
+    {
+        RespHead::encode_handshake(logger, &mut self.buf, seq, hs)
+            .map_err(|_| RpcIntErr::Encode)?;
+        Ok(EncodedFrame::new(false, &self.buf, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_req_incremental() {
+        let compressor = Compressor::default();
+        let mut buf = Vec::new();
+        let mut wire = Vec::new();
+        let action = RpcAction::Str("Svc.sum");
+        ReqHead::encode_frame(&mut buf, 1, CODEC_DEFAULT, action, 7, b"hello", true, &compressor)
+            .expect("encode");
+        wire.extend_from_slice(&buf);
+        ReqHead::encode_credit(&mut buf, 1, 9, 16);
+        wire.extend_from_slice(&buf);
+
+        let mut decoder = FrameDecoder::new();
+        // Feed byte by byte
+        for (i, b) in wire.iter().enumerate() {
+            if i < RPC_REQ_HEADER_LEN {
+                assert_eq!(decoder.want_req().unwrap(), RPC_REQ_HEADER_LEN - i);
+            }
+            assert!(decoder.want_req().unwrap() > 0);
+            assert!(decoder.decode_req().unwrap().is_none());
+            decoder.feed(&[*b]);
+            if decoder.want_req().unwrap() == 0 {
+                break;
+            }
+        }
+        let frame = decoder.decode_req().unwrap().expect("frame");
+        assert_eq!(frame.head.seq.get(), 7);
+        assert_eq!(frame.action, action);
+        assert!(frame.is_more());
+        assert!(frame.meta.is_empty() && frame.blob.is_empty());
+        assert_eq!(frame.msg, b"hello");
+        let first_len = wire.len() - buf.len();
+        // The rest of the credit frame
+        decoder.feed(&wire[first_len..]);
+        assert_eq!(decoder.want_req().unwrap(), 0);
+        let req = decoder.decode_req().unwrap().expect("frame").into_req().expect("req");
+        assert_eq!(req.seq, 9);
+        assert_eq!(req.action, RpcAction::Num(CREDIT_ACTION as i32));
+        assert_eq!(ReqHead::decode_credit(req.msg), Ok(16));
+        assert!(!req.more && req.blob.is_none() && req.meta.is_empty());
+        assert_eq!(decoder.pending(), 0);
+        assert!(decoder.decode_req().unwrap().is_none());
+
+        // Malformed header
+        let mut bad = wire.clone();
+        bad[0] = 0;
+        decoder.feed(&bad);
+        assert!(decoder.want_req().is_err());
+    }
+
+    #[test]
+    fn test_decode_req_compressed() {
+        let data: Vec<u8> = (0..8192).map(|i| (i % 16) as u8).collect();
+        let mut buf = Vec::new();
+        for algo in crate::compress::supported() {
+            let compressor = Compressor::new(algo, algo, 1024);
+            let action = RpcAction::Num(3);
+            ReqHead::encode_frame(&mut buf, 1, CODEC_DEFAULT, action, 5, &data, false, &compressor)
+                .expect("encode");
+            let mut decoder = FrameDecoder::new();
+            decoder.feed(&buf);
+            let frame = decoder.decode_req().unwrap().expect("frame");
+            assert_eq!(frame.ext.is_msg_compressed(), algo != COMPRESS_NONE);
+            let req = frame.into_req().expect("req");
+            assert_eq!(req.action, action);
+            assert_eq!(req.msg, &data[..]);
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        let logger = captains_log::filter::LogFilter::new();
+        let data: Vec<u8> = (0..100).collect();
+        let mut blob = Buffer::alloc(data.len() as i32).expect("alloc");
+        blob.copy_from_slice(&data);
+        let push = RpcSvrPush { seq: 3, msg: b"msg".to_vec(), blob: Some(blob) };
+        let mut encoder = FrameEncoder::new();
+        encoder.set_checksum(true);
+        let mut wire = Vec::new();
+        let frame = encoder
+            .encode_push(&logger, &push, RPC_VERSION_2, &Compressor::default())
+            .expect("encode");
+        let trailer = frame.trailer.expect("trailer");
+        for part in frame.parts() {
+            wire.extend_from_slice(part);
+        }
+        assert_eq!(wire.len(), frame.total_len());
+        assert_eq!(&wire[wire.len() - RPC_CHECKSUM_LEN..], &trailer);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.set_checksum(true);
+        decoder.feed(&wire[..wire.len() - 1]);
+        assert_eq!(decoder.want_resp().unwrap(), 1);
+        decoder.feed(&wire[wire.len() - 1..]);
+        let frame = decoder.decode_resp().unwrap().expect("frame");
+        assert!(frame.head.is_push());
+        assert_eq!(frame.blob, &data[..]);
+        assert_eq!(frame.into_msg().unwrap(), b"msg");
+
+        let mut decoder = FrameDecoder::new();
+        decoder.set_checksum(true);
+        let l = wire.len();
+        wire[l - RPC_CHECKSUM_LEN - 1] ^= 1;
+        decoder.feed(&wire);
+        assert!(matches!(decoder.decode_resp(), Err(RpcIntErr::Checksum)));
+    }
+
+    #[test]
+    fn test_blob_chunks() {
+        use crate::server::RpcSvrResp;
+
+        let logger = captains_log::filter::LogFilter::new();
+        let codec = razor_rpc_codec::MsgpCodec::default();
+        let data: Vec<u8> = (0..RPC_CHUNK_SIZE * 2 + 100).map(|i| (i % 251) as u8).collect();
+        let new_resp = || {
+            let mut blob = Buffer::alloc(data.len() as i32).expect("alloc");
+            blob.copy_from_slice(&data);
+            RpcSvrResp {
+                seq: 5,
+                msg: Some(b"msg".to_vec()),
+                blob: Some(blob),
+                res: Some(Ok(())),
+                meta: None,
+                more: false,
+            }
+        };
+        let mut encoder = FrameEncoder::new();
+        encoder.set_checksum(true);
+        let mut decoder = FrameDecoder::new();
+        decoder.set_checksum(true);
+
+        // Not negotiated, the blob is inline
+        let mut resp = new_resp();
+        let (_, frame) =
+            encoder.encode_resp(&logger, &codec, &mut resp, RPC_VERSION_2, &Compressor::default());
+        assert!(frame.chunks.is_none());
+        assert_eq!(frame.blob.expect("blob").len(), data.len());
+
+        encoder.set_chunk(true);
+        let mut resp = new_resp();
+        let (seq, mut frame) =
+            encoder.encode_resp(&logger, &codec, &mut resp, RPC_VERSION_2, &Compressor::default());
+        assert_eq!(seq, 5);
+        assert!(frame.blob.is_none());
+        let chunks = frame.chunks.as_mut().expect("chunks");
+        assert_eq!(chunks.remaining(), data.len());
+        let mut received = Vec::new();
+        let mut count = 0;
+        while let Some(chunk) = chunks.next_frame() {
+            for part in chunk.parts() {
+                decoder.feed(part);
+            }
+            let chunk = decoder.decode_resp().unwrap().expect("chunk");
+            assert!(chunk.head.is_chunk() && !chunk.head.has_ext());
+            assert_eq!(chunk.head.seq.get(), 5);
+            assert!(chunk.msg.is_empty() && chunk.blob.len() <= RPC_CHUNK_SIZE);
+            received.extend_from_slice(chunk.blob);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+        assert_eq!(received, data);
+        for part in frame.parts() {
+            decoder.feed(part);
+        }
+        let frame = decoder.decode_resp().unwrap().expect("frame");
+        assert_eq!(frame.head.flag, 0);
+        assert!(frame.blob.is_empty());
+        assert_eq!(frame.into_msg().unwrap(), b"msg");
+    }
+
+    #[test]
+    fn test_decode_resp() {
+        let logger = captains_log::filter::LogFilter::new();
+        let mut encoder = FrameEncoder::new();
+        let mut wire = Vec::new();
+        for err in [None, Some(RpcIntErr::Method), Some(RpcIntErr::GoAway)] {
+            let frame = encoder.encode_internal(&logger, 11, err.map(EncodedErr::Rpc));
+            assert!(frame.trailer.is_none() && frame.blob.is_none());
+            wire.extend_from_slice(frame.head);
+        }
+        // All the frames arrive at once
+        let mut decoder = FrameDecoder::new();
+        decoder.feed(&wire);
+        let frame = decoder.decode_resp().unwrap().expect("ping");
+        assert_eq!(frame.head.flag, 0);
+        assert!(frame.msg.is_empty() && frame.blob.is_empty());
+        let frame = decoder.decode_resp().unwrap().expect("error");
+        assert_eq!(frame.head.flag, RESP_FLAG_HAS_ERR_STRING);
+        assert_eq!(frame.blob, RpcIntErr::Method.as_bytes());
+        let frame = decoder.decode_resp().unwrap().expect("goaway");
+        assert!(frame.head.is_goaway());
+        assert_eq!(frame.head.seq.get(), 11);
+        assert!(decoder.decode_resp().unwrap().is_none());
+        let frame = encoder.encode_credit(&logger, 12, 16);
+        decoder.feed(frame.head);
+        let frame = decoder.decode_resp().unwrap().expect("credit");
+        assert!(frame.head.is_credit());
+        assert_eq!(frame.head.msg_len.get(), 16);
+        assert!(frame.msg.is_empty() && frame.blob.is_empty());
+        assert_eq!(decoder.pending(), 0);
+
+        let data: Vec<u8> = (0..8192).map(|i| (i % 16) as u8).collect();
+        let mut blob = Buffer::alloc(data.len() as i32).expect("alloc");
+        blob.copy_from_slice(&data);
+        let push = RpcSvrPush { seq: 3, msg: data.clone(), blob: Some(blob) };
+        for algo in crate::compress::supported() {
+            let compressor = Compressor::new(algo, algo, 1024);
+            let frame =
+                encoder.encode_push(&logger, &push, RPC_VERSION_2, &compressor).expect("encode");
+            for part in frame.parts() {
+                decoder.feed(part);
+            }
+            let frame = decoder.decode_resp().unwrap().expect("frame");
+            assert_eq!(frame.ext.is_blob_compressed(), algo != COMPRESS_NONE);
+            let mut blob = vec![0u8; frame.blob_len().unwrap()];
+            frame.copy_blob(&mut blob).unwrap();
+            assert_eq!(blob, data);
+            assert_eq!(frame.into_msg().unwrap(), &data[..]);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+//! Cancellation of in-flight requests abandoned by the client
+//!
+//! When the client gives up on a request (timeout, or the caller dropped it),
+//! a cancel frame with the seq is sent, and RpcServer will pass it to
+//! [Dispatch::cancel_req](crate::server::dispatch::Dispatch::cancel_req).
+//!
+//! A Dispatch can register a [CancelToken] for each request with [CancelRegistry],
+//! and the handler can watch the token to stop early.
+
+use rustc_hash::FxHashMap;
+use std::sync::{
+    Arc, Mutex, Weak,
+    atomic::{AtomicBool, Ordering},
+};
+
+struct CancelInner {
+    cancelled: AtomicBool,
+    /// Drop the sender to wake up all the waiters
+    tx: Mutex<Option<crossfire::MTx<()>>>,
+    rx: crossfire::MAsyncRx<()>,
+}
+
+/// A token to check whether the request is cancelled by the client
+#[derive(Clone)]
+pub struct CancelToken(Arc<CancelInner>);
+
+impl CancelToken {
+    #[inline]
+    pub fn new() -> Self {
+        let (tx, rx) = crossfire::mpmc::unbounded_async();
+        Self(Arc::new(CancelInner {
+            cancelled: AtomicBool::new(false),
+            tx: Mutex::new(Some(tx)),
+            rx,
+        }))
+    }
+
+    #[inline]
+    pub fn cancel(&self) {
+        if !self.0.cancelled.swap(true, Ordering::SeqCst) {
+            let _ = self.0.tx.lock().unwrap().take();
+        }
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wait until the token is cancelled, you can select it with the handler future.
+    pub async fn cancelled(&self) {
+        if self.is_cancelled() {
+            return;
+        }
+        // Nothing is ever sent, recv() only returns on disconnect
+        let _ = self.0.rx.recv().await;
+    }
+}
+
+impl Default for CancelToken {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "CancelToken(cancelled={})", self.is_cancelled())
+    }
+}
+
+/// Track the [CancelToken] of in-flight requests inside a connection, indexed by seq.
+///
+/// Only weak references are kept, the entry of finished requests are cleaned up lazily.
+pub struct CancelRegistry {
+    tokens: Mutex<(FxHashMap<u64, Weak<CancelInner>>, usize)>,
+}
+
+const CANCEL_GC_THRESHOLD: usize = 1024;
+
+impl CancelRegistry {
+    #[inline]
+    pub fn new() -> Self {
+        Self { tokens: Mutex::new((FxHashMap::default(), CANCEL_GC_THRESHOLD)) }
+    }
+
+    /// Create a token for the request `seq`
+    pub fn register(&self, seq: u64) -> CancelToken {
+        let token = CancelToken::new();
+        let mut guard = self.tokens.lock().unwrap();
+        let (tokens, threshold) = &mut *guard;
+        if tokens.len() >= *threshold {
+            tokens.retain(|_, t| t.strong_count() > 0);
+            *threshold = (tokens.len() * 2).max(CANCEL_GC_THRESHOLD);
+        }
+        tokens.insert(seq, Arc::downgrade(&token.0));
+        token
+    }
+
+    /// Cancel the request `seq`, return false if the request is not found or already finished
+    pub fn cancel(&self, seq: u64) -> bool {
+        let weak = self.tokens.lock().unwrap().0.remove(&seq);
+        if let Some(inner) = weak.and_then(|t| t.upgrade()) {
+            CancelToken(inner).cancel();
+            return true;
+        }
+        false
+    }
+}
+
+impl Default for CancelRegistry {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_registry() {
+        let registry = CancelRegistry::new();
+        let token1 = registry.register(1);
+        let token2 = registry.register(2);
+        assert!(!token1.is_cancelled());
+        assert!(registry.cancel(1));
+        assert!(token1.is_cancelled());
+        assert!(!token2.is_cancelled());
+        // already removed
+        assert!(!registry.cancel(1));
+        drop(token2);
+        // request finished
+        assert!(!registry.cancel(2));
+        assert!(!registry.cancel(3));
+    }
+
+    #[test]
+    fn test_cancel_registry_gc() {
+        let registry = CancelRegistry::new();
+        let token = registry.register(0);
+        for seq in 1..(CANCEL_GC_THRESHOLD as u64 * 3) {
+            let _ = registry.register(seq);
+        }
+        assert!(registry.tokens.lock().unwrap().0.len() < CANCEL_GC_THRESHOLD * 2);
+        assert!(registry.cancel(0));
+        assert!(token.is_cancelled());
+    }
+}
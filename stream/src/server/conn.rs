@@ -0,0 +1,280 @@
+//! The protocol state of a server connection, shared by the transports on a byte stream
+//!
+//! [ConnProto] keeps the [FrameDecoder] of the reader, the [FrameEncoder] of the writer, and the
+//! settings agreed by the handshake. The transport only owns the stream: it implements
+//! [ConnIO] for itself, and forwards the methods of [ServerTransport](super::ServerTransport)
+//! to ConnProto, for example:
+//!
+//! ``` ignore
+//! async fn read_req<'a>(
+//!     &'a self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+//! ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+//!     self.proto.read_req::<RT, _>(logger, self, close_ch).await
+//! }
+//!
+//! async fn write_resp<T: ServerTaskEncode>(
+//!     &self, logger: &LogFilter, codec: &impl Codec, task: T,
+//! ) -> io::Result<()> {
+//!     self.proto.write_resp(logger, self, codec, task).await
+//! }
+//! ```
+
+use super::{RpcSvrPush, RpcSvrReq, ServerConfig, task::ServerTaskEncode};
+use crate::proto::{self, EncodedFrame, FrameDecoder, FrameEncoder, Negotiated};
+use crate::{Codec, compress, error::*, io_with_timeout};
+use captains_log::filter::LogFilter;
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::{fmt, future::Future, io};
+
+/// Move the bytes of a connection, implemented by the transport.
+///
+/// The read side is only used by the reader coroutine, and the write side by the writer.
+pub trait ConnIO: fmt::Debug + Sync {
+    /// Fill the whole `buf`, without timeout
+    fn read_exact(&self, buf: &mut [u8]) -> impl Future<Output = io::Result<()>> + Send;
+
+    /// Write all the [parts()](EncodedFrame::parts) of the frame within the write_timeout,
+    /// without flush
+    fn write_frame(&self, frame: &EncodedFrame<'_>) -> impl Future<Output = io::Result<()>> + Send;
+}
+
+/// The protocol state of one connection, refer to the module doc.
+pub struct ConnProto {
+    config: ServerConfig,
+    /// for read
+    decoder: UnsafeCell<FrameDecoder>,
+    /// highest protocol version received from the client or negotiated by handshake,
+    /// decide whether to write resp meta
+    peer_ver: AtomicU8,
+    /// compression negotiated by handshake
+    compression: AtomicU8,
+    /// CRC32C trailer negotiated by handshake
+    checksum: AtomicBool,
+    /// Blob chunks negotiated by handshake
+    chunk: AtomicBool,
+    /// for write
+    encoder: UnsafeCell<FrameEncoder>,
+}
+
+// The decoder is only used by the reader coroutine, and the encoder by the writer coroutine
+unsafe impl Send for ConnProto {}
+
+unsafe impl Sync for ConnProto {}
+
+impl ConnProto {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            decoder: UnsafeCell::new(FrameDecoder::new()),
+            peer_ver: AtomicU8::new(proto::RPC_VERSION_1),
+            compression: AtomicU8::new(compress::COMPRESS_NONE),
+            checksum: AtomicBool::new(false),
+            chunk: AtomicBool::new(false),
+            encoder: UnsafeCell::new(FrameEncoder::new()),
+        }
+    }
+
+    #[inline(always)]
+    pub fn config(&self) -> &ServerConfig {
+        &self.config
+    }
+
+    /// The decoder of the reader, for the transport reading on its own.
+    ///
+    /// Call [decode_req()](Self::decode_req) when the decoder has a complete frame.
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    pub fn get_decoder(&self) -> &mut FrameDecoder {
+        let decoder = unsafe { &mut *self.decoder.get() };
+        decoder.set_checksum(self.checksum.load(Ordering::Relaxed));
+        decoder
+    }
+
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_encoder(&self) -> &mut FrameEncoder {
+        let encoder = unsafe { &mut *self.encoder.get() };
+        encoder.set_checksum(self.checksum.load(Ordering::Relaxed));
+        encoder.set_chunk(self.chunk.load(Ordering::Relaxed));
+        encoder
+    }
+
+    #[inline(always)]
+    fn get_compressor(&self) -> compress::Compressor {
+        compress::Compressor::new(
+            self.config.compression,
+            self.compression.load(Ordering::Relaxed),
+            self.config.compress_threshold,
+        )
+    }
+
+    /// Read a request from the connection.
+    ///
+    /// Waits for the next request within idle_timeout, returns RpcIntErr::Timeout on idle or
+    /// when `close_ch` is closed. The rest of the request is read within read_timeout.
+    ///
+    /// NOTE: you should consume the buffer ref before recv another request.
+    pub async fn read_req<'a, RT: AsyncRuntime, S: ConnIO>(
+        &'a self, logger: &LogFilter, conn: &S, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        let decoder = self.get_decoder();
+        loop {
+            let want = match decoder.want_req() {
+                Ok(want) => want,
+                Err(e) => {
+                    logger_warn!(logger, "{:?}: decode_head error, {}", conn, e);
+                    return Err(e);
+                }
+            };
+            if want == 0 {
+                break;
+            }
+            // Wait for the next request within idle_timeout
+            let idle = decoder.pending() == 0;
+            let buf = decoder.read_buf(want);
+            if idle {
+                let cancel_f = close_ch.recv_with_timer(RT::sleep(self.config.idle_timeout));
+                match Cancellable::new(conn.read_exact(buf), cancel_f).await {
+                    Ok(Err(e)) => {
+                        logger_debug!(logger, "{:?}: recv_req: err {}", conn, e);
+                        return Err(RpcIntErr::IO);
+                    }
+                    Err(()) => {
+                        logger_trace!(logger, "{:?}: read timeout", conn);
+                        return Err(RpcIntErr::Timeout);
+                    }
+                    _ => {}
+                }
+            } else if let Err(e) =
+                io_with_timeout::<RT, _>(self.config.read_timeout, conn.read_exact(buf)).await
+            {
+                logger_trace!(logger, "{:?}: read req error: {}", conn, e);
+                return Err(RpcIntErr::IO);
+            }
+            decoder.advance(want);
+        }
+        self.decode_req(logger, conn)
+    }
+
+    /// Yield the complete request in the decoder, and track the protocol version of the client
+    pub fn decode_req<'a>(
+        &'a self, logger: &LogFilter, conn: &impl fmt::Debug,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        let frame = match self.get_decoder().decode_req() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_warn!(logger, "{:?}: decode req error, {}", conn, e);
+                return Err(e);
+            }
+        };
+        logger_trace!(logger, "{:?}: recv req: {}", conn, frame.head);
+        if frame.head.has_ext() && self.peer_ver.load(Ordering::Relaxed) < frame.head.ver {
+            self.peer_ver.store(frame.head.ver, Ordering::Relaxed);
+        }
+        match frame.into_req() {
+            Ok(req) => Ok(req),
+            Err(e) => {
+                logger_warn!(logger, "{:?}: decode req payload error", conn);
+                Err(e)
+            }
+        }
+    }
+
+    /// Write the response of the task, the chunks of the blob first
+    pub async fn write_resp<S: ConnIO, T: ServerTaskEncode>(
+        &self, logger: &LogFilter, conn: &S, codec: &impl Codec, mut task: T,
+    ) -> io::Result<()> {
+        let peer_ver = self.peer_ver.load(Ordering::Relaxed);
+        let compressor = self.get_compressor();
+        let (seq, mut frame) =
+            self.get_encoder().encode_resp(&logger, codec, &mut task, peer_ver, &compressor);
+        if let Some(chunks) = frame.chunks.as_mut() {
+            while let Some(chunk) = chunks.next_frame() {
+                if let Err(e) = conn.write_frame(&chunk).await {
+                    logger_warn!(logger, "{:?}: write chunk seq={} err: {}", conn, seq, e);
+                    return Err(e);
+                }
+            }
+        }
+        if let Err(e) = conn.write_frame(&frame).await {
+            logger_warn!(logger, "{:?}: send_resp write resp seq={} err: {}", conn, seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send resp seq={}", conn, seq);
+        Ok(())
+    }
+
+    /// Write the answer to the handshake and apply the negotiated settings, without flush.
+    ///
+    /// Called by the writer coroutine, while the reader waits for it.
+    pub async fn write_handshake<S: ConnIO>(
+        &self, logger: &LogFilter, conn: &S, seq: u64, negotiated: &Negotiated,
+    ) -> io::Result<()> {
+        let Ok(frame) =
+            self.get_encoder().encode_handshake(&logger, seq, &negotiated.to_handshake())
+        else {
+            return Err(io::ErrorKind::InvalidInput.into());
+        };
+        if let Err(e) = conn.write_frame(&frame).await {
+            logger_warn!(logger, "{:?}: write handshake err: {}", conn, e);
+            return Err(e);
+        }
+        self.peer_ver.store(negotiated.version, Ordering::Relaxed);
+        self.compression.store(negotiated.compression, Ordering::Relaxed);
+        self.checksum.store(negotiated.has(proto::RPC_FEATURE_CHECKSUM), Ordering::Relaxed);
+        self.chunk.store(negotiated.has(proto::RPC_FEATURE_CHUNK), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Write a message pushed by the server, the push failing to encode is dropped
+    pub async fn write_push<S: ConnIO>(
+        &self, logger: &LogFilter, conn: &S, push: RpcSvrPush,
+    ) -> io::Result<()> {
+        let peer_ver = self.peer_ver.load(Ordering::Relaxed);
+        let compressor = self.get_compressor();
+        let frame = match self.get_encoder().encode_push(&logger, &push, peer_ver, &compressor) {
+            Ok(frame) => frame,
+            Err(_) => {
+                // Drop the push, the connection is still healthy
+                logger_warn!(logger, "{:?}: drop push seq={} encode err", conn, push.seq);
+                return Ok(());
+            }
+        };
+        if let Err(e) = conn.write_frame(&frame).await {
+            logger_warn!(logger, "{:?}: write push seq={} err: {}", conn, push.seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send push seq={}", conn, push.seq);
+        Ok(())
+    }
+
+    /// Grant the client `credit` more items of the client-streaming request `seq`
+    pub async fn write_credit<S: ConnIO>(
+        &self, logger: &LogFilter, conn: &S, seq: u64, credit: u32,
+    ) -> io::Result<()> {
+        let frame = self.get_encoder().encode_credit(&logger, seq, credit);
+        if let Err(e) = conn.write_frame(&frame).await {
+            logger_warn!(logger, "{:?}: write credit seq={} err: {}", conn, seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send credit seq={} {}", conn, seq, credit);
+        Ok(())
+    }
+
+    /// Write the ping response, an error or GOAWAY
+    pub async fn write_resp_internal<S: ConnIO>(
+        &self, logger: &LogFilter, conn: &S, seq: u64, err: Option<EncodedErr>,
+    ) -> io::Result<()> {
+        let frame = self.get_encoder().encode_internal(&logger, seq, err);
+        if let Err(e) = conn.write_frame(&frame).await {
+            logger_warn!(logger, "{:?}: send_resp write resp seq={} err: {}", conn, seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send resp seq={}", conn, seq);
+        Ok(())
+    }
+}
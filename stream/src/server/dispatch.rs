@@ -1,15 +1,21 @@
 use super::RpcSvrReq;
+use super::push::ServerPush;
 use super::task::*;
 use crate::Codec;
+use crate::error::{DispatchErr, RpcIntErr};
 use std::marker::PhantomData;
 use std::sync::Arc;
+use std::time::Instant;
 
 /// Dispatch should be a user-defined struct initialized for every connection, by ServerFacts::new_dispatcher.
 ///
 /// Dispatch must have Sync, because the connection reader and writer access concurrently.
 ///
-/// A `Codec` should be created and holds inside, shared by the read/write coroutine.
+/// The `Codec` instances are created per connection, shared by the read/write coroutine.
 /// If you have encryption in the Codec, it could have shared states.
+///
+/// To accept clients with different codecs, define `Codec` with multiple formats, refer to
+/// [CodecRegistry](crate::server::registry::CodecRegistry).
 pub trait Dispatch: Send + Sync + Sized + Clone + 'static {
     type RespTask: ServerTaskResp;
 
@@ -21,10 +27,60 @@ pub trait Dispatch: Send + Sync + Sized + Clone + 'static {
     /// If you are processing them directly in the connection coroutine, should make sure not
     /// blocking the thread for long.
     /// This is an async fn, but you should avoid waiting as much as possible.
-    /// Should return Err(()) when codec decode_req failed.
+    /// Should return Err when codec decode_req failed, RpcServer answers RpcIntErr::Decode,
+    /// with the detail when [ServerConfig::decode_err_detail](crate::server::ServerConfig) is set.
+    /// Return [DispatchErr::Rpc] to answer other errors of the request not handled.
+    ///
+    /// `codec` is selected by the format id in the request header.
     fn dispatch_req<'a>(
         &'a self, codec: &Arc<Self::Codec>, req: RpcSvrReq<'a>, noti: RespNoti<Self::RespTask>,
-    ) -> impl Future<Output = Result<(), ()>> + Send;
+    ) -> impl Future<Output = Result<(), DispatchErr>> + Send;
+
+    /// Called from the connection reader coroutine when the client abandons the request `seq`.
+    ///
+    /// The response of the request is still expected (with result or an error),
+    /// but the client will discard it. By default, it's ignored.
+    /// Refer to [CancelRegistry](crate::server::cancel::CancelRegistry).
+    #[inline]
+    fn cancel_req(&self, _seq: u64) {}
+
+    /// Called from the connection reader coroutine when the client grants `credit` more partial
+    /// responses of the server-streaming request `seq`. By default, it's ignored.
+    ///
+    /// Refer to [StreamRegistry](crate::server::streaming::StreamRegistry).
+    #[inline]
+    fn credit_req(&self, _seq: u64, _credit: u32) {}
+
+    /// Called from the connection reader coroutine for each frame following the client-streaming
+    /// request `seq` (which is dispatched with [RpcSvrReq::more]), `msg` is None on the end of
+    /// input. By default, the frames are dropped.
+    ///
+    /// Waiting here will stop reading from the connection, which is the back-pressure to
+    /// the client. Refer to [StreamRegistry](crate::server::streaming::StreamRegistry).
+    #[inline]
+    fn recv_frame<'a>(
+        &'a self, _seq: u64, _msg: Option<&'a [u8]>,
+    ) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+
+    /// Called once for each connection before reading requests, with the handle to push messages
+    /// to the client. By default, it's dropped.
+    ///
+    /// Refer to [ServerPush].
+    #[inline]
+    fn set_push(&mut self, _push: ServerPush<Self::Codec>) {}
+
+    /// The names of the string actions served, advertised to the client with their numeric ids
+    /// by handshake, refer to [MethodTable](crate::proto::MethodTable).
+    ///
+    /// The requests of them might carry [RpcAction::Num](crate::proto::RpcAction) with the id
+    /// from [method_id()](crate::proto::method_id) instead of the string.
+    /// By default there's none, the client always sends the string.
+    #[inline]
+    fn methods(&self) -> &[&'static str] {
+        &[]
+    }
 }
 
 /// A Dispatch trait impl with a closure, only useful for writing tests.
@@ -106,7 +162,10 @@ where
     #[inline]
     async fn dispatch_req<'a>(
         &'a self, codec: &Arc<Self::Codec>, req: RpcSvrReq<'a>, noti: RespNoti<R>,
-    ) -> Result<(), ()> {
+    ) -> Result<(), DispatchErr> {
+        if req.deadline.is_some_and(|d| d <= Instant::now()) {
+            return Err(RpcIntErr::Deadline.into());
+        }
         match <T as ServerTaskDecode<R>>::decode_req(
             codec.as_ref(),
             req.action,
@@ -115,15 +174,15 @@ where
             req.blob,
             noti,
         ) {
-            Err(_) => {
-                error!("action {:?} seq={} decode err", req.action, req.seq);
-                return Err(());
+            Err(e) => {
+                error!("action {:?} seq={} decode err: {}", req.action, req.seq, e);
+                return Err(e.into());
             }
             Ok(task) => {
                 let handle = self.task_handle.clone();
                 if let Err(_) = (handle)(task).await {
                     error!("action {:?} seq={} dispatch err", req.action, req.seq);
-                    return Err(());
+                    return Err(RpcIntErr::Internal.into());
                 }
                 Ok(())
             }
@@ -1,12 +1,12 @@
 //! This module contains traits defined for the server-side
 //!
 
-use crate::proto::RpcAction;
+use crate::proto::{Negotiated, RpcAction, RpcMeta};
 use crate::{Codec, error::*};
 use captains_log::filter::LogFilter;
 use io_buffer::Buffer;
 use orb::prelude::*;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fmt, future::Future, io, sync::Arc};
 
 pub mod task;
@@ -20,6 +20,16 @@ pub mod graceful;
 pub mod dispatch;
 use dispatch::Dispatch;
 
+pub mod cancel;
+
+pub mod registry;
+
+pub mod push;
+
+pub mod streaming;
+
+pub mod conn;
+
 /// General config for server-side
 #[derive(Clone)]
 pub struct ServerConfig {
@@ -33,6 +43,19 @@ pub struct ServerConfig {
     pub server_close_wait: Duration,
     /// In bytes. when non-zero, overwrite the default DEFAULT_BUF_SIZE of transport
     pub stream_buf_size: usize,
+    /// Compress the responses with this algorithm, when the client supports it.
+    ///
+    /// Defaults to [COMPRESS_NONE](crate::compress::COMPRESS_NONE), refer to [crate::compress]
+    /// for the cargo features.
+    pub compression: u8,
+    /// In bytes. Only the msg or blob not smaller than this is compressed
+    pub compress_threshold: usize,
+    /// Reply the detail of CodecError (the message type and serde error) along with
+    /// RpcIntErr::Decode when a request fails to decode, like "rpc_decode: decode u32: ...".
+    ///
+    /// Helps debugging the clients in other languages, but exposes the type names.
+    /// Defaults to false.
+    pub decode_err_detail: bool,
 }
 
 impl Default for ServerConfig {
@@ -43,6 +66,9 @@ impl Default for ServerConfig {
             idle_timeout: Duration::from_secs(120),
             server_close_wait: Duration::from_secs(90),
             stream_buf_size: 0,
+            compression: crate::compress::COMPRESS_NONE,
+            compress_threshold: 4096,
+            decode_err_detail: false,
         }
     }
 }
@@ -68,14 +94,20 @@ pub trait ServerFacts: orb::AsyncRuntime + Sync + Send + 'static + Sized {
 /// The implementation can be found on:
 ///
 /// - [razor-rpc-tcp](https://docs.rs/razor-rpc-tcp): For TCP and Unix socket
+/// - [razor-rpc-tls](https://docs.rs/razor-rpc-tls): For TLS over TCP and Unix socket
+/// - [razor-rpc-quic](https://docs.rs/razor-rpc-quic): For QUIC
+/// - [razor-rpc-mem](https://docs.rs/razor-rpc-mem): For the client in the same process
+/// - [razor-rpc-shm](https://docs.rs/razor-rpc-shm): For the processes on the same host, by shared memory
+/// - [razor-rpc-ws](https://docs.rs/razor-rpc-ws): For WebSocket, through the HTTP proxies
 pub trait ServerTransport: Send + Sync + Sized + 'static + fmt::Debug {
-    type Listener: AsyncListener;
+    type Listener: ServerListener;
 
     fn bind(addr: &str) -> impl Future<Output = io::Result<Self::Listener>> + Send;
 
     /// The implementation is expected to store the conn_count until dropped
     fn new_conn(
-        stream: <Self::Listener as AsyncListener>::Conn, config: &ServerConfig, conn_count: Arc<()>,
+        stream: <Self::Listener as ServerListener>::Conn, config: &ServerConfig,
+        conn_count: Arc<()>,
     ) -> Self;
 
     /// Read a request from the socket
@@ -88,9 +120,43 @@ pub trait ServerTransport: Send + Sync + Sized + 'static + fmt::Debug {
         &self, logger: &LogFilter, codec: &impl Codec, task: T,
     ) -> impl Future<Output = io::Result<()>> + Send;
 
+    /// Answer the handshake from the client, and apply the negotiated settings on the connection.
+    ///
+    /// Called by the writer coroutine when the first frame is a handshake, the reader waits for
+    /// the answer to be written before reading the next frame.
+    ///
+    /// By default, the transport does not support handshake and returns `Unsupported`,
+    /// the client will receive an error and fall back to v1 behavior.
+    fn write_handshake(
+        &self, _logger: &LogFilter, _seq: u64, _negotiated: &Negotiated,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Write a message pushed by the server, refer to [push].
+    ///
+    /// Called by the writer coroutine, only when push is negotiated by the handshake.
+    /// By default, the transport does not support push and returns `Unsupported`.
+    fn write_push(
+        &self, _logger: &LogFilter, _push: RpcSvrPush,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
+    /// Grant the client `credit` more items of the client-streaming request `seq`, refer to
+    /// [StreamRegistry](streaming::StreamRegistry).
+    ///
+    /// Called by the writer coroutine, only when stream is negotiated by the handshake.
+    /// By default, the transport does not support it and returns `Unsupported`.
+    fn write_credit(
+        &self, _logger: &LogFilter, _seq: u64, _credit: u32,
+    ) -> impl Future<Output = io::Result<()>> + Send {
+        async { Err(io::ErrorKind::Unsupported.into()) }
+    }
+
     /// Write out ping resp or error
     fn write_resp_internal(
-        &self, logger: &LogFilter, seq: u64, err: Option<RpcIntErr>,
+        &self, logger: &LogFilter, seq: u64, err: Option<EncodedErr>,
     ) -> impl Future<Output = io::Result<()>> + Send;
 
     /// Flush the response for the socket writer, if the transport has buffering logic
@@ -100,6 +166,36 @@ pub trait ServerTransport: Send + Sync + Sized + 'static + fmt::Debug {
     fn close_conn(&self, logger: &LogFilter) -> impl Future<Output = ()> + Send;
 }
 
+/// The listener of [ServerTransport], accepting the connections for RpcServer.
+///
+/// Implemented for all the `AsyncListener` of orb (TCP and Unix socket),
+/// a transport not based on the stream sockets (like QUIC) implements it by itself.
+pub trait ServerListener: Send + fmt::Debug + 'static {
+    type Conn: Send + 'static;
+
+    fn accept(&mut self) -> impl Future<Output = io::Result<Self::Conn>> + Send;
+
+    fn local_addr(&self) -> io::Result<String>;
+}
+
+impl<L> ServerListener for L
+where
+    L: AsyncListener + Send + fmt::Debug + 'static,
+    L::Conn: Send + 'static,
+{
+    type Conn = L::Conn;
+
+    #[inline(always)]
+    fn accept(&mut self) -> impl Future<Output = io::Result<Self::Conn>> + Send {
+        AsyncListener::accept(self)
+    }
+
+    #[inline(always)]
+    fn local_addr(&self) -> io::Result<String> {
+        AsyncListener::local_addr(self)
+    }
+}
+
 /// A temporary struct to hold data buffer return by ServerTransport
 ///
 /// NOTE: `RpcAction` and `msg` contains slice that reference to ServerTransport's internal buffer,
@@ -107,8 +203,25 @@ pub trait ServerTransport: Send + Sync + Sized + 'static + fmt::Debug {
 pub struct RpcSvrReq<'a> {
     pub seq: u64,
     pub action: RpcAction<'a>,
+    /// The codec format id of msg in the request header
+    pub format: u8,
     pub msg: &'a [u8],
     pub blob: Option<Buffer>, // for write, this contains data
+    pub meta: RpcMeta,
+    /// Converted from the timeout in meta by RpcServer on arrival, transport should leave it None
+    pub deadline: Option<Instant>,
+    /// Flagged with [EXT_FLAG_MORE](crate::proto::EXT_FLAG_MORE), more request frames of the seq
+    /// follow, refer to `Request stream` in [crate::proto].
+    pub more: bool,
+    /// Flagged with [EXT_FLAG_ONEWAY](crate::proto::EXT_FLAG_ONEWAY), the client expects no
+    /// response. RpcServer drops the response of it.
+    pub oneway: bool,
+    /// Set by RpcServer from [ServerConfig::decode_err_detail], transport should leave it false.
+    /// Refer to [EncodedErr::decode_err()].
+    pub decode_err_detail: bool,
+    /// Set by the transport authenticating the client (like TLS), shared by the requests of
+    /// the connection.
+    pub peer: Option<Arc<PeerIdentity>>,
 }
 
 impl<'a> fmt::Debug for RpcSvrReq<'a> {
@@ -118,6 +231,18 @@ impl<'a> fmt::Debug for RpcSvrReq<'a> {
     }
 }
 
+/// The identity of the client verified by the transport, refer to [RpcSvrReq::peer]
+#[derive(Debug, Clone, Default)]
+pub struct PeerIdentity {
+    /// The DER encoded certificate chain of the client, the end-entity first.
+    /// Empty when the client is not required to present one.
+    pub certs: Vec<Vec<u8>>,
+    /// The server name requested by the client (SNI)
+    pub server_name: Option<String>,
+    /// The application protocol negotiated (ALPN)
+    pub alpn: Option<Vec<u8>>,
+}
+
 /// A Struct to hold pre encoded buffer for server response
 #[allow(dead_code)]
 #[derive(Debug)]
@@ -129,6 +254,11 @@ pub struct RpcSvrResp {
     pub blob: Option<Buffer>,
 
     pub res: Option<Result<(), EncodedErr>>,
+
+    pub meta: Option<RpcMeta>,
+
+    /// A partial response of server-streaming, more responses of the seq follow
+    pub more: bool,
 }
 
 impl task::ServerTaskEncode for RpcSvrResp {
@@ -149,6 +279,29 @@ impl task::ServerTaskEncode for RpcSvrResp {
             Err(e) => return (self.seq, Err(e)),
         }
     }
+
+    #[inline]
+    fn get_resp_meta(&self) -> Option<&RpcMeta> {
+        self.meta.as_ref()
+    }
+
+    #[inline]
+    fn has_more(&self) -> bool {
+        self.more
+    }
+}
+
+impl task::ServerTaskResp for RpcSvrResp {}
+
+/// A message to push to the client, refer to [push]
+#[derive(Debug)]
+pub struct RpcSvrPush {
+    /// Numbered by the connection, unrelated to the request seq
+    pub seq: u64,
+
+    pub msg: Vec<u8>,
+
+    pub blob: Option<Buffer>,
 }
 
 /// An ServerFacts for general use
@@ -0,0 +1,100 @@
+//! Messages pushed by the server without a request
+//!
+//! RpcServer creates a [ServerPush] for each connection and passes it to
+//! [Dispatch::set_push](crate::server::dispatch::Dispatch::set_push) before reading requests.
+//! The Dispatch can keep it (or clone it to the handlers) to send messages to the client at any
+//! time, they are written by the connection writer in between the responses.
+//!
+//! Push is only allowed when the client accepts it in the handshake, refer to
+//! [ClientFacts::on_push](crate::client::ClientFacts::on_push).
+//! The handle does not keep the connection alive, pushing after the connection closed returns
+//! an error.
+
+use super::RpcSvrPush;
+use crate::{Codec, error::RpcIntErr};
+use arc_swap::ArcSwap;
+use io_buffer::Buffer;
+use serde::Serialize;
+use std::fmt;
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+};
+
+struct PushInner<C: Codec> {
+    tx: crossfire::MTx<RpcSvrPush>,
+    /// Shared with the connection writer, follows the format of the client
+    codec: Arc<ArcSwap<C>>,
+    /// Set after the client accepts push in the handshake
+    enabled: AtomicBool,
+    seq: AtomicU64,
+}
+
+/// A handle to push messages to the client of a connection
+pub struct ServerPush<C: Codec>(Arc<PushInner<C>>);
+
+impl<C: Codec> Clone for ServerPush<C> {
+    #[inline]
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<C: Codec> fmt::Debug for ServerPush<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "push(enabled={}, seq={})", self.is_enabled(), self.0.seq.load(Ordering::Relaxed))
+    }
+}
+
+impl<C: Codec> ServerPush<C> {
+    #[inline]
+    pub(crate) fn new(tx: crossfire::MTx<RpcSvrPush>, codec: Arc<ArcSwap<C>>) -> Self {
+        Self(Arc::new(PushInner {
+            tx,
+            codec,
+            enabled: AtomicBool::new(false),
+            seq: AtomicU64::new(1),
+        }))
+    }
+
+    #[inline]
+    pub(crate) fn set_enabled(&self) {
+        self.0.enabled.store(true, Ordering::Release);
+    }
+
+    /// Whether the client accepts push, which is known after the handshake
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.0.enabled.load(Ordering::Acquire)
+    }
+
+    /// Encode `msg` with the codec of the connection and push it to the client, returns the seq.
+    ///
+    /// Returns Err(RpcIntErr::Version) when the client does not accept push,
+    /// Err(RpcIntErr::IO) when the connection has closed.
+    pub fn push<M: Serialize>(&self, msg: &M, blob: Option<Buffer>) -> Result<u64, RpcIntErr> {
+        if !self.is_enabled() {
+            return Err(RpcIntErr::Version);
+        }
+        let msg = match self.0.codec.load().encode(msg) {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("push {}", e);
+                return Err(RpcIntErr::Encode);
+            }
+        };
+        self.push_encoded(msg, blob)
+    }
+
+    /// Push an encoded msg to the client, returns the seq.
+    pub fn push_encoded(&self, msg: Vec<u8>, blob: Option<Buffer>) -> Result<u64, RpcIntErr> {
+        if !self.is_enabled() {
+            return Err(RpcIntErr::Version);
+        }
+        let seq = self.0.seq.fetch_add(1, Ordering::Relaxed);
+        if self.0.tx.send(RpcSvrPush { seq, msg, blob }).is_err() {
+            return Err(RpcIntErr::IO);
+        }
+        Ok(seq)
+    }
+}
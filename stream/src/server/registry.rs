@@ -0,0 +1,80 @@
+//! Select the codec by the format id in the request header
+//!
+//! Refer to [Codec::FORMAT] and [Codec::from_format()].
+
+use crate::Codec;
+use crate::proto::CODEC_DEFAULT;
+use std::sync::Arc;
+
+/// The codecs of a connection keyed by format id, constructed from `Dispatch::Codec`.
+///
+/// To accept clients with different codecs on one server, use a `Dispatch::Codec` supporting
+/// multiple formats, like [MultiCodec](razor_rpc_codec::MultiCodec).
+/// Format id 0 (sent by old clients) maps to the default codec.
+pub struct CodecRegistry<C: Codec> {
+    codecs: Vec<(u8, Arc<C>)>,
+}
+
+impl<C: Codec> CodecRegistry<C> {
+    pub fn new() -> Self {
+        let default = Arc::new(C::default());
+        let mut codecs = vec![(CODEC_DEFAULT, default.clone())];
+        for format in C::formats() {
+            if format == CODEC_DEFAULT {
+                continue;
+            }
+            if format == C::FORMAT {
+                codecs.push((format, default.clone()));
+            } else if let Some(codec) = C::from_format(format) {
+                codecs.push((format, Arc::new(codec)));
+            }
+        }
+        Self { codecs }
+    }
+
+    /// Return None if the format is not supported
+    #[inline]
+    pub fn get(&self, format: u8) -> Option<&Arc<C>> {
+        for (f, codec) in self.codecs.iter() {
+            if *f == format {
+                return Some(codec);
+            }
+        }
+        None
+    }
+
+    /// The codec for format id 0
+    #[inline(always)]
+    pub fn get_default(&self) -> &Arc<C> {
+        &self.codecs[0].1
+    }
+
+    /// The format ids supported, for handshake
+    #[inline]
+    pub fn formats(&self) -> Vec<u8> {
+        let mut formats: Vec<u8> = self.codecs[1..].iter().map(|(f, _)| *f).collect();
+        formats.push(CODEC_DEFAULT);
+        formats
+    }
+}
+
+impl<C: Codec> Default for CodecRegistry<C> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use razor_rpc_codec::{FORMAT_MSGPACK, MsgpCodec};
+
+    #[test]
+    fn test_registry() {
+        let registry = CodecRegistry::<MsgpCodec>::new();
+        assert!(Arc::ptr_eq(registry.get(0).unwrap(), registry.get(FORMAT_MSGPACK).unwrap()));
+        assert!(registry.get(3).is_none());
+        assert_eq!(registry.formats(), vec![FORMAT_MSGPACK, CODEC_DEFAULT]);
+    }
+}
@@ -1,7 +1,17 @@
-use crate::proto::RpcAction;
+use crate::proto::{
+    CANCEL_ACTION, CODEC_DEFAULT, CREDIT_ACTION, HANDSHAKE_ACTION, Handshake, MethodTable,
+    Negotiated, RPC_FEATURE_GOAWAY, RPC_FEATURE_PUSH, RPC_FEATURE_STREAM, ReqHead, RpcAction,
+};
+use crate::server::push::ServerPush;
+use crate::server::registry::CodecRegistry;
 use crate::server::*;
+use arc_swap::ArcSwap;
 use captains_log::filter::LogFilter;
+use futures::future::{Either, select};
+use futures::pin_mut;
+use rustc_hash::FxHashSet;
 use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
@@ -61,7 +71,18 @@ where
                 debug!("listening on {:?}", listener);
                 let handle = self.facts.spawn(async move {
                     loop {
-                        match listener.accept().await {
+                        let r = {
+                            let accept_f = listener.accept();
+                            let close_f = server_close_rx.recv();
+                            pin_mut!(accept_f, close_f);
+                            match select(accept_f, close_f).await {
+                                Either::Left((r, _)) => r,
+                                // The server has closed or dropped, release the listener
+                                // even if the runtime does not abort the coroutine
+                                Either::Right(_) => return,
+                            }
+                        };
+                        match r {
                             Err(e) => {
                                 warn!("{:?} accept error: {}", listener, e);
                                 return;
@@ -86,25 +107,46 @@ where
     }
 
     fn server_conn<T: ServerTransport, D: Dispatch>(
-        conn: T, facts: &F, dispatch: D, server_close_rx: crossfire::MAsyncRx<()>,
+        conn: T, facts: &F, mut dispatch: D, server_close_rx: crossfire::MAsyncRx<()>,
     ) {
         let conn = Arc::new(conn);
 
         let (done_tx, done_rx) = crossfire::mpsc::unbounded_async();
-        let codec = Arc::new(D::Codec::default());
+        let registry = Arc::new(CodecRegistry::<D::Codec>::new());
+        // The responses are encoded with the format of their request (carried by RespNoti),
+        // while the push follows the format of the latest request.
+        let push_codec = Arc::new(ArcSwap::new(registry.get_default().clone()));
 
-        let noti = RespNoti(done_tx);
+        let noti = RespNoti::new(done_tx);
+        let (push_tx, push_rx) = crossfire::mpsc::unbounded_async();
+        let push = ServerPush::new(push_tx, push_codec.clone());
+        dispatch.set_push(push.clone());
+        // Whether the client accepts partial responses, set by the reader after handshake
+        let streaming = Arc::new(AtomicBool::new(false));
         struct Reader<T: ServerTransport, D: Dispatch> {
             noti: RespNoti<D::RespTask>,
             conn: Arc<T>,
             server_close_rx: crossfire::MAsyncRx<()>,
-            codec: Arc<D::Codec>,
+            registry: Arc<CodecRegistry<D::Codec>>,
+            push_codec: Arc<ArcSwap<D::Codec>>,
+            /// Enabled when the client accepts push
+            push: ServerPush<D::Codec>,
+            streaming: Arc<AtomicBool>,
+            /// The preferred compression by config
+            compression: u8,
+            /// Reply the detail of decode error by config
+            decode_err_detail: bool,
             dispatch: D,
             logger: Arc<LogFilter>,
         }
         let reader = Reader::<T, D> {
             noti,
-            codec: codec.clone(),
+            registry: registry.clone(),
+            push_codec,
+            push,
+            streaming: streaming.clone(),
+            compression: facts.get_config().compression,
+            decode_err_detail: facts.get_config().decode_err_detail,
             dispatch,
             conn: conn.clone(),
             server_close_rx,
@@ -114,24 +156,135 @@ where
 
         impl<T: ServerTransport, D: Dispatch> Reader<T, D> {
             async fn run(self) -> Result<(), ()> {
+                let mut last_seq = 0;
+                let mut negotiated = Negotiated::default();
+                let mut is_first = true;
+                let mut format = CODEC_DEFAULT;
+                // The client-streaming requests still receiving frames
+                let mut inbound = FxHashSet::default();
                 loop {
                     match self.conn.read_req(&self.logger, &self.server_close_rx).await {
-                        Ok(req) => {
+                        Ok(mut req) => {
+                            if req.action == RpcAction::Num(HANDSHAKE_ACTION as i32) && is_first {
+                                is_first = false;
+                                negotiated = self.handshake(&req).await?;
+                                continue;
+                            }
+                            is_first = false;
+                            // The frames of streaming carry the seq of their request
+                            last_seq = last_seq.max(req.seq);
                             if req.action == RpcAction::Num(0) && req.msg.len() == 0 {
                                 // ping request
                                 self.send_quick_resp(req.seq, None)?;
+                                continue;
+                            }
+                            if req.action == RpcAction::Num(CANCEL_ACTION as i32)
+                                && req.msg.len() == 0
+                            {
+                                logger_trace!(
+                                    self.logger,
+                                    "{:?} cancel seq={}",
+                                    self.conn,
+                                    req.seq
+                                );
+                                self.dispatch.cancel_req(req.seq);
+                                continue;
+                            }
+                            if req.action == RpcAction::Num(CREDIT_ACTION as i32) {
+                                match ReqHead::decode_credit(req.msg) {
+                                    Ok(credit) => self.dispatch.credit_req(req.seq, credit),
+                                    Err(_) => logger_warn!(
+                                        self.logger,
+                                        "{:?} malformed credit seq={}",
+                                        self.conn,
+                                        req.seq
+                                    ),
+                                }
+                                continue;
+                            }
+                            if inbound.contains(&req.seq) {
+                                if req.more {
+                                    self.dispatch.recv_frame(req.seq, Some(req.msg)).await;
+                                } else {
+                                    inbound.remove(&req.seq);
+                                    self.dispatch.recv_frame(req.seq, None).await;
+                                }
+                                continue;
+                            }
+                            if req.more {
+                                // Even if the request is rejected below, the frames are recognized
+                                inbound.insert(req.seq);
+                            }
+                            // The one-way request is never answered, even on error
+                            let oneway = req.oneway;
+                            if let Some(timeout) = req.meta.get_timeout() {
+                                if timeout.is_zero() {
+                                    logger_debug!(
+                                        self.logger,
+                                        "{:?} drop {:?} due to deadline exceeded",
+                                        self.conn,
+                                        req
+                                    );
+                                    if !oneway {
+                                        self.send_quick_resp(req.seq, Some(RpcIntErr::Deadline))?;
+                                    }
+                                    continue;
+                                }
+                                req.deadline = Some(Instant::now() + timeout);
+                            }
+                            req.decode_err_detail = self.decode_err_detail;
+                            let seq = req.seq;
+                            let Some(codec) = self.registry.get(req.format) else {
+                                logger_warn!(
+                                    self.logger,
+                                    "{:?} {:?} format {} not supported",
+                                    self.conn,
+                                    req,
+                                    req.format
+                                );
+                                if !oneway {
+                                    self.send_quick_resp(seq, Some(RpcIntErr::Decode))?;
+                                }
+                                continue;
+                            };
+                            if req.format != format {
+                                format = req.format;
+                                self.push_codec.store(codec.clone());
+                            }
+                            let noti = if oneway {
+                                RespNoti::discard()
                             } else {
-                                let seq = req.seq;
-                                if self
-                                    .dispatch
-                                    .dispatch_req(&self.codec, req, self.noti.clone())
-                                    .await
-                                    .is_err()
-                                {
-                                    self.send_quick_resp(seq, Some(RpcIntErr::Decode.into()))?;
+                                self.noti.with_format(req.format)
+                            };
+                            if let Err(e) = self.dispatch.dispatch_req(codec, req, noti).await {
+                                logger_warn!(self.logger, "{:?} seq={} {}", self.conn, seq, e);
+                                if !oneway {
+                                    let err = e.to_encoded(self.decode_err_detail);
+                                    self.send_err(seq, Some(err))?;
                                 }
                             }
                         }
+                        Err(RpcIntErr::Timeout)
+                            if negotiated.has(RPC_FEATURE_GOAWAY)
+                                && self.server_close_rx.is_disconnected() =>
+                        {
+                            // Server closing, the connection is still healthy.
+                            // Tell the client to stop sending, the writer will exit after
+                            // all the accepted requests are answered.
+                            // (The idle connection is just closed as before.)
+                            logger_debug!(
+                                self.logger,
+                                "{:?} send goaway last_seq={}",
+                                self.conn,
+                                last_seq
+                            );
+                            self.send_quick_resp(last_seq, Some(RpcIntErr::GoAway))?;
+                            return Ok(());
+                        }
+                        Err(RpcIntErr::Checksum) => {
+                            logger_error!(self.logger, "{:?} checksum mismatch, close", self.conn);
+                            return Err(());
+                        }
                         Err(_e) => {
                             // XXX read_req return error not used
                             return Err(());
@@ -140,9 +293,69 @@ where
                 }
             }
 
+            /// Answer the handshake, the client falls back to v1 on error response
+            async fn handshake(&self, req: &RpcSvrReq<'_>) -> Result<Negotiated, ()> {
+                let peer = match Handshake::decode(req.msg) {
+                    Ok(peer) => peer,
+                    Err(_) => {
+                        logger_warn!(self.logger, "{:?} decode handshake error", self.conn);
+                        self.send_quick_resp(req.seq, Some(RpcIntErr::Decode))?;
+                        return Ok(Negotiated::default());
+                    }
+                };
+                let local = Handshake {
+                    codecs: self.registry.formats(),
+                    compressions: crate::compress::preference(self.compression),
+                    methods: MethodTable::new(self.dispatch.methods()),
+                    ..Default::default()
+                };
+                let negotiated = match local.negotiate(&peer) {
+                    Ok(n) => n,
+                    Err(e) => {
+                        logger_warn!(self.logger, "{:?} handshake {:?} failed", self.conn, peer);
+                        self.send_quick_resp(req.seq, Some(e))?;
+                        return Ok(Negotiated::default());
+                    }
+                };
+                // The writer encodes the answer, and applies the settings before reading on
+                let (done, applied) = crossfire::spsc::unbounded_async();
+                self.send_ctl(RespCtl::Handshake(req.seq, negotiated.clone(), done))?;
+                match applied.recv().await {
+                    Ok(true) => {
+                        logger_debug!(self.logger, "{:?} handshake {:?}", self.conn, negotiated);
+                        if negotiated.has(RPC_FEATURE_PUSH) {
+                            self.push.set_enabled();
+                        }
+                        if negotiated.has(RPC_FEATURE_STREAM) {
+                            self.streaming.store(true, Ordering::Release);
+                        }
+                        Ok(negotiated)
+                    }
+                    Ok(false) => Ok(Negotiated::default()),
+                    Err(_) => {
+                        logger_warn!(
+                            self.logger,
+                            "{:?} reader abort due to writer has err",
+                            self.conn
+                        );
+                        Err(())
+                    }
+                }
+            }
+
             #[inline]
             fn send_quick_resp(&self, seq: u64, err: Option<RpcIntErr>) -> Result<(), ()> {
-                if self.noti.send_err(seq, err).is_err() {
+                self.send_err(seq, err.map(EncodedErr::Rpc))
+            }
+
+            #[inline]
+            fn send_err(&self, seq: u64, err: Option<EncodedErr>) -> Result<(), ()> {
+                self.send_ctl(RespCtl::Err(seq, err))
+            }
+
+            #[inline]
+            fn send_ctl(&self, ctl: RespCtl) -> Result<(), ()> {
+                if self.noti.send_ctl(ctl).is_err() {
                     logger_warn!(self.logger, "{:?} reader abort due to writer has err", self.conn);
                     return Err(());
                 }
@@ -151,12 +364,21 @@ where
         }
 
         struct Writer<T: ServerTransport, D: Dispatch> {
-            codec: Arc<D::Codec>,
-            done_rx: crossfire::AsyncRx<Result<D::RespTask, (u64, Option<RpcIntErr>)>>,
+            registry: Arc<CodecRegistry<D::Codec>>,
+            done_rx: crossfire::AsyncRx<RespItem<D::RespTask>>,
+            push_rx: crossfire::AsyncRx<RpcSvrPush>,
+            streaming: Arc<AtomicBool>,
             conn: Arc<T>,
             logger: Arc<LogFilter>,
         }
-        let writer = Writer::<T, D> { done_rx, codec, conn, logger: facts.new_logger() };
+        let writer = Writer::<T, D> {
+            done_rx,
+            push_rx,
+            streaming,
+            registry,
+            conn,
+            logger: facts.new_logger(),
+        };
         facts.spawn_detach(async move { writer.run().await });
 
         impl<T: ServerTransport, D: Dispatch> Writer<T, D> {
@@ -164,27 +386,116 @@ where
                 macro_rules! process {
                     ($task: expr) => {{
                         match $task {
-                            Ok(_task) => {
+                            Ok((_, _task))
+                                if _task.has_more() && !self.streaming.load(Ordering::Acquire) =>
+                            {
+                                logger_warn!(
+                                    self.logger,
+                                    "{:?} drop partial resp {:?}, client does not support stream",
+                                    self.conn,
+                                    _task
+                                );
+                            }
+                            Ok((format, _task)) => {
                                 logger_trace!(self.logger, "write_resp {:?}", _task);
+                                // The format is checked by the reader
+                                let codec = match self.registry.get(format) {
+                                    Some(codec) => codec,
+                                    None => self.registry.get_default(),
+                                };
                                 self.conn
-                                    .write_resp::<D::RespTask>(
-                                        &self.logger,
-                                        self.codec.as_ref(),
-                                        _task,
-                                    )
+                                    .write_resp::<D::RespTask>(&self.logger, codec.as_ref(), _task)
                                     .await?;
                             }
-                            Err((seq, err)) => {
+                            Err(RespCtl::Err(seq, err)) => {
                                 self.conn.write_resp_internal(&self.logger, seq, err).await?;
                             }
+                            Err(RespCtl::Handshake(seq, negotiated, done)) => {
+                                let applied = match self
+                                    .conn
+                                    .write_handshake(&self.logger, seq, &negotiated)
+                                    .await
+                                {
+                                    Ok(_) => true,
+                                    Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                                        let err = Some(EncodedErr::Rpc(RpcIntErr::Version));
+                                        self.conn
+                                            .write_resp_internal(&self.logger, seq, err)
+                                            .await?;
+                                        false
+                                    }
+                                    Err(e) => {
+                                        logger_warn!(
+                                            self.logger,
+                                            "{:?} write handshake error: {}",
+                                            self.conn,
+                                            e
+                                        );
+                                        return Err(e);
+                                    }
+                                };
+                                let _ = done.send(applied);
+                            }
+                            Err(RespCtl::Credit(seq, credit)) => {
+                                match self.conn.write_credit(&self.logger, seq, credit).await {
+                                    Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                                        logger_warn!(
+                                            self.logger,
+                                            "{:?} credit not supported",
+                                            self.conn
+                                        );
+                                    }
+                                    r => r?,
+                                }
+                            }
+                        }
+                    }};
+                }
+                macro_rules! push {
+                    ($push: expr) => {{
+                        let _push = $push;
+                        logger_trace!(self.logger, "write_push seq={}", _push.seq);
+                        match self.conn.write_push(&self.logger, _push).await {
+                            Err(e) if e.kind() == io::ErrorKind::Unsupported => {
+                                logger_warn!(self.logger, "{:?} push not supported", self.conn);
+                            }
+                            r => r?,
                         }
                     }};
                 }
-                while let Ok(task) = self.done_rx.recv().await {
-                    process!(task);
+                // The writer exits when all the RespNoti drop, ServerPush does not keep it alive
+                let mut push_open = true;
+                loop {
+                    if push_open {
+                        let r = {
+                            let done_f = self.done_rx.recv();
+                            let push_f = self.push_rx.recv();
+                            pin_mut!(done_f, push_f);
+                            match select(done_f, push_f).await {
+                                Either::Left((r, _)) => Either::Left(r),
+                                Either::Right((r, _)) => Either::Right(r),
+                            }
+                        };
+                        match r {
+                            Either::Left(Ok(task)) => process!(task),
+                            Either::Left(Err(_)) => break,
+                            Either::Right(Ok(push)) => push!(push),
+                            Either::Right(Err(_)) => {
+                                push_open = false;
+                                continue;
+                            }
+                        }
+                    } else if let Ok(task) = self.done_rx.recv().await {
+                        process!(task);
+                    } else {
+                        break;
+                    }
                     while let Ok(task) = self.done_rx.try_recv() {
                         process!(task);
                     }
+                    while let Ok(push) = self.push_rx.try_recv() {
+                        push!(push);
+                    }
                     self.conn.flush_resp(&self.logger).await?;
                 }
                 logger_trace!(self.logger, "{:?} writer exits", self.conn);
@@ -203,7 +514,8 @@ where
     ///
     /// Steps:
     /// - listeners coroutine is abort
-    /// - drop the close channel to notify connection read coroutines.
+    /// - drop the close channel to notify connection read coroutines,
+    /// which send GOAWAY with the last accepted seq to the clients (if negotiated by handshake).
     /// - the writer coroutines will exit after all the reference of RespNoti channel drop to 0
     /// - wait for connection coroutines to exit with a timeout defined by
     /// ServerConfig.server_close_wait
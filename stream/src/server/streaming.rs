@@ -0,0 +1,271 @@
+//! Flow control of the streaming requests inside a connection
+//!
+//! For server-streaming, the handler should [acquire()](RespWindow::acquire) a unit of the
+//! [RespWindow] before sending each partial response. The window starts with
+//! [RPC_STREAM_WINDOW], and grows as the client grants credit, which RpcServer passes to
+//! [Dispatch::credit_req](crate::server::dispatch::Dispatch::credit_req).
+//!
+//! For client-streaming, the frames following the request are passed to
+//! [Dispatch::recv_frame](crate::server::dispatch::Dispatch::recv_frame), and queued in
+//! [ReqFrames]. The client sends at most [RPC_STREAM_WINDOW] items ahead of the handler,
+//! more credit is granted to the client as the handler takes them. So a slow handler only holds
+//! back its own input, the connection reader does not wait unless the client ignores the credit.
+//!
+//! NOTE: The handler of a streaming request must not run inside the connection reader coroutine,
+//! otherwise the credits and frames will never arrive.
+
+use crate::proto::RPC_STREAM_WINDOW;
+use crate::server::task::{RespCtl, RespNoti};
+use futures::task::AtomicWaker;
+use rustc_hash::FxHashMap;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::Poll;
+
+/// Track the flow control of the streaming requests inside a connection, indexed by seq.
+pub struct StreamRegistry {
+    windows: Mutex<FxHashMap<u64, Arc<Window>>>,
+    inputs: Mutex<FxHashMap<u64, crossfire::MAsyncTx<Vec<u8>>>>,
+}
+
+impl StreamRegistry {
+    #[inline]
+    pub fn new() -> Self {
+        Self { windows: Mutex::new(FxHashMap::default()), inputs: Mutex::new(FxHashMap::default()) }
+    }
+
+    /// Open the window for the partial responses of the server-streaming request `seq`
+    pub fn open_window(self: &Arc<Self>, seq: u64) -> RespWindow {
+        let window = Arc::new(Window {
+            credit: AtomicU64::new(RPC_STREAM_WINDOW as u64),
+            closed: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        });
+        self.windows.lock().unwrap().insert(seq, window.clone());
+        RespWindow { seq, window, registry: Arc::downgrade(self) }
+    }
+
+    /// Grow the window of the request `seq` by `credit`, ignored if the request has finished.
+    pub fn grant(&self, seq: u64, credit: u32) {
+        if let Some(window) = self.windows.lock().unwrap().get(&seq) {
+            window.credit.fetch_add(credit as u64, Ordering::AcqRel);
+            window.waker.wake();
+        }
+    }
+
+    /// Open the queue for the frames following the client-streaming request `seq`, the credit
+    /// is sent to the client through `noti` as the handler takes them.
+    pub fn open_input<T: Send + 'static>(&self, seq: u64, noti: RespNoti<T>) -> ReqFrames<T> {
+        let (tx, rx) = crossfire::mpsc::bounded_async(RPC_STREAM_WINDOW as usize);
+        self.inputs.lock().unwrap().insert(seq, tx);
+        ReqFrames { seq, rx, noti, consumed: 0 }
+    }
+
+    /// Queue a frame of the request `seq`, `msg` is None on the end of input.
+    ///
+    /// Waits while the queue is full, which only happens when the client sends beyond the
+    /// credit. The frame is dropped if the handler has gone.
+    pub async fn recv_frame(&self, seq: u64, msg: Option<&[u8]>) {
+        match msg {
+            Some(msg) => {
+                let tx = self.inputs.lock().unwrap().get(&seq).cloned();
+                if let Some(tx) = tx {
+                    let _ = tx.send(msg.to_vec()).await;
+                }
+            }
+            None => {
+                self.close_input(seq);
+            }
+        }
+    }
+
+    /// Close the input of the request `seq`, the handler will see the end of input.
+    #[inline]
+    pub fn close_input(&self, seq: u64) {
+        let _ = self.inputs.lock().unwrap().remove(&seq);
+    }
+}
+
+impl Default for StreamRegistry {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for StreamRegistry {
+    fn drop(&mut self) {
+        // The connection has closed, wake up the handlers waiting for credit
+        for window in self.windows.get_mut().unwrap().values() {
+            window.closed.store(true, Ordering::Release);
+            window.waker.wake();
+        }
+    }
+}
+
+struct Window {
+    /// The number of partial responses allowed to send
+    credit: AtomicU64,
+    closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Window {
+    #[inline]
+    fn try_take(&self) -> bool {
+        self.credit.fetch_update(Ordering::AcqRel, Ordering::Acquire, |c| c.checked_sub(1)).is_ok()
+    }
+}
+
+/// The number of partial responses allowed to send, refer to [StreamRegistry::open_window]
+pub struct RespWindow {
+    seq: u64,
+    window: Arc<Window>,
+    registry: Weak<StreamRegistry>,
+}
+
+impl RespWindow {
+    /// Wait for a unit of the window.
+    ///
+    /// Returns Err(()) when the connection has closed.
+    pub async fn acquire(&self) -> Result<(), ()> {
+        poll_fn(|ctx| {
+            // Registered before checking, so that the credit arriving in between is not missed
+            self.window.waker.register(ctx.waker());
+            if self.window.try_take() {
+                Poll::Ready(Ok(()))
+            } else if self.window.closed.load(Ordering::Acquire) {
+                Poll::Ready(Err(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl Drop for RespWindow {
+    fn drop(&mut self) {
+        if let Some(registry) = self.registry.upgrade() {
+            registry.windows.lock().unwrap().remove(&self.seq);
+        }
+    }
+}
+
+/// The frames following a client-streaming request, refer to [StreamRegistry::open_input]
+pub struct ReqFrames<T: Send + 'static> {
+    seq: u64,
+    rx: crossfire::AsyncRx<Vec<u8>>,
+    noti: RespNoti<T>,
+    /// Items taken since the last credit
+    consumed: u32,
+}
+
+impl<T: Send + 'static> ReqFrames<T> {
+    /// Returns None on the end of input, or the connection has closed.
+    pub async fn recv(&mut self) -> Option<Vec<u8>> {
+        let msg = self.rx.recv().await.ok()?;
+        self.consumed += 1;
+        if self.consumed >= RPC_STREAM_WINDOW / 2 {
+            // Err when the connection has closed, the input will end
+            let _ = self.noti.send_ctl(RespCtl::Credit(self.seq, self.consumed));
+            self.consumed = 0;
+        }
+        Some(msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::task::RespItem;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_stream_window() {
+        let registry = Arc::new(StreamRegistry::new());
+        let window = registry.open_window(1);
+        block_on(async {
+            for _ in 0..RPC_STREAM_WINDOW {
+                window.acquire().await.expect("acquire");
+            }
+        });
+        assert_eq!(window.window.credit.load(Ordering::Acquire), 0);
+        registry.grant(1, 2);
+        // not found
+        registry.grant(2, 2);
+        block_on(async {
+            window.acquire().await.expect("acquire");
+            window.acquire().await.expect("acquire");
+        });
+        assert_eq!(window.window.credit.load(Ordering::Acquire), 0);
+        // Not limited to the initial window
+        registry.grant(1, RPC_STREAM_WINDOW * 4);
+        block_on(async {
+            for _ in 0..RPC_STREAM_WINDOW * 4 {
+                window.acquire().await.expect("acquire");
+            }
+        });
+        assert_eq!(window.window.credit.load(Ordering::Acquire), 0);
+        drop(window);
+        assert!(registry.windows.lock().unwrap().is_empty());
+
+        let window = registry.open_window(3);
+        drop(registry);
+        block_on(async {
+            for _ in 0..RPC_STREAM_WINDOW {
+                window.acquire().await.expect("acquire");
+            }
+            assert!(window.acquire().await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_stream_input() {
+        let registry = StreamRegistry::new();
+        let (tx, rx) = crossfire::mpsc::unbounded_async::<RespItem<()>>();
+        let noti = RespNoti::new(tx);
+        let mut frames = registry.open_input(1, noti.clone());
+        block_on(async {
+            registry.recv_frame(1, Some(b"a")).await;
+            registry.recv_frame(1, Some(b"b")).await;
+            // not found
+            registry.recv_frame(2, Some(b"c")).await;
+            registry.recv_frame(1, None).await;
+            assert_eq!(frames.recv().await, Some(b"a".to_vec()));
+            assert_eq!(frames.recv().await, Some(b"b".to_vec()));
+            assert_eq!(frames.recv().await, None);
+        });
+        assert!(rx.try_recv().is_err());
+
+        // The credit is granted as the handler takes the items
+        let mut frames = registry.open_input(2, noti.clone());
+        block_on(async {
+            for _ in 0..RPC_STREAM_WINDOW {
+                registry.recv_frame(2, Some(b"e")).await;
+            }
+            for _ in 0..RPC_STREAM_WINDOW / 2 - 1 {
+                assert!(frames.recv().await.is_some());
+            }
+            assert!(rx.try_recv().is_err());
+            assert!(frames.recv().await.is_some());
+            match rx.try_recv() {
+                Ok(Err(RespCtl::Credit(2, credit))) => assert_eq!(credit, RPC_STREAM_WINDOW / 2),
+                r => panic!("unexpected {:?}", r),
+            }
+        });
+        registry.close_input(2);
+
+        let frames = registry.open_input(3, noti);
+        drop(frames);
+        // the handler has gone, not blocked
+        block_on(async {
+            for _ in 0..RPC_STREAM_WINDOW * 2 {
+                registry.recv_frame(3, Some(b"d")).await;
+            }
+        });
+        registry.close_input(3);
+        assert!(registry.inputs.lock().unwrap().is_empty());
+    }
+}
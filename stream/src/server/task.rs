@@ -10,7 +10,7 @@
 
 pub use razor_stream_macros::server_task_enum;
 
-use crate::proto::{RpcAction, RpcActionOwned};
+use crate::proto::{CODEC_DEFAULT, Negotiated, RpcAction, RpcActionOwned, RpcMeta};
 use crate::{Codec, error::*};
 use io_buffer::Buffer;
 use serde::{Deserialize, Serialize};
@@ -24,7 +24,7 @@ pub trait ServerTaskDecode<R: Send + Unpin + 'static>: Send + Sized + Unpin + 's
     fn decode_req<'a, C: Codec>(
         codec: &'a C, action: RpcAction<'a>, seq: u64, req: &'a [u8], blob: Option<Buffer>,
         noti: RespNoti<R>,
-    ) -> Result<Self, ()>;
+    ) -> Result<Self, CodecError>;
 }
 
 /// How to encode a server response
@@ -37,6 +37,26 @@ pub trait ServerTaskEncode: Send + 'static + Unpin {
     fn encode_resp<'a, 'b, C: Codec>(
         &'a mut self, codec: &'b C, buf: &'b mut Vec<u8>,
     ) -> (u64, Result<(usize, Option<&'a [u8]>), EncodedErr>);
+
+    /// Metadata headers send back to client, called before encode_resp().
+    ///
+    /// By Default, return None when the task does not carry response metadata
+    #[inline(always)]
+    fn get_resp_meta(&self) -> Option<&RpcMeta> {
+        None
+    }
+
+    /// Whether more responses of the same seq follow (server-streaming), called before encode_resp().
+    ///
+    /// A partial response is sent with [RespNoti::send()], and the last one with [RespNoti::done()].
+    /// The partial responses are dropped when the client does not negotiate
+    /// [RPC_FEATURE_STREAM](crate::proto::RPC_FEATURE_STREAM).
+    ///
+    /// By default, return false as the response is final.
+    #[inline(always)]
+    fn has_more(&self) -> bool {
+        false
+    }
 }
 
 /// How to notify Rpc framework when a task is done
@@ -124,7 +144,7 @@ where
     fn decode_req<'a, C: Codec>(
         codec: &'a C, action: RpcAction<'a>, seq: u64, msg: &'a [u8], blob: Option<Buffer>,
         noti: RespNoti<T>,
-    ) -> Result<Self, ()> {
+    ) -> Result<Self, CodecError> {
         let req = codec.decode(msg)?;
         Ok(Self { seq, action: action.into(), msg: req, blob, res: None, noti: Some(noti) })
     }
@@ -154,7 +174,8 @@ where
         if let Some(res) = self.res.as_ref() {
             match res {
                 Ok(_) => match codec.encode_into(&self.msg, buf) {
-                    Err(_) => {
+                    Err(e) => {
+                        error!("seq={} {}", self.seq, e);
                         return (self.seq, Err(RpcIntErr::Encode.into()));
                     }
                     Ok(msg_len) => {
@@ -232,7 +253,7 @@ where
     fn decode_req<'a, C: Codec>(
         codec: &'a C, action: RpcAction<'a>, seq: u64, msg: &'a [u8], blob: Option<Buffer>,
         noti: RespNoti<T>,
-    ) -> Result<Self, ()> {
+    ) -> Result<Self, CodecError> {
         let req = codec.decode(msg)?;
         Ok(Self {
             seq,
@@ -275,7 +296,8 @@ where
                 Ok(_) => {
                     if let Some(resp) = self.resp.as_ref() {
                         match codec.encode_into(resp, buf) {
-                            Err(_) => {
+                            Err(e) => {
+                                error!("seq={} {}", self.seq, e);
                                 return (self.seq, Err(RpcIntErr::Encode.into()));
                             }
                             Ok(msg_len) => {
@@ -297,33 +319,91 @@ where
     }
 }
 
+/// The item sent by [RespNoti] to the writer of the connection: a response with the format id of
+/// its request, or a [RespCtl] from the reader
+pub type RespItem<T> = Result<(u8, T), RespCtl>;
+
+/// The frames written by the writer of the connection on behalf of the reader
+#[derive(Debug)]
+pub enum RespCtl {
+    /// An internal error of the seq, None for the answer of ping
+    Err(u64, Option<EncodedErr>),
+    /// The answer of the handshake of the seq, the writer replies whether the negotiated settings
+    /// are applied on the connection
+    Handshake(u64, Negotiated, crossfire::Tx<bool>),
+    /// Grant the client more items of the client-streaming request seq, refer to
+    /// [ReqFrames](crate::server::streaming::ReqFrames)
+    Credit(u64, u32),
+}
+
 /// A writer channel to send response to the server framework.
 ///
 /// It can be cloned anywhere.
 /// The user doesn't need to call it directly.
+///
+/// The responses are encoded with the codec of the format id of their request, which is carried
+/// by the RespNoti passed to [Dispatch::dispatch_req()](crate::server::dispatch::Dispatch).
 pub struct RespNoti<T: Send + 'static>(
-    pub(crate) crossfire::MTx<Result<T, (u64, Option<RpcIntErr>)>>,
+    /// None for the one-way requests, the responses are dropped
+    pub(crate) Option<crossfire::MTx<RespItem<T>>>,
+    /// The format id of the request
+    pub(crate) u8,
 );
 
 impl<T: Send + 'static> Clone for RespNoti<T> {
     #[inline]
     fn clone(&self) -> Self {
-        Self(self.0.clone())
+        Self(self.0.clone(), self.1)
     }
 }
 
 impl<T: Send + 'static> RespNoti<T> {
-    pub fn new(tx: crossfire::MTx<Result<T, (u64, Option<RpcIntErr>)>>) -> Self {
-        Self(tx)
+    pub fn new(tx: crossfire::MTx<RespItem<T>>) -> Self {
+        Self(Some(tx), CODEC_DEFAULT)
+    }
+
+    /// For the one-way requests, which drops the responses
+    #[inline]
+    pub fn discard() -> Self {
+        Self(None, CODEC_DEFAULT)
+    }
+
+    /// For the request of `format`
+    #[inline]
+    pub(crate) fn with_format(&self, format: u8) -> Self {
+        Self(self.0.clone(), format)
+    }
+
+    /// Whether the responses are dropped, refer to [RpcSvrReq::oneway](crate::server::RpcSvrReq)
+    #[inline(always)]
+    pub fn is_discard(&self) -> bool {
+        self.0.is_none()
     }
 
     #[inline]
     pub fn done(self, task: T) {
-        let _ = self.0.send(Ok(task));
+        if let Some(tx) = self.0.as_ref() {
+            let _ = tx.send(Ok((self.1, task)));
+        }
     }
 
+    /// Send a partial response, which `has_more()`, refer to [ServerTaskEncode::has_more()].
+    ///
+    /// Can be called many times before [done()](Self::done).
+    /// Returns Err(()) when the connection has closed, or the responses are dropped.
     #[inline]
-    pub(crate) fn send_err(&self, seq: u64, err: Option<RpcIntErr>) -> Result<(), ()> {
-        if self.0.send(Err((seq, err))).is_err() { return Err(()) } else { Ok(()) }
+    pub fn send(&self, task: T) -> Result<(), ()> {
+        match self.0.as_ref() {
+            Some(tx) if tx.send(Ok((self.1, task))).is_ok() => Ok(()),
+            _ => Err(()),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn send_ctl(&self, ctl: RespCtl) -> Result<(), ()> {
+        match self.0.as_ref() {
+            Some(tx) => tx.send(Err(ctl)).map_err(|_| ()),
+            None => Ok(()),
+        }
     }
 }
@@ -1,6 +1,9 @@
 use nix::errno::Errno;
 use razor_rpc_codec::MsgpCodec;
-use razor_stream::{Codec, error::RpcErrCodec};
+use razor_stream::{
+    Codec,
+    error::{CodecError, RpcErrCodec},
+};
 use razor_stream::{
     proto::{RpcAction, RpcActionOwned},
     server::RpcSvrResp,
@@ -32,7 +35,7 @@ impl<R: Send + Unpin + 'static> ServerTaskDecode<R> for StaticActionTask {
     fn decode_req<'a, C: Codec>(
         _codec: &'a C, _action: RpcAction<'a>, _seq: u64, _req: &'a [u8],
         _blob: Option<io_buffer::Buffer>, _noti: RespNoti<R>,
-    ) -> Result<Self, ()> {
+    ) -> Result<Self, CodecError> {
         Ok(StaticActionTask)
     }
 }
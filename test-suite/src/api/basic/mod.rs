@@ -1,2 +1,22 @@
 #[cfg(test)]
+pub mod test_aead;
+#[cfg(test)]
+pub mod test_decode_err;
+#[cfg(test)]
+pub mod test_json;
+#[cfg(test)]
+pub mod test_method_id;
+#[cfg(test)]
+pub mod test_oneway;
+#[cfg(test)]
+pub mod test_prost;
+#[cfg(test)]
+pub mod test_quic;
+#[cfg(test)]
 pub mod test_remote;
+#[cfg(test)]
+pub mod test_stream;
+#[cfg(test)]
+pub mod test_tls;
+#[cfg(test)]
+pub mod test_ws;
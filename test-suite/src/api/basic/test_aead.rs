@@ -0,0 +1,69 @@
+use crate::api::client::MyClient;
+use crate::api::server::{CalServer, EchoServer, create_api_server};
+use crate::api::service::{CalService, EchoService};
+use crate::*;
+use razor_rpc::client::ClientConfig;
+use razor_rpc::error::RpcError;
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn, dispatch::Inline};
+use razor_rpc_codec::{AeadCodec, AeadKey, AeadKeyProvider, AeadKeyRing};
+use razor_rpc_tcp::TcpServer;
+use std::sync::{Arc, LazyLock};
+
+static KEYS: LazyLock<AeadKeyRing> = LazyLock::new(AeadKeyRing::new);
+
+#[derive(Default)]
+struct TestKeys;
+
+impl AeadKeyProvider for TestKeys {
+    fn current_key(&self) -> Option<(u32, AeadKey)> {
+        KEYS.current_key()
+    }
+
+    fn get_key(&self, key_id: u32) -> Option<AeadKey> {
+        KEYS.get_key(key_id)
+    }
+}
+
+type TestAeadCodec = AeadCodec<crate::Codec, TestKeys>;
+
+#[logfn]
+#[rstest]
+fn test_api_aead(runner: TestRunner) {
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    runner.block_on(async move {
+        KEYS.insert(1, AeadKey::chacha20_poly1305(&[1; 32]));
+        let mut server = create_api_server(ServerConfig::default(), rt_server);
+        let mut service_mux = ServiceMuxDyn::<TestAeadCodec>::new();
+        service_mux.add(Arc::new(CalServer {}));
+        service_mux.add(Arc::new(EchoServer {}));
+        let dispatch = Inline::new(Arc::new(service_mux));
+        let actual_server_addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("server listen");
+
+        let client = MyClient::<TestAeadCodec>::new(
+            ClientConfig::default(),
+            &actual_server_addr,
+            rt_client.clone(),
+        );
+        assert_eq!(client.cal.inc(41).await.unwrap(), 42);
+        assert_eq!(client.echo.repeat("hello".to_string()).await.unwrap(), "hello");
+        match client.cal.div((10, 0)).await.unwrap_err() {
+            RpcError::User(msg) => assert_eq!(msg, "divide by zero"),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        // Rotate the key shared by both sides
+        KEYS.insert(2, AeadKey::aes256_gcm(&[2; 32]));
+        assert!(KEYS.set_current(2));
+        KEYS.remove(1);
+        assert_eq!(client.cal.add((10, 20)).await.unwrap(), 30);
+
+        // The plain client is not served
+        let client =
+            MyClient::<crate::Codec>::new(ClientConfig::default(), &actual_server_addr, rt_client);
+        assert!(client.cal.inc(1).await.is_err());
+    });
+}
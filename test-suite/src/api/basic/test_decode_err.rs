@@ -0,0 +1,41 @@
+use crate::api::client::APIClient;
+use crate::api::server::{CalServer, create_api_server};
+use crate::*;
+use razor_rpc::client::{APIClientFacts, AsyncEndpoint, ClientConfig};
+use razor_rpc::error::{RpcError, RpcIntErr};
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn, dispatch::Inline};
+use razor_rpc_tcp::{TcpClient, TcpServer};
+use std::sync::Arc;
+
+/// The request of wrong type fails with RpcIntErr::Decode, whether the server replies the
+/// detail or not. The detail string is not taken as the user error.
+#[logfn]
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_api_decode_err(runner: TestRunner, #[case] detail: bool) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut config = ServerConfig::default();
+        config.decode_err_detail = detail;
+        let mut server = create_api_server(config, rt.clone());
+        let mut service_mux = ServiceMuxDyn::<crate::Codec>::new();
+        service_mux.add(Arc::new(CalServer {}));
+        let dispatch = Inline::new(Arc::new(service_mux));
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt);
+        let endpoint = AsyncEndpoint::new(facts.create_pool_async::<TcpClient<crate::RT>>(&addr));
+        // () is empty or nil, not an isize in any codec
+        let r = endpoint.call::<_, isize, String>("CalService.inc", &()).await;
+        assert_eq!(r.unwrap_err(), RpcIntErr::Decode);
+        // The div error is a user string
+        let r = endpoint.call::<_, isize, String>("CalService.div", &(1isize, 0isize)).await;
+        assert_eq!(r.unwrap_err(), RpcError::User("divide by zero".to_string()));
+        let r = endpoint.call::<_, isize, String>("CalService.inc", &41isize).await;
+        assert_eq!(r.unwrap(), 42);
+    });
+}
@@ -0,0 +1,68 @@
+use crate::api::client::MyClient;
+use crate::api::server::{CalServer, EchoServer, create_api_server};
+use crate::*;
+use nix::errno::Errno;
+use razor_rpc::client::ClientConfig;
+use razor_rpc::error::RpcError;
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn, dispatch::Inline};
+use razor_rpc_codec::{JsonCodec, MultiCodec};
+use razor_rpc_tcp::TcpServer;
+use std::sync::Arc;
+
+use crate::api::service::{CalService, EchoService};
+
+#[logfn]
+#[rstest]
+#[case(false)]
+#[case(true)]
+fn test_api_json(runner: TestRunner, #[case] multi: bool) {
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt_server);
+        let actual_server_addr = if multi {
+            // Serve the JSON clients along with the default codec
+            let mut service_mux = ServiceMuxDyn::<MultiCodec<crate::Codec, JsonCodec>>::new();
+            service_mux.add(Arc::new(CalServer {}));
+            service_mux.add(Arc::new(EchoServer {}));
+            let dispatch = Inline::new(Arc::new(service_mux));
+            server.listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch).await
+        } else {
+            let mut service_mux = ServiceMuxDyn::<JsonCodec>::new();
+            service_mux.add(Arc::new(CalServer {}));
+            service_mux.add(Arc::new(EchoServer {}));
+            let dispatch = Inline::new(Arc::new(service_mux));
+            server.listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch).await
+        }
+        .expect("server listen");
+
+        let client = MyClient::<JsonCodec>::new(
+            ClientConfig::default(),
+            &actual_server_addr,
+            rt_client.clone(),
+        );
+        assert_eq!(client.cal.inc(41).await.unwrap(), 42);
+        assert_eq!(client.cal.add((10, 20)).await.unwrap(), 30);
+        assert_eq!(client.cal.div((10, 2)).await.unwrap(), 5);
+        // The string error is not quoted by JSON
+        match client.cal.div((10, 0)).await.unwrap_err() {
+            RpcError::User(msg) => assert_eq!(msg, "divide by zero"),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(client.echo.repeat("\"hello\"".to_string()).await.unwrap(), "\"hello\"");
+        match client.echo.io_error("test".to_string()).await.unwrap_err() {
+            RpcError::User(errno) => assert_eq!(errno, Errno::EIO),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        if multi {
+            let client = MyClient::<crate::Codec>::new(
+                ClientConfig::default(),
+                &actual_server_addr,
+                rt_client,
+            );
+            assert_eq!(client.cal.inc(1).await.unwrap(), 2);
+            assert_eq!(client.echo.repeat("hello".to_string()).await.unwrap(), "hello");
+        }
+    });
+}
@@ -0,0 +1,68 @@
+use crate::api::client::MyClient;
+use crate::api::server::{
+    CalServer, EchoServer, create_api_server, create_service_mux_dispatch,
+    create_service_mux_struct_dispatch,
+};
+use crate::*;
+use razor_rpc::client::ClientConfig;
+use razor_rpc::server::ServerConfig;
+use razor_rpc_tcp::TcpServer;
+use razor_stream::server::dispatch::Dispatch;
+
+use crate::api::service::{CalService, EchoService};
+
+const METHODS: [&str; 6] = [
+    "CalService.add",
+    "CalService.div",
+    "CalService.inc",
+    "EchoService.io_error",
+    "EchoService.notify",
+    "EchoService.repeat",
+];
+
+#[logfn]
+#[rstest]
+fn test_api_methods() {
+    let dispatch = create_service_mux_struct_dispatch(CalServer {}, EchoServer {});
+    let mut names = dispatch.methods().to_vec();
+    names.sort();
+    assert_eq!(names, METHODS);
+    let dispatch = create_service_mux_dispatch(CalServer {}, EchoServer {});
+    let mut names = dispatch.methods().to_vec();
+    names.sort();
+    assert_eq!(names, METHODS);
+}
+
+/// The calls go by numeric id with handshake, and by string without
+#[logfn]
+#[rstest]
+#[case(true, true)]
+#[case(false, true)]
+#[case(true, false)]
+#[case(false, false)]
+fn test_api_method_id(runner: TestRunner, #[case] handshake: bool, #[case] mux_struct: bool) {
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    runner.block_on(async move {
+        let mut client_config = ClientConfig::default();
+        client_config.handshake = handshake;
+        let mut server = create_api_server(ServerConfig::default(), rt_server);
+        let actual_server_addr = if mux_struct {
+            let dispatch = create_service_mux_struct_dispatch(CalServer {}, EchoServer {});
+            server.listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch).await
+        } else {
+            let dispatch = create_service_mux_dispatch(CalServer {}, EchoServer {});
+            server.listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch).await
+        }
+        .expect("server listen");
+        let client = MyClient::<crate::Codec>::new(client_config, &actual_server_addr, rt_client);
+        for i in 0..10 {
+            assert_eq!(client.cal.inc(i).await.unwrap(), i + 1);
+            assert_eq!(client.cal.add((i, 2)).await.unwrap(), i + 2);
+            assert_eq!(
+                client.echo.repeat(format!("hello {}", i)).await.unwrap(),
+                format!("hello {}", i)
+            );
+        }
+    });
+}
@@ -0,0 +1,43 @@
+use crate::api::client::MyClient;
+use crate::api::server::{
+    CalServer, EchoServer, NOTIFIED, create_api_server, listen_with_services,
+};
+use crate::*;
+use razor_rpc::client::ClientConfig;
+use razor_rpc::server::ServerConfig;
+
+use crate::api::service::EchoService;
+
+#[logfn]
+#[rstest]
+#[case(true)]
+#[case(false)]
+fn test_api_oneway(runner: TestRunner, #[case] handshake: bool) {
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    runner.block_on(async move {
+        let client_config = ClientConfig { handshake, ..Default::default() };
+        let server = create_api_server(ServerConfig::default(), rt_server);
+        let (_server, actual_server_addr) =
+            listen_with_services(server, "127.0.0.1:0", CalServer {}, EchoServer {})
+                .await
+                .expect("server listen");
+        let client = MyClient::<crate::Codec>::new(client_config, &actual_server_addr, rt_client);
+
+        let prefix = format!("oneway-{}", handshake);
+        for i in 0..10 {
+            client.echo.notify(format!("{}-{}", prefix, i)).await.expect("notify");
+        }
+        // The requests are processed in order by the inline dispatch
+        assert_eq!(client.echo.repeat("done".to_string()).await.unwrap(), "done");
+        let notified: Vec<_> = NOTIFIED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|msg| msg.starts_with(&prefix))
+            .cloned()
+            .collect();
+        let expected: Vec<_> = (0..10).map(|i| format!("{}-{}", prefix, i)).collect();
+        assert_eq!(notified, expected);
+    });
+}
@@ -0,0 +1,45 @@
+use crate::api::client::{APIClient, PoolCaller};
+use crate::api::server::{GreetServer, create_api_server};
+use crate::api::service::{GreetClient, GreetReq, GreetService};
+use crate::*;
+use razor_rpc::client::{APIClientFacts, ClientConfig};
+use razor_rpc::error::RpcError;
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn, dispatch::Inline};
+use razor_rpc_codec::{JsonCodec, MultiCodec, ProstCodec, Proto};
+use razor_rpc_tcp::{TcpClient, TcpServer};
+use std::sync::Arc;
+
+fn new_client<C: razor_rpc::Codec>(addr: &str, rt: crate::RT) -> GreetClient<PoolCaller<C>> {
+    let facts = APIClient::<C>::new(ClientConfig::default(), rt);
+    GreetClient::new(facts.create_pool_async::<TcpClient<crate::RT>>(addr))
+}
+
+#[logfn]
+#[rstest]
+fn test_api_prost(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        // The same service serves protobuf and JSON clients
+        let mut service_mux = ServiceMuxDyn::<MultiCodec<ProstCodec, JsonCodec>>::new();
+        service_mux.add(Arc::new(GreetServer {}));
+        let dispatch = Inline::new(Arc::new(service_mux));
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+
+        let client = new_client::<ProstCodec>(&addr, rt.clone());
+        let resp = client.greet(Proto(GreetReq { name: "razor".to_string(), times: 2 })).await;
+        assert_eq!(resp.unwrap().into_inner().lines, vec!["hello razor #0", "hello razor #1"]);
+        let resp = client.greet(Proto(GreetReq::default())).await;
+        match resp.unwrap_err() {
+            RpcError::User(msg) => assert_eq!(msg, "empty name"),
+            e => panic!("unexpected error {:?}", e),
+        }
+
+        let client = new_client::<JsonCodec>(&addr, rt);
+        let resp = client.greet(Proto(GreetReq { name: "json".to_string(), times: 1 })).await;
+        assert_eq!(resp.unwrap().lines, vec!["hello json #0"]);
+    });
+}
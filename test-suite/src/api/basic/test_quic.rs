@@ -0,0 +1,72 @@
+use super::test_tls::{ALPN, CERTS, PeerServer, TestTls};
+use crate::api::client::APIClient;
+use crate::api::server::{CalServer, StreamServer, create_api_server};
+use crate::api::service::{StreamClient, StreamService};
+use crate::*;
+use futures::StreamExt;
+use razor_rpc::client::{APIClientFacts, AsyncEndpoint, ClientConfig};
+use razor_rpc::server::dispatch::{Inline, Spawn};
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn};
+use razor_rpc_quic::{QuicClient, QuicServer};
+use std::sync::Arc;
+
+#[logfn]
+#[rstest]
+fn test_api_quic(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let mut service_mux = ServiceMuxDyn::<crate::Codec>::new();
+        service_mux.add(Arc::new(CalServer {}));
+        service_mux.add(Arc::new(PeerServer));
+        let dispatch = Inline::new(Arc::new(service_mux));
+        let addr = server
+            .listen::<QuicServer<crate::RT, TestTls>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt);
+        let endpoint =
+            AsyncEndpoint::new(facts.create_pool_async::<QuicClient<crate::RT, TestTls>>(&addr));
+        let r = endpoint.call::<_, isize, ()>("CalService.inc", &41isize).await;
+        assert_eq!(r.unwrap(), 42);
+        let r = endpoint
+            .call::<_, (Option<String>, Option<Vec<u8>>, Vec<u8>), ()>("PeerService.whoami", &())
+            .await;
+        let (server_name, alpn, cert) = r.unwrap();
+        assert_eq!(server_name.as_deref(), Some("localhost"));
+        assert_eq!(alpn.as_deref(), Some(ALPN));
+        assert_eq!(cert, CERTS.client_cert.to_vec());
+
+        // Each request on its own stream
+        let args: Vec<isize> = (0..200).collect();
+        let calls = args.iter().map(|i| endpoint.call::<_, isize, ()>("CalService.inc", i));
+        let results = futures::future::join_all(calls).await;
+        for (i, r) in results.into_iter().enumerate() {
+            assert_eq!(r.unwrap(), i as isize + 1);
+        }
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_api_quic_stream(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let dispatch = Spawn::<crate::Codec, _, _>::new(StreamServer::default(), rt.clone());
+        let addr = server
+            .listen::<QuicServer<crate::RT, TestTls>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt);
+        let client =
+            StreamClient::new(facts.create_pool_async::<QuicClient<crate::RT, TestTls>>(&addr));
+
+        let items: Vec<u32> =
+            client.range(1000).map(|item| item.expect("item")).collect::<Vec<_>>().await;
+        assert_eq!(items, (0..1000).collect::<Vec<_>>());
+        let sum = client.sum(futures::stream::iter(0..1000u32)).await.expect("sum");
+        assert_eq!(sum, (0..1000u64).sum::<u64>());
+    });
+}
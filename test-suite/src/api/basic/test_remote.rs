@@ -8,7 +8,6 @@ use nix::errno::Errno;
 use razor_rpc::client::ClientConfig;
 use razor_rpc::error::RpcError;
 use razor_rpc::server::ServerConfig;
-use razor_rpc_codec::MsgpCodec;
 use razor_rpc_tcp::TcpServer;
 
 // Import the service traits to make the methods available
@@ -68,7 +67,7 @@ fn test_api_remote_calls(runner: TestRunner, #[case] is_tcp: bool, #[case] dispa
 
         debug!("API server addr {:?}", actual_server_addr);
 
-        let client = MyClient::<MsgpCodec>::new(client_config, &actual_server_addr, rt_client);
+        let client = MyClient::<crate::Codec>::new(client_config, &actual_server_addr, rt_client);
 
         // Test CalService methods
         // Test inc method
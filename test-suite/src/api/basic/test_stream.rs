@@ -0,0 +1,191 @@
+use crate::api::client::APIClient;
+use crate::api::server::{StreamServer, create_api_server};
+use crate::api::service::{RANGE_LIMIT, SLOW_SUM_DELAY, StreamClient, StreamService};
+use crate::*;
+use futures::StreamExt;
+use futures::future::{Either, select};
+use futures::pin_mut;
+use razor_rpc::client::{APIClientFacts, ClientConfig};
+use razor_rpc::error::{RpcError, RpcIntErr};
+use razor_rpc::server::ServerConfig;
+use razor_rpc::server::dispatch::{Inline, Spawn};
+use razor_rpc_tcp::{TcpClient, TcpServer};
+use razor_stream::proto::RPC_STREAM_WINDOW;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+type Client = StreamClient<crate::api::client::PoolCaller<crate::Codec>>;
+
+fn new_client(addr: &str, rt: crate::RT) -> Client {
+    let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt);
+    StreamClient::new(facts.create_pool_async::<TcpClient<crate::RT>>(addr))
+}
+
+#[logfn]
+#[rstest]
+#[case(0)]
+#[case(10)]
+#[case(1000)]
+fn test_api_stream_range(runner: TestRunner, #[case] count: u32) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let dispatch = Spawn::<crate::Codec, _, _>::new(StreamServer::default(), rt.clone());
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let client = new_client(&addr, rt);
+
+        let items: Vec<u32> =
+            client.range(count).map(|item| item.expect("item")).collect::<Vec<_>>().await;
+        assert_eq!(items, (0..count).collect::<Vec<_>>());
+
+        let items = client.range(RANGE_LIMIT + 1).collect::<Vec<_>>().await;
+        assert_eq!(items.len(), 1);
+        match &items[0] {
+            Err(RpcError::User(e)) => assert_eq!(e, "too many"),
+            r => panic!("unexpected {:?}", r),
+        }
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_api_stream_slow_consumer(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let service = StreamServer::default();
+        let produced = service.produced.clone();
+        let dispatch = Spawn::<crate::Codec, _, _>::new(service, rt.clone());
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let client = new_client(&addr, rt);
+
+        let count = RPC_STREAM_WINDOW * 10;
+        let stream = client.range(count);
+        futures::pin_mut!(stream);
+        let mut consumed = 0;
+        for round in 0..4 {
+            for _ in 0..RPC_STREAM_WINDOW {
+                assert_eq!(stream.next().await.unwrap().expect("item"), consumed);
+                consumed += 1;
+            }
+            crate::RT::sleep(Duration::from_millis(200)).await;
+            // The server is held back by the credit
+            let ahead = produced.load(Ordering::SeqCst) - consumed as usize;
+            log::info!("round {} server is {} items ahead", round, ahead);
+            assert!(ahead <= RPC_STREAM_WINDOW as usize);
+        }
+        while let Some(item) = stream.next().await {
+            assert_eq!(item.expect("item"), consumed);
+            consumed += 1;
+        }
+        assert_eq!(consumed, count);
+    });
+}
+
+#[logfn]
+#[rstest]
+#[case(0)]
+#[case(100)]
+#[case(1000)]
+fn test_api_stream_sum(runner: TestRunner, #[case] count: u32) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let dispatch = Spawn::<crate::Codec, _, _>::new(StreamServer::default(), rt.clone());
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let client = new_client(&addr, rt);
+
+        let sum = client.sum(futures::stream::iter(0..count)).await.expect("sum");
+        assert_eq!(sum, (0..count as u64).sum::<u64>());
+        // The connection is still good for other calls
+        let items = client.range(3).map(|item| item.expect("item")).collect::<Vec<_>>().await;
+        assert_eq!(items, vec![0, 1, 2]);
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_api_stream_slow_input(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let dispatch = Spawn::<crate::Codec, _, _>::new(StreamServer::default(), rt.clone());
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let client = new_client(&addr, rt);
+
+        let count = RPC_STREAM_WINDOW * 4;
+        let sum_f = client.slow_sum(futures::stream::iter(0..count));
+        let other_f = async {
+            crate::RT::sleep(Duration::from_millis(100)).await;
+            let start = Instant::now();
+            // The handler of slow_sum holds back its own input, not the connection
+            let items = client.range(3).map(|item| item.expect("item")).collect::<Vec<_>>().await;
+            assert_eq!(items, vec![0, 1, 2]);
+            assert!(start.elapsed() < SLOW_SUM_DELAY / 2);
+        };
+        let (sum, _) = futures::future::join(sum_f, other_f).await;
+        assert_eq!(sum.expect("sum"), (0..count as u64).sum::<u64>());
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_api_stream_sum_dropped(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let service = StreamServer::default();
+        let summed = service.summed.clone();
+        let dispatch = Spawn::<crate::Codec, _, _>::new(service, rt.clone());
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let client = new_client(&addr, rt);
+
+        {
+            // The input never ends, drop the call
+            let sum_f = client.sum(futures::stream::pending());
+            let sleep_f = crate::RT::sleep(Duration::from_millis(100));
+            pin_mut!(sum_f, sleep_f);
+            assert!(matches!(select(sum_f, sleep_f).await, Either::Right(_)));
+        }
+        // The cancel frame is sent on drop, the input of the server is closed before the timer tick
+        crate::RT::sleep(Duration::from_millis(300)).await;
+        assert_eq!(summed.load(Ordering::SeqCst), 1);
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_api_stream_inline_unsupported(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let dispatch = Inline::<crate::Codec, _>::new(StreamServer::default());
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+        let client = new_client(&addr, rt);
+
+        let items = client.range(10).collect::<Vec<_>>().await;
+        assert_eq!(items.len(), 1);
+        assert!(matches!(items[0], Err(RpcError::Rpc(RpcIntErr::Internal))));
+
+        let r = client.sum(futures::stream::iter(0..100)).await;
+        assert!(matches!(r, Err(RpcError::Rpc(RpcIntErr::Internal))));
+    });
+}
@@ -0,0 +1,139 @@
+use crate::api::client::APIClient;
+use crate::api::server::{CalServer, create_api_server};
+use crate::*;
+use razor_rpc::Codec;
+use razor_rpc::client::{APIClientFacts, AsyncEndpoint, ClientConfig};
+use razor_rpc::server::task::APIServerReq;
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn, ServiceStatic, dispatch::Inline};
+use razor_rpc_tls::rustls::pki_types::{
+    CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName,
+};
+use razor_rpc_tls::rustls::{self, RootCertStore};
+use razor_rpc_tls::{TlsClient, TlsClientProvider, TlsServer, TlsServerProvider};
+use std::sync::{Arc, LazyLock};
+
+pub(crate) const ALPN: &[u8] = b"razor";
+
+pub(crate) struct TestCerts {
+    server: Arc<rustls::ServerConfig>,
+    client: Arc<rustls::ClientConfig>,
+    /// Without the client certificate
+    anonymous: Arc<rustls::ClientConfig>,
+    pub client_cert: CertificateDer<'static>,
+}
+
+fn self_signed(name: &str) -> (CertificateDer<'static>, PrivateKeyDer<'static>) {
+    let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).expect("rcgen");
+    let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+    (cert.cert.der().clone(), key.into())
+}
+
+pub(crate) static CERTS: LazyLock<TestCerts> = LazyLock::new(|| {
+    let (server_cert, server_key) = self_signed("localhost");
+    let (client_cert, client_key) = self_signed("client.razor");
+    let mut client_roots = RootCertStore::empty();
+    client_roots.add(client_cert.clone()).expect("add root");
+    let mut server_roots = RootCertStore::empty();
+    server_roots.add(server_cert.clone()).expect("add root");
+    let server =
+        razor_rpc_tls::server_config(vec![server_cert], server_key, Some(client_roots), &[ALPN])
+            .expect("server config");
+    let client = razor_rpc_tls::client_config(
+        server_roots.clone(),
+        Some((vec![client_cert.clone()], client_key)),
+        &[ALPN],
+    )
+    .expect("client config");
+    let anonymous =
+        razor_rpc_tls::client_config(server_roots, None, &[ALPN]).expect("client config");
+    TestCerts {
+        server: Arc::new(server),
+        client: Arc::new(client),
+        anonymous: Arc::new(anonymous),
+        client_cert,
+    }
+});
+
+pub(crate) struct TestTls;
+
+impl TlsServerProvider for TestTls {
+    fn server_config() -> Arc<rustls::ServerConfig> {
+        CERTS.server.clone()
+    }
+}
+
+impl TlsClientProvider for TestTls {
+    fn client_config() -> Arc<rustls::ClientConfig> {
+        CERTS.client.clone()
+    }
+
+    // Connect by ip, verify and send SNI by name
+    fn server_name(_addr: &str) -> Option<ServerName<'static>> {
+        Some(ServerName::try_from("localhost").unwrap())
+    }
+}
+
+struct AnonymousTls;
+
+impl TlsClientProvider for AnonymousTls {
+    fn client_config() -> Arc<rustls::ClientConfig> {
+        CERTS.anonymous.clone()
+    }
+
+    fn server_name(_addr: &str) -> Option<ServerName<'static>> {
+        Some(ServerName::try_from("localhost").unwrap())
+    }
+}
+
+/// Answer the identity of the client verified by TLS
+pub(crate) struct PeerServer;
+
+impl<C: Codec> ServiceStatic<C> for PeerServer {
+    const SERVICE_NAME: &'static str = "PeerService";
+
+    async fn serve(&self, req: APIServerReq<C>) {
+        let Some(peer) = req.peer.clone() else {
+            return req.set_result((None::<String>, None::<Vec<u8>>, Vec::<u8>::new()));
+        };
+        let cert = peer.certs.first().cloned().unwrap_or_default();
+        req.set_result((peer.server_name.clone(), peer.alpn.clone(), cert));
+    }
+}
+
+#[logfn]
+#[rstest]
+fn test_api_tls(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let mut service_mux = ServiceMuxDyn::<crate::Codec>::new();
+        service_mux.add(Arc::new(CalServer {}));
+        service_mux.add(Arc::new(PeerServer));
+        let dispatch = Inline::new(Arc::new(service_mux));
+        let addr = server
+            .listen::<TlsServer<crate::RT, TestTls>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen");
+
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt.clone());
+        let endpoint =
+            AsyncEndpoint::new(facts.create_pool_async::<TlsClient<crate::RT, TestTls>>(&addr));
+        let r = endpoint.call::<_, isize, ()>("CalService.inc", &41isize).await;
+        assert_eq!(r.unwrap(), 42);
+        let r = endpoint
+            .call::<_, (Option<String>, Option<Vec<u8>>, Vec<u8>), ()>("PeerService.whoami", &())
+            .await;
+        let (server_name, alpn, cert) = r.unwrap();
+        assert_eq!(server_name.as_deref(), Some("localhost"));
+        assert_eq!(alpn.as_deref(), Some(ALPN));
+        assert_eq!(cert, CERTS.client_cert.to_vec());
+
+        // The server requires the client certificate
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt);
+        let endpoint = AsyncEndpoint::new(
+            facts.create_pool_async::<TlsClient<crate::RT, AnonymousTls>>(&addr),
+        );
+        let r = endpoint.call::<_, isize, ()>("CalService.inc", &1isize).await;
+        assert!(r.is_err());
+    });
+}
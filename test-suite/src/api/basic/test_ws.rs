@@ -0,0 +1,56 @@
+use crate::api::client::APIClient;
+use crate::api::server::{CalServer, create_api_server};
+use crate::*;
+use orb::net::UnifyStream;
+use orb::prelude::*;
+use razor_rpc::client::{APIClientFacts, AsyncEndpoint, ClientConfig};
+use razor_rpc::server::{ServerConfig, ServiceMuxDyn, dispatch::Inline};
+use razor_rpc_tcp::{TcpClient, TcpServer};
+use razor_rpc_ws::{WsClient, WsServer};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[logfn]
+#[rstest]
+fn test_api_ws(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        // Serve WebSocket next to TCP with the same dispatch
+        let mut server = create_api_server(ServerConfig::default(), rt.clone());
+        let mut service_mux = ServiceMuxDyn::<crate::Codec>::new();
+        service_mux.add(Arc::new(CalServer {}));
+        let dispatch = Inline::new(Arc::new(service_mux));
+        let tcp_addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch.clone())
+            .await
+            .expect("listen tcp");
+        let ws_addr = server
+            .listen::<WsServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("listen ws");
+
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt.clone());
+        let endpoint = AsyncEndpoint::new(
+            facts.create_pool_async::<WsClient<crate::RT>>(&format!("ws://{}/rpc", ws_addr)),
+        );
+        for i in 0..100isize {
+            let r = endpoint.call::<_, isize, ()>("CalService.inc", &i).await;
+            assert_eq!(r.unwrap(), i + 1);
+        }
+        let facts = APIClient::<crate::Codec>::new(ClientConfig::default(), rt);
+        let endpoint =
+            AsyncEndpoint::new(facts.create_pool_async::<TcpClient<crate::RT>>(&tcp_addr));
+        let r = endpoint.call::<_, isize, ()>("CalService.inc", &41isize).await;
+        assert_eq!(r.unwrap(), 42);
+
+        // The plain HTTP request is refused
+        let mut stream =
+            UnifyStream::<crate::RT>::connect_timeout(&ws_addr, Duration::from_secs(1))
+                .await
+                .expect("connect");
+        stream.write_all(b"GET /rpc HTTP/1.1\r\nHost: localhost\r\n\r\n").await.expect("write");
+        let mut buf = vec![0u8; 1024];
+        let n = stream.read(&mut buf).await.expect("read");
+        assert!(buf[..n].starts_with(b"HTTP/1.1 426"), "{}", String::from_utf8_lossy(&buf[..n]));
+    });
+}
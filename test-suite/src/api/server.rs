@@ -1,12 +1,15 @@
 use super::service::*;
+use futures::{Stream, StreamExt};
 use nix::errno::Errno;
 use razor_rpc::error::RpcError;
 use razor_rpc::server::{ServiceMuxDyn, dispatch::Inline, service, service_mux_struct};
-use razor_rpc_codec::MsgpCodec;
+use razor_rpc_codec::Proto;
 use razor_rpc_tcp::TcpServer;
 use razor_stream::server::{RpcServer, ServerConfig};
+use orb::prelude::*;
 use rstest::*;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 pub type APIServer = razor_rpc::server::ServerDefault<crate::RT>;
 
@@ -46,6 +49,62 @@ impl EchoService for EchoServer {
     async fn io_error(&self, _msg: String) -> Result<(), RpcError<Errno>> {
         return Err(RpcError::User(Errno::EIO));
     }
+
+    async fn notify(&self, msg: String) -> Result<(), RpcError<()>> {
+        NOTIFIED.lock().unwrap().push(msg);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GreetServer();
+
+#[service]
+impl GreetService for GreetServer {
+    async fn greet(&self, req: Proto<GreetReq>) -> Result<Proto<GreetResp>, RpcError<String>> {
+        if req.name.is_empty() {
+            return Err(RpcError::User("empty name".to_string()));
+        }
+        let lines = (0..req.times).map(|i| format!("hello {} #{}", req.name, i)).collect();
+        Ok(Proto(GreetResp { lines }))
+    }
+}
+
+/// The messages received by EchoServer::notify()
+pub static NOTIFIED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[derive(Clone, Debug, Default)]
+pub struct StreamServer {
+    /// The number of items taken from the range streams
+    pub produced: Arc<AtomicUsize>,
+    /// The number of finished sum calls
+    pub summed: Arc<AtomicUsize>,
+}
+
+#[service]
+impl StreamService for StreamServer {
+    fn range(&self, n: u32) -> impl Stream<Item = Result<u32, RpcError<String>>> + Send {
+        let produced = self.produced.clone();
+        futures::stream::iter(0..n).map(move |i| {
+            if n > RANGE_LIMIT {
+                // The stream ends with the first error
+                return Err(RpcError::User("too many".to_string()));
+            }
+            produced.fetch_add(1, Ordering::SeqCst);
+            Ok(i)
+        })
+    }
+
+    async fn sum(&self, input: impl Stream<Item = u32> + Send) -> Result<u64, RpcError<()>> {
+        let sum = input.fold(0u64, |acc, i| async move { acc + i as u64 }).await;
+        self.summed.fetch_add(1, Ordering::SeqCst);
+        Ok(sum)
+    }
+
+    async fn slow_sum(&self, input: impl Stream<Item = u32> + Send) -> Result<u64, RpcError<()>> {
+        crate::RT::sleep(SLOW_SUM_DELAY).await;
+        self.sum(input).await
+    }
 }
 
 // Create an API server with the given services
@@ -64,7 +123,7 @@ pub async fn listen_with_services(
     echo_server: EchoServer,
 ) -> Result<(RpcServer<APIServer>, String), Box<dyn std::error::Error>> {
     // Create service mux and add services
-    let mut service_mux = ServiceMuxDyn::<MsgpCodec>::new();
+    let mut service_mux = ServiceMuxDyn::<crate::Codec>::new();
     service_mux.add(Arc::new(cal_server));
     service_mux.add(Arc::new(echo_server));
 
@@ -92,7 +151,7 @@ pub fn echo_server() -> EchoServer {
 pub fn create_service_mux_dispatch(
     cal_server: CalServer, echo_server: EchoServer,
 ) -> impl razor_stream::server::dispatch::Dispatch {
-    let mut service_mux = ServiceMuxDyn::<MsgpCodec>::new();
+    let mut service_mux = ServiceMuxDyn::<crate::Codec>::new();
     service_mux.add(Arc::new(cal_server));
     service_mux.add(Arc::new(echo_server));
 
@@ -112,11 +171,11 @@ pub fn create_service_mux_struct_dispatch(
 
     let service_mux = TestServiceMux { cal: Arc::new(cal_server), echo: Arc::new(echo_server) };
 
-    Inline::<MsgpCodec, TestServiceMux>::new(service_mux)
+    Inline::<crate::Codec, TestServiceMux>::new(service_mux)
 }
 
 // Fixture that returns a service mux dispatch
 #[fixture]
-pub fn service_mux_dispatch() -> ServiceMuxDyn<MsgpCodec> {
-    ServiceMuxDyn::<MsgpCodec>::new()
+pub fn service_mux_dispatch() -> ServiceMuxDyn<crate::Codec> {
+    ServiceMuxDyn::<crate::Codec>::new()
 }
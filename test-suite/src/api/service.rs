@@ -1,6 +1,8 @@
+use futures::Stream;
 use nix::errno::Errno;
-use razor_rpc::client::endpoint_async;
+use razor_rpc::client::{endpoint_async, oneway};
 use razor_rpc::error::RpcError;
+use razor_rpc_codec::Proto;
 
 #[endpoint_async(CalClient)]
 #[async_trait::async_trait]
@@ -17,4 +19,48 @@ pub trait EchoService {
     fn repeat(&self, msg: String) -> impl Future<Output = Result<String, RpcError<()>>> + Send;
 
     fn io_error(&self, _msg: String) -> impl Future<Output = Result<(), RpcError<Errno>>> + Send;
+
+    #[oneway]
+    fn notify(&self, msg: String) -> impl Future<Output = Result<(), RpcError<()>>> + Send;
+}
+
+#[endpoint_async(StreamClient)]
+pub trait StreamService {
+    /// Yields 0..n, or an error if n exceeds RANGE_LIMIT
+    fn range(&self, n: u32) -> impl Stream<Item = Result<u32, RpcError<String>>> + Send;
+
+    fn sum(
+        &self, input: impl Stream<Item = u32> + Send,
+    ) -> impl Future<Output = Result<u64, RpcError<()>>> + Send;
+
+    /// Like sum, but takes the input only after SLOW_SUM_DELAY
+    fn slow_sum(
+        &self, input: impl Stream<Item = u32> + Send,
+    ) -> impl Future<Output = Result<u64, RpcError<()>>> + Send;
+}
+
+pub const SLOW_SUM_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+pub const RANGE_LIMIT: u32 = 10000;
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GreetReq {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(uint32, tag = "2")]
+    pub times: u32,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct GreetResp {
+    #[prost(string, repeated, tag = "1")]
+    pub lines: Vec<String>,
+}
+
+/// Service with prost messages, served by ProstCodec
+#[endpoint_async(GreetClient)]
+pub trait GreetService {
+    fn greet(
+        &self, req: Proto<GreetReq>,
+    ) -> impl Future<Output = Result<Proto<GreetResp>, RpcError<String>>> + Send;
 }
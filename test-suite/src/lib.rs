@@ -32,7 +32,11 @@ pub fn new_rt() -> RT {
     }
 }
 
+#[cfg(not(feature = "bincode"))]
 pub type Codec = razor_rpc_codec::MsgpCodec;
+/// Run the test-suite with bincode by `-F bincode`
+#[cfg(feature = "bincode")]
+pub type Codec = razor_rpc_codec::BincodeCodec;
 
 #[macro_export]
 macro_rules! async_spawn {
@@ -1,5 +1,15 @@
+mod test_checksum;
+mod test_chunk;
 mod test_client_drop;
+mod test_compress;
 mod test_error_handling;
+mod test_format;
+mod test_handshake;
+mod test_mem;
 mod test_normal;
+mod test_oneway;
 mod test_ping;
+mod test_push;
+mod test_shm;
+mod test_stream;
 mod test_timeout;
@@ -0,0 +1,94 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::compress::{COMPRESS_LZ4, COMPRESS_NONE};
+use razor_stream::proto::{RPC_FEATURE_CHECKSUM, RpcAction};
+use razor_stream::server::{
+    ServerConfig,
+    task::{ServerTaskAction, ServerTaskDone},
+};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+#[logfn]
+#[rstest]
+#[case(true, COMPRESS_NONE)]
+#[case(true, COMPRESS_LZ4)]
+#[case(false, COMPRESS_NONE)]
+fn test_checksum(runner: TestRunner, #[case] checksum: bool, #[case] compression: u8) {
+    let client_config = ClientConfig { checksum, compression, ..Default::default() };
+    let server_config = ServerConfig { compression, ..Default::default() };
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    let store: Arc<Mutex<Option<Buffer>>> = Arc::new(Mutex::new(None));
+    let dispatch_task = {
+        let _store = store.clone();
+        move |task: FileServerTask| async move {
+            match task {
+                FileServerTask::Open(open_task) => {
+                    open_task.set_result(Ok(()));
+                }
+                FileServerTask::IO(mut io_task) => {
+                    let RpcAction::Num(action_num) = io_task.get_action() else {
+                        return Err(());
+                    };
+                    match FileAction::try_from(action_num as u8).map_err(|_| ())? {
+                        FileAction::Write => {
+                            let blob = io_task.req_blob.take().expect("blob");
+                            io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                            _store.lock().unwrap().replace(blob);
+                        }
+                        FileAction::Read => {
+                            let blob = _store.lock().unwrap().take().expect("stored");
+                            io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                            io_task.resp_blob = Some(blob);
+                        }
+                        _ => unreachable!(),
+                    }
+                    io_task.set_result(Ok(()));
+                }
+            }
+            Ok(())
+        }
+    };
+
+    runner.block_on(async move {
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            "127.0.0.1:0",
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+        assert_eq!(client.get_negotiated().has(RPC_FEATURE_CHECKSUM), checksum);
+
+        client.ping().await.expect("ping");
+        let (tx, rx) = mpsc::unbounded_async();
+        let open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        assert!(rx.recv().await.unwrap().get_result().is_ok());
+
+        let data_len = 16 * 1024;
+        let mut write_data = Buffer::alloc(data_len).expect("alloc");
+        rand_buffer(&mut write_data);
+        let write_task = FileClientTaskWrite::new(tx.clone(), 1, 0, write_data.clone());
+        client.send_task(write_task.into(), true).await.expect("send write task");
+        assert!(rx.recv().await.unwrap().get_result().is_ok());
+
+        let read_task = FileClientTaskRead::new(tx.clone(), 1, 0, data_len as usize);
+        client.send_task(read_task.into(), true).await.expect("send read task");
+        let completed_read_task = rx.recv().await.unwrap();
+        assert!(completed_read_task.get_result().is_ok());
+        if let FileClientTask::Read(task) = completed_read_task {
+            assert_eq!(task.read_data.as_ref().unwrap().as_ref(), write_data.as_ref());
+        }
+        assert!(!client.is_closed());
+    });
+}
@@ -0,0 +1,84 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::proto::{RPC_CHUNK_SIZE, RPC_FEATURE_CHUNK};
+use razor_stream::server::{ServerConfig, task::ServerTaskDone};
+use std::sync::Arc;
+
+#[logfn]
+#[rstest]
+#[case(true, false)]
+#[case(true, true)]
+#[case(false, false)]
+fn test_chunk(runner: TestRunner, #[case] handshake: bool, #[case] checksum: bool) {
+    let client_config = ClientConfig { handshake, checksum, ..Default::default() };
+    let server_config = ServerConfig::default();
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    let data_len = RPC_CHUNK_SIZE * 2 + 1000;
+    let mut data = Buffer::alloc(data_len as i32).expect("alloc");
+    rand_buffer(&mut data);
+    let data = Arc::new(data);
+    let dispatch_task = {
+        let _data = data.clone();
+        move |task: FileServerTask| async move {
+            match task {
+                FileServerTask::Open(open_task) => {
+                    open_task.set_result(Ok(()));
+                }
+                FileServerTask::IO(mut io_task) => {
+                    io_task.resp = Some(FileIOResp { ret_size: _data.len() as u64 });
+                    io_task.resp_blob = Some((*_data).clone());
+                    io_task.set_result(Ok(()));
+                }
+            }
+            Ok(())
+        }
+    };
+
+    runner.block_on(async move {
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            "127.0.0.1:0",
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+        assert_eq!(client.get_negotiated().has(RPC_FEATURE_CHUNK), handshake);
+        let (tx, rx) = mpsc::unbounded_async();
+
+        // The chunks are written into the pre-allocated buffer
+        let mut read_task = FileClientTaskRead::new(tx.clone(), 1, 0, data_len);
+        read_task.read_data = Some(Buffer::alloc(data_len as i32).expect("alloc"));
+        client.send_task(read_task.into(), true).await.expect("send read task");
+        let completed_read_task = rx.recv().await.unwrap();
+        assert!(completed_read_task.get_result().is_ok());
+        if let FileClientTask::Read(task) = completed_read_task {
+            assert_eq!(task.resp.as_ref().unwrap().ret_size, data_len as u64);
+            assert_eq!(&task.read_data.as_ref().unwrap()[..], &data[..]);
+            assert_eq!(task.resp_blob_received, if handshake { data_len as u64 } else { 0 });
+        }
+
+        // Without a buffer, the buffer grows with the chunks
+        let read_task = FileClientTaskRead::new(tx.clone(), 1, 0, data_len);
+        client.send_task(read_task.into(), true).await.expect("send read task");
+        let completed_read_task = rx.recv().await.unwrap();
+        assert!(completed_read_task.get_result().is_ok());
+        if let FileClientTask::Read(task) = completed_read_task {
+            assert_eq!(&task.read_data.as_ref().unwrap()[..], &data[..]);
+        }
+
+        let open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        assert!(rx.recv().await.unwrap().get_result().is_ok());
+        assert!(rx.try_recv().is_err());
+        assert!(!client.is_closed());
+    });
+}
@@ -0,0 +1,102 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::Buffer;
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::compress::{COMPRESS_LZ4, COMPRESS_NONE, COMPRESS_ZSTD};
+use razor_stream::proto::RpcAction;
+use razor_stream::server::{
+    ServerConfig,
+    task::{ServerTaskAction, ServerTaskDone},
+};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+
+#[logfn]
+#[rstest]
+#[case(COMPRESS_LZ4, COMPRESS_LZ4)]
+#[case(COMPRESS_ZSTD, COMPRESS_ZSTD)]
+#[case(COMPRESS_LZ4, COMPRESS_NONE)]
+#[case(COMPRESS_NONE, COMPRESS_ZSTD)]
+#[case(COMPRESS_NONE, COMPRESS_NONE)]
+fn test_compress(runner: TestRunner, #[case] client_compress: u8, #[case] server_compress: u8) {
+    let client_config = ClientConfig { compression: client_compress, ..Default::default() };
+    let server_config = ServerConfig { compression: server_compress, ..Default::default() };
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    let store: Arc<Mutex<Option<Buffer>>> = Arc::new(Mutex::new(None));
+    let dispatch_task = {
+        let _store = store.clone();
+        move |task: FileServerTask| async move {
+            match task {
+                FileServerTask::Open(open_task) => {
+                    open_task.set_result(Ok(()));
+                }
+                FileServerTask::IO(mut io_task) => {
+                    let RpcAction::Num(action_num) = io_task.get_action() else {
+                        return Err(());
+                    };
+                    match FileAction::try_from(action_num as u8).map_err(|_| ())? {
+                        FileAction::Write => {
+                            let blob = io_task.req_blob.take().expect("blob");
+                            io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                            _store.lock().unwrap().replace(blob);
+                        }
+                        FileAction::Read => {
+                            let blob = _store.lock().unwrap().take().expect("stored");
+                            io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                            io_task.resp_blob = Some(blob);
+                        }
+                        _ => unreachable!(),
+                    }
+                    io_task.set_result(Ok(()));
+                }
+            }
+            Ok(())
+        }
+    };
+
+    runner.block_on(async move {
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            "127.0.0.1:0",
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+        // The preference of server wins, lz4 is the first supported when server has none
+        let expected =
+            if server_compress != COMPRESS_NONE { server_compress } else { client_compress };
+        if expected != COMPRESS_NONE {
+            assert_eq!(client.get_negotiated().compression, expected);
+        }
+
+        // Compressible data larger than the threshold
+        let data_len = 64 * 1024;
+        let data: Vec<u8> = (0..data_len).map(|i| (i % 251) as u8).collect();
+        let write_data = Buffer::from(data);
+        let (tx, rx) = mpsc::unbounded_async();
+        let write_task = FileClientTaskWrite::new(tx.clone(), 1, 0, write_data.clone());
+        client.send_task(write_task.into(), true).await.expect("send write task");
+        let completed_write_task = rx.recv().await.unwrap();
+        assert!(completed_write_task.get_result().is_ok());
+        if let FileClientTask::Write(task) = completed_write_task {
+            assert_eq!(task.resp.unwrap().ret_size, data_len as u64);
+        }
+
+        let read_task = FileClientTaskRead::new(tx.clone(), 1, 0, data_len as usize);
+        client.send_task(read_task.into(), true).await.expect("send read task");
+        let completed_read_task = rx.recv().await.unwrap();
+        assert!(completed_read_task.get_result().is_ok());
+        if let FileClientTask::Read(task) = completed_read_task {
+            assert_eq!(task.resp.unwrap().ret_size, data_len as u64);
+            assert_eq!(task.read_data.as_ref().unwrap().as_ref(), write_data.as_ref());
+        }
+        assert!(!client.is_closed());
+    });
+}
@@ -3,7 +3,7 @@ use crate::*;
 use crossfire::mpsc;
 use nix::errno::Errno;
 use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
-use razor_stream::error::RpcError;
+use razor_stream::error::{RpcError, RpcIntErr};
 use razor_stream::server::{ServerConfig, task::ServerTaskDone};
 
 #[logfn]
@@ -72,3 +72,46 @@ fn test_server_returns_error(runner: TestRunner, #[case] is_tcp: bool) {
         log::info!("Write task completed successfully.");
     });
 }
+
+#[logfn]
+#[rstest]
+fn test_dispatch_err(runner: TestRunner) {
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    // The handler fails without answering the IO task
+    let dispatch_task = move |task: FileServerTask| async move {
+        match task {
+            FileServerTask::Open(open_task) => {
+                open_task.set_result(Ok(()));
+                Ok(())
+            }
+            FileServerTask::IO(_io_task) => Err(()),
+        }
+    };
+
+    runner.block_on(async move {
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            ServerConfig::default(),
+            "127.0.0.1:0",
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(ClientConfig::default(), &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+
+        let (tx, rx) = mpsc::unbounded_async();
+        let write_task = FileClientTaskWrite::new(tx.clone(), 1, 0, vec![1, 2, 3].into());
+        client.send_task(write_task.into(), true).await.expect("send write task");
+        let result = rx.recv().await.unwrap();
+        // Answered as an internal error, not a decode error
+        assert_eq!(result.get_result().unwrap_err(), &RpcError::Rpc(RpcIntErr::Internal));
+
+        let open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        assert!(rx.recv().await.unwrap().get_result().is_ok());
+    });
+}
@@ -0,0 +1,88 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use orb::net::UnifyStream;
+use orb::prelude::*;
+use razor_rpc_codec::{Codec, JsonCodec, MultiCodec};
+use razor_rpc_tcp::TcpServer;
+use razor_stream::compress::{COMPRESS_NONE, Compressor};
+use razor_stream::proto::{FrameDecoder, ReqHead, RpcAction};
+use razor_stream::server::{ServerConfig, dispatch::DispatchClosure, task::ServerTaskDone};
+use std::time::Duration;
+
+/// Encode a read request of `inode` with `codec`
+fn encode_read<C: Codec>(codec: &C, seq: u64, inode: u64) -> Vec<u8> {
+    let (tx, _rx) = mpsc::unbounded_async();
+    let mut task = FileClientTaskRead::new(tx, inode, 0, 0);
+    task.common.seq = seq;
+    let task: FileClientTask = task.into();
+    let mut buf = Vec::with_capacity(256);
+    let mut blob_buf = Vec::new();
+    let compressor = Compressor::new(COMPRESS_NONE, COMPRESS_NONE, 0);
+    let action = RpcAction::Num(FileAction::Read as i32);
+    ReqHead::encode(codec, &mut buf, 0, &task, action, &compressor, &mut blob_buf).expect("encode");
+    buf
+}
+
+#[logfn]
+#[rstest]
+fn test_resp_format(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    // The read of inode 1 is answered after the following requests
+    let dispatch_task = move |task: FileServerTask| async move {
+        if let FileServerTask::IO(mut io_task) = task {
+            async_spawn_detach!(async move {
+                if io_task.req.inode == 1 {
+                    crate::RT::sleep(Duration::from_millis(200)).await;
+                }
+                io_task.resp = Some(FileIOResp { ret_size: io_task.req.inode });
+                io_task.set_result(Ok(()));
+            });
+        }
+        Ok(())
+    };
+
+    runner.block_on(async move {
+        let mut server = init_server(ServerConfig::default(), rt);
+        let dispatch = DispatchClosure::<
+            MultiCodec<crate::Codec, JsonCodec>,
+            FileServerTask,
+            FileServerTask,
+            _,
+            _,
+        >::new(dispatch_task);
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("server listen");
+
+        // One connection sending requests of two formats
+        let mut stream = UnifyStream::<crate::RT>::connect_timeout(&addr, Duration::from_secs(1))
+            .await
+            .expect("connect");
+        stream.write_all(&encode_read(&JsonCodec::default(), 1, 1)).await.expect("write");
+        stream.write_all(&encode_read(&crate::Codec::default(), 2, 2)).await.expect("write");
+
+        // Each response is encoded with the format of its request
+        let mut decoder = FrameDecoder::new();
+        for expect_seq in [2u64, 1] {
+            loop {
+                let want = decoder.want_resp().expect("resp head");
+                if want == 0 {
+                    break;
+                }
+                stream.read_exact(decoder.read_buf(want)).await.expect("read");
+                decoder.advance(want);
+            }
+            let frame = decoder.decode_resp().expect("decode").expect("frame");
+            let seq = frame.head.seq.get();
+            assert_eq!(seq, expect_seq);
+            let resp: FileIOResp = if seq == 1 {
+                JsonCodec::default().decode(frame.msg).expect("json")
+            } else {
+                crate::Codec::default().decode(frame.msg).expect("default codec")
+            };
+            assert_eq!(resp.ret_size, seq);
+        }
+    });
+}
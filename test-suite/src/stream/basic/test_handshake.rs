@@ -0,0 +1,78 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::error::{RpcError, RpcIntErr};
+use razor_stream::proto::{
+    Negotiated, RPC_FEATURE_CHECKSUM, RPC_FEATURE_PUSH, RPC_FEATURES_ALL, RPC_VERSION_MAX,
+};
+use razor_stream::server::{ServerConfig, task::ServerTaskDone};
+use std::time::Duration;
+
+#[logfn]
+#[rstest]
+#[case(true)]
+#[case(false)]
+fn test_client_handshake(runner: TestRunner, #[case] handshake: bool) {
+    let client_config = ClientConfig { handshake, ..Default::default() };
+    let server_config = ServerConfig::default();
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    let dispatch_task = move |task: FileServerTask| async move {
+        match task {
+            FileServerTask::Open(open_task) => {
+                open_task.set_result(Ok(()));
+            }
+            FileServerTask::IO(mut io_task) => {
+                io_task.resp = Some(Default::default());
+                io_task.set_result(Ok(()));
+            }
+        }
+        Ok(())
+    };
+
+    runner.block_on(async move {
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            "127.0.0.1:0",
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+        let negotiated = client.get_negotiated().clone();
+        if handshake {
+            assert_eq!(negotiated.version, RPC_VERSION_MAX);
+            // checksum and push are opt-in
+            assert_eq!(
+                negotiated.features,
+                RPC_FEATURES_ALL & !RPC_FEATURE_CHECKSUM & !RPC_FEATURE_PUSH
+            );
+        } else {
+            assert_eq!(negotiated, Negotiated::default());
+        }
+        // The requests still work after handshake
+        let (tx, rx) = mpsc::unbounded_async();
+        let open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        let completed_open_task = rx.recv().await.unwrap();
+        assert!(completed_open_task.get_result().is_ok());
+        // The deadline is carried in meta, which the v1 server does not understand
+        let mut open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        open_task.common.set_timeout(Duration::from_secs(5));
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        let completed_open_task = rx.recv().await.unwrap();
+        if handshake {
+            assert!(completed_open_task.get_result().is_ok());
+        } else {
+            assert_eq!(
+                completed_open_task.get_result().unwrap_err(),
+                &RpcError::Rpc(RpcIntErr::Version)
+            );
+        }
+        assert!(!client.is_closed());
+    });
+}
@@ -0,0 +1,166 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use razor_rpc_mem::{MemClient, MemServer};
+use razor_stream::client::stream::ClientStream;
+use razor_stream::client::{
+    ClientCaller, ClientConfig, ClientPool, FailoverPool, task::ClientTaskGetResult,
+};
+use razor_stream::error::RpcIntErr;
+use razor_stream::server::{RpcServer, ServerConfig};
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+use std::time::Duration;
+
+async fn init_mem_server<H, FH>(
+    server_handle: H, addr: &str, rt: crate::RT,
+) -> (RpcServer<MyServer>, String)
+where
+    H: FnOnce(FileServerTask) -> FH + Send + Sync + 'static + Clone,
+    FH: Future<Output = Result<(), ()>> + Send + 'static,
+{
+    let mut server = init_server(ServerConfig::default(), rt);
+    let dispatch = new_closure_dispatcher(server_handle);
+    let addr = server.listen::<MemServer<crate::RT>, _>(addr, dispatch).await.expect("listen");
+    (server, addr)
+}
+
+#[logfn]
+#[rstest]
+fn test_mem_pool(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let store = Arc::new(Mutex::new(None));
+        let (_server, addr) =
+            init_mem_server(new_store_handle(counter.clone(), store), "test-mem:0", rt.clone())
+                .await;
+        assert!(addr.starts_with("test-mem:"));
+        assert_ne!(addr, "test-mem:0");
+
+        let facts = MyClient::new(ClientConfig::default(), rt);
+        let pool = ClientPool::<MyClient, MemClient<crate::RT>>::new(facts, &addr, 100);
+        let (tx, rx) = mpsc::unbounded_async();
+        for i in 0..1000 {
+            pool.send_req(
+                FileClientTaskOpen::new(tx.clone(), format!("/tmp/file_{}.txt", i)).into(),
+            )
+            .await;
+        }
+        for _ in 0..1000 {
+            let task = rx.recv().await.unwrap();
+            assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        }
+
+        // Larger than the buffer of the stream
+        let data_len = 1024 * 1024;
+        let mut data = Buffer::alloc(data_len).expect("alloc");
+        rand_buffer(&mut data);
+        pool.send_req(FileClientTaskWrite::new(tx.clone(), 1, 0, data.clone()).into()).await;
+        let task = rx.recv().await.unwrap();
+        assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        pool.send_req(FileClientTaskRead::new(tx.clone(), 1, 0, data_len as usize).into()).await;
+        let FileClientTask::Read(task) = rx.recv().await.unwrap() else { unreachable!() };
+        assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        assert_eq!(task.resp.as_ref().unwrap().ret_size, data_len as u64);
+        assert_eq!(task.read_data.as_ref().unwrap().as_ref(), data.as_ref());
+        assert_eq!(counter.load(Ordering::SeqCst), 1002);
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_mem_addr(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let store = Arc::new(Mutex::new(None));
+        let handle = new_store_handle(counter, store);
+        let (server, addr) = init_mem_server(handle.clone(), "test-mem-addr", rt.clone()).await;
+        assert_eq!(addr, "test-mem-addr");
+        let mut server2 = init_server(ServerConfig::default(), rt.clone());
+        let r = server2
+            .listen::<MemServer<crate::RT>, _>(&addr, new_closure_dispatcher(handle.clone()))
+            .await;
+        assert_eq!(r.unwrap_err().kind(), std::io::ErrorKind::AddrInUse);
+
+        let facts = MyClient::new(ClientConfig::default(), rt.clone());
+        let client =
+            ClientStream::<MyClient, MemClient<crate::RT>>::connect(facts, &addr, "mem", None)
+                .await;
+        assert!(client.is_ok());
+        drop(client);
+
+        // The address is released with the server
+        drop(server);
+        crate::RT::sleep(Duration::from_millis(100)).await;
+        let facts = MyClient::new(ClientConfig::default(), rt.clone());
+        let r = ClientStream::<MyClient, MemClient<crate::RT>>::connect(facts, &addr, "mem", None)
+            .await;
+        assert!(matches!(r, Err(RpcIntErr::Unreachable)));
+        let (_server, addr2) = init_mem_server(handle, &addr, rt).await;
+        assert_eq!(addr2, addr);
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_mem_failover(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let counter1 = Arc::new(AtomicUsize::new(0));
+        let counter2 = Arc::new(AtomicUsize::new(0));
+        let store = Arc::new(Mutex::new(None));
+        let (server1, addr1) = init_mem_server(
+            new_store_handle(counter1.clone(), store.clone()),
+            "test-mem-failover:0",
+            rt.clone(),
+        )
+        .await;
+        let (_server2, addr2) = init_mem_server(
+            new_store_handle(counter2.clone(), store),
+            "test-mem-failover:0",
+            rt.clone(),
+        )
+        .await;
+        assert_ne!(addr1, addr2);
+
+        let facts = MyClient::new(ClientConfig::default(), rt);
+        let client = FailoverPool::<MyClient, MemClient<crate::RT>>::new(
+            facts,
+            vec![addr1, addr2],
+            false,
+            3,
+            100,
+        );
+        let (tx, rx) = mpsc::unbounded_async();
+        const REQUESTS: usize = 1000;
+        for i in 0..REQUESTS {
+            let open_task = FileClientTaskOpen::new(tx.clone(), format!("/tmp/file_{}.txt", i));
+            client.send_req(open_task.into()).await;
+        }
+        for _ in 0..REQUESTS {
+            let task = rx.recv().await.unwrap();
+            assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        }
+        assert_eq!(counter1.load(Ordering::SeqCst), REQUESTS);
+
+        // Stop server 1 to trigger failover
+        drop(server1);
+        for i in 0..REQUESTS {
+            let open_task = FileClientTaskOpen::new(tx.clone(), format!("/tmp/file_{}.txt", i));
+            client.send_req(open_task.into()).await;
+        }
+        drop(tx);
+        let mut recv_count = 0;
+        while let Ok(task) = rx.recv().await {
+            recv_count += 1;
+            assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        }
+        assert_eq!(recv_count, REQUESTS);
+        assert!(counter2.load(Ordering::SeqCst) > 0);
+    });
+}
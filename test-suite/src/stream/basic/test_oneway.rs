@@ -0,0 +1,81 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::proto::RPC_FEATURE_ONEWAY;
+use razor_stream::server::{ServerConfig, task::ServerTaskDone};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[logfn]
+#[rstest]
+#[case(true)]
+#[case(false)]
+fn test_oneway(runner: TestRunner, #[case] handshake: bool) {
+    let client_config = ClientConfig { handshake, ..Default::default() };
+    let server_config = ServerConfig::default();
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    let written = Arc::new(AtomicUsize::new(0));
+    let dispatch_task = {
+        let _written = written.clone();
+        move |task: FileServerTask| async move {
+            match task {
+                FileServerTask::Open(open_task) => {
+                    open_task.set_result(Ok(()));
+                }
+                FileServerTask::IO(mut io_task) => {
+                    let blob = io_task.req_blob.take().expect("blob");
+                    io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                    _written.fetch_add(1, Ordering::SeqCst);
+                    // Dropped by the server for the one-way request
+                    io_task.set_result(Ok(()));
+                }
+            }
+            Ok(())
+        }
+    };
+
+    runner.block_on(async move {
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            "127.0.0.1:0",
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+        assert_eq!(client.get_negotiated().has(RPC_FEATURE_ONEWAY), handshake);
+
+        let (tx, rx) = mpsc::unbounded_async();
+        let count = 10;
+        for i in 0..count {
+            let mut data = Buffer::alloc(1024).expect("alloc");
+            rand_buffer(&mut data);
+            let write_task = FileClientTaskWrite::new(tx.clone(), 1, i * 1024, data);
+            client.send_oneway(write_task.into(), false).await.expect("send oneway");
+        }
+        if handshake {
+            // Done right after written, without waiting for the server
+            assert_eq!(client.get_inflight_count(), 0);
+        }
+        client.flush_req().await.expect("flush");
+        for _ in 0..count {
+            let task = rx.recv().await.unwrap();
+            assert!(task.get_result().is_ok());
+        }
+
+        // The requests are processed in order, the one-way ones are handled before this
+        let open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        assert!(rx.recv().await.unwrap().get_result().is_ok());
+        assert_eq!(written.load(Ordering::SeqCst), count as usize);
+        assert!(rx.try_recv().is_err());
+        assert!(!client.is_closed());
+    });
+}
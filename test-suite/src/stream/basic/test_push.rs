@@ -0,0 +1,116 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use razor_rpc_tcp::{TcpClient, TcpServer};
+use razor_stream::client::stream::ClientStream;
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::compress::{COMPRESS_LZ4, COMPRESS_NONE};
+use razor_stream::error::{DispatchErr, RpcIntErr};
+use razor_stream::proto::RPC_FEATURE_PUSH;
+use razor_stream::server::{
+    RpcSvrReq, ServerConfig,
+    dispatch::Dispatch,
+    push::ServerPush,
+    task::{RespNoti, ServerTaskDecode, ServerTaskDone},
+};
+use std::sync::{Arc, Mutex};
+
+/// Push the path and a blob to the client on open
+#[derive(Clone)]
+struct PushDispatch {
+    blob: Buffer,
+    push: Option<ServerPush<crate::Codec>>,
+    results: Arc<Mutex<Vec<Result<u64, RpcIntErr>>>>,
+}
+
+impl Dispatch for PushDispatch {
+    type RespTask = FileServerTask;
+
+    type Codec = crate::Codec;
+
+    async fn dispatch_req<'a>(
+        &'a self, codec: &Arc<crate::Codec>, req: RpcSvrReq<'a>, noti: RespNoti<FileServerTask>,
+    ) -> Result<(), DispatchErr> {
+        let task = <FileServerTask as ServerTaskDecode<FileServerTask>>::decode_req(
+            codec.as_ref(),
+            req.action,
+            req.seq,
+            req.msg,
+            req.blob,
+            noti,
+        )?;
+        match task {
+            FileServerTask::Open(open_task) => {
+                let push = self.push.as_ref().expect("set_push");
+                let r = push.push(&open_task.req.path, Some(self.blob.clone()));
+                self.results.lock().unwrap().push(r);
+                open_task.set_result(Ok(()));
+            }
+            FileServerTask::IO(io_task) => {
+                io_task.set_result(Ok(()));
+            }
+        }
+        Ok(())
+    }
+
+    fn set_push(&mut self, push: ServerPush<crate::Codec>) {
+        self.push = Some(push);
+    }
+}
+
+#[logfn]
+#[rstest]
+#[case(true, COMPRESS_NONE)]
+#[case(true, COMPRESS_LZ4)]
+#[case(false, COMPRESS_NONE)]
+fn test_push(runner: TestRunner, #[case] accept: bool, #[case] compression: u8) {
+    let client_config = ClientConfig { compression, ..Default::default() };
+    let server_config = ServerConfig { compression, ..Default::default() };
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    let mut blob = Buffer::alloc(16 * 1024).expect("alloc");
+    rand_buffer(&mut blob);
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let dispatch = PushDispatch { blob: blob.clone(), push: None, results: results.clone() };
+
+    runner.block_on(async move {
+        let mut server = init_server(server_config, rt_server);
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("server listen");
+        let (facts, push_rx) = if accept {
+            let (facts, push_rx) = MyClient::new_with_push(client_config, rt_client);
+            (facts, Some(push_rx))
+        } else {
+            (MyClient::new(client_config, rt_client), None)
+        };
+        let mut client =
+            ClientStream::<MyClient, TcpClient<crate::RT>>::connect(facts, &addr, "push", None)
+                .await
+                .expect("connect client");
+        assert_eq!(client.get_negotiated().has(RPC_FEATURE_PUSH), accept);
+
+        let path = "/tmp/test.txt".to_string();
+        let (tx, rx) = mpsc::unbounded_async();
+        for _ in 0..2 {
+            let open_task = FileClientTaskOpen::new(tx.clone(), path.clone());
+            client.send_task(open_task.into(), true).await.expect("send open task");
+            assert!(rx.recv().await.unwrap().get_result().is_ok());
+        }
+        if let Some(push_rx) = push_rx {
+            assert_eq!(*results.lock().unwrap(), vec![Ok(1), Ok(2)]);
+            for seq in 1..=2 {
+                let push = push_rx.recv().await.expect("recv push");
+                assert_eq!(push.seq, seq);
+                let msg: String = push.decode(&crate::Codec::default()).expect("decode");
+                assert_eq!(msg, path);
+                assert_eq!(push.blob.as_deref(), Some(blob.as_ref()));
+            }
+        } else {
+            assert_eq!(*results.lock().unwrap(), vec![Err(RpcIntErr::Version); 2]);
+        }
+        assert!(!client.is_closed());
+    });
+}
@@ -0,0 +1,131 @@
+use crate::stream::{client::*, server::*};
+use crate::*;
+use crossfire::mpsc;
+use io_buffer::{Buffer, rand_buffer};
+use razor_rpc_shm::{ShmClient, ShmServer};
+use razor_stream::client::{
+    ClientCaller, ClientConfig, ClientPool, FailoverPool, task::ClientTaskGetResult,
+};
+use razor_stream::server::{RpcServer, ServerConfig};
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
+
+async fn init_shm_server<H, FH>(
+    server_handle: H, addr: &str, rt: crate::RT,
+) -> (RpcServer<MyServer>, String)
+where
+    H: FnOnce(FileServerTask) -> FH + Send + Sync + 'static + Clone,
+    FH: Future<Output = Result<(), ()>> + Send + 'static,
+{
+    let _ = std::fs::remove_file(addr);
+    let mut server = init_server(ServerConfig::default(), rt);
+    let dispatch = new_closure_dispatcher(server_handle);
+    let addr = server.listen::<ShmServer<crate::RT>, _>(addr, dispatch).await.expect("listen");
+    (server, addr)
+}
+
+#[logfn]
+#[rstest]
+fn test_shm_pool(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let store = Arc::new(Mutex::new(None));
+        let (_server, addr) = init_shm_server(
+            new_store_handle(counter.clone(), store),
+            "/tmp/razor-rpc-test-shm",
+            rt.clone(),
+        )
+        .await;
+
+        // Smaller than the blob, the ring wraps around
+        let mut client_config = ClientConfig::default();
+        client_config.stream_buf_size = 64 * 1024;
+        let facts = MyClient::new(client_config, rt);
+        let pool = ClientPool::<MyClient, ShmClient<crate::RT>>::new(facts, &addr, 100);
+        let (tx, rx) = mpsc::unbounded_async();
+        for i in 0..1000 {
+            pool.send_req(
+                FileClientTaskOpen::new(tx.clone(), format!("/tmp/file_{}.txt", i)).into(),
+            )
+            .await;
+        }
+        for _ in 0..1000 {
+            let task = rx.recv().await.unwrap();
+            assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        }
+
+        let data_len = 8 * 1024 * 1024;
+        let mut data = Buffer::alloc(data_len).expect("alloc");
+        rand_buffer(&mut data);
+        pool.send_req(FileClientTaskWrite::new(tx.clone(), 1, 0, data.clone()).into()).await;
+        let task = rx.recv().await.unwrap();
+        assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        pool.send_req(FileClientTaskRead::new(tx.clone(), 1, 0, data_len as usize).into()).await;
+        let FileClientTask::Read(task) = rx.recv().await.unwrap() else { unreachable!() };
+        assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        assert_eq!(task.resp.as_ref().unwrap().ret_size, data_len as u64);
+        assert_eq!(task.read_data.as_ref().unwrap().as_ref(), data.as_ref());
+        assert_eq!(counter.load(Ordering::SeqCst), 1002);
+    });
+}
+
+#[logfn]
+#[rstest]
+fn test_shm_failover(runner: TestRunner) {
+    let rt = runner.rt.clone();
+    runner.block_on(async move {
+        let counter1 = Arc::new(AtomicUsize::new(0));
+        let counter2 = Arc::new(AtomicUsize::new(0));
+        let store = Arc::new(Mutex::new(None));
+        let (server1, addr1) = init_shm_server(
+            new_store_handle(counter1.clone(), store.clone()),
+            "/tmp/razor-rpc-test-shm-failover-1",
+            rt.clone(),
+        )
+        .await;
+        let (_server2, addr2) = init_shm_server(
+            new_store_handle(counter2.clone(), store),
+            "/tmp/razor-rpc-test-shm-failover-2",
+            rt.clone(),
+        )
+        .await;
+
+        let facts = MyClient::new(ClientConfig::default(), rt);
+        let client = FailoverPool::<MyClient, ShmClient<crate::RT>>::new(
+            facts,
+            vec![addr1, addr2],
+            false,
+            3,
+            100,
+        );
+        let (tx, rx) = mpsc::unbounded_async();
+        const REQUESTS: usize = 1000;
+        for i in 0..REQUESTS {
+            let open_task = FileClientTaskOpen::new(tx.clone(), format!("/tmp/file_{}.txt", i));
+            client.send_req(open_task.into()).await;
+        }
+        for _ in 0..REQUESTS {
+            let task = rx.recv().await.unwrap();
+            assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        }
+        assert_eq!(counter1.load(Ordering::SeqCst), REQUESTS);
+
+        // Stop server 1 to trigger failover
+        drop(server1);
+        for i in 0..REQUESTS {
+            let open_task = FileClientTaskOpen::new(tx.clone(), format!("/tmp/file_{}.txt", i));
+            client.send_req(open_task.into()).await;
+        }
+        drop(tx);
+        let mut recv_count = 0;
+        while let Ok(task) = rx.recv().await {
+            recv_count += 1;
+            assert!(task.get_result().is_ok(), "task err: {:?}", task.get_result());
+        }
+        assert_eq!(recv_count, REQUESTS);
+        assert!(counter2.load(Ordering::SeqCst) > 0);
+    });
+}
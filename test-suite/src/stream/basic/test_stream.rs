@@ -0,0 +1,101 @@
+use crate::stream::client::*;
+use crate::stream::server::init_server;
+use crate::*;
+use crossfire::mpsc;
+use razor_rpc_tcp::TcpServer;
+use razor_stream::Codec;
+use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::error::{CodecError, DispatchErr};
+use razor_stream::proto::{RPC_FEATURE_STREAM, RpcAction};
+use razor_stream::server::{
+    RpcSvrReq, RpcSvrResp, ServerConfig, dispatch::Dispatch, task::RespNoti,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Answer FileAction::List with `count` entries in partial responses, then the count
+#[derive(Clone)]
+struct ListDispatch {
+    count: usize,
+    interval: Duration,
+}
+
+impl Dispatch for ListDispatch {
+    type RespTask = RpcSvrResp;
+
+    type Codec = crate::Codec;
+
+    async fn dispatch_req<'a>(
+        &'a self, codec: &Arc<crate::Codec>, req: RpcSvrReq<'a>, noti: RespNoti<RpcSvrResp>,
+    ) -> Result<(), DispatchErr> {
+        if req.action != RpcAction::Num(FileAction::List as i32) {
+            return Err(CodecError::decode::<FileOpenReq>("not a list action").into());
+        }
+        let list_req: FileOpenReq = codec.decode(req.msg)?;
+        let resp = |msg: Vec<u8>, more: bool| RpcSvrResp {
+            seq: req.seq,
+            msg: Some(msg),
+            blob: None,
+            res: Some(Ok(())),
+            meta: None,
+            more,
+        };
+        for i in 0..self.count {
+            if !self.interval.is_zero() {
+                crate::RT::sleep(self.interval).await;
+            }
+            let entry = codec.encode(&format!("{}/{}", list_req.path, i))?;
+            if noti.send(resp(entry, true)).is_err() {
+                // The connection has closed
+                return Ok(());
+            }
+        }
+        noti.done(resp(codec.encode(&self.count)?, false));
+        Ok(())
+    }
+}
+
+#[logfn]
+#[rstest]
+#[case(true, 10, 0)]
+#[case(true, 4, 700)]
+#[case(false, 3, 0)]
+fn test_server_stream(
+    runner: TestRunner, #[case] handshake: bool, #[case] count: usize, #[case] interval_ms: u64,
+) {
+    // The whole stream lasts longer than task_timeout, each frame keeps the task alive
+    let client_config = ClientConfig { handshake, task_timeout: 2, ..Default::default() };
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+    let dispatch = ListDispatch { count, interval: Duration::from_millis(interval_ms) };
+
+    runner.block_on(async move {
+        let mut server = init_server(ServerConfig::default(), rt_server);
+        let addr = server
+            .listen::<TcpServer<crate::RT>, _>("127.0.0.1:0", dispatch)
+            .await
+            .expect("server listen");
+        let mut client =
+            init_client(client_config, &addr, None, rt_client).await.expect("connect client");
+        assert_eq!(client.get_negotiated().has(RPC_FEATURE_STREAM), handshake);
+
+        let (tx, rx) = mpsc::unbounded_async();
+        for _ in 0..2 {
+            let list_task = FileClientTaskList::new(tx.clone(), "/tmp".to_string());
+            client.send_task(list_task.into(), true).await.expect("send list task");
+            let completed_task = rx.recv().await.unwrap();
+            assert!(completed_task.get_result().is_ok());
+            let FileClientTask::List(task) = completed_task else { unreachable!() };
+            assert_eq!(task.resp, Some(count));
+            if handshake {
+                let expected: Vec<String> = (0..count).map(|i| format!("/tmp/{}", i)).collect();
+                assert_eq!(task.entries, expected);
+            } else {
+                // The partial responses are dropped for a client without stream support
+                assert!(task.entries.is_empty());
+            }
+        }
+
+        assert!(!client.is_closed());
+    });
+}
@@ -1,7 +1,10 @@
 use crate::stream::{client::*, server::*};
 use crate::*;
 use crossfire::mpsc;
-use razor_stream::client::{ClientConfig, task::ClientTaskGetResult};
+use razor_stream::client::{
+    ClientConfig,
+    task::{ClientTaskCancel, ClientTaskGetResult},
+};
 use razor_stream::error::{RpcError, RpcIntErr};
 use razor_stream::server::{ServerConfig, task::ServerTaskDone};
 use std::time::Duration;
@@ -69,3 +72,123 @@ fn test_client_task_timeout(runner: TestRunner, #[case] is_tcp: bool) {
         log::info!("Open task timed out as expected.");
     });
 }
+
+#[logfn]
+#[rstest]
+#[case(true)]
+#[case(false)]
+fn test_client_task_deadline(runner: TestRunner, #[case] is_tcp: bool) {
+    let client_config = ClientConfig::default();
+    let server_config = ServerConfig::default();
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    let dispatch_task = move |task: FileServerTask| async move {
+        match task {
+            FileServerTask::Open(open_task) => {
+                open_task.set_result(Ok(()));
+                Ok(())
+            }
+            FileServerTask::IO(mut io_task) => {
+                io_task.resp = Some(Default::default());
+                io_task.set_result(Ok(()));
+                Ok(())
+            }
+        }
+    };
+
+    runner.block_on(async move {
+        let server_bind_addr =
+            if is_tcp { "127.0.0.1:0" } else { "/tmp/razor-rpc-test-socket-deadline" };
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            &server_bind_addr,
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+
+        let (tx, rx) = mpsc::unbounded_async();
+        // Deadline with enough budget
+        let mut open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        open_task.set_timeout(Duration::from_secs(5));
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        let completed_open_task = rx.recv().await.unwrap();
+        assert!(completed_open_task.get_result().is_ok());
+
+        // Deadline already passed
+        let mut open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        open_task.set_deadline(std::time::Instant::now());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        let completed_open_task = rx.recv().await.unwrap();
+        let result = completed_open_task.get_result();
+        assert_eq!(result.unwrap_err(), &RpcError::Rpc(RpcIntErr::Deadline));
+    });
+}
+
+#[logfn]
+#[rstest]
+#[case(true)]
+#[case(false)]
+fn test_client_task_cancel(runner: TestRunner, #[case] is_tcp: bool) {
+    let client_config = ClientConfig { task_timeout: 10, ..Default::default() };
+    let server_config = ServerConfig::default();
+    let rt_server = runner.rt.clone();
+    let rt_client = runner.rt.clone();
+
+    let dispatch_task = move |task: FileServerTask| async move {
+        match task {
+            FileServerTask::Open(open_task) => {
+                crate::RT::sleep(Duration::from_secs(3)).await;
+                open_task.set_result(Ok(()));
+                Ok(())
+            }
+            FileServerTask::IO(mut io_task) => {
+                io_task.resp = Some(Default::default());
+                io_task.set_result(Ok(()));
+                Ok(())
+            }
+        }
+    };
+
+    runner.block_on(async move {
+        let server_bind_addr =
+            if is_tcp { "127.0.0.1:0" } else { "/tmp/razor-rpc-test-socket-cancel" };
+        let (_server, actual_server_addr) = init_server_closure::<_, _, crate::RT>(
+            dispatch_task,
+            server_config.clone(),
+            &server_bind_addr,
+            rt_server,
+        )
+        .await
+        .expect("server listen");
+        let mut client = init_client(client_config, &actual_server_addr, None, rt_client)
+            .await
+            .expect("connect client");
+
+        let (tx, rx) = mpsc::unbounded_async();
+        // Cancel before sending
+        let mut open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        let cancel = ClientTaskCancel::new();
+        open_task.cancel = Some(cancel.clone());
+        cancel.cancel();
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        let completed_open_task = rx.recv().await.unwrap();
+        let result = completed_open_task.get_result();
+        assert_eq!(result.unwrap_err(), &RpcError::Rpc(RpcIntErr::Cancelled));
+
+        // Cancel in-flight, the timer will fail it before the server responds
+        let mut open_task = FileClientTaskOpen::new(tx.clone(), "/tmp/test.txt".to_string());
+        let cancel = ClientTaskCancel::new();
+        open_task.cancel = Some(cancel.clone());
+        client.send_task(open_task.into(), true).await.expect("send open task");
+        cancel.cancel();
+        let completed_open_task = rx.recv().await.unwrap();
+        let result = completed_open_task.get_result();
+        assert_eq!(result.unwrap_err(), &RpcError::Rpc(RpcIntErr::Cancelled));
+    });
+}
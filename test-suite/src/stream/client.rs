@@ -1,7 +1,6 @@
 use crossfire::*;
 use io_buffer::Buffer;
 use nix::errno::Errno;
-use razor_rpc_codec::MsgpCodec;
 use razor_rpc_tcp::TcpClient;
 use razor_stream::client::stream::ClientStream;
 use razor_stream::client::task::*;
@@ -10,7 +9,7 @@ use razor_stream::error::{RpcError, RpcIntErr};
 use serde_derive::{Deserialize, Serialize};
 use std::sync::{Arc, atomic::AtomicU64};
 
-pub type MyClient = ClientDefault<FileClientTask, crate::RT, MsgpCodec>;
+pub type MyClient = ClientDefault<FileClientTask, crate::RT, crate::Codec>;
 
 pub type FileClient = ClientStream<MyClient, TcpClient<crate::RT>>;
 
@@ -27,6 +26,7 @@ pub enum FileAction {
     Open = 1,
     Read = 2,
     Write = 3,
+    List = 4,
 }
 
 impl TryFrom<u8> for FileAction {
@@ -37,6 +37,7 @@ impl TryFrom<u8> for FileAction {
             1 => Ok(FileAction::Open),
             2 => Ok(FileAction::Read),
             3 => Ok(FileAction::Write),
+            4 => Ok(FileAction::List),
             _ => Err(RpcIntErr::Method.into()),
         }
     }
@@ -51,6 +52,8 @@ pub enum FileClientTask {
     Read(FileClientTaskRead),
     #[action(FileAction::Write)]
     Write(FileClientTaskWrite),
+    #[action(FileAction::List)]
+    List(FileClientTaskList),
 }
 
 #[derive(Default, Deserialize, Serialize, Debug)]
@@ -84,6 +87,36 @@ impl FileClientTaskOpen {
     }
 }
 
+/// Receive the entries of a directory in stream, the final response is the count
+#[client_task(debug)]
+pub struct FileClientTaskList {
+    #[field(common)]
+    pub common: ClientTaskCommon,
+    #[field(req)]
+    pub req: FileOpenReq,
+    #[field(resp)]
+    pub resp: Option<usize>,
+    #[field(frames)]
+    pub entries: Vec<String>,
+    #[field(res)]
+    pub res: Option<Result<(), RpcError<Errno>>>,
+    #[field(noti)]
+    pub sender: Option<MTx<FileClientTask>>,
+}
+
+impl FileClientTaskList {
+    pub fn new(sender: MTx<FileClientTask>, path: String) -> Self {
+        Self {
+            common: Default::default(),
+            sender: Some(sender),
+            req: FileOpenReq { path },
+            res: None,
+            resp: None,
+            entries: Vec::new(),
+        }
+    }
+}
+
 #[derive(Default, Deserialize, Serialize, Debug)]
 pub struct FileIOReq {
     pub inode: u64,
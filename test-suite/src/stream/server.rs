@@ -1,8 +1,13 @@
 use super::client::{FileAction, FileIOReq, FileIOResp, FileOpenReq};
+use io_buffer::Buffer;
 use nix::errno::Errno;
-use razor_rpc_codec::MsgpCodec;
 use razor_rpc_tcp::TcpServer;
 use razor_stream::server::{dispatch::*, task::*, *};
+use std::future::{Ready, ready};
+use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+};
 
 pub type MyServer = razor_stream::server::ServerDefault<crate::RT>;
 
@@ -33,7 +38,31 @@ where
     H: FnOnce(FileServerTask) -> FH + Send + Sync + 'static + Clone,
     FH: Future<Output = Result<(), ()>> + Send + 'static,
 {
-    DispatchClosure::<MsgpCodec, FileServerTask, FileServerTask, _, _>::new(handle.clone())
+    DispatchClosure::<crate::Codec, FileServerTask, FileServerTask, _, _>::new(handle.clone())
+}
+
+/// Count the requests, keep the blob of write for the following read
+pub fn new_store_handle(
+    counter: Arc<AtomicUsize>, store: Arc<Mutex<Option<Buffer>>>,
+) -> impl FnOnce(FileServerTask) -> Ready<Result<(), ()>> + Send + Sync + Clone + 'static {
+    move |task: FileServerTask| {
+        counter.fetch_add(1, Ordering::SeqCst);
+        match task {
+            FileServerTask::Open(open_task) => open_task.set_result(Ok(())),
+            FileServerTask::IO(mut io_task) => {
+                if let Some(blob) = io_task.req_blob.take() {
+                    io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                    store.lock().unwrap().replace(blob);
+                } else {
+                    let blob = store.lock().unwrap().take().unwrap_or_else(|| Buffer::from(vec![]));
+                    io_task.resp = Some(FileIOResp { ret_size: blob.len() as u64 });
+                    io_task.resp_blob = Some(blob);
+                }
+                io_task.set_result(Ok(()));
+            }
+        }
+        ready(Ok(()))
+    }
 }
 
 #[server_task_enum(req, resp, error = Errno)]
@@ -11,7 +11,22 @@ pub fn create_mock_request<T: serde::Serialize>(
 ) -> APIServerReq<MsgpCodec> {
     let codec = Arc::new(MsgpCodec::default());
     let req_data = codec.encode(req).expect("encode");
-    return APIServerReq { seq, service, method, req: Some(req_data), codec, noti };
+    return APIServerReq {
+        seq,
+        service: service.into(),
+        method: method.into(),
+        req: Some(req_data),
+        codec,
+        noti,
+        meta: Default::default(),
+        resp_meta: Default::default(),
+        deadline: None,
+        cancel: Default::default(),
+        streams: None,
+        input: None,
+        decode_err_detail: false,
+        peer: None,
+    };
 }
 
 #[derive(Debug, Deserialize, Serialize, PartialEq)]
@@ -50,6 +65,18 @@ impl MultiErrorServiceImpl {
     }
 }
 
+// Service with a slow handler, which holds back the requests queued after it
+pub struct SlowService;
+
+#[service]
+impl SlowService {
+    #[method]
+    async fn sleep(&self, arg: MyArg) -> Result<MyResp, RpcError<String>> {
+        std::thread::sleep(std::time::Duration::from_millis(arg.value as u64));
+        Ok(MyResp { result: arg.value })
+    }
+}
+
 // Service with `impl Future` return type (non-async fn)
 pub struct ImplFutureService;
 #[service]
@@ -6,6 +6,7 @@ use razor_rpc::{
 use razor_rpc_codec::MsgpCodec;
 use razor_stream::server::task::RespNoti;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 mod common;
 use common::*;
@@ -30,7 +31,7 @@ async fn test_multi_error_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 1);
     assert!(resp.res.as_ref().unwrap().is_ok());
     let decoded_resp: MyResp = codec.decode(&resp.msg.unwrap()).unwrap();
@@ -45,7 +46,7 @@ async fn test_multi_error_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 2);
     assert_eq!(
         resp.res.unwrap().unwrap_err(),
@@ -61,7 +62,7 @@ async fn test_multi_error_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 3);
     assert_eq!(resp.res.unwrap().unwrap_err(), EncodedErr::Num(42));
 
@@ -74,7 +75,7 @@ async fn test_multi_error_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 4);
     assert_eq!(resp.res.unwrap().unwrap_err(), EncodedErr::Num(nix::errno::Errno::EPERM as u32));
 
@@ -87,11 +88,50 @@ async fn test_multi_error_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 5);
     assert_eq!(resp.res.unwrap().unwrap_err(), EncodedErr::Rpc(RpcIntErr::Method));
 }
 
+#[tokio::test]
+async fn test_expired_in_queue() {
+    let service_impl = SlowService;
+    let codec = MsgpCodec::default();
+    let (tx, rx) = crossfire::mpsc::unbounded_async();
+    let noti = RespNoti::new(tx);
+    let service_name = <SlowService as ServiceStatic<MsgpCodec>>::SERVICE_NAME;
+
+    let slow_req = create_mock_request(
+        1,
+        service_name.to_string(),
+        "sleep".to_string(),
+        &MyArg { value: 200 },
+        noti.clone(),
+    );
+    let mut short_req = create_mock_request(
+        2,
+        service_name.to_string(),
+        "sleep".to_string(),
+        &MyArg { value: 0 },
+        noti.clone(),
+    );
+    short_req.deadline = Some(Instant::now() + Duration::from_millis(50));
+    assert!(!short_req.is_expired());
+
+    // A queued dispatcher serves the requests one by one
+    for req in [slow_req, short_req] {
+        ServiceStatic::serve(&service_impl, req).await;
+    }
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
+    assert_eq!(resp.seq, 1);
+    let decoded_resp: MyResp = codec.decode(&resp.msg.unwrap()).unwrap();
+    assert_eq!(decoded_resp.result, 200);
+    // The short deadline passed behind the slow handler
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
+    assert_eq!(resp.seq, 2);
+    assert_eq!(resp.res.unwrap().unwrap_err(), EncodedErr::Rpc(RpcIntErr::Deadline));
+}
+
 #[tokio::test]
 async fn test_impl_future_service() {
     assert_eq!(<ImplFutureService as ServiceStatic<MsgpCodec>>::SERVICE_NAME, "ImplFutureService");
@@ -108,7 +148,7 @@ async fn test_impl_future_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 1);
     assert!(resp.res.as_ref().unwrap().is_ok());
     let decoded_resp: MyResp = codec.decode(&resp.msg.unwrap()).unwrap();
@@ -134,7 +174,7 @@ async fn test_async_trait_service() {
         noti.clone(),
     );
     ServiceStatic::serve(&service_impl, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 1);
     assert!(resp.res.as_ref().unwrap().is_ok());
     let decoded_resp: MyResp = codec.decode(&resp.msg.unwrap()).unwrap();
@@ -160,7 +200,7 @@ async fn test_service_mux_struct() {
         noti.clone(),
     );
     ServiceStatic::serve(&services, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 1);
     assert!(resp.res.as_ref().unwrap().is_ok());
     let decoded_resp: MyResp = codec.decode(&resp.msg.unwrap()).unwrap();
@@ -175,7 +215,7 @@ async fn test_service_mux_struct() {
         noti.clone(),
     );
     ServiceStatic::serve(&services, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 2);
     assert!(resp.res.as_ref().unwrap().is_ok());
     let decoded_resp: MyResp = codec.decode(&resp.msg.unwrap()).unwrap();
@@ -190,7 +230,7 @@ async fn test_service_mux_struct() {
         noti.clone(),
     );
     ServiceStatic::serve(&services, req).await;
-    let resp = rx.recv().await.unwrap().unwrap();
+    let (_, resp) = rx.recv().await.unwrap().unwrap();
     assert_eq!(resp.seq, 3);
     assert_eq!(resp.res.unwrap().unwrap_err(), EncodedErr::Rpc(RpcIntErr::Service));
 }
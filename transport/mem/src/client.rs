@@ -0,0 +1,227 @@
+use crate::{MEM_DEFAULT_BUF_SIZE, MemStream};
+use captains_log::filter::LogFilter;
+use crossfire::MAsyncRx;
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use razor_stream::client::timer::ClientTaskTimer;
+use razor_stream::client::{ClientConfig, ClientFacts, ClientTransport};
+use razor_stream::error::*;
+use razor_stream::{io_with_timeout, proto};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::transmute;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{fmt, io};
+
+pub struct MemClient<RT: AsyncRuntime> {
+    stream: UnsafeCell<MemStream>,
+    /// for read
+    decoder: UnsafeCell<proto::FrameDecoder>,
+    /// CRC32C trailer negotiated by handshake
+    checksum: AtomicBool,
+    conn_id: String,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    _phan: PhantomData<fn(&RT)>,
+}
+
+unsafe impl<RT: AsyncRuntime> Send for MemClient<RT> {}
+unsafe impl<RT: AsyncRuntime> Sync for MemClient<RT> {}
+
+impl<RT: AsyncRuntime> fmt::Debug for MemClient<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mem client {}", self.conn_id)
+    }
+}
+
+impl<RT: AsyncRuntime> MemClient<RT> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_stream_mut(&self) -> &mut MemStream {
+        unsafe { std::mem::transmute(self.stream.get()) }
+    }
+
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_decoder(&self) -> &mut proto::FrameDecoder {
+        let decoder: &mut proto::FrameDecoder = unsafe { transmute(self.decoder.get()) };
+        decoder.set_checksum(self.checksum.load(Ordering::Relaxed));
+        decoder
+    }
+
+    /// Read until the decoder has a complete response frame.
+    ///
+    /// The first read is aborted by `close_ch`, return Ok(false) in that case.
+    async fn _recv_frame(
+        &self, logger: &LogFilter, close_ch: Option<&MAsyncRx<()>>,
+    ) -> Result<bool, RpcIntErr> {
+        let reader = self.get_stream_mut();
+        let decoder = self.get_decoder();
+        loop {
+            let want = match decoder.want_resp() {
+                Ok(want) => want,
+                Err(e) => {
+                    logger_debug!(
+                        logger,
+                        "{:?} rpc client decode_response_header err: {}",
+                        self,
+                        e
+                    );
+                    return Err(e);
+                }
+            };
+            if want == 0 {
+                return Ok(true);
+            }
+            let idle = decoder.pending() == 0;
+            let buf = decoder.read_buf(want);
+            if let (true, Some(close_ch)) = (idle, close_ch) {
+                match Cancellable::new(reader.read_exact(buf), close_ch.recv()).await {
+                    Ok(Err(e)) => {
+                        logger_debug!(logger, "{:?} rpc client read resp err: {:?}", self, e);
+                        return Err(e.into());
+                    }
+                    Err(_) => return Ok(false),
+                    _ => {}
+                }
+            } else if let Err(e) =
+                io_with_timeout::<RT, _>(self.read_timeout, reader.read_exact(buf)).await
+            {
+                logger_debug!(logger, "{:?} rpc client read resp err: {}", self, e);
+                return Err(e.into());
+            }
+            decoder.advance(want);
+        }
+    }
+}
+
+impl<RT: AsyncRuntime> ClientTransport for MemClient<RT> {
+    async fn connect(addr: &str, conn_id: &str, config: &ClientConfig) -> Result<Self, RpcIntErr> {
+        let mut buf_size = config.stream_buf_size;
+        if buf_size == 0 {
+            buf_size = MEM_DEFAULT_BUF_SIZE;
+        }
+        let stream = match MemStream::connect(addr, buf_size) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Cannot connect addr {}: {}", addr, e);
+                return Err(RpcIntErr::Unreachable.into());
+            }
+        };
+        Ok(Self {
+            stream: UnsafeCell::new(stream),
+            decoder: UnsafeCell::new(proto::FrameDecoder::new()),
+            checksum: AtomicBool::new(false),
+            conn_id: conn_id.to_string(),
+            write_timeout: config.write_timeout,
+            read_timeout: config.read_timeout,
+            _phan: PhantomData,
+        })
+    }
+
+    async fn handshake<F: ClientFacts>(
+        &self, logger: &LogFilter, local: &proto::Handshake,
+    ) -> Result<proto::Negotiated, RpcIntErr> {
+        let mut buf = Vec::with_capacity(proto::RPC_REQ_HEADER_LEN + 32);
+        if proto::ReqHead::encode_handshake(&mut buf, 0, local).is_err() {
+            return Err(RpcIntErr::Encode);
+        }
+        if let Err(e) = self.write_req::<F>(logger, &buf, None, true).await {
+            return Err(e.into());
+        }
+        self._recv_frame(logger, None).await?;
+        let frame = match self.get_decoder().decode_resp() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_debug!(logger, "{:?} rpc client read handshake err: {}", self, e);
+                return Err(e);
+            }
+        };
+        if frame.head.flag != 0 {
+            // An old server does not know the handshake action, or the handshake is not supported
+            logger_debug!(logger, "{:?} server does not support handshake, fall back to v1", self);
+            return Ok(proto::Negotiated::default());
+        }
+        let Ok(answer) = proto::Handshake::decode(frame.msg) else {
+            logger_warn!(logger, "{:?} rpc client decode handshake err", self);
+            return Err(RpcIntErr::Decode);
+        };
+        // Verify the choice of the server
+        match local.negotiate(&answer) {
+            Ok(negotiated) => {
+                logger_debug!(logger, "{:?} handshake {:?}", self, negotiated);
+                self.checksum.store(negotiated.has(proto::RPC_FEATURE_CHECKSUM), Ordering::Relaxed);
+                return Ok(negotiated);
+            }
+            Err(e) => {
+                logger_warn!(logger, "{:?} server answers unsupported {:?}", self, answer);
+                return Err(e);
+            }
+        }
+    }
+
+    #[inline(always)]
+    async fn close_conn<F: ClientFacts>(&self, logger: &LogFilter) {
+        if self.flush_req::<F>(logger).await.is_ok() {
+            let _ = self.get_stream_mut().shutdown_write().await;
+        }
+    }
+
+    #[inline(always)]
+    async fn flush_req<F: ClientFacts>(&self, logger: &LogFilter) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        if let Err(e) = io_with_timeout::<RT, _>(self.write_timeout, writer.flush()).await {
+            logger_warn!(logger, "{:?} flush_req flush err: {}", self, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: flush_req ok", self);
+        Ok(())
+    }
+
+    #[inline(always)]
+    async fn write_req<'a, F: ClientFacts>(
+        &'a self, logger: &LogFilter, buf: &'a [u8], blob: Option<&'a [u8]>, need_flush: bool,
+    ) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        let frame = proto::EncodedFrame::new(self.checksum.load(Ordering::Relaxed), buf, blob);
+        for part in frame.parts() {
+            if let Err(e) =
+                io_with_timeout::<RT, _>(self.write_timeout, writer.write_all(part)).await
+            {
+                logger_warn!(logger, "{:?} write_req err: {}", self, e);
+                return Err(e);
+            }
+        }
+        if need_flush {
+            self.flush_req::<F>(logger).await?;
+        }
+        return Ok(());
+    }
+
+    /// return false to indicate aborted by close_f
+    #[inline]
+    async fn read_resp<F: ClientFacts>(
+        &self, facts: &F, logger: &LogFilter, codec: &F::Codec, close_ch: Option<&MAsyncRx<()>>,
+        task_reg: &mut ClientTaskTimer<F>,
+    ) -> Result<bool, RpcIntErr> {
+        if !self._recv_frame(logger, close_ch).await? {
+            return Ok(false);
+        }
+        match self.get_decoder().decode_resp() {
+            Ok(Some(frame)) => {
+                logger_trace!(logger, "{:?} rpc client read head response {}", self, frame.head);
+                task_reg.recv_resp(facts, logger, codec, frame).await;
+                return Ok(true);
+            }
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_warn!(logger, "{:?} rpc client decode resp err: {}", self, e);
+                return Err(e);
+            }
+        }
+    }
+}
@@ -0,0 +1,214 @@
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, allow(unused_attributes))]
+
+//! # razor-rpc-mem
+//!
+//! This crate provides an in-memory loopback transport for [`razor-stream`](https://docs.rs/razor-stream),
+//! for the client and server living in the same process. The frames are the same as TCP, but
+//! passed through [crossfire](https://docs.rs/crossfire) channels instead of a socket, so
+//! `ClientPool`, `FailoverPool` and `RpcServer` work on it unchanged.
+//!
+//! The address is a name in a process-wide registry, bound by [MemServer] and connected by
+//! [MemClient]. Like port 0 of TCP, a name ending with ":0" is assigned a unique number, the
+//! actual address is returned by `RpcServer::listen()`.
+//!
+//! ```ignore
+//! let addr = server.listen::<MemServer<RT>, _>("calc:0", dispatch).await?;
+//! let pool = facts.create_pool_async::<MemClient<RT>>(&addr);
+//! ```
+
+#[macro_use]
+extern crate captains_log;
+mod client;
+pub use client::*;
+mod server;
+pub use server::*;
+
+use crossfire::{AsyncRx, MAsyncTx, MTx};
+use razor_stream::server::ServerListener;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::{fmt, io};
+
+pub const MEM_DEFAULT_BUF_SIZE: usize = 64 * 1024;
+
+/// The buffers in flight of each direction, the writer waits when it's full
+pub const MEM_CHANNEL_SIZE: usize = 64;
+
+/// The listeners by address
+static REGISTRY: LazyLock<Mutex<HashMap<String, MTx<MemStream>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+static NEXT_PORT: AtomicU64 = AtomicU64::new(1);
+
+static NEXT_CONN: AtomicU64 = AtomicU64::new(1);
+
+/// Accept the connections of [MemServer], the address is released on drop.
+pub struct MemListener {
+    addr: String,
+    rx: AsyncRx<MemStream>,
+}
+
+impl MemListener {
+    pub fn bind(addr: &str) -> io::Result<Self> {
+        let mut registry = REGISTRY.lock().unwrap();
+        let addr = match addr.strip_suffix(":0") {
+            Some(name) => loop {
+                let addr = format!("{}:{}", name, NEXT_PORT.fetch_add(1, Ordering::Relaxed));
+                if !registry.contains_key(&addr) {
+                    break addr;
+                }
+            },
+            None => addr.to_string(),
+        };
+        if registry.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, addr));
+        }
+        let (tx, rx) = crossfire::mpsc::unbounded_async();
+        registry.insert(addr.clone(), tx);
+        Ok(Self { addr, rx })
+    }
+}
+
+impl Drop for MemListener {
+    fn drop(&mut self) {
+        REGISTRY.lock().unwrap().remove(&self.addr);
+    }
+}
+
+impl fmt::Debug for MemListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mem listener {}", self.addr)
+    }
+}
+
+impl ServerListener for MemListener {
+    type Conn = MemStream;
+
+    async fn accept(&mut self) -> io::Result<MemStream> {
+        match self.rx.recv().await {
+            Ok(stream) => Ok(stream),
+            Err(_) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        Ok(self.addr.clone())
+    }
+}
+
+/// One direction of the connection is a channel of buffers.
+///
+/// The reader and writer coroutine use the stream at the same time (like razor-rpc-tcp), the
+/// read buffer and write buffer are owned by each of them.
+pub struct MemStream {
+    name: String,
+    /// None after shutdown_write, the peer reads EOF
+    tx: Option<MAsyncTx<Vec<u8>>>,
+    rx: AsyncRx<Vec<u8>>,
+    /// received, `rbuf[rpos..]` is not read yet
+    rbuf: Vec<u8>,
+    rpos: usize,
+    /// to send, on flush or exceeding buf_size
+    wbuf: Vec<u8>,
+    buf_size: usize,
+}
+
+impl MemStream {
+    /// Connect to the listener bound on addr
+    pub(crate) fn connect(addr: &str, buf_size: usize) -> io::Result<Self> {
+        let registry = REGISTRY.lock().unwrap();
+        let Some(listener) = registry.get(addr) else {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        };
+        let id = NEXT_CONN.fetch_add(1, Ordering::Relaxed);
+        let (client_tx, server_rx) = crossfire::mpsc::bounded_async(MEM_CHANNEL_SIZE);
+        let (server_tx, client_rx) = crossfire::mpsc::bounded_async(MEM_CHANNEL_SIZE);
+        // The buf_size of the server side is set by new_conn()
+        let server = Self::new(format!("mem {}#{}", addr, id), server_tx, server_rx, 0);
+        if listener.send(server).is_err() {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+        Ok(Self::new(format!("mem {}#{}", addr, id), client_tx, client_rx, buf_size))
+    }
+
+    fn new(name: String, tx: MAsyncTx<Vec<u8>>, rx: AsyncRx<Vec<u8>>, buf_size: usize) -> Self {
+        Self { name, tx: Some(tx), rx, rbuf: Vec::new(), rpos: 0, wbuf: Vec::new(), buf_size }
+    }
+
+    #[inline]
+    pub(crate) fn set_buf_size(&mut self, buf_size: usize) {
+        self.buf_size = buf_size;
+    }
+
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            if self.rpos == self.rbuf.len() {
+                match self.rx.recv().await {
+                    Ok(data) => {
+                        self.rbuf = data;
+                        self.rpos = 0;
+                    }
+                    // The peer has shutdown or gone
+                    Err(_) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                }
+                continue;
+            }
+            let n = (buf.len() - offset).min(self.rbuf.len() - self.rpos);
+            buf[offset..offset + n].copy_from_slice(&self.rbuf[self.rpos..self.rpos + n]);
+            offset += n;
+            self.rpos += n;
+        }
+        Ok(())
+    }
+
+    /// Copy the data into the write buffer, which is sent when it's full.
+    ///
+    /// The data not smaller than buf_size (usually a blob) is sent as a whole.
+    pub async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if buf.len() >= self.buf_size {
+            self.flush().await?;
+            return self.send(buf.to_vec()).await;
+        }
+        self.wbuf.extend_from_slice(buf);
+        if self.wbuf.len() >= self.buf_size {
+            return self.flush().await;
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if self.wbuf.is_empty() {
+            return Ok(());
+        }
+        let data = std::mem::replace(&mut self.wbuf, Vec::with_capacity(self.buf_size));
+        self.send(data).await
+    }
+
+    #[inline]
+    async fn send(&mut self, data: Vec<u8>) -> io::Result<()> {
+        let Some(tx) = self.tx.as_ref() else {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        };
+        match tx.send(data).await {
+            Ok(_) => Ok(()),
+            // The peer has gone
+            Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    /// Flush and close the sending channel, the peer reads EOF after the data
+    pub async fn shutdown_write(&mut self) -> io::Result<()> {
+        let r = self.flush().await;
+        self.tx = None;
+        r
+    }
+}
+
+impl fmt::Debug for MemStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
@@ -0,0 +1,140 @@
+use crate::{MEM_DEFAULT_BUF_SIZE, MemListener, MemStream};
+use captains_log::filter::LogFilter;
+use orb::prelude::*;
+use razor_stream::server::conn::{ConnIO, ConnProto};
+use razor_stream::server::{
+    RpcSvrPush, RpcSvrReq, ServerConfig, ServerTransport, task::ServerTaskEncode,
+};
+use razor_stream::{Codec, error::*};
+use razor_stream::{io_with_timeout, proto};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::{fmt, io};
+
+pub struct MemServer<RT: AsyncRuntime> {
+    stream: UnsafeCell<MemStream>,
+    _conn_count: Arc<()>,
+    proto: ConnProto,
+    _phan: PhantomData<fn(&RT)>,
+}
+
+unsafe impl<RT: AsyncRuntime> Send for MemServer<RT> {}
+
+unsafe impl<RT: AsyncRuntime> Sync for MemServer<RT> {}
+
+impl<RT: AsyncRuntime> MemServer<RT> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_stream_mut(&self) -> &mut MemStream {
+        unsafe { &mut *self.stream.get() }
+    }
+}
+
+impl<RT: AsyncRuntime> fmt::Debug for MemServer<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get_stream_mut().fmt(f)
+    }
+}
+
+impl<RT: AsyncRuntime> ConnIO for MemServer<RT> {
+    #[inline(always)]
+    async fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        self.get_stream_mut().read_exact(buf).await
+    }
+
+    #[inline]
+    async fn write_frame(&self, frame: &proto::EncodedFrame<'_>) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        let timeout = self.proto.config().write_timeout;
+        for part in frame.parts() {
+            io_with_timeout::<RT, _>(timeout, writer.write_all(part)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<RT: AsyncRuntime> ServerTransport for MemServer<RT> {
+    type Listener = MemListener;
+
+    async fn bind(addr: &str) -> io::Result<Self::Listener> {
+        MemListener::bind(addr)
+    }
+
+    fn new_conn(mut stream: MemStream, config: &ServerConfig, conn_count: Arc<()>) -> Self {
+        let mut buf_size = config.stream_buf_size;
+        if buf_size == 0 {
+            buf_size = MEM_DEFAULT_BUF_SIZE;
+        }
+        stream.set_buf_size(buf_size);
+        Self {
+            stream: UnsafeCell::new(stream),
+            proto: ConnProto::new(config),
+            _conn_count: conn_count,
+            _phan: PhantomData,
+        }
+    }
+
+    /// recv_req and return a temporary structure.
+    ///
+    /// NOTE: you should consume the buffer ref before recv another request.
+    #[inline]
+    async fn read_req<'a>(
+        &'a self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        self.proto.read_req::<RT, _>(logger, self, close_ch).await
+    }
+
+    #[inline]
+    async fn write_resp<T: ServerTaskEncode>(
+        &self, logger: &LogFilter, codec: &impl Codec, task: T,
+    ) -> io::Result<()> {
+        self.proto.write_resp(logger, self, codec, task).await
+    }
+
+    async fn write_handshake(
+        &self, logger: &LogFilter, seq: u64, negotiated: &proto::Negotiated,
+    ) -> io::Result<()> {
+        self.proto.write_handshake(logger, self, seq, negotiated).await?;
+        self.flush_resp(logger).await
+    }
+
+    #[inline]
+    async fn write_push(&self, logger: &LogFilter, push: RpcSvrPush) -> io::Result<()> {
+        self.proto.write_push(logger, self, push).await
+    }
+
+    #[inline]
+    async fn write_credit(&self, logger: &LogFilter, seq: u64, credit: u32) -> io::Result<()> {
+        self.proto.write_credit(logger, self, seq, credit).await
+    }
+
+    #[inline(always)]
+    async fn write_resp_internal(
+        &self, logger: &LogFilter, seq: u64, err: Option<EncodedErr>,
+    ) -> io::Result<()> {
+        self.proto.write_resp_internal(logger, self, seq, err).await
+    }
+
+    #[inline(always)]
+    async fn flush_resp(&self, logger: &LogFilter) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        if let Err(e) =
+            io_with_timeout::<RT, _>(self.proto.config().write_timeout, writer.flush()).await
+        {
+            logger_warn!(logger, "{:?}: flush err: {}", self, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: flush_resp ok", self);
+        Ok(())
+    }
+
+    #[inline]
+    async fn close_conn(&self, logger: &LogFilter) {
+        if self.flush_resp(logger).await.is_ok() {
+            let _ = self.get_stream_mut().shutdown_write().await;
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use crate::*;
+use captains_log::filter::LogFilter;
+use crossfire::{AsyncRx, MAsyncRx};
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::{Endpoint, TransportConfig, VarInt};
+use razor_stream::client::timer::ClientTaskTimer;
+use razor_stream::client::{ClientConfig, ClientFacts, ClientTransport};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::sync::atomic::AtomicBool;
+use std::{fmt, io};
+
+pub struct QuicClient<RT: AsyncRuntime, P: TlsClientProvider> {
+    /// Keep the socket of the connection
+    _endpoint: Endpoint,
+    shared: Arc<Shared>,
+    events: AsyncRx<StreamEvent>,
+    /// for read
+    inbound: UnsafeCell<Inbound>,
+    /// CRC32C trailer negotiated by handshake
+    checksum: AtomicBool,
+    conn_id: String,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    _phan: PhantomData<fn(&RT, &P)>,
+}
+
+unsafe impl<RT: AsyncRuntime, P: TlsClientProvider> Send for QuicClient<RT, P> {}
+unsafe impl<RT: AsyncRuntime, P: TlsClientProvider> Sync for QuicClient<RT, P> {}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> fmt::Debug for QuicClient<RT, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "quic client {}", self.conn_id)
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> Drop for QuicClient<RT, P> {
+    fn drop(&mut self) {
+        // The background tasks keep the connection until closed
+        self.shared.conn.close(VarInt::from_u32(0), b"");
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> QuicClient<RT, P> {
+    // The reader owns the inbound, like the decoder of razor-rpc-tcp
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_inbound(&self) -> &mut Inbound {
+        unsafe { &mut *self.inbound.get() }
+    }
+
+    /// Wait until a stream has a complete response frame, return the key of the stream.
+    ///
+    /// The wait of an idle connection is aborted by `close_ch`, return Ok(None) in that case.
+    async fn _recv_frame(
+        &self, logger: &LogFilter, close_ch: Option<&MAsyncRx<()>>,
+    ) -> Result<Option<u64>, RpcIntErr> {
+        let inbound = self.get_inbound();
+        loop {
+            let checksum = self.checksum.load(Ordering::Relaxed);
+            match inbound.ready(checksum, proto::FrameDecoder::want_resp) {
+                Ok(Some(key)) => return Ok(Some(key)),
+                Ok(None) => {}
+                Err(e) => {
+                    logger_debug!(
+                        logger,
+                        "{:?} rpc client decode_response_header err: {}",
+                        self,
+                        e
+                    );
+                    return Err(e);
+                }
+            }
+            let event = match (inbound.is_idle(), close_ch) {
+                (true, Some(close_ch)) => {
+                    match Cancellable::new(self.events.recv(), close_ch.recv()).await {
+                        Ok(r) => r.map_err(|_| io::Error::from(io::ErrorKind::NotConnected)),
+                        Err(_) => return Ok(None),
+                    }
+                }
+                _ => razor_rpc_tcp::io_with_timeout!(RT, self.read_timeout, async {
+                    self.events
+                        .recv()
+                        .await
+                        .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))
+                }),
+            };
+            match event {
+                Ok(StreamEvent::Data(key, data)) => inbound.feed(key, &data),
+                Ok(StreamEvent::End(key)) => {
+                    inbound.end(key);
+                }
+                Ok(StreamEvent::Open(..)) => unreachable!(),
+                Ok(StreamEvent::Closed(e)) => {
+                    logger_debug!(logger, "{:?} rpc client connection closed: {}", self, e);
+                    return Err(RpcIntErr::IO);
+                }
+                Err(e) => {
+                    logger_debug!(logger, "{:?} rpc client read resp err: {}", self, e);
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    /// Open a stream for the request seq
+    async fn open_stream(&self, seq: u64) -> io::Result<SendStream> {
+        let (send, recv) = razor_rpc_tcp::io_with_timeout!(RT, self.write_timeout, async {
+            self.shared.conn.open_bi().await.map_err(io::Error::other)
+        })?;
+        self.shared.insert_send(seq, None);
+        self.shared.spawn_recv(self.shared.new_key(), recv, Some(seq));
+        Ok(send)
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> ClientTransport for QuicClient<RT, P> {
+    async fn connect(addr: &str, conn_id: &str, config: &ClientConfig) -> Result<Self, RpcIntErr> {
+        let remote = match resolve(addr) {
+            Ok(remote) => remote,
+            Err(e) => {
+                warn!("Cannot resolve addr {}: {}", addr, e);
+                return Err(RpcIntErr::Unreachable);
+            }
+        };
+        let Some(server_name) = P::server_name(addr) else {
+            warn!("Invalid tls server name of addr {}", addr);
+            return Err(RpcIntErr::Unreachable);
+        };
+        let crypto = match QuicClientConfig::try_from(P::client_config()) {
+            Ok(crypto) => crypto,
+            Err(e) => {
+                warn!("Cannot setup quic for addr {}: {}", addr, e);
+                return Err(RpcIntErr::Unreachable);
+            }
+        };
+        let mut transport = TransportConfig::default();
+        transport.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+        let mut quic_config = quinn::ClientConfig::new(Arc::new(crypto));
+        quic_config.transport_config(Arc::new(transport));
+        let local: SocketAddr =
+            if remote.is_ipv6() { "[::]:0".parse().unwrap() } else { "0.0.0.0:0".parse().unwrap() };
+        let endpoint = match Endpoint::client(local) {
+            Ok(endpoint) => endpoint,
+            Err(e) => {
+                warn!("Cannot bind quic endpoint for addr {}: {}", addr, e);
+                return Err(RpcIntErr::Unreachable);
+            }
+        };
+        let connecting =
+            match endpoint.connect_with(quic_config, remote, server_name.to_str().as_ref()) {
+                Ok(connecting) => connecting,
+                Err(e) => {
+                    warn!("Cannot connect addr {}: {}", addr, e);
+                    return Err(RpcIntErr::Unreachable);
+                }
+            };
+        let conn = match razor_rpc_tcp::io_with_timeout!(RT, config.connect_timeout, async {
+            connecting.await.map_err(io::Error::other)
+        }) {
+            Ok(conn) => conn,
+            Err(e) => {
+                warn!("Cannot connect addr {}: {}", addr, e);
+                return Err(RpcIntErr::Unreachable);
+            }
+        };
+        let (tx, events) = crossfire::mpsc::bounded_async(EVENT_QUEUE_SIZE);
+        let shared = match Shared::new(conn, tx) {
+            Ok(shared) => shared,
+            Err(e) => {
+                error!("quic client {}: {}", conn_id, e);
+                return Err(RpcIntErr::Internal);
+            }
+        };
+        // For push and GOAWAY
+        shared.spawn_accept_uni();
+        Ok(Self {
+            _endpoint: endpoint,
+            shared,
+            events,
+            inbound: UnsafeCell::new(Inbound::default()),
+            checksum: AtomicBool::new(false),
+            conn_id: conn_id.to_string(),
+            read_timeout: config.read_timeout,
+            write_timeout: config.write_timeout,
+            _phan: PhantomData,
+        })
+    }
+
+    async fn handshake<F: ClientFacts>(
+        &self, logger: &LogFilter, local: &proto::Handshake,
+    ) -> Result<proto::Negotiated, RpcIntErr> {
+        let mut buf = Vec::with_capacity(proto::RPC_REQ_HEADER_LEN + 32);
+        if proto::ReqHead::encode_handshake(&mut buf, 0, local).is_err() {
+            return Err(RpcIntErr::Encode);
+        }
+        if let Err(e) = self.write_req::<F>(logger, &buf, None, true).await {
+            return Err(e.into());
+        }
+        let Some(key) = self._recv_frame(logger, None).await? else { unreachable!() };
+        let frame = match self.get_inbound().decoder(key).decode_resp() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_debug!(logger, "{:?} rpc client read handshake err: {}", self, e);
+                return Err(e);
+            }
+        };
+        if frame.head.flag != 0 {
+            // An old server does not know the handshake action, or the handshake is not supported
+            logger_debug!(logger, "{:?} server does not support handshake, fall back to v1", self);
+            return Ok(proto::Negotiated::default());
+        }
+        let Ok(answer) = proto::Handshake::decode(frame.msg) else {
+            logger_warn!(logger, "{:?} rpc client decode handshake err", self);
+            return Err(RpcIntErr::Decode);
+        };
+        // Verify the choice of the server
+        match local.negotiate(&answer) {
+            Ok(negotiated) => {
+                logger_debug!(logger, "{:?} handshake {:?}", self, negotiated);
+                self.checksum.store(negotiated.has(proto::RPC_FEATURE_CHECKSUM), Ordering::Relaxed);
+                Ok(negotiated)
+            }
+            Err(e) => {
+                logger_warn!(logger, "{:?} server answers unsupported {:?}", self, answer);
+                Err(e)
+            }
+        }
+    }
+
+    #[inline(always)]
+    async fn close_conn<F: ClientFacts>(&self, logger: &LogFilter) {
+        logger_debug!(logger, "{:?} close_conn", self);
+        self.shared.finish_all();
+        self.shared.conn.close(VarInt::from_u32(0), b"");
+    }
+
+    /// The data is sent by quinn without buffering
+    #[inline(always)]
+    async fn flush_req<F: ClientFacts>(&self, logger: &LogFilter) -> io::Result<()> {
+        logger_trace!(logger, "{:?}: flush_req ok", self);
+        Ok(())
+    }
+
+    /// Write the request to the stream of its seq, open one for a new seq
+    #[inline(always)]
+    async fn write_req<'a, F: ClientFacts>(
+        &'a self, logger: &LogFilter, buf: &'a [u8], blob: Option<&'a [u8]>, _need_flush: bool,
+    ) -> io::Result<()> {
+        let seq = match proto::ReqHead::decode_head(&buf[..proto::RPC_REQ_HEADER_LEN]) {
+            Ok(head) => head.seq.get(),
+            Err(_) => return Err(io::ErrorKind::InvalidInput.into()),
+        };
+        let mut send = match self.shared.take_send(seq) {
+            Some(send) => send,
+            None => match self.open_stream(seq).await {
+                Ok(send) => send,
+                Err(e) => {
+                    logger_warn!(logger, "{:?} open stream seq={} err: {}", self, seq, e);
+                    return Err(e);
+                }
+            },
+        };
+        let frame = proto::EncodedFrame::new(self.checksum.load(Ordering::Relaxed), buf, blob);
+        if let Err(e) =
+            razor_rpc_tcp::io_with_timeout!(RT, self.write_timeout, write_frame(&mut send, &frame))
+        {
+            logger_warn!(logger, "{:?} write_req seq={} err: {}", self, seq, e);
+            self.shared.remove_send(seq);
+            return Err(e);
+        }
+        // Kept for the following frames of seq, until the server finishes the response
+        self.shared.put_send(seq, send, false);
+        Ok(())
+    }
+
+    /// return false to indicate aborted by close_f
+    #[inline]
+    async fn read_resp<F: ClientFacts>(
+        &self, facts: &F, logger: &LogFilter, codec: &F::Codec, close_ch: Option<&MAsyncRx<()>>,
+        task_reg: &mut ClientTaskTimer<F>,
+    ) -> Result<bool, RpcIntErr> {
+        let Some(key) = self._recv_frame(logger, close_ch).await? else {
+            return Ok(false);
+        };
+        match self.get_inbound().decoder(key).decode_resp() {
+            Ok(Some(frame)) => {
+                logger_trace!(logger, "{:?} rpc client read head response {}", self, frame.head);
+                task_reg.recv_resp(facts, logger, codec, frame).await;
+                Ok(true)
+            }
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_warn!(logger, "{:?} rpc client decode resp err: {}", self, e);
+                Err(e)
+            }
+        }
+    }
+}
@@ -1 +1,348 @@
-//! This is a placeholder
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, allow(unused_attributes))]
+
+//! # razor-rpc-quic
+//!
+//! This crate provides a QUIC transport implementation for [`razor-stream`](https://docs.rs/razor-stream),
+//! built on [quinn](https://docs.rs/quinn). The frames are the same as TCP.
+//!
+//! Each request `seq` is carried by its own bidirectional stream opened by the client,
+//! the follow-up frames of the same `seq` (request stream, cancel and credit) and the responses
+//! (including partial responses and blob chunks) go through the same stream. So a lost packet
+//! only stalls the request it belongs to, instead of all the in-flight requests on the
+//! connection. The server finishes the stream after the final response, the client finishes its
+//! half in turn. Frames not belonging to a request (push and GOAWAY) are sent on a
+//! unidirectional stream opened by the server.
+//!
+//! The TLS settings are provided by [TlsServerProvider] and [TlsClientProvider] of
+//! [`razor-rpc-tls`](https://docs.rs/razor-rpc-tls), the configs should support TLS 1.3.
+//!
+//! ```ignore
+//! server.listen::<QuicServer<RT, MyTls>, _>("0.0.0.0:8443", dispatch).await?;
+//! let pool = facts.create_pool_async::<QuicClient<RT, MyTls>>(&addr);
+//! ```
+//!
+//! The background tasks of the connection are spawned by the runtime detected by quinn
+//! (tokio or smol), which should be the same as `RT`.
+
+#[macro_use]
+extern crate captains_log;
+mod client;
+pub use client::*;
+mod server;
+pub use server::*;
+
+pub use quinn;
+pub use razor_rpc_tls::{TlsClientProvider, TlsServerProvider, client_config, server_config};
+
+use bytes::Bytes;
+use crossfire::MAsyncTx;
+use quinn::{Connection, RecvStream, SendStream};
+use razor_rpc_tls::rustls::pki_types::CertificateDer;
+use razor_stream::error::RpcIntErr;
+use razor_stream::proto;
+use razor_stream::server::PeerIdentity;
+use rustc_hash::FxHashMap;
+use std::collections::hash_map::Entry;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// The concurrent requests allowed on a connection, the client waits to open more streams
+pub const MAX_CONCURRENT_STREAMS: u32 = 1024;
+
+/// Keep the idle connections of the client pool from the idle timeout of QUIC
+pub const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The max size of the data read from a stream at a time
+const RECV_BUF_SIZE: usize = 64 * 1024;
+
+/// The events queued ahead of the reader of a connection. When it's full, the streams are not
+/// read, so that the flow control of QUIC pushes back on the peer.
+const EVENT_QUEUE_SIZE: usize = 64;
+
+/// Sent by the background tasks of a connection to the reader
+pub(crate) enum StreamEvent {
+    /// A bidirectional stream is accepted by the server, with the send half of it
+    Open(u64, SendStream),
+    Data(u64, Bytes),
+    /// The stream is finished or reset by the peer
+    End(u64),
+    /// The connection is lost
+    Closed(quinn::ConnectionError),
+}
+
+/// Shared by the transport and the background tasks of one connection
+pub(crate) struct Shared {
+    pub conn: Connection,
+    rt: Arc<dyn quinn::Runtime>,
+    tx: MAsyncTx<StreamEvent>,
+    next_key: AtomicU64,
+    /// The send half of the stream by seq, None when it's taken by the writer
+    sends: Mutex<FxHashMap<u64, Option<SendStream>>>,
+}
+
+impl Shared {
+    pub fn new(conn: Connection, tx: MAsyncTx<StreamEvent>) -> io::Result<Arc<Self>> {
+        let Some(rt) = quinn::default_runtime() else {
+            return Err(io::Error::other("no async runtime found for quinn"));
+        };
+        Ok(Arc::new(Self {
+            conn,
+            rt,
+            tx,
+            next_key: AtomicU64::new(0),
+            sends: Mutex::new(FxHashMap::default()),
+        }))
+    }
+
+    #[inline]
+    fn spawn(&self, f: impl Future<Output = ()> + Send + 'static) {
+        self.rt.spawn(Box::pin(f));
+    }
+
+    /// Identify a stream in [StreamEvent]
+    #[inline]
+    pub fn new_key(&self) -> u64 {
+        self.next_key.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Forward the data of the stream to the reader.
+    ///
+    /// `seq` of the stream opened by the client, to finish the send half when the server ends.
+    pub fn spawn_recv(self: &Arc<Self>, key: u64, mut recv: RecvStream, seq: Option<u64>) {
+        let shared = self.clone();
+        self.spawn(async move {
+            loop {
+                // Waits while the reader is behind, the stream is not read meanwhile
+                match recv.read_chunk(RECV_BUF_SIZE, true).await {
+                    Ok(Some(chunk)) => {
+                        if shared.tx.send(StreamEvent::Data(key, chunk.bytes)).await.is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        trace!("quic stream {} read err: {}", key, e);
+                        break;
+                    }
+                }
+            }
+            if let Some(seq) = seq {
+                shared.end_send(seq);
+            }
+            let _ = shared.tx.send(StreamEvent::End(key)).await;
+        });
+    }
+
+    /// Accept the bidirectional streams opened by the client
+    pub fn spawn_accept_bi(self: &Arc<Self>) {
+        let shared = self.clone();
+        self.spawn(async move {
+            loop {
+                match shared.conn.accept_bi().await {
+                    Ok((send, recv)) => {
+                        // Open is received before the data of the stream
+                        let key = shared.new_key();
+                        if shared.tx.send(StreamEvent::Open(key, send)).await.is_err() {
+                            return;
+                        }
+                        shared.spawn_recv(key, recv, None);
+                    }
+                    Err(e) => {
+                        let _ = shared.tx.send(StreamEvent::Closed(e)).await;
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Accept the unidirectional streams opened by the server
+    pub fn spawn_accept_uni(self: &Arc<Self>) {
+        let shared = self.clone();
+        self.spawn(async move {
+            loop {
+                match shared.conn.accept_uni().await {
+                    Ok(recv) => shared.spawn_recv(shared.new_key(), recv, None),
+                    Err(e) => {
+                        let _ = shared.tx.send(StreamEvent::Closed(e)).await;
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Register the send half for seq.
+    ///
+    /// Return false if seq already has one, the new one is finished.
+    pub fn insert_send(&self, seq: u64, send: Option<SendStream>) -> bool {
+        match self.sends.lock().unwrap().entry(seq) {
+            Entry::Occupied(_) => {
+                if let Some(mut send) = send {
+                    let _ = send.finish();
+                }
+                false
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(send);
+                true
+            }
+        }
+    }
+
+    /// Take the send half of seq for writing, put back by [put_send()](Self::put_send)
+    #[inline]
+    pub fn take_send(&self, seq: u64) -> Option<SendStream> {
+        self.sends.lock().unwrap().get_mut(&seq).and_then(|send| send.take())
+    }
+
+    /// Put back the send half after writing, finish it on the last frame of seq
+    pub fn put_send(&self, seq: u64, mut send: SendStream, finish: bool) {
+        {
+            let mut sends = self.sends.lock().unwrap();
+            if !finish {
+                if let Some(slot) = sends.get_mut(&seq) {
+                    *slot = Some(send);
+                    return;
+                }
+                // Ended by the peer during writing
+            } else {
+                sends.remove(&seq);
+            }
+        }
+        let _ = send.finish();
+    }
+
+    /// Forget the send half of seq, which is broken
+    #[inline]
+    pub fn remove_send(&self, seq: u64) {
+        self.sends.lock().unwrap().remove(&seq);
+    }
+
+    /// Finish the send half of seq, if it's not taken by the writer
+    fn end_send(&self, seq: u64) {
+        let send = self.sends.lock().unwrap().remove(&seq);
+        if let Some(Some(mut send)) = send {
+            let _ = send.finish();
+        }
+    }
+
+    /// Finish all the send halves not taken
+    pub fn finish_all(&self) {
+        let mut sends = self.sends.lock().unwrap();
+        for (_, send) in sends.drain() {
+            if let Some(mut send) = send {
+                let _ = send.finish();
+            }
+        }
+    }
+}
+
+/// Decode the frames of the streams for the reader
+#[derive(Default)]
+pub(crate) struct Inbound {
+    decoders: FxHashMap<u64, proto::FrameDecoder>,
+    /// The stream fed last time, which might have more complete frames
+    ready: Option<u64>,
+    /// The send half of the streams accepted, before the first frame arrives
+    opened: FxHashMap<u64, SendStream>,
+}
+
+impl Inbound {
+    /// The stream holding a complete frame
+    pub fn ready(
+        &mut self, checksum: bool, want: fn(&mut proto::FrameDecoder) -> Result<usize, RpcIntErr>,
+    ) -> Result<Option<u64>, RpcIntErr> {
+        let Some(key) = self.ready else {
+            return Ok(None);
+        };
+        let decoder = self.decoder(key);
+        decoder.set_checksum(checksum);
+        if want(decoder)? == 0 {
+            return Ok(Some(key));
+        }
+        self.ready = None;
+        Ok(None)
+    }
+
+    #[inline]
+    pub fn decoder(&mut self, key: u64) -> &mut proto::FrameDecoder {
+        self.decoders.entry(key).or_default()
+    }
+
+    /// Whether there's no frame partially received
+    pub fn is_idle(&mut self) -> bool {
+        self.decoders.values_mut().all(|decoder| decoder.pending() == 0)
+    }
+
+    #[inline]
+    pub fn open(&mut self, key: u64, send: SendStream) {
+        self.opened.insert(key, send);
+    }
+
+    #[inline]
+    pub fn feed(&mut self, key: u64, data: &[u8]) {
+        self.decoder(key).feed(data);
+        self.ready = Some(key);
+    }
+
+    /// Take the send half of the stream accepted, on the first frame
+    #[inline]
+    pub fn take_opened(&mut self, key: u64) -> Option<SendStream> {
+        self.opened.remove(&key)
+    }
+
+    /// Drop the stream ended, return the send half if no frame is received from it.
+    pub fn end(&mut self, key: u64) -> Option<SendStream> {
+        if let Some(mut decoder) = self.decoders.remove(&key)
+            && decoder.pending() > 0
+        {
+            warn!("quic stream {} ends with a partial frame", key);
+        }
+        if self.ready == Some(key) {
+            self.ready = None;
+        }
+        self.opened.remove(&key)
+    }
+}
+
+/// Write the parts of an encoded frame
+pub(crate) async fn write_frame(
+    send: &mut SendStream, frame: &proto::EncodedFrame<'_>,
+) -> io::Result<()> {
+    for part in frame.parts() {
+        send.write_all(part).await.map_err(io::Error::other)?;
+    }
+    Ok(())
+}
+
+/// The identity of the peer, available after the handshake
+pub(crate) fn peer_identity(conn: &Connection) -> PeerIdentity {
+    let certs = match conn.peer_identity().map(|id| id.downcast::<Vec<CertificateDer<'static>>>()) {
+        Some(Ok(certs)) => certs.iter().map(|cert| cert.to_vec()).collect(),
+        _ => Vec::new(),
+    };
+    match conn.handshake_data().map(|data| data.downcast::<quinn::crypto::rustls::HandshakeData>())
+    {
+        Some(Ok(data)) => {
+            PeerIdentity { certs, server_name: data.server_name, alpn: data.protocol }
+        }
+        _ => PeerIdentity { certs, ..Default::default() },
+    }
+}
+
+/// Resolve "host:port" to the UDP address
+pub(crate) fn resolve(addr: &str) -> io::Result<SocketAddr> {
+    if let Ok(addr) = addr.parse() {
+        return Ok(addr);
+    }
+    match addr.to_socket_addrs()?.next() {
+        Some(addr) => Ok(addr),
+        None => Err(io::ErrorKind::AddrNotAvailable.into()),
+    }
+}
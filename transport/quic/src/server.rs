@@ -0,0 +1,511 @@
+use crate::*;
+use captains_log::filter::LogFilter;
+use crossfire::AsyncRx;
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use quinn::crypto::rustls::QuicServerConfig;
+use quinn::{Endpoint, Incoming, TransportConfig, VarInt};
+use razor_stream::server::{
+    RpcSvrPush, RpcSvrReq, ServerConfig, ServerListener, ServerTransport, task::ServerTaskEncode,
+};
+use razor_stream::{Codec, compress, error::*};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU8};
+use std::{fmt, io};
+
+/// Accept the QUIC connections of [QuicServer]
+pub struct QuicListener {
+    endpoint: Endpoint,
+}
+
+impl fmt::Debug for QuicListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.endpoint.local_addr() {
+            Ok(addr) => write!(f, "quic listener {}", addr),
+            Err(_) => write!(f, "quic listener"),
+        }
+    }
+}
+
+impl ServerListener for QuicListener {
+    type Conn = Incoming;
+
+    async fn accept(&mut self) -> io::Result<Incoming> {
+        match self.endpoint.accept().await {
+            Some(incoming) => Ok(incoming),
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "quic endpoint closed")),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        self.endpoint.local_addr().map(|addr| addr.to_string())
+    }
+}
+
+/// The QUIC handshake is done on reading the first request, the identity of the client is set to
+/// [RpcSvrReq::peer].
+pub struct QuicServer<RT: AsyncRuntime, P: TlsServerProvider> {
+    /// Taken by the QUIC handshake
+    incoming: UnsafeCell<Option<Incoming>>,
+    remote: SocketAddr,
+    /// Set after the QUIC handshake
+    shared: OnceLock<Arc<Shared>>,
+    peer: OnceLock<Arc<PeerIdentity>>,
+    events: AsyncRx<StreamEvent>,
+    /// For the background tasks started after the QUIC handshake
+    events_tx: MAsyncTx<StreamEvent>,
+    _conn_count: Arc<()>,
+    config: ServerConfig,
+    /// for read
+    inbound: UnsafeCell<Inbound>,
+    /// The unidirectional stream for push and GOAWAY, for write
+    ctrl: UnsafeCell<Option<SendStream>>,
+    /// highest protocol version received from the client or negotiated by handshake,
+    /// decide whether to write resp meta
+    peer_ver: AtomicU8,
+    /// compression negotiated by handshake
+    compression: AtomicU8,
+    /// CRC32C trailer negotiated by handshake
+    checksum: AtomicBool,
+    /// Blob chunks negotiated by handshake
+    chunk: AtomicBool,
+    /// for write
+    encoder: UnsafeCell<proto::FrameEncoder>,
+    _phan: PhantomData<fn(&RT, &P)>,
+}
+
+unsafe impl<RT: AsyncRuntime, P: TlsServerProvider> Send for QuicServer<RT, P> {}
+
+unsafe impl<RT: AsyncRuntime, P: TlsServerProvider> Sync for QuicServer<RT, P> {}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> Drop for QuicServer<RT, P> {
+    fn drop(&mut self) {
+        // The background tasks keep the connection until closed
+        if let Some(shared) = self.shared.get() {
+            shared.conn.close(VarInt::from_u32(0), b"");
+        }
+    }
+}
+
+/// Where a response frame is written
+enum Target {
+    /// The stream of the request seq
+    Seq(u64),
+    /// The unidirectional stream
+    Ctrl,
+}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> QuicServer<RT, P> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_inbound(&self) -> &mut Inbound {
+        unsafe { &mut *self.inbound.get() }
+    }
+
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_encoder(&self) -> &mut proto::FrameEncoder {
+        let encoder: &mut proto::FrameEncoder = unsafe { &mut *self.encoder.get() };
+        encoder.set_checksum(self.checksum.load(Ordering::Relaxed));
+        encoder.set_chunk(self.chunk.load(Ordering::Relaxed));
+        encoder
+    }
+
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_ctrl(&self) -> &mut Option<SendStream> {
+        unsafe { &mut *self.ctrl.get() }
+    }
+
+    #[inline(always)]
+    fn get_shared(&self) -> io::Result<&Arc<Shared>> {
+        match self.shared.get() {
+            Some(shared) => Ok(shared),
+            None => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    #[inline(always)]
+    fn get_compressor(&self) -> compress::Compressor {
+        compress::Compressor::new(
+            self.config.compression,
+            self.compression.load(Ordering::Relaxed),
+            self.config.compress_threshold,
+        )
+    }
+
+    /// Take the stream to write, the unidirectional stream is opened on demand,
+    /// or when the stream of seq is gone.
+    async fn take_stream(&self, target: Target) -> io::Result<(SendStream, Target)> {
+        let shared = self.get_shared()?;
+        if let Target::Seq(seq) = target
+            && let Some(send) = shared.take_send(seq)
+        {
+            return Ok((send, target));
+        }
+        if let Some(send) = self.get_ctrl().take() {
+            return Ok((send, Target::Ctrl));
+        }
+        let send = razor_rpc_tcp::io_with_timeout!(RT, self.config.write_timeout, async {
+            shared.conn.open_uni().await.map_err(io::Error::other)
+        })?;
+        Ok((send, Target::Ctrl))
+    }
+
+    /// Write the frames to the target stream in order, finish the stream of seq when `last`
+    async fn write_frames(
+        &self, target: Target, frames: &[&proto::EncodedFrame<'_>], last: bool,
+    ) -> io::Result<()> {
+        let (mut send, target) = self.take_stream(target).await?;
+        for frame in frames.iter() {
+            if let Err(e) = razor_rpc_tcp::io_with_timeout!(
+                RT,
+                self.config.write_timeout,
+                write_frame(&mut send, frame)
+            ) {
+                if let Target::Seq(seq) = target {
+                    self.get_shared()?.remove_send(seq);
+                }
+                return Err(e);
+            }
+        }
+        match target {
+            Target::Seq(seq) => self.get_shared()?.put_send(seq, send, last),
+            Target::Ctrl => *self.get_ctrl() = Some(send),
+        }
+        Ok(())
+    }
+
+    /// Complete the QUIC handshake within read_timeout, before reading the first request
+    async fn quic_handshake(
+        &self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<(), RpcIntErr> {
+        let incoming: &mut Option<Incoming> = unsafe { &mut *self.incoming.get() };
+        let Some(incoming) = incoming.take() else {
+            return Err(RpcIntErr::IO);
+        };
+        let connecting = match incoming.accept() {
+            Ok(connecting) => connecting,
+            Err(e) => {
+                logger_warn!(logger, "{:?}: quic accept err: {}", self, e);
+                return Err(RpcIntErr::IO);
+            }
+        };
+        let cancel_f = close_ch.recv_with_timer(RT::sleep(self.config.read_timeout));
+        let conn = match Cancellable::new(connecting, cancel_f).await {
+            Ok(Ok(conn)) => conn,
+            Ok(Err(e)) => {
+                logger_warn!(logger, "{:?}: quic handshake err: {}", self, e);
+                return Err(RpcIntErr::IO);
+            }
+            Err(()) => {
+                logger_debug!(logger, "{:?}: quic handshake timeout", self);
+                return Err(RpcIntErr::Timeout);
+            }
+        };
+        let peer = peer_identity(&conn);
+        let shared = match Shared::new(conn, self.events_tx.clone()) {
+            Ok(shared) => shared,
+            Err(e) => {
+                logger_error!(logger, "{:?}: {}", self, e);
+                return Err(RpcIntErr::Internal);
+            }
+        };
+        shared.spawn_accept_bi();
+        logger_debug!(logger, "{:?}: quic handshake done, sni {:?}", self, peer.server_name);
+        let _ = self.peer.set(Arc::new(peer));
+        let _ = self.shared.set(shared);
+        Ok(())
+    }
+
+    /// Wait until a stream has a complete request frame, return the key of the stream
+    async fn recv_frame(
+        &self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<u64, RpcIntErr> {
+        let inbound = self.get_inbound();
+        loop {
+            let checksum = self.checksum.load(Ordering::Relaxed);
+            match inbound.ready(checksum, proto::FrameDecoder::want_req) {
+                Ok(Some(key)) => return Ok(key),
+                Ok(None) => {}
+                Err(e) => {
+                    logger_warn!(logger, "{:?}: decode_head error, {}", self, e);
+                    return Err(e);
+                }
+            }
+            let event = if inbound.is_idle() {
+                // Wait for the next request within idle_timeout
+                let cancel_f = close_ch.recv_with_timer(RT::sleep(self.config.idle_timeout));
+                match Cancellable::new(self.events.recv(), cancel_f).await {
+                    Ok(r) => r.map_err(|_| io::Error::from(io::ErrorKind::NotConnected)),
+                    Err(()) => {
+                        logger_trace!(logger, "{:?}: read timeout", self);
+                        return Err(RpcIntErr::Timeout);
+                    }
+                }
+            } else {
+                razor_rpc_tcp::io_with_timeout!(RT, self.config.read_timeout, async {
+                    self.events
+                        .recv()
+                        .await
+                        .map_err(|_| io::Error::from(io::ErrorKind::NotConnected))
+                })
+            };
+            match event {
+                Ok(StreamEvent::Open(key, send)) => inbound.open(key, send),
+                Ok(StreamEvent::Data(key, data)) => inbound.feed(key, &data),
+                Ok(StreamEvent::End(key)) => {
+                    if let Some(mut send) = inbound.end(key) {
+                        let _ = send.finish();
+                    }
+                }
+                Ok(StreamEvent::Closed(e)) => {
+                    logger_debug!(logger, "{:?}: recv_req: closed {}", self, e);
+                    return Err(RpcIntErr::IO);
+                }
+                Err(e) => {
+                    logger_trace!(logger, "{:?}: read req error: {}", self, e);
+                    return Err(RpcIntErr::IO);
+                }
+            }
+        }
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> fmt::Debug for QuicServer<RT, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "quic server {}", self.remote)
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> ServerTransport for QuicServer<RT, P> {
+    type Listener = QuicListener;
+
+    async fn bind(addr: &str) -> io::Result<Self::Listener> {
+        let addr = resolve(addr)?;
+        let crypto = QuicServerConfig::try_from(P::server_config()).map_err(io::Error::other)?;
+        let mut transport = TransportConfig::default();
+        transport.max_concurrent_bidi_streams(VarInt::from_u32(MAX_CONCURRENT_STREAMS));
+        let mut quic_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+        quic_config.transport_config(Arc::new(transport));
+        let endpoint = Endpoint::server(quic_config, addr)?;
+        Ok(QuicListener { endpoint })
+    }
+
+    fn new_conn(incoming: Incoming, config: &ServerConfig, conn_count: Arc<()>) -> Self {
+        let (events_tx, events) = crossfire::mpsc::bounded_async(EVENT_QUEUE_SIZE);
+        Self {
+            remote: incoming.remote_address(),
+            incoming: UnsafeCell::new(Some(incoming)),
+            shared: OnceLock::new(),
+            peer: OnceLock::new(),
+            events,
+            events_tx,
+            config: config.clone(),
+            inbound: UnsafeCell::new(Inbound::default()),
+            ctrl: UnsafeCell::new(None),
+            peer_ver: AtomicU8::new(proto::RPC_VERSION_1),
+            compression: AtomicU8::new(compress::COMPRESS_NONE),
+            checksum: AtomicBool::new(false),
+            chunk: AtomicBool::new(false),
+            encoder: UnsafeCell::new(proto::FrameEncoder::new()),
+            _conn_count: conn_count,
+            _phan: PhantomData,
+        }
+    }
+
+    /// recv_req and return a temporary structure.
+    ///
+    /// NOTE: you should consume the buffer ref before recv another request.
+    async fn read_req<'a>(
+        &'a self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        if self.shared.get().is_none() {
+            self.quic_handshake(logger, close_ch).await?;
+        }
+        let key = self.recv_frame(logger, close_ch).await?;
+        let inbound = self.get_inbound();
+        let opened = inbound.take_opened(key);
+        let frame = match inbound.decoder(key).decode_req() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_warn!(logger, "{:?}: decode req error, {}", self, e);
+                return Err(e);
+            }
+        };
+        logger_trace!(logger, "{:?}: recv req: {}", self, frame.head);
+        let seq = frame.head.seq.get();
+        if let Some(mut send) = opened {
+            // The first frame of the stream opened by the client
+            let control = match frame.head.get_action() {
+                Ok(action) => {
+                    action as u32 == proto::CANCEL_ACTION || action as u32 == proto::CREDIT_ACTION
+                }
+                Err(_) => false,
+            };
+            if control || (frame.is_oneway() && !frame.is_more()) {
+                // No response to write
+                let _ = send.finish();
+            } else if let Some(shared) = self.shared.get() {
+                shared.insert_send(seq, Some(send));
+            }
+        }
+        if frame.head.has_ext() && self.peer_ver.load(Ordering::Relaxed) < frame.head.ver {
+            self.peer_ver.store(frame.head.ver, Ordering::Relaxed);
+        }
+        match frame.into_req() {
+            Ok(mut req) => {
+                req.peer = self.peer.get().cloned();
+                Ok(req)
+            }
+            Err(e) => {
+                logger_warn!(logger, "{:?}: decode req payload error", self);
+                Err(e)
+            }
+        }
+    }
+
+    #[inline]
+    async fn write_resp<T: ServerTaskEncode>(
+        &self, logger: &LogFilter, codec: &impl Codec, mut task: T,
+    ) -> io::Result<()> {
+        let last = !task.has_more();
+        let peer_ver = self.peer_ver.load(Ordering::Relaxed);
+        let compressor = self.get_compressor();
+        let (seq, mut frame) =
+            self.get_encoder().encode_resp(&logger, codec, &mut task, peer_ver, &compressor);
+        // The chunks and the response go to the same stream
+        let (mut send, target) = self.take_stream(Target::Seq(seq)).await?;
+        let mut r = Ok(());
+        if let Some(chunks) = frame.chunks.as_mut() {
+            while let Some(chunk) = chunks.next_frame() {
+                r = razor_rpc_tcp::io_with_timeout!(
+                    RT,
+                    self.config.write_timeout,
+                    write_frame(&mut send, &chunk)
+                );
+                if r.is_err() {
+                    break;
+                }
+            }
+        }
+        if r.is_ok() {
+            r = razor_rpc_tcp::io_with_timeout!(
+                RT,
+                self.config.write_timeout,
+                write_frame(&mut send, &frame)
+            );
+        }
+        match (r, target) {
+            (Err(e), target) => {
+                if matches!(target, Target::Seq(_)) {
+                    self.get_shared()?.remove_send(seq);
+                }
+                logger_warn!(logger, "{:?}: send_resp write resp seq={} err: {}", self, seq, e);
+                return Err(e);
+            }
+            (Ok(()), Target::Seq(seq)) => self.get_shared()?.put_send(seq, send, last),
+            (Ok(()), Target::Ctrl) => *self.get_ctrl() = Some(send),
+        }
+        logger_trace!(logger, "{:?}: send resp seq={}", self, seq);
+        Ok(())
+    }
+
+    async fn write_handshake(
+        &self, logger: &LogFilter, seq: u64, negotiated: &proto::Negotiated,
+    ) -> io::Result<()> {
+        let Ok(frame) =
+            self.get_encoder().encode_handshake(&logger, seq, &negotiated.to_handshake())
+        else {
+            return Err(io::ErrorKind::InvalidInput.into());
+        };
+        if let Err(e) = self.write_frames(Target::Seq(seq), &[&frame], true).await {
+            logger_warn!(logger, "{:?}: write handshake err: {}", self, e);
+            return Err(e);
+        }
+        self.peer_ver.store(negotiated.version, Ordering::Relaxed);
+        self.compression.store(negotiated.compression, Ordering::Relaxed);
+        self.checksum.store(negotiated.has(proto::RPC_FEATURE_CHECKSUM), Ordering::Relaxed);
+        self.chunk.store(negotiated.has(proto::RPC_FEATURE_CHUNK), Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn write_push(&self, logger: &LogFilter, push: RpcSvrPush) -> io::Result<()> {
+        let peer_ver = self.peer_ver.load(Ordering::Relaxed);
+        let compressor = self.get_compressor();
+        let frame = match self.get_encoder().encode_push(&logger, &push, peer_ver, &compressor) {
+            Ok(frame) => frame,
+            Err(_) => {
+                // Drop the push, the connection is still healthy
+                logger_warn!(logger, "{:?}: drop push seq={} encode err", self, push.seq);
+                return Ok(());
+            }
+        };
+        // The pushes are kept in order on the unidirectional stream
+        if let Err(e) = self.write_frames(Target::Ctrl, &[&frame], false).await {
+            logger_warn!(logger, "{:?}: write push seq={} err: {}", self, push.seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send push seq={}", self, push.seq);
+        Ok(())
+    }
+
+    async fn write_credit(&self, logger: &LogFilter, seq: u64, credit: u32) -> io::Result<()> {
+        let frame = self.get_encoder().encode_credit(&logger, seq, credit);
+        // The stream of the request is still open for the response
+        if let Err(e) = self.write_frames(Target::Seq(seq), &[&frame], false).await {
+            logger_warn!(logger, "{:?}: write credit seq={} err: {}", self, seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send credit seq={} {}", self, seq, credit);
+        Ok(())
+    }
+
+    #[inline(always)]
+    async fn write_resp_internal(
+        &self, logger: &LogFilter, seq: u64, err: Option<EncodedErr>,
+    ) -> io::Result<()> {
+        // GOAWAY carries the last seq accepted, not the response of it
+        let target = match err {
+            Some(EncodedErr::Rpc(RpcIntErr::GoAway)) => Target::Ctrl,
+            _ => Target::Seq(seq),
+        };
+        let frame = self.get_encoder().encode_internal(&logger, seq, err);
+        if let Err(e) = self.write_frames(target, &[&frame], true).await {
+            logger_warn!(logger, "{:?}: send_resp write resp seq={} err: {}", self, seq, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: send resp seq={}", self, seq);
+        Ok(())
+    }
+
+    /// The data is sent by quinn without buffering
+    #[inline(always)]
+    async fn flush_resp(&self, logger: &LogFilter) -> io::Result<()> {
+        logger_trace!(logger, "{:?}: flush_resp ok", self);
+        Ok(())
+    }
+
+    #[inline]
+    async fn close_conn(&self, logger: &LogFilter) {
+        // The handshake is not done, nothing to close
+        let Some(shared) = self.shared.get() else {
+            return;
+        };
+        shared.finish_all();
+        if let Some(mut send) = self.get_ctrl().take() {
+            let _ = send.finish();
+        }
+        // Closing the connection discards the data not acknowledged,
+        // wait for the client to close after receiving the responses
+        if RT::timeout(self.config.write_timeout, shared.conn.closed()).await.is_err() {
+            logger_debug!(logger, "{:?}: close_conn wait timeout", self);
+        }
+        shared.conn.close(VarInt::from_u32(0), b"");
+    }
+}
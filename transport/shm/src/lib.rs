@@ -0,0 +1,49 @@
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, allow(unused_attributes))]
+
+//! # razor-rpc-shm
+//!
+//! This crate provides a shared memory transport for [`razor-stream`](https://docs.rs/razor-stream),
+//! for the client and server processes on the same host (Linux only). The frames are the same as
+//! TCP, but exchanged through two ring buffers in a memfd, one for each direction.
+//!
+//! The server listens on a Unix socket path. On connect, the client creates the memfd and two
+//! eventfds, and passes them to the server by `SCM_RIGHTS`. After that the socket only tells the
+//! hangup of the peer, the data does not go through the kernel. But it is not zero-copy: a frame
+//! (including the blob) is still copied from the buffer of the writer into the ring, and from the
+//! ring into the buffer of the reader.
+//!
+//! A side waiting for data or space of the ring sets a flag in the shared memory, the peer writes
+//! its eventfd only when the flag is set. The eventfd and the socket are polled by a dedicated OS
+//! thread per connection, which wakes the coroutines through [crossfire](https://docs.rs/crossfire)
+//! channels, so it works with any runtime.
+//!
+//! The blocking connect of the client runs in the blocking pool of the runtime, and the server
+//! receives the shared memory of each connection on its own thread, so a silent client does not
+//! hold up the others.
+//!
+//! ```ignore
+//! server.listen::<ShmServer<RT>, _>("/run/my-service.sock", dispatch).await?;
+//! let pool = facts.create_pool_async::<ShmClient<RT>>("/run/my-service.sock");
+//! ```
+//!
+//! The size of each ring is `ClientConfig::stream_buf_size` rounded up to the power of two,
+//! [SHM_DEFAULT_RING_SIZE] by default.
+
+#[macro_use]
+extern crate captains_log;
+mod client;
+pub use client::*;
+mod server;
+pub use server::*;
+mod stream;
+pub use stream::ShmStream;
+
+use std::time::Duration;
+
+pub const SHM_DEFAULT_RING_SIZE: usize = 4 * 1024 * 1024;
+
+pub const SHM_MIN_RING_SIZE: usize = 64 * 1024;
+
+/// The server drops the connection which does not pass the shared memory in time
+pub const SHM_BOOTSTRAP_TIMEOUT: Duration = Duration::from_secs(1);
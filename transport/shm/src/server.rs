@@ -0,0 +1,237 @@
+use crate::ShmStream;
+use crate::stream::{new_eventfd, notify};
+use captains_log::filter::LogFilter;
+use crossfire::{AsyncRx, MTx};
+use orb::prelude::*;
+use razor_stream::server::conn::{ConnIO, ConnProto};
+use razor_stream::server::{
+    RpcSvrPush, RpcSvrReq, ServerConfig, ServerListener, ServerTransport, task::ServerTaskEncode,
+};
+use razor_stream::{Codec, error::*};
+use razor_stream::{io_with_timeout, proto};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::sync::Arc;
+use std::{fmt, io, thread};
+
+/// Accept the connections of [ShmServer] on a Unix socket path, the path is removed on drop.
+///
+/// The connections are accepted by a thread, and bootstrapped by a short-lived thread each.
+pub struct ShmListener {
+    path: String,
+    rx: AsyncRx<ShmStream>,
+    /// Stop the accept thread
+    stop: OwnedFd,
+}
+
+impl ShmListener {
+    pub fn bind(path: &str) -> io::Result<Self> {
+        let listener = UnixListener::bind(path)?;
+        let stop = new_eventfd()?;
+        let stop_fd = stop.try_clone()?;
+        let (tx, rx) = crossfire::mpsc::unbounded_async();
+        let _path = path.to_string();
+        thread::Builder::new()
+            .name("razor-shm-accept".to_string())
+            .spawn(move || Self::accept_loop(&_path, listener, stop_fd, tx))?;
+        Ok(Self { path: path.to_string(), rx, stop })
+    }
+
+    fn accept_loop(path: &str, listener: UnixListener, stop: OwnedFd, tx: MTx<ShmStream>) {
+        loop {
+            let mut fds = [
+                libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: stop.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            ];
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("shm listener {} poll err: {}", path, e);
+                return;
+            }
+            if fds[1].revents != 0 {
+                return;
+            }
+            if fds[0].revents == 0 {
+                continue;
+            }
+            let sock = match listener.accept() {
+                Ok((sock, _)) => sock,
+                Err(e) => {
+                    warn!("shm listener {} accept err: {}", path, e);
+                    continue;
+                }
+            };
+            // A client sending nothing should not stall the others
+            let _path = path.to_string();
+            let _tx = tx.clone();
+            let r =
+                thread::Builder::new().name("razor-shm-bootstrap".to_string()).spawn(move || {
+                    match ShmStream::accept(&_path, sock) {
+                        Ok(stream) => {
+                            let _ = _tx.send(stream);
+                        }
+                        Err(e) => warn!("shm listener {} bootstrap err: {}", _path, e),
+                    }
+                });
+            if let Err(e) = r {
+                warn!("shm listener {} spawn bootstrap err: {}", path, e);
+            }
+        }
+    }
+}
+
+impl Drop for ShmListener {
+    fn drop(&mut self) {
+        notify(&self.stop);
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl fmt::Debug for ShmListener {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "shm listener {}", self.path)
+    }
+}
+
+impl ServerListener for ShmListener {
+    type Conn = ShmStream;
+
+    async fn accept(&mut self) -> io::Result<ShmStream> {
+        match self.rx.recv().await {
+            Ok(stream) => Ok(stream),
+            Err(_) => Err(io::ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<String> {
+        Ok(self.path.clone())
+    }
+}
+
+pub struct ShmServer<RT: AsyncRuntime> {
+    stream: UnsafeCell<ShmStream>,
+    _conn_count: Arc<()>,
+    proto: ConnProto,
+    _phan: PhantomData<fn(&RT)>,
+}
+
+unsafe impl<RT: AsyncRuntime> Send for ShmServer<RT> {}
+
+unsafe impl<RT: AsyncRuntime> Sync for ShmServer<RT> {}
+
+impl<RT: AsyncRuntime> ShmServer<RT> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_stream_mut(&self) -> &mut ShmStream {
+        unsafe { &mut *self.stream.get() }
+    }
+}
+
+impl<RT: AsyncRuntime> fmt::Debug for ShmServer<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get_stream_mut().fmt(f)
+    }
+}
+
+impl<RT: AsyncRuntime> ConnIO for ShmServer<RT> {
+    #[inline(always)]
+    async fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        self.get_stream_mut().read_exact(buf).await
+    }
+
+    #[inline]
+    async fn write_frame(&self, frame: &proto::EncodedFrame<'_>) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        let timeout = self.proto.config().write_timeout;
+        for part in frame.parts() {
+            io_with_timeout::<RT, _>(timeout, writer.write_all(part)).await?;
+        }
+        Ok(())
+    }
+}
+
+impl<RT: AsyncRuntime> ServerTransport for ShmServer<RT> {
+    type Listener = ShmListener;
+
+    async fn bind(addr: &str) -> io::Result<Self::Listener> {
+        ShmListener::bind(addr)
+    }
+
+    /// The size of the rings is decided by the client
+    fn new_conn(stream: ShmStream, config: &ServerConfig, conn_count: Arc<()>) -> Self {
+        Self {
+            stream: UnsafeCell::new(stream),
+            proto: ConnProto::new(config),
+            _conn_count: conn_count,
+            _phan: PhantomData,
+        }
+    }
+
+    /// recv_req and return a temporary structure.
+    ///
+    /// NOTE: you should consume the buffer ref before recv another request.
+    #[inline]
+    async fn read_req<'a>(
+        &'a self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        self.proto.read_req::<RT, _>(logger, self, close_ch).await
+    }
+
+    #[inline]
+    async fn write_resp<T: ServerTaskEncode>(
+        &self, logger: &LogFilter, codec: &impl Codec, task: T,
+    ) -> io::Result<()> {
+        self.proto.write_resp(logger, self, codec, task).await
+    }
+
+    async fn write_handshake(
+        &self, logger: &LogFilter, seq: u64, negotiated: &proto::Negotiated,
+    ) -> io::Result<()> {
+        self.proto.write_handshake(logger, self, seq, negotiated).await?;
+        self.flush_resp(logger).await
+    }
+
+    #[inline]
+    async fn write_push(&self, logger: &LogFilter, push: RpcSvrPush) -> io::Result<()> {
+        self.proto.write_push(logger, self, push).await
+    }
+
+    #[inline]
+    async fn write_credit(&self, logger: &LogFilter, seq: u64, credit: u32) -> io::Result<()> {
+        self.proto.write_credit(logger, self, seq, credit).await
+    }
+
+    #[inline(always)]
+    async fn write_resp_internal(
+        &self, logger: &LogFilter, seq: u64, err: Option<EncodedErr>,
+    ) -> io::Result<()> {
+        self.proto.write_resp_internal(logger, self, seq, err).await
+    }
+
+    #[inline(always)]
+    async fn flush_resp(&self, logger: &LogFilter) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        if let Err(e) =
+            io_with_timeout::<RT, _>(self.proto.config().write_timeout, writer.flush()).await
+        {
+            logger_warn!(logger, "{:?}: flush err: {}", self, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: flush_resp ok", self);
+        Ok(())
+    }
+
+    #[inline]
+    async fn close_conn(&self, logger: &LogFilter) {
+        if self.flush_resp(logger).await.is_ok() {
+            let _ = self.get_stream_mut().shutdown_write().await;
+        }
+    }
+}
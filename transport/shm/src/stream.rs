@@ -0,0 +1,519 @@
+use crate::{SHM_BOOTSTRAP_TIMEOUT, SHM_MIN_RING_SIZE};
+use crossfire::{AsyncRx, MTx};
+use std::net::Shutdown;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::{fmt, io, mem, ptr, thread};
+
+/// The headers of the rings are in the first page, followed by the data of the rings
+const SHM_DATA_OFFSET: usize = 4096;
+
+/// The payload along with the fds on bootstrap
+const SHM_MAGIC: &[u8; 8] = b"RZSHM\0\0\x01";
+
+/// Updated by the reader of the ring
+#[repr(C, align(64))]
+struct ReaderSide {
+    head: AtomicU64,
+    /// Set when the reader waits for data
+    waiting: AtomicU32,
+}
+
+/// Updated by the writer of the ring
+#[repr(C, align(64))]
+struct WriterSide {
+    tail: AtomicU64,
+    /// Set when the writer waits for space
+    waiting: AtomicU32,
+    /// Set by shutdown_write
+    closed: AtomicU32,
+}
+
+/// In the shared memory, the indexes keep increasing and wrap by the capacity
+#[repr(C)]
+struct RingHead {
+    reader: ReaderSide,
+    writer: WriterSide,
+}
+
+/// One direction of the connection
+struct Ring {
+    head: *const RingHead,
+    data: *mut u8,
+    cap: usize,
+}
+
+impl Ring {
+    #[inline(always)]
+    fn head(&self) -> &RingHead {
+        unsafe { &*self.head }
+    }
+
+    /// Copy buf into the ring at index pos, the space should be checked by the caller
+    #[inline]
+    unsafe fn copy_in(&self, pos: u64, buf: &[u8]) {
+        let offset = pos as usize & (self.cap - 1);
+        let first = buf.len().min(self.cap - offset);
+        unsafe {
+            ptr::copy_nonoverlapping(buf.as_ptr(), self.data.add(offset), first);
+            ptr::copy_nonoverlapping(buf.as_ptr().add(first), self.data, buf.len() - first);
+        }
+    }
+
+    /// Copy the ring at index pos into buf, the data should be checked by the caller
+    #[inline]
+    unsafe fn copy_out(&self, pos: u64, buf: &mut [u8]) {
+        let offset = pos as usize & (self.cap - 1);
+        let first = buf.len().min(self.cap - offset);
+        unsafe {
+            ptr::copy_nonoverlapping(self.data.add(offset), buf.as_mut_ptr(), first);
+            ptr::copy_nonoverlapping(self.data, buf.as_mut_ptr().add(first), buf.len() - first);
+        }
+    }
+}
+
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self { ptr: ptr as *mut u8, len })
+    }
+
+    /// The ring 0 is written by the client, the ring 1 is written by the server
+    fn ring(&self, index: usize) -> Ring {
+        let cap = (self.len - SHM_DATA_OFFSET) / 2;
+        unsafe {
+            Ring {
+                head: self.ptr.add(index * mem::size_of::<RingHead>()) as *const RingHead,
+                data: self.ptr.add(SHM_DATA_OFFSET + index * cap),
+                cap,
+            }
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+/// A connection over the shared memory.
+///
+/// The reader and writer coroutine use the stream at the same time (like razor-rpc-tcp), the rx
+/// ring and tx ring are owned by each of them.
+pub struct ShmStream {
+    name: String,
+    /// Kept to tell the hangup to the peer
+    sock: UnixStream,
+    rx: Ring,
+    tx: Ring,
+    /// The index of the rx ring read
+    rpos: u64,
+    /// The index of the tx ring written
+    wpos: u64,
+    peer_bell: OwnedFd,
+    /// Set by the notifier on hangup of the peer
+    peer_gone: Arc<AtomicBool>,
+    /// Wakes the reader
+    data_rx: AsyncRx<()>,
+    /// Wakes the writer
+    space_rx: AsyncRx<()>,
+    /// Unmapped after the rings are dropped
+    _mapping: Mapping,
+}
+
+unsafe impl Send for ShmStream {}
+
+impl ShmStream {
+    /// Create the shared memory and pass it to the server listening on path, blocking
+    pub(crate) fn connect(path: &str, ring_size: usize) -> io::Result<Self> {
+        let cap = ring_size.max(SHM_MIN_RING_SIZE).next_power_of_two();
+        let len = SHM_DATA_OFFSET + cap * 2;
+        let memfd = new_memfd(len)?;
+        let mapping = Mapping::new(&memfd, len)?;
+        let client_bell = new_eventfd()?;
+        let server_bell = new_eventfd()?;
+        let sock = UnixStream::connect(path)?;
+        let fds = [memfd.as_raw_fd(), client_bell.as_raw_fd(), server_bell.as_raw_fd()];
+        send_fds(&sock, SHM_MAGIC, &fds)?;
+        let name = format!("shm {} fd={}", path, sock.as_raw_fd());
+        Self::new(name, sock, mapping, 0, client_bell, server_bell)
+    }
+
+    /// Receive the shared memory from the client, on a bootstrap thread
+    pub(crate) fn accept(path: &str, sock: UnixStream) -> io::Result<Self> {
+        sock.set_read_timeout(Some(SHM_BOOTSTRAP_TIMEOUT))?;
+        let mut magic = [0u8; 8];
+        let mut fds = recv_fds(&sock, &mut magic, 3)?;
+        if &magic != SHM_MAGIC || fds.len() != 3 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid shm bootstrap"));
+        }
+        sock.set_read_timeout(None)?;
+        let server_bell = fds.pop().unwrap();
+        let client_bell = fds.pop().unwrap();
+        let memfd = fds.pop().unwrap();
+        let len = check_memfd(&memfd)?;
+        let mapping = Mapping::new(&memfd, len)?;
+        let name = format!("shm {} fd={}", path, sock.as_raw_fd());
+        Self::new(name, sock, mapping, 1, server_bell, client_bell)
+    }
+
+    fn new(
+        name: String, sock: UnixStream, mapping: Mapping, index: usize, bell: OwnedFd,
+        peer_bell: OwnedFd,
+    ) -> io::Result<Self> {
+        let (data_tx, data_rx) = crossfire::mpsc::unbounded_async();
+        let (space_tx, space_rx) = crossfire::mpsc::unbounded_async();
+        let peer_gone = Arc::new(AtomicBool::new(false));
+        let notifier = Notifier {
+            bell,
+            sock: sock.try_clone()?,
+            peer_gone: peer_gone.clone(),
+            data_tx,
+            space_tx,
+        };
+        thread::Builder::new().name("razor-shm".to_string()).spawn(move || notifier.run())?;
+        Ok(Self {
+            name,
+            sock,
+            rx: mapping.ring(1 - index),
+            tx: mapping.ring(index),
+            rpos: 0,
+            wpos: 0,
+            peer_bell,
+            peer_gone,
+            data_rx,
+            space_rx,
+            _mapping: mapping,
+        })
+    }
+
+    /// The data available in the rx ring
+    #[inline]
+    fn readable(&self) -> io::Result<usize> {
+        let tail = self.rx.head().writer.tail.load(Ordering::SeqCst);
+        let avail = tail.wrapping_sub(self.rpos);
+        if avail > self.rx.cap as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "shm ring corrupted"));
+        }
+        Ok(avail as usize)
+    }
+
+    /// The space available in the tx ring
+    #[inline]
+    fn writable(&self) -> io::Result<usize> {
+        if self.peer_gone.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let head = self.tx.head().reader.head.load(Ordering::SeqCst);
+        let used = self.wpos.wrapping_sub(head);
+        if used > self.tx.cap as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "shm ring corrupted"));
+        }
+        Ok(self.tx.cap - used as usize)
+    }
+
+    async fn wait_data(&mut self) -> io::Result<()> {
+        while self.data_rx.try_recv().is_ok() {}
+        let ring = self.rx.head();
+        ring.reader.waiting.store(1, Ordering::SeqCst);
+        if self.readable()? > 0 {
+            ring.reader.waiting.store(0, Ordering::SeqCst);
+            return Ok(());
+        }
+        if ring.writer.closed.load(Ordering::SeqCst) != 0 || self.peer_gone.load(Ordering::SeqCst) {
+            // The data written before closing
+            if self.readable()? > 0 {
+                return Ok(());
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        // peer_gone is set before the notifier exits
+        let _ = self.data_rx.recv().await;
+        Ok(())
+    }
+
+    async fn wait_space(&mut self) -> io::Result<()> {
+        while self.space_rx.try_recv().is_ok() {}
+        let ring = self.tx.head();
+        ring.writer.waiting.store(1, Ordering::SeqCst);
+        if self.writable()? > 0 {
+            ring.writer.waiting.store(0, Ordering::SeqCst);
+            return Ok(());
+        }
+        // The reader might be waiting for the data written
+        self.wake_reader();
+        // peer_gone is set before the notifier exits
+        let _ = self.space_rx.recv().await;
+        Ok(())
+    }
+
+    #[inline]
+    fn wake_reader(&self) {
+        if self.tx.head().reader.waiting.swap(0, Ordering::SeqCst) != 0 {
+            notify(&self.peer_bell);
+        }
+    }
+
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            let avail = self.readable()?;
+            if avail == 0 {
+                self.wait_data().await?;
+                continue;
+            }
+            let n = avail.min(buf.len() - offset);
+            unsafe { self.rx.copy_out(self.rpos, &mut buf[offset..offset + n]) };
+            offset += n;
+            self.rpos += n as u64;
+            let ring = self.rx.head();
+            ring.reader.head.store(self.rpos, Ordering::SeqCst);
+            if ring.writer.waiting.swap(0, Ordering::SeqCst) != 0 {
+                notify(&self.peer_bell);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the data into the tx ring, waits when the ring is full.
+    ///
+    /// The data is visible to the peer at once, but the peer waiting is woken on flush.
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            let free = self.writable()?;
+            if free == 0 {
+                self.wait_space().await?;
+                continue;
+            }
+            let n = free.min(buf.len());
+            unsafe { self.tx.copy_in(self.wpos, &buf[..n]) };
+            self.wpos += n as u64;
+            self.tx.head().writer.tail.store(self.wpos, Ordering::SeqCst);
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        if self.peer_gone.load(Ordering::SeqCst) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.wake_reader();
+        Ok(())
+    }
+
+    /// The peer reads EOF after the data written
+    pub async fn shutdown_write(&mut self) -> io::Result<()> {
+        self.tx.head().writer.closed.store(1, Ordering::SeqCst);
+        notify(&self.peer_bell);
+        Ok(())
+    }
+}
+
+impl Drop for ShmStream {
+    fn drop(&mut self) {
+        self.tx.head().writer.closed.store(1, Ordering::SeqCst);
+        notify(&self.peer_bell);
+        // Stop the notifier, and the peer sees the hangup
+        let _ = self.sock.shutdown(Shutdown::Both);
+    }
+}
+
+impl fmt::Debug for ShmStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+/// Poll the eventfd and the socket of a connection, on its own thread
+struct Notifier {
+    bell: OwnedFd,
+    sock: UnixStream,
+    peer_gone: Arc<AtomicBool>,
+    data_tx: MTx<()>,
+    space_tx: MTx<()>,
+}
+
+impl Notifier {
+    fn run(self) {
+        loop {
+            let mut fds = [
+                libc::pollfd { fd: self.bell.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: self.sock.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            ];
+            if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                warn!("shm notifier poll err: {}", e);
+                break;
+            }
+            // Nothing is sent on the socket after bootstrap, it's the hangup
+            if fds[1].revents != 0 {
+                break;
+            }
+            if fds[0].revents & libc::POLLIN != 0 {
+                let mut count = 0u64;
+                unsafe {
+                    libc::read(
+                        self.bell.as_raw_fd(),
+                        &mut count as *mut u64 as *mut libc::c_void,
+                        mem::size_of::<u64>(),
+                    );
+                }
+                let r_data = self.data_tx.send(());
+                let r_space = self.space_tx.send(());
+                if r_data.is_err() && r_space.is_err() {
+                    // The stream is dropped
+                    return;
+                }
+            }
+        }
+        self.peer_gone.store(true, Ordering::SeqCst);
+        let _ = self.data_tx.send(());
+        let _ = self.space_tx.send(());
+    }
+}
+
+/// Write the eventfd
+#[inline]
+pub(crate) fn notify(fd: &OwnedFd) {
+    let count = 1u64;
+    unsafe {
+        libc::write(
+            fd.as_raw_fd(),
+            &count as *const u64 as *const libc::c_void,
+            mem::size_of::<u64>(),
+        );
+    }
+}
+
+pub(crate) fn new_eventfd() -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// The memfd sealed from resizing, which would fault the mapping of the peer
+fn new_memfd(len: usize) -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::memfd_create(c"razor-rpc-shm".as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    if unsafe { libc::ftruncate(fd.as_raw_fd(), len as libc::off_t) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(fd)
+}
+
+/// Verify the memfd from the client, return the size
+fn check_memfd(fd: &OwnedFd) -> io::Result<usize> {
+    let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+    if seals < 0 || seals & libc::F_SEAL_SHRINK == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "shm memfd not sealed"));
+    }
+    let mut stat: libc::stat = unsafe { mem::zeroed() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = stat.st_size as usize;
+    let cap = len.saturating_sub(SHM_DATA_OFFSET) / 2;
+    if cap < SHM_MIN_RING_SIZE || !cap.is_power_of_two() || len != SHM_DATA_OFFSET + cap * 2 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid shm size"));
+    }
+    Ok(len)
+}
+
+fn send_fds(sock: &UnixStream, payload: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let fds_len = mem::size_of_val(fds);
+    let mut cmsg_buf = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize];
+    let mut iov =
+        libc::iovec { iov_base: payload.as_ptr() as *mut libc::c_void, iov_len: payload.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = cmsg_buf.len() as _;
+    let n = unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len as u32) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr() as *const u8, libc::CMSG_DATA(cmsg), fds_len);
+        libc::sendmsg(sock.as_raw_fd(), &msg, libc::MSG_NOSIGNAL)
+    };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if n as usize != payload.len() {
+        return Err(io::ErrorKind::WriteZero.into());
+    }
+    Ok(())
+}
+
+fn recv_fds(sock: &UnixStream, payload: &mut [u8], max_fds: usize) -> io::Result<Vec<OwnedFd>> {
+    let fds_len = max_fds * mem::size_of::<RawFd>();
+    let mut cmsg_buf = vec![0u8; unsafe { libc::CMSG_SPACE(fds_len as u32) } as usize];
+    let mut iov =
+        libc::iovec { iov_base: payload.as_mut_ptr() as *mut libc::c_void, iov_len: payload.len() };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = cmsg_buf.len() as _;
+    let n = unsafe { libc::recvmsg(sock.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Own the fds received before any check, so they are closed on error
+    let mut fds = Vec::new();
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for i in 0..data_len / mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    if n as usize != payload.len() || msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated shm bootstrap"));
+    }
+    Ok(fds)
+}
@@ -0,0 +1,298 @@
+use crate::stream::TlsStream;
+use captains_log::filter::LogFilter;
+use crossfire::MAsyncRx;
+use orb::net::UnifyStream;
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use razor_rpc_tcp::CLIENT_DEFAULT_BUF_SIZE;
+use razor_stream::client::timer::ClientTaskTimer;
+use razor_stream::client::{ClientConfig, ClientFacts, ClientTransport};
+use razor_stream::error::*;
+use razor_stream::proto;
+use rustls::pki_types::ServerName;
+use rustls::{ClientConnection, Connection};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::mem::transmute;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{fmt, io};
+
+/// Provides the TLS settings of [TlsClient].
+///
+/// The transport is constructed by `ClientTransport::connect()` with the address only, so the
+/// config is provided by type, usually from a global.
+pub trait TlsClientProvider: Send + Sync + 'static {
+    /// Refer to [client_config()](crate::client_config) to build one
+    fn client_config() -> Arc<rustls::ClientConfig>;
+
+    /// The name to verify the server certificate, also sent as SNI.
+    ///
+    /// By default, it's the host of `addr`. An IP address is verified but not sent as SNI.
+    fn server_name(addr: &str) -> Option<ServerName<'static>> {
+        ServerName::try_from(host_of(addr).to_string()).ok()
+    }
+}
+
+/// Strip the port of "host:port" or "[ipv6]:port"
+fn host_of(addr: &str) -> &str {
+    if let Some(rest) = addr.strip_prefix('[') {
+        if let Some((host, _)) = rest.split_once(']') {
+            return host;
+        }
+    }
+    match addr.rsplit_once(':') {
+        Some((host, _)) if !host.contains(':') => host,
+        _ => addr,
+    }
+}
+
+pub struct TlsClient<RT: AsyncRuntime, P: TlsClientProvider> {
+    stream: UnsafeCell<TlsStream<RT>>,
+    /// for read
+    decoder: UnsafeCell<proto::FrameDecoder>,
+    /// CRC32C trailer negotiated by handshake
+    checksum: AtomicBool,
+    conn_id: String,
+    read_timeout: Duration,
+    write_timeout: Duration,
+    _phan: PhantomData<fn(&P)>,
+}
+
+unsafe impl<RT: AsyncRuntime, P: TlsClientProvider> Send for TlsClient<RT, P> {}
+unsafe impl<RT: AsyncRuntime, P: TlsClientProvider> Sync for TlsClient<RT, P> {}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> fmt::Debug for TlsClient<RT, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "tls client {}", self.conn_id)
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> TlsClient<RT, P> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_stream_mut(&self) -> &mut TlsStream<RT> {
+        unsafe { transmute(self.stream.get()) }
+    }
+
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_decoder(&self) -> &mut proto::FrameDecoder {
+        let decoder: &mut proto::FrameDecoder = unsafe { transmute(self.decoder.get()) };
+        decoder.set_checksum(self.checksum.load(Ordering::Relaxed));
+        decoder
+    }
+
+    /// Read until the decoder has a complete response frame.
+    ///
+    /// The first read is aborted by `close_ch`, return Ok(false) in that case.
+    async fn _recv_frame(
+        &self, logger: &LogFilter, close_ch: Option<&MAsyncRx<()>>,
+    ) -> Result<bool, RpcIntErr> {
+        let reader = self.get_stream_mut();
+        let decoder = self.get_decoder();
+        loop {
+            let want = match decoder.want_resp() {
+                Ok(want) => want,
+                Err(e) => {
+                    logger_debug!(
+                        logger,
+                        "{:?} rpc client decode_response_header err: {}",
+                        self,
+                        e
+                    );
+                    return Err(e);
+                }
+            };
+            if want == 0 {
+                return Ok(true);
+            }
+            let idle = decoder.pending() == 0;
+            let buf = decoder.read_buf(want);
+            if let (true, Some(close_ch)) = (idle, close_ch) {
+                match Cancellable::new(reader.read_exact(buf), close_ch.recv()).await {
+                    Ok(Err(e)) => {
+                        logger_debug!(logger, "{:?} rpc client read resp err: {:?}", self, e);
+                        return Err(e.into());
+                    }
+                    Err(_) => return Ok(false),
+                    _ => {}
+                }
+            } else if let Err(e) =
+                razor_rpc_tcp::io_with_timeout!(RT, self.read_timeout, reader.read_exact(buf))
+            {
+                logger_debug!(logger, "{:?} rpc client read resp err: {}", self, e);
+                return Err(e.into());
+            }
+            decoder.advance(want);
+        }
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsClientProvider> ClientTransport for TlsClient<RT, P> {
+    async fn connect(addr: &str, conn_id: &str, config: &ClientConfig) -> Result<Self, RpcIntErr> {
+        let Some(server_name) = P::server_name(addr) else {
+            warn!("Invalid tls server name of addr {}", addr);
+            return Err(RpcIntErr::Unreachable);
+        };
+        let tls = match ClientConnection::new(P::client_config(), server_name) {
+            Ok(tls) => tls,
+            Err(e) => {
+                warn!("Cannot setup tls for addr {}: {}", addr, e);
+                return Err(RpcIntErr::Unreachable);
+            }
+        };
+        let stream: UnifyStream<RT> =
+            match UnifyStream::<RT>::connect_timeout(addr, config.connect_timeout).await {
+                Ok(_stream) => _stream,
+                Err(e) => {
+                    warn!("Cannot connect addr {}: {}", addr, e);
+                    return Err(RpcIntErr::Unreachable);
+                }
+            };
+        let mut buf_size = config.stream_buf_size;
+        if buf_size == 0 {
+            buf_size = CLIENT_DEFAULT_BUF_SIZE;
+        }
+        let mut stream = TlsStream::new(stream, Connection::Client(tls), buf_size);
+        if let Err(e) =
+            razor_rpc_tcp::io_with_timeout!(RT, config.connect_timeout, stream.handshake())
+        {
+            warn!("tls handshake with addr {} err: {}", addr, e);
+            return Err(RpcIntErr::Unreachable);
+        }
+        Ok(Self {
+            stream: UnsafeCell::new(stream),
+            decoder: UnsafeCell::new(proto::FrameDecoder::new()),
+            checksum: AtomicBool::new(false),
+            conn_id: conn_id.to_string(),
+            write_timeout: config.write_timeout,
+            read_timeout: config.read_timeout,
+            _phan: PhantomData,
+        })
+    }
+
+    async fn handshake<F: ClientFacts>(
+        &self, logger: &LogFilter, local: &proto::Handshake,
+    ) -> Result<proto::Negotiated, RpcIntErr> {
+        let mut buf = Vec::with_capacity(proto::RPC_REQ_HEADER_LEN + 32);
+        if proto::ReqHead::encode_handshake(&mut buf, 0, local).is_err() {
+            return Err(RpcIntErr::Encode);
+        }
+        if let Err(e) = self.write_req::<F>(logger, &buf, None, true).await {
+            return Err(e.into());
+        }
+        self._recv_frame(logger, None).await?;
+        let frame = match self.get_decoder().decode_resp() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_debug!(logger, "{:?} rpc client read handshake err: {}", self, e);
+                return Err(e);
+            }
+        };
+        if frame.head.flag != 0 {
+            // An old server does not know the handshake action, or the handshake is not supported
+            logger_debug!(logger, "{:?} server does not support handshake, fall back to v1", self);
+            return Ok(proto::Negotiated::default());
+        }
+        let Ok(answer) = proto::Handshake::decode(frame.msg) else {
+            logger_warn!(logger, "{:?} rpc client decode handshake err", self);
+            return Err(RpcIntErr::Decode);
+        };
+        // Verify the choice of the server
+        match local.negotiate(&answer) {
+            Ok(negotiated) => {
+                logger_debug!(logger, "{:?} handshake {:?}", self, negotiated);
+                self.checksum.store(negotiated.has(proto::RPC_FEATURE_CHECKSUM), Ordering::Relaxed);
+                return Ok(negotiated);
+            }
+            Err(e) => {
+                logger_warn!(logger, "{:?} server answers unsupported {:?}", self, answer);
+                return Err(e);
+            }
+        }
+    }
+
+    #[inline(always)]
+    async fn close_conn<F: ClientFacts>(&self, logger: &LogFilter) {
+        let stream = self.get_stream_mut();
+        // close_notify is flushed along with the pending requests
+        if let Err(e) =
+            razor_rpc_tcp::io_with_timeout!(RT, self.write_timeout, stream.shutdown_write())
+        {
+            logger_debug!(logger, "{:?} close_conn err: {}", self, e);
+        }
+    }
+
+    #[inline(always)]
+    async fn flush_req<F: ClientFacts>(&self, logger: &LogFilter) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        if let Err(e) = razor_rpc_tcp::io_with_timeout!(RT, self.write_timeout, writer.flush()) {
+            logger_warn!(logger, "{:?} flush_req flush err: {}", self, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: flush_req ok", self);
+        Ok(())
+    }
+
+    #[inline(always)]
+    async fn write_req<'a, F: ClientFacts>(
+        &'a self, logger: &LogFilter, buf: &'a [u8], blob: Option<&'a [u8]>, need_flush: bool,
+    ) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        let frame = proto::EncodedFrame::new(self.checksum.load(Ordering::Relaxed), buf, blob);
+        for part in frame.parts() {
+            if let Err(e) =
+                razor_rpc_tcp::io_with_timeout!(RT, self.write_timeout, writer.write_all(part))
+            {
+                logger_warn!(logger, "{:?} write_req err: {}", self, e);
+                return Err(e);
+            }
+        }
+        if need_flush {
+            self.flush_req::<F>(logger).await?;
+        }
+        return Ok(());
+    }
+
+    /// return false to indicate aborted by close_f
+    #[inline]
+    async fn read_resp<F: ClientFacts>(
+        &self, facts: &F, logger: &LogFilter, codec: &F::Codec, close_ch: Option<&MAsyncRx<()>>,
+        task_reg: &mut ClientTaskTimer<F>,
+    ) -> Result<bool, RpcIntErr> {
+        if !self._recv_frame(logger, close_ch).await? {
+            return Ok(false);
+        }
+        match self.get_decoder().decode_resp() {
+            Ok(Some(frame)) => {
+                logger_trace!(logger, "{:?} rpc client read head response {}", self, frame.head);
+                task_reg.recv_resp(facts, logger, codec, frame).await;
+                return Ok(true);
+            }
+            Ok(None) => unreachable!(),
+            Err(e) => {
+                logger_warn!(logger, "{:?} rpc client decode resp err: {}", self, e);
+                return Err(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(host_of("localhost:8000"), "localhost");
+        assert_eq!(host_of("127.0.0.1:8000"), "127.0.0.1");
+        assert_eq!(host_of("[::1]:8000"), "::1");
+        assert_eq!(host_of("example.com"), "example.com");
+        assert_eq!(host_of("::1"), "::1");
+    }
+}
@@ -0,0 +1,93 @@
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, allow(unused_attributes))]
+
+//! # razor-rpc-tls
+//!
+//! This crate provides a TLS transport implementation for [`razor-stream`](https://docs.rs/razor-stream),
+//! with [rustls](https://docs.rs/rustls) over the TCP / unix socket of
+//! [`razor-rpc-tcp`](https://docs.rs/razor-rpc-tcp). The frames are the same as TCP.
+//!
+//! Because the transports are constructed without arguments besides the address and config,
+//! the rustls configs are provided by type with [TlsServerProvider] and [TlsClientProvider]:
+//!
+//! ```ignore
+//! static SERVER_TLS: OnceLock<Arc<rustls::ServerConfig>> = OnceLock::new();
+//!
+//! struct MyTls;
+//!
+//! impl TlsServerProvider for MyTls {
+//!     fn server_config() -> Arc<rustls::ServerConfig> {
+//!         SERVER_TLS.get().expect("tls initialized").clone()
+//!     }
+//! }
+//!
+//! let config = razor_rpc_tls::server_config(certs, key, Some(client_roots), &[b"razor"])?;
+//! SERVER_TLS.set(Arc::new(config)).unwrap();
+//! server.listen::<TlsServer<RT, MyTls>, _>("0.0.0.0:8443", dispatch).await?;
+//! ```
+//!
+//! With mutual TLS, the verified client certificate is set to
+//! [RpcSvrReq::peer](razor_stream::server::RpcSvrReq) for `Dispatch`, and passed on to
+//! `APIServerReq::peer` of razor-rpc.
+
+#[macro_use]
+extern crate captains_log;
+mod client;
+pub use client::*;
+mod server;
+pub use server::*;
+mod stream;
+
+pub use rustls;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, RootCertStore, ServerConfig};
+use std::sync::Arc;
+
+/// Build a rustls ServerConfig with the ring crypto provider.
+///
+/// - `client_roots`: require the clients to present certificate signed by them (mutual TLS).
+/// - `alpn`: the application protocols supported, in the order of preference.
+///
+/// To select the certificate by SNI, build the config with your own `ResolvesServerCert`.
+pub fn server_config(
+    certs: Vec<CertificateDer<'static>>, key: PrivateKeyDer<'static>,
+    client_roots: Option<RootCertStore>, alpn: &[&[u8]],
+) -> Result<ServerConfig, rustls::Error> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match client_roots {
+        Some(roots) => {
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .map_err(|e| rustls::Error::General(e.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut config = builder.with_single_cert(certs, key)?;
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    Ok(config)
+}
+
+/// Build a rustls ClientConfig with the ring crypto provider.
+///
+/// - `roots`: to verify the server certificate.
+/// - `client_cert`: the certificate chain and key presented to the server for mutual TLS.
+/// - `alpn`: the application protocols supported, in the order of preference.
+pub fn client_config(
+    roots: RootCertStore,
+    client_cert: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>, alpn: &[&[u8]],
+) -> Result<ClientConfig, rustls::Error> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots);
+    let mut config = match client_cert {
+        Some((certs, key)) => builder.with_client_auth_cert(certs, key)?,
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+    Ok(config)
+}
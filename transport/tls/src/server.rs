@@ -0,0 +1,211 @@
+use crate::stream::TlsStream;
+use captains_log::filter::LogFilter;
+use orb::net::{UnifyListener, UnifyStream};
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use razor_rpc_tcp::SERVER_DEFAULT_BUF_SIZE;
+use razor_stream::proto;
+use razor_stream::server::conn::{ConnIO, ConnProto};
+use razor_stream::server::{
+    PeerIdentity, RpcSvrPush, RpcSvrReq, ServerConfig, ServerTransport, task::ServerTaskEncode,
+};
+use razor_stream::{Codec, error::*};
+use rustls::{Connection, ServerConnection};
+use std::cell::UnsafeCell;
+use std::marker::PhantomData;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use std::{fmt, io};
+
+/// Provides the TLS settings of [TlsServer].
+///
+/// The transport is constructed by `ServerTransport::new_conn()` without arguments of it,
+/// so the config is provided by type, usually from a global.
+pub trait TlsServerProvider: Send + Sync + 'static {
+    /// Refer to [server_config()](crate::server_config) to build one
+    fn server_config() -> Arc<rustls::ServerConfig>;
+}
+
+/// The TLS handshake is done on reading the first request, the identity of the client is set to
+/// [RpcSvrReq::peer].
+pub struct TlsServer<RT: AsyncRuntime, P: TlsServerProvider> {
+    /// Err when rustls rejects the server config
+    stream: UnsafeCell<Result<TlsStream<RT>, rustls::Error>>,
+    _conn_count: Arc<()>,
+    /// Set after the TLS handshake
+    peer: OnceLock<Arc<PeerIdentity>>,
+    proto: ConnProto,
+    _phan: PhantomData<fn(&P)>,
+}
+
+unsafe impl<RT: AsyncRuntime, P: TlsServerProvider> Send for TlsServer<RT, P> {}
+
+unsafe impl<RT: AsyncRuntime, P: TlsServerProvider> Sync for TlsServer<RT, P> {}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> TlsServer<RT, P> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_stream_mut(&self) -> io::Result<&mut TlsStream<RT>> {
+        match unsafe { &mut *self.stream.get() } {
+            Ok(stream) => Ok(stream),
+            Err(e) => Err(io::Error::other(e.clone())),
+        }
+    }
+
+    /// Complete the TLS handshake within read_timeout, before reading the first request
+    async fn tls_handshake(
+        &self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<(), RpcIntErr> {
+        let stream = match self.get_stream_mut() {
+            Ok(stream) => stream,
+            Err(e) => {
+                logger_error!(logger, "{:?}: tls config err: {}", self, e);
+                return Err(RpcIntErr::Internal);
+            }
+        };
+        let cancel_f = close_ch.recv_with_timer(RT::sleep(self.proto.config().read_timeout));
+        match Cancellable::new(stream.handshake(), cancel_f).await {
+            Ok(Err(e)) => {
+                logger_warn!(logger, "{:?}: tls handshake err: {}", self, e);
+                return Err(RpcIntErr::IO);
+            }
+            Err(()) => {
+                logger_debug!(logger, "{:?}: tls handshake timeout", self);
+                return Err(RpcIntErr::Timeout);
+            }
+            _ => {}
+        }
+        let peer = stream.peer_identity();
+        logger_debug!(logger, "{:?}: tls handshake done, sni {:?}", self, peer.server_name);
+        let _ = self.peer.set(Arc::new(peer));
+        Ok(())
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> fmt::Debug for TlsServer<RT, P> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.get_stream_mut() {
+            Ok(stream) => stream.fmt(f),
+            Err(_) => write!(f, "tls server"),
+        }
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> ConnIO for TlsServer<RT, P> {
+    #[inline(always)]
+    async fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        self.get_stream_mut()?.read_exact(buf).await
+    }
+
+    #[inline]
+    async fn write_frame(&self, frame: &proto::EncodedFrame<'_>) -> io::Result<()> {
+        let writer = self.get_stream_mut()?;
+        let timeout = self.proto.config().write_timeout;
+        for part in frame.parts() {
+            razor_rpc_tcp::io_with_timeout!(RT, timeout, writer.write_all(part))?;
+        }
+        Ok(())
+    }
+}
+
+impl<RT: AsyncRuntime, P: TlsServerProvider> ServerTransport for TlsServer<RT, P> {
+    type Listener = UnifyListener<RT>;
+
+    async fn bind(addr: &str) -> io::Result<Self::Listener> {
+        Self::Listener::bind(addr).await
+    }
+
+    fn new_conn(stream: UnifyStream<RT>, config: &ServerConfig, conn_count: Arc<()>) -> Self {
+        let mut buf_size = config.stream_buf_size;
+        if buf_size == 0 {
+            buf_size = SERVER_DEFAULT_BUF_SIZE;
+        }
+        let stream = ServerConnection::new(P::server_config())
+            .map(|tls| TlsStream::new(stream, Connection::Server(tls), buf_size));
+        Self {
+            stream: UnsafeCell::new(stream),
+            peer: OnceLock::new(),
+            proto: ConnProto::new(config),
+            _conn_count: conn_count,
+            _phan: PhantomData,
+        }
+    }
+
+    /// recv_req and return a temporary structure.
+    ///
+    /// NOTE: you should consume the buffer ref before recv another request.
+    async fn read_req<'a>(
+        &'a self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        if self.peer.get().is_none() {
+            self.tls_handshake(logger, close_ch).await?;
+        }
+        let mut req = self.proto.read_req::<RT, _>(logger, self, close_ch).await?;
+        req.peer = self.peer.get().cloned();
+        Ok(req)
+    }
+
+    #[inline]
+    async fn write_resp<T: ServerTaskEncode>(
+        &self, logger: &LogFilter, codec: &impl Codec, task: T,
+    ) -> io::Result<()> {
+        self.proto.write_resp(logger, self, codec, task).await
+    }
+
+    async fn write_handshake(
+        &self, logger: &LogFilter, seq: u64, negotiated: &proto::Negotiated,
+    ) -> io::Result<()> {
+        self.proto.write_handshake(logger, self, seq, negotiated).await?;
+        self.flush_resp(logger).await
+    }
+
+    #[inline]
+    async fn write_push(&self, logger: &LogFilter, push: RpcSvrPush) -> io::Result<()> {
+        self.proto.write_push(logger, self, push).await
+    }
+
+    #[inline]
+    async fn write_credit(&self, logger: &LogFilter, seq: u64, credit: u32) -> io::Result<()> {
+        self.proto.write_credit(logger, self, seq, credit).await
+    }
+
+    #[inline(always)]
+    async fn write_resp_internal(
+        &self, logger: &LogFilter, seq: u64, err: Option<EncodedErr>,
+    ) -> io::Result<()> {
+        self.proto.write_resp_internal(logger, self, seq, err).await
+    }
+
+    #[inline(always)]
+    async fn flush_resp(&self, logger: &LogFilter) -> io::Result<()> {
+        let writer = self.get_stream_mut()?;
+        if let Err(e) =
+            razor_rpc_tcp::io_with_timeout!(RT, self.proto.config().write_timeout, writer.flush())
+        {
+            logger_warn!(logger, "{:?}: flush err: {}", self, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: flush_resp ok", self);
+        Ok(())
+    }
+
+    #[inline]
+    async fn close_conn(&self, logger: &LogFilter) {
+        // The handshake is not done, nothing to close
+        if self.peer.get().is_none() {
+            return;
+        }
+        if let Ok(writer) = self.get_stream_mut() {
+            // close_notify is flushed along with the pending responses
+            if let Err(e) = razor_rpc_tcp::io_with_timeout!(
+                RT,
+                self.proto.config().write_timeout,
+                writer.shutdown_write()
+            ) {
+                logger_debug!(logger, "{:?}: close_conn err: {}", self, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use orb::net::UnifyStream;
+use orb::prelude::*;
+use razor_stream::server::PeerIdentity;
+use rustls::Connection;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Mutex;
+
+/// Drive a rustls session over the async socket.
+///
+/// The reader and writer coroutine use the stream at the same time (like razor-rpc-tcp), the
+/// read buffer and write buffer are owned by each of them, while the rustls session is
+/// guarded by a mutex, which is never held across await.
+pub(crate) struct TlsStream<RT: AsyncRuntime> {
+    io: UnifyStream<RT>,
+    tls: Mutex<Connection>,
+    /// ciphertext received, `rbuf[rpos..rlen]` is not consumed by rustls yet
+    rbuf: Box<[u8]>,
+    rpos: usize,
+    rlen: usize,
+    /// ciphertext to send, written to the socket on flush or exceeding buf_size
+    wbuf: Vec<u8>,
+    buf_size: usize,
+}
+
+impl<RT: AsyncRuntime> TlsStream<RT> {
+    pub fn new(io: UnifyStream<RT>, tls: Connection, buf_size: usize) -> Self {
+        Self {
+            io,
+            tls: Mutex::new(tls),
+            rbuf: vec![0; buf_size].into_boxed_slice(),
+            rpos: 0,
+            rlen: 0,
+            wbuf: Vec::with_capacity(buf_size),
+            buf_size,
+        }
+    }
+
+    /// Exchange the handshake messages until the session is established
+    pub async fn handshake(&mut self) -> io::Result<()> {
+        loop {
+            self.flush().await?;
+            if !self.tls.lock().unwrap().is_handshaking() {
+                return Ok(());
+            }
+            if let Err(e) = self.read_tls().await {
+                // Send the alert to the peer if any
+                let _ = self.flush().await;
+                return Err(e);
+            }
+        }
+    }
+
+    /// The identity of the peer, available after handshake
+    pub fn peer_identity(&self) -> PeerIdentity {
+        let tls = self.tls.lock().unwrap();
+        let certs = match tls.peer_certificates() {
+            Some(certs) => certs.iter().map(|cert| cert.to_vec()).collect(),
+            None => Vec::new(),
+        };
+        let server_name = match &*tls {
+            Connection::Server(conn) => conn.server_name().map(|name| name.to_string()),
+            Connection::Client(_) => None,
+        };
+        PeerIdentity { certs, server_name, alpn: tls.alpn_protocol().map(|p| p.to_vec()) }
+    }
+
+    /// Read some ciphertext from the socket into the session
+    async fn read_tls(&mut self) -> io::Result<()> {
+        if self.rpos == self.rlen {
+            let n = self.io.read(&mut self.rbuf[..]).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.rpos = 0;
+            self.rlen = n;
+        }
+        let mut tls = self.tls.lock().unwrap();
+        let mut rd = &self.rbuf[self.rpos..self.rlen];
+        self.rpos += tls.read_tls(&mut rd)?;
+        if let Err(e) = tls.process_new_packets() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        Ok(())
+    }
+
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            let r = self.tls.lock().unwrap().reader().read(&mut buf[offset..]);
+            match r {
+                // close_notify received
+                Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Ok(n) => offset += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.read_tls().await?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Encrypt the data into the write buffer, which is written to the socket when it's full.
+    pub async fn write_all(&mut self, mut buf: &[u8]) -> io::Result<()> {
+        while !buf.is_empty() {
+            {
+                let mut tls = self.tls.lock().unwrap();
+                let n = tls.writer().write(buf)?;
+                buf = &buf[n..];
+                while tls.wants_write() {
+                    tls.write_tls(&mut self.wbuf)?;
+                }
+            }
+            if self.wbuf.len() >= self.buf_size {
+                self.write_buf().await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn flush(&mut self) -> io::Result<()> {
+        {
+            let mut tls = self.tls.lock().unwrap();
+            while tls.wants_write() {
+                tls.write_tls(&mut self.wbuf)?;
+            }
+        }
+        self.write_buf().await
+    }
+
+    #[inline]
+    async fn write_buf(&mut self) -> io::Result<()> {
+        if self.wbuf.is_empty() {
+            return Ok(());
+        }
+        let r = self.io.write_all(&self.wbuf).await;
+        self.wbuf.clear();
+        r
+    }
+
+    /// Send close_notify and shutdown the write direction of the socket
+    pub async fn shutdown_write(&mut self) -> io::Result<()> {
+        self.tls.lock().unwrap().send_close_notify();
+        self.flush().await?;
+        self.io.shutdown_write().await
+    }
+}
+
+impl<RT: AsyncRuntime> fmt::Debug for TlsStream<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.io.fmt(f)
+    }
+}
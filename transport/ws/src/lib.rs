@@ -0,0 +1,55 @@
+#![cfg_attr(docsrs, feature(doc_cfg))]
+#![cfg_attr(docsrs, allow(unused_attributes))]
+
+//! # razor-rpc-ws
+//!
+//! This crate provides a WebSocket transport for [`razor-stream`](https://docs.rs/razor-stream),
+//! for the clients behind the HTTP-only load balancers or proxies, which cannot pass the raw TCP
+//! of [`razor-rpc-tcp`](https://docs.rs/razor-rpc-tcp). The frames are the same as TCP, carried
+//! in the binary messages of WebSocket (RFC 6455).
+//!
+//! [WsServer] upgrades the HTTP connections, a request not upgrading to WebSocket is answered
+//! with "426 Upgrade Required". It listens by `RpcServer::listen()` next to TCP, serving the same
+//! dispatch:
+//!
+//! ```ignore
+//! server.listen::<TcpServer<RT>, _>("0.0.0.0:8000", dispatch.clone()).await?;
+//! server.listen::<WsServer<RT>, _>("0.0.0.0:8080", dispatch).await?;
+//! let pool = facts.create_pool_async::<WsClient<RT>>("ws://gateway.local:80/rpc");
+//! ```
+//!
+//! The address of the client is "ws://host:port/path" (or "host:port", with path "/"), the
+//! subprotocol [WS_PROTOCOL] is offered in the upgrade. The address of the server is the one of
+//! TCP, any path is accepted. "wss://" is not supported, terminate TLS on the proxy instead.
+
+#[macro_use]
+extern crate captains_log;
+mod client;
+pub use client::*;
+mod server;
+pub use server::*;
+mod stream;
+
+/// The subprotocol of the upgrade, the server answers it when offered
+pub const WS_PROTOCOL: &str = "razor-rpc";
+
+/// Split the address into "host:port" and path
+fn split_addr(addr: &str) -> (&str, &str) {
+    let addr = addr.strip_prefix("ws://").unwrap_or(addr);
+    match addr.find('/') {
+        Some(i) => (&addr[..i], &addr[i..]),
+        None => (addr, "/"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_addr() {
+        assert_eq!(split_addr("ws://127.0.0.1:8080/rpc"), ("127.0.0.1:8080", "/rpc"));
+        assert_eq!(split_addr("ws://[::1]:8080"), ("[::1]:8080", "/"));
+        assert_eq!(split_addr("localhost:8080"), ("localhost:8080", "/"));
+    }
+}
@@ -0,0 +1,185 @@
+use crate::split_addr;
+use crate::stream::WsStream;
+use captains_log::filter::LogFilter;
+use orb::net::{UnifyListener, UnifyStream};
+use orb::prelude::*;
+use orb::utils::Cancellable;
+use razor_rpc_tcp::SERVER_DEFAULT_BUF_SIZE;
+use razor_stream::proto;
+use razor_stream::server::conn::{ConnIO, ConnProto};
+use razor_stream::server::{
+    RpcSvrPush, RpcSvrReq, ServerConfig, ServerTransport, task::ServerTaskEncode,
+};
+use razor_stream::{Codec, error::*};
+use std::cell::UnsafeCell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use std::{fmt, io};
+
+/// The HTTP upgrade is done on reading the first request, within `read_timeout`.
+pub struct WsServer<RT: AsyncRuntime> {
+    stream: UnsafeCell<WsStream<RT>>,
+    _conn_count: Arc<()>,
+    /// Set after the HTTP upgrade
+    upgraded: AtomicBool,
+    proto: ConnProto,
+}
+
+unsafe impl<RT: AsyncRuntime> Send for WsServer<RT> {}
+
+unsafe impl<RT: AsyncRuntime> Sync for WsServer<RT> {}
+
+impl<RT: AsyncRuntime> WsServer<RT> {
+    // Because async runtimes does not support splitting read and write to static handler,
+    // we use unsafe to achieve such goal,
+    #[inline(always)]
+    #[allow(clippy::mut_from_ref)]
+    fn get_stream_mut(&self) -> &mut WsStream<RT> {
+        unsafe { &mut *self.stream.get() }
+    }
+
+    /// Complete the HTTP upgrade within read_timeout, before reading the first request
+    async fn upgrade(
+        &self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<(), RpcIntErr> {
+        let stream = self.get_stream_mut();
+        let cancel_f = close_ch.recv_with_timer(RT::sleep(self.proto.config().read_timeout));
+        match Cancellable::new(stream.server_handshake(), cancel_f).await {
+            Ok(Ok(path)) => {
+                logger_debug!(logger, "{:?}: websocket upgraded, path {}", self, path);
+            }
+            Ok(Err(e)) => {
+                logger_warn!(logger, "{:?}: websocket upgrade err: {}", self, e);
+                return Err(RpcIntErr::IO);
+            }
+            Err(()) => {
+                logger_debug!(logger, "{:?}: websocket upgrade timeout", self);
+                return Err(RpcIntErr::Timeout);
+            }
+        }
+        self.upgraded.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl<RT: AsyncRuntime> fmt::Debug for WsServer<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.get_stream_mut().fmt(f)
+    }
+}
+
+impl<RT: AsyncRuntime> ConnIO for WsServer<RT> {
+    #[inline(always)]
+    async fn read_exact(&self, buf: &mut [u8]) -> io::Result<()> {
+        self.get_stream_mut().read_exact(buf).await
+    }
+
+    #[inline]
+    async fn write_frame(&self, frame: &proto::EncodedFrame<'_>) -> io::Result<()> {
+        if !self.upgraded.load(Ordering::Acquire) {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        let writer = self.get_stream_mut();
+        let timeout = self.proto.config().write_timeout;
+        for part in frame.parts() {
+            razor_rpc_tcp::io_with_timeout!(RT, timeout, writer.write_all(part))?;
+        }
+        Ok(())
+    }
+}
+
+impl<RT: AsyncRuntime> ServerTransport for WsServer<RT> {
+    type Listener = UnifyListener<RT>;
+
+    /// The address may be written as the one of the client, the path is ignored
+    async fn bind(addr: &str) -> io::Result<Self::Listener> {
+        Self::Listener::bind(split_addr(addr).0).await
+    }
+
+    fn new_conn(stream: UnifyStream<RT>, config: &ServerConfig, conn_count: Arc<()>) -> Self {
+        let mut buf_size = config.stream_buf_size;
+        if buf_size == 0 {
+            buf_size = SERVER_DEFAULT_BUF_SIZE;
+        }
+        Self {
+            stream: UnsafeCell::new(WsStream::new(stream, buf_size, false)),
+            upgraded: AtomicBool::new(false),
+            proto: ConnProto::new(config),
+            _conn_count: conn_count,
+        }
+    }
+
+    /// recv_req and return a temporary structure.
+    ///
+    /// NOTE: you should consume the buffer ref before recv another request.
+    async fn read_req<'a>(
+        &'a self, logger: &LogFilter, close_ch: &crossfire::MAsyncRx<()>,
+    ) -> Result<RpcSvrReq<'a>, RpcIntErr> {
+        if !self.upgraded.load(Ordering::Acquire) {
+            self.upgrade(logger, close_ch).await?;
+        }
+        self.proto.read_req::<RT, _>(logger, self, close_ch).await
+    }
+
+    #[inline]
+    async fn write_resp<T: ServerTaskEncode>(
+        &self, logger: &LogFilter, codec: &impl Codec, task: T,
+    ) -> io::Result<()> {
+        self.proto.write_resp(logger, self, codec, task).await
+    }
+
+    async fn write_handshake(
+        &self, logger: &LogFilter, seq: u64, negotiated: &proto::Negotiated,
+    ) -> io::Result<()> {
+        self.proto.write_handshake(logger, self, seq, negotiated).await?;
+        self.flush_resp(logger).await
+    }
+
+    #[inline]
+    async fn write_push(&self, logger: &LogFilter, push: RpcSvrPush) -> io::Result<()> {
+        self.proto.write_push(logger, self, push).await
+    }
+
+    #[inline]
+    async fn write_credit(&self, logger: &LogFilter, seq: u64, credit: u32) -> io::Result<()> {
+        self.proto.write_credit(logger, self, seq, credit).await
+    }
+
+    #[inline(always)]
+    async fn write_resp_internal(
+        &self, logger: &LogFilter, seq: u64, err: Option<EncodedErr>,
+    ) -> io::Result<()> {
+        self.proto.write_resp_internal(logger, self, seq, err).await
+    }
+
+    #[inline(always)]
+    async fn flush_resp(&self, logger: &LogFilter) -> io::Result<()> {
+        let writer = self.get_stream_mut();
+        if let Err(e) =
+            razor_rpc_tcp::io_with_timeout!(RT, self.proto.config().write_timeout, writer.flush())
+        {
+            logger_warn!(logger, "{:?}: flush err: {}", self, e);
+            return Err(e);
+        }
+        logger_trace!(logger, "{:?}: flush_resp ok", self);
+        Ok(())
+    }
+
+    #[inline]
+    async fn close_conn(&self, logger: &LogFilter) {
+        // Not upgraded, nothing to close
+        if !self.upgraded.load(Ordering::Acquire) {
+            return;
+        }
+        let writer = self.get_stream_mut();
+        // The close frame is sent after the pending responses
+        if let Err(e) = razor_rpc_tcp::io_with_timeout!(
+            RT,
+            self.proto.config().write_timeout,
+            writer.shutdown_write()
+        ) {
+            logger_debug!(logger, "{:?}: close_conn err: {}", self, e);
+        }
+    }
+}
@@ -0,0 +1,424 @@
+use crate::WS_PROTOCOL;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use orb::net::UnifyStream;
+use orb::prelude::*;
+use sha1::{Digest, Sha1};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{fmt, io};
+
+/// Appended to Sec-WebSocket-Key to compute Sec-WebSocket-Accept
+const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The limit of the HTTP head of the upgrade
+const HTTP_HEAD_MAX: usize = 8 * 1024;
+
+/// Reserved in front of the write buffer for the frame header
+const FRAME_HEAD_MAX: usize = 14;
+
+const OP_CONTINUATION: u8 = 0x0;
+const OP_BINARY: u8 = 0x2;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xa;
+
+/// Carry the byte stream of the frames in the binary messages of WebSocket.
+///
+/// The reader and writer coroutine use the stream at the same time (like razor-rpc-tcp), the read
+/// buffer and write buffer are owned by each of them. A ping received by the reader is answered
+/// by the writer on its next flush.
+pub(crate) struct WsStream<RT: AsyncRuntime> {
+    io: UnifyStream<RT>,
+    /// The client masks the frames sent, the server requires masked frames
+    client: bool,
+    /// received, `rbuf[rpos..rlen]` is not consumed yet
+    rbuf: Box<[u8]>,
+    rpos: usize,
+    rlen: usize,
+    /// The payload left of the data frame reading
+    frame_left: u64,
+    /// The mask of the data frame reading, and the offset in the payload
+    rmask: Option<[u8; 4]>,
+    rmask_pos: usize,
+    /// The payload of the binary message, sent on flush or exceeding buf_size
+    wbuf: Vec<u8>,
+    buf_size: usize,
+    pong: Mutex<Option<Vec<u8>>>,
+}
+
+impl<RT: AsyncRuntime> WsStream<RT> {
+    pub fn new(io: UnifyStream<RT>, buf_size: usize, client: bool) -> Self {
+        let mut wbuf = Vec::with_capacity(FRAME_HEAD_MAX + buf_size);
+        wbuf.resize(FRAME_HEAD_MAX, 0);
+        Self {
+            io,
+            client,
+            rbuf: vec![0; buf_size.max(HTTP_HEAD_MAX)].into_boxed_slice(),
+            rpos: 0,
+            rlen: 0,
+            frame_left: 0,
+            rmask: None,
+            rmask_pos: 0,
+            wbuf,
+            buf_size,
+            pong: Mutex::new(None),
+        }
+    }
+
+    /// Send the upgrade request, and verify the answer of the server
+    pub async fn client_handshake(&mut self, host: &str, path: &str) -> io::Result<()> {
+        let key = BASE64.encode([random_u64().to_ne_bytes(), random_u64().to_ne_bytes()].concat());
+        let req = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: {}\r\n\r\n",
+            path, host, key, WS_PROTOCOL
+        );
+        self.io.write_all(req.as_bytes()).await?;
+        let head = self.read_http_head().await?;
+        let status = head.lines().next().unwrap_or("");
+        if status.split(' ').nth(1) != Some("101") {
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                format!("websocket upgrade refused: {}", status),
+            ));
+        }
+        if header(&head, "sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid Sec-WebSocket-Accept"));
+        }
+        Ok(())
+    }
+
+    /// Answer the upgrade request of the client, return the path requested.
+    ///
+    /// The request not upgrading to WebSocket is answered with "426 Upgrade Required".
+    pub async fn server_handshake(&mut self) -> io::Result<String> {
+        let head = self.read_http_head().await?;
+        let mut request = head.lines().next().unwrap_or("").split(' ');
+        let (method, path) = (request.next(), request.next());
+        let upgrade = method == Some("GET")
+            && header(&head, "upgrade").is_some_and(|v| has_token(v, "websocket"))
+            && header(&head, "connection").is_some_and(|v| has_token(v, "upgrade"))
+            && header(&head, "sec-websocket-version") == Some("13");
+        let key = match header(&head, "sec-websocket-key") {
+            Some(key) if upgrade => key,
+            _ => {
+                let _ = self
+                    .io
+                    .write_all(
+                        b"HTTP/1.1 426 Upgrade Required\r\nUpgrade: websocket\r\n\
+                        Sec-WebSocket-Version: 13\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+                    )
+                    .await;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "not a websocket upgrade"));
+            }
+        };
+        let mut resp = format!(
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+            Sec-WebSocket-Accept: {}\r\n",
+            accept_key(key)
+        );
+        if header(&head, "sec-websocket-protocol").is_some_and(|v| has_token(v, WS_PROTOCOL)) {
+            resp.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", WS_PROTOCOL));
+        }
+        resp.push_str("\r\n");
+        self.io.write_all(resp.as_bytes()).await?;
+        Ok(path.unwrap_or("/").to_string())
+    }
+
+    /// Read until the end of the HTTP head, the data following is kept in the read buffer
+    async fn read_http_head(&mut self) -> io::Result<String> {
+        loop {
+            if let Some(end) = self.rbuf[..self.rlen].windows(4).position(|w| w == b"\r\n\r\n") {
+                self.rpos = end + 4;
+                return Ok(String::from_utf8_lossy(&self.rbuf[..end]).into_owned());
+            }
+            if self.rlen >= HTTP_HEAD_MAX {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "http head too large"));
+            }
+            let n = self.io.read(&mut self.rbuf[self.rlen..HTTP_HEAD_MAX]).await?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.rlen += n;
+        }
+    }
+
+    async fn read_raw(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            if self.rpos == self.rlen {
+                let n = self.io.read(&mut self.rbuf[..]).await?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.rpos = 0;
+                self.rlen = n;
+            }
+            let n = (buf.len() - offset).min(self.rlen - self.rpos);
+            buf[offset..offset + n].copy_from_slice(&self.rbuf[self.rpos..self.rpos + n]);
+            offset += n;
+            self.rpos += n;
+        }
+        Ok(())
+    }
+
+    /// Read the frame headers until the payload of a binary message, handle the control frames
+    async fn next_frame(&mut self) -> io::Result<()> {
+        loop {
+            let mut head = [0u8; 2];
+            self.read_raw(&mut head).await?;
+            if head[0] & 0x70 != 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "websocket rsv bits set"));
+            }
+            let opcode = head[0] & 0x0f;
+            let masked = head[1] & 0x80 != 0;
+            if masked == self.client {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "invalid websocket mask"));
+            }
+            let len = match head[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    self.read_raw(&mut len).await?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    self.read_raw(&mut len).await?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            let mask = if masked {
+                let mut mask = [0u8; 4];
+                self.read_raw(&mut mask).await?;
+                Some(mask)
+            } else {
+                None
+            };
+            match opcode {
+                OP_BINARY | OP_CONTINUATION => {
+                    if len > 0 {
+                        self.frame_left = len;
+                        self.rmask = mask;
+                        self.rmask_pos = 0;
+                        return Ok(());
+                    }
+                }
+                OP_PING | OP_PONG | OP_CLOSE => {
+                    if len > 125 {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "websocket control frame too large",
+                        ));
+                    }
+                    let mut payload = vec![0u8; len as usize];
+                    self.read_raw(&mut payload).await?;
+                    if let Some(mask) = mask {
+                        apply_mask(&mut payload, mask, 0);
+                    }
+                    match opcode {
+                        OP_PING => {
+                            self.pong.lock().unwrap().replace(payload);
+                        }
+                        OP_CLOSE => return Err(io::ErrorKind::UnexpectedEof.into()),
+                        _ => {}
+                    }
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unexpected websocket opcode",
+                    ));
+                }
+            }
+        }
+    }
+
+    pub async fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        let mut offset = 0;
+        while offset < buf.len() {
+            if self.frame_left == 0 {
+                self.next_frame().await?;
+            }
+            let n = ((buf.len() - offset) as u64).min(self.frame_left) as usize;
+            let part = &mut buf[offset..offset + n];
+            self.read_raw(part).await?;
+            if let Some(mask) = self.rmask {
+                apply_mask(part, mask, self.rmask_pos);
+                self.rmask_pos += n;
+            }
+            self.frame_left -= n as u64;
+            offset += n;
+        }
+        Ok(())
+    }
+
+    /// Append the data to the message, which is sent when it exceeds buf_size.
+    ///
+    /// The server sends the data not smaller than buf_size (usually a blob) in its own frame
+    /// without copying, the client has to copy it for masking.
+    pub async fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        if !self.client && buf.len() >= self.buf_size {
+            self.flush().await?;
+            let (head, n) = frame_head(OP_BINARY, buf.len(), None);
+            self.io.write_all(&head[..n]).await?;
+            return self.io.write_all(buf).await;
+        }
+        self.wbuf.extend_from_slice(buf);
+        if self.wbuf.len() - FRAME_HEAD_MAX >= self.buf_size {
+            return self.flush().await;
+        }
+        Ok(())
+    }
+
+    /// Send the message buffered, and the pong if any
+    pub async fn flush(&mut self) -> io::Result<()> {
+        let pong = self.pong.lock().unwrap().take();
+        if let Some(mut pong) = pong {
+            self.write_control(OP_PONG, &mut pong).await?;
+        }
+        let len = self.wbuf.len() - FRAME_HEAD_MAX;
+        if len == 0 {
+            return Ok(());
+        }
+        let mask = self.new_mask();
+        if let Some(mask) = mask {
+            apply_mask(&mut self.wbuf[FRAME_HEAD_MAX..], mask, 0);
+        }
+        // The header is written right before the payload, to send them at once
+        let (head, n) = frame_head(OP_BINARY, len, mask);
+        self.wbuf[FRAME_HEAD_MAX - n..FRAME_HEAD_MAX].copy_from_slice(&head[..n]);
+        let r = self.io.write_all(&self.wbuf[FRAME_HEAD_MAX - n..]).await;
+        self.wbuf.truncate(FRAME_HEAD_MAX);
+        r
+    }
+
+    async fn write_control(&mut self, opcode: u8, payload: &mut [u8]) -> io::Result<()> {
+        let mask = self.new_mask();
+        if let Some(mask) = mask {
+            apply_mask(payload, mask, 0);
+        }
+        let (head, n) = frame_head(opcode, payload.len(), mask);
+        let mut frame = Vec::with_capacity(n + payload.len());
+        frame.extend_from_slice(&head[..n]);
+        frame.extend_from_slice(payload);
+        self.io.write_all(&frame).await
+    }
+
+    #[inline]
+    fn new_mask(&self) -> Option<[u8; 4]> {
+        if self.client { Some((random_u64() as u32).to_ne_bytes()) } else { None }
+    }
+
+    /// Send the close frame (normal closure) and shutdown the write direction of the socket
+    pub async fn shutdown_write(&mut self) -> io::Result<()> {
+        self.flush().await?;
+        let mut status = 1000u16.to_be_bytes();
+        self.write_control(OP_CLOSE, &mut status).await?;
+        self.io.shutdown_write().await
+    }
+}
+
+impl<RT: AsyncRuntime> fmt::Debug for WsStream<RT> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.io.fmt(f)
+    }
+}
+
+/// Return the header and its length
+fn frame_head(opcode: u8, len: usize, mask: Option<[u8; 4]>) -> ([u8; FRAME_HEAD_MAX], usize) {
+    let mut head = [0u8; FRAME_HEAD_MAX];
+    // FIN, a message per frame
+    head[0] = 0x80 | opcode;
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    let mut n = 2;
+    if len < 126 {
+        head[1] = mask_bit | len as u8;
+    } else if len <= u16::MAX as usize {
+        head[1] = mask_bit | 126;
+        head[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+        n = 4;
+    } else {
+        head[1] = mask_bit | 127;
+        head[2..10].copy_from_slice(&(len as u64).to_be_bytes());
+        n = 10;
+    }
+    if let Some(mask) = mask {
+        head[n..n + 4].copy_from_slice(&mask);
+        n += 4;
+    }
+    (head, n)
+}
+
+#[inline]
+fn apply_mask(buf: &mut [u8], mask: [u8; 4], pos: usize) {
+    for (i, b) in buf.iter_mut().enumerate() {
+        *b ^= mask[(pos + i) & 3];
+    }
+}
+
+fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(WS_GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+/// The value of the header in the HTTP head, the name is case-insensitive
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    for line in head.lines().skip(1) {
+        if let Some((key, value)) = line.split_once(':') {
+            if key.trim().eq_ignore_ascii_case(name) {
+                return Some(value.trim());
+            }
+        }
+    }
+    None
+}
+
+/// Whether the comma separated header value contains the token
+fn has_token(value: &str, token: &str) -> bool {
+    value.split(',').any(|v| v.trim().eq_ignore_ascii_case(token))
+}
+
+/// For the masks and the key of the handshake, seeded by the random keys of std
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accept_key() {
+        // The example of RFC 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn test_frame_head() {
+        let (head, n) = frame_head(OP_BINARY, 5, None);
+        assert_eq!(&head[..n], &[0x82, 5]);
+        let (head, n) = frame_head(OP_BINARY, 300, Some([1, 2, 3, 4]));
+        assert_eq!(&head[..n], &[0x82, 0x80 | 126, 1, 44, 1, 2, 3, 4]);
+        let (head, n) = frame_head(OP_CLOSE, 70000, None);
+        assert_eq!(&head[..n], &[0x88, 127, 0, 0, 0, 0, 0, 1, 0x11, 0x70]);
+    }
+
+    #[test]
+    fn test_header() {
+        let head =
+            "GET / HTTP/1.1\r\nHost: a\r\nConnection: keep-alive, Upgrade\r\nupgrade: WebSocket";
+        assert_eq!(header(head, "host"), Some("a"));
+        assert!(has_token(header(head, "Connection").unwrap(), "upgrade"));
+        assert!(has_token(header(head, "Upgrade").unwrap(), "websocket"));
+        assert_eq!(header(head, "sec-websocket-key"), None);
+    }
+}